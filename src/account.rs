@@ -0,0 +1,202 @@
+//! Accounts, and a keyring of well known development accounts.
+//!
+//! An account is identified by its public key. Whoever holds the matching secret key controls
+//! the account, so there is no central list of who may take part. Anybody can make a keypair
+//! and start receiving money or signing blocks.
+//!
+//! For tests and experiments it is handy to have a few accounts with memorable names. The
+//! [`Keyring`] derives them from public seeds like `//Alice`, the same way Substrate does.
+//! Because the seeds are public, these accounts are no good for anything of value.
+
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, PublicKey, Signature},
+    Hash,
+};
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+/// The identity of an account. It is the account's public key.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AccountId(PublicKey);
+
+impl AccountId {
+    pub fn public(&self) -> PublicKey {
+        self.0
+    }
+
+    /// Check that this account signed the given message hash.
+    pub fn verify(&self, message: &Hash, signature: &Signature) -> bool {
+        self.0.verify(message, signature)
+    }
+}
+
+/// The all zero account. Zero is not the x coordinate of any point on the curve, so nobody
+/// can sign for this account. It makes a safe placeholder, for example as the author of a
+/// genesis block.
+impl Default for AccountId {
+    fn default() -> Self {
+        AccountId(PublicKey([0; 32]))
+    }
+}
+
+impl From<PublicKey> for AccountId {
+    fn from(public: PublicKey) -> Self {
+        AccountId(public)
+    }
+}
+
+impl From<&Keypair> for AccountId {
+    fn from(pair: &Keypair) -> Self {
+        AccountId(pair.public())
+    }
+}
+
+impl From<Keyring> for AccountId {
+    fn from(keyring: Keyring) -> Self {
+        keyring.to_account_id()
+    }
+}
+
+impl Encode for AccountId {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.0.encode_to(dest);
+    }
+}
+
+impl Decode for AccountId {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(AccountId(PublicKey::decode(input)?))
+    }
+}
+
+/// Development accounts are shown by name, and every other account by its full public key.
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Keyring::from_account_id(self) {
+            Some(keyring) => write!(f, "{keyring:?}"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl fmt::Debug for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Keyring::from_account_id(self) {
+            Some(keyring) => write!(f, "{keyring:?}"),
+            None => write!(f, "AccountId({:?})", self.0),
+        }
+    }
+}
+
+/// Accounts are written either as the name of a development account, in any case, or as
+/// a public key in hex with a `0x` prefix.
+impl FromStr for AccountId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(keyring) = s.parse::<Keyring>() {
+            return Ok(keyring.to_account_id());
+        }
+        let hex = s
+            .strip_prefix("0x")
+            .filter(|hex| hex.len() == 64 && hex.is_ascii())
+            .ok_or_else(|| {
+                format!("unknown account `{s}`, expected a name like alice or a 0x public key")
+            })?;
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|_| format!("`{s}` is not a valid public key"))?;
+        }
+        Ok(AccountId(PublicKey(bytes)))
+    }
+}
+
+/// The well known development accounts.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Keyring {
+    Alice,
+    Bob,
+    Charlie,
+    Dave,
+    Eve,
+    Ferdie,
+}
+
+impl Keyring {
+    pub const ALL: [Keyring; 6] = [
+        Keyring::Alice,
+        Keyring::Bob,
+        Keyring::Charlie,
+        Keyring::Dave,
+        Keyring::Eve,
+        Keyring::Ferdie,
+    ];
+
+    /// The keypair for this account, derived from a seed like `//Alice`.
+    pub fn pair(self) -> &'static Keypair {
+        static PAIRS: OnceLock<[Keypair; 6]> = OnceLock::new();
+        let pairs =
+            PAIRS.get_or_init(|| Keyring::ALL.map(|k| Keypair::from_seed(&format!("//{k:?}"))));
+        &pairs[self as usize]
+    }
+
+    pub fn public(self) -> PublicKey {
+        self.pair().public()
+    }
+
+    pub fn to_account_id(self) -> AccountId {
+        AccountId(self.public())
+    }
+
+    /// Find the development account with the given id, if it is one.
+    pub fn from_account_id(account: &AccountId) -> Option<Keyring> {
+        Keyring::ALL.into_iter().find(|k| k.public() == account.0)
+    }
+}
+
+impl FromStr for Keyring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Keyring::ALL
+            .into_iter()
+            .find(|k| format!("{k:?}").eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown development account `{s}`"))
+    }
+}
+
+#[test]
+fn account_keyring_accounts_are_distinct() {
+    let mut accounts: Vec<_> = Keyring::ALL.map(Keyring::to_account_id).into();
+    accounts.sort();
+    accounts.dedup();
+
+    assert_eq!(accounts.len(), Keyring::ALL.len());
+    for keyring in Keyring::ALL {
+        assert_eq!(
+            Keyring::from_account_id(&keyring.to_account_id()),
+            Some(keyring)
+        );
+    }
+}
+
+#[test]
+fn account_id_parses_names_and_public_keys() {
+    let alice = Keyring::Alice.to_account_id();
+    let stranger = AccountId::from(&Keypair::from_seed("stranger"));
+
+    assert_eq!("alice".parse(), Ok(alice));
+    assert_eq!("Alice".parse(), Ok(alice));
+    assert_eq!(alice.to_string(), "Alice");
+    assert_eq!(stranger.to_string().parse(), Ok(stranger));
+    assert!("mallory".parse::<AccountId>().is_err());
+    assert!("0x1234".parse::<AccountId>().is_err());
+}
+
+#[test]
+fn account_id_round_trips_through_codec() {
+    let alice = Keyring::Alice.to_account_id();
+
+    assert_eq!(AccountId::decode_all(&alice.encode()), Ok(alice));
+}
@@ -0,0 +1,112 @@
+//! A full node for the accounted currency from chapter 1, for running several nodes side by
+//! side and watching them gossip over TCP.
+//!
+//! Start one node, then point more nodes at it:
+//!
+//! ```text
+//! cargo run --bin node -- 127.0.0.1:3030
+//! cargo run --bin node -- 127.0.0.1:3031 127.0.0.1:3030
+//! ```
+//!
+//! Every node starts from the same genesis ledger, in which each development account holds
+//! 1000. Type transactions in the same syntax as the repl to submit them, `author` to author
+//! a block from the pool, or `status` to see where the node is. The pool hands out the
+//! highest fees first, and it is bounded, so a flood of transactions cannot exhaust the
+//! node's memory.
+
+use diy_blockchain::{
+    c1_state_machine::p4_accounted_currency::{
+        AccountedCurrency, AccountingTransaction, Balances, Ledger,
+    },
+    c4_client::{
+        BoundedPool, FullClient, ImportBlock, LongestChain, MemoryStore, PoolLimits, TcpNode,
+    },
+    Keyring,
+};
+use std::{
+    env,
+    io::{self, BufRead},
+    sync::mpsc::{channel, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+type FeePool = BoundedPool<AccountingTransaction, fn(AccountingTransaction) -> u64>;
+type Node = TcpNode<(), AccountedCurrency, LongestChain, FeePool>;
+
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let Some(listen_addr) = args.next() else {
+        eprintln!("usage: node <listen address> [peer address]...");
+        return Ok(());
+    };
+
+    let genesis_state = Ledger::from(
+        Keyring::ALL
+            .iter()
+            .map(|keyring| (keyring.to_account_id(), 1000))
+            .collect::<Balances>(),
+    );
+    let client = FullClient::new(
+        (),
+        LongestChain::default(),
+        BoundedPool::by_fee::<AccountedCurrency>(PoolLimits::default()),
+        MemoryStore::new(),
+        genesis_state,
+    )?;
+    let mut node = Node::bind(client, listen_addr)?;
+    println!("listening on {}", node.local_addr()?);
+    for peer_addr in args {
+        node.connect(&peer_addr)?;
+    }
+
+    // Reading from stdin blocks, so it gets a thread of its own, and the node only ever
+    // looks at the lines that have already arrived.
+    let (line_sender, lines) = channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if line_sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        node.poll()?;
+        match lines.try_recv() {
+            Ok(line) => handle_command(&mut node, line.trim()),
+            Err(TryRecvError::Empty) => thread::sleep(Duration::from_millis(10)),
+            Err(TryRecvError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+fn handle_command(node: &mut Node, line: &str) {
+    match line {
+        "" => (),
+        "author" => match node.author_block() {
+            Ok(block_hash) => println!("authored {block_hash}"),
+            Err(e) => println!("could not author a block: {e:?}"),
+        },
+        "status" => {
+            let client = node.client();
+            println!(
+                "best block {}, {} leaves, {} peers, {} transactions in the pool",
+                client.best_block(),
+                client.all_leaves().len(),
+                node.peer_count(),
+                client.pool_size(),
+            );
+        }
+        _ => match line.parse::<AccountingTransaction>() {
+            Ok(t) => {
+                if node.submit_transaction(t) {
+                    println!("submitted");
+                } else {
+                    println!("the pool refused the transaction");
+                }
+            }
+            Err(e) => println!("{e}"),
+        },
+    }
+}
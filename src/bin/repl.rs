@@ -0,0 +1,229 @@
+//! A repl-like program for interacting with the state machines from chapter 1.
+//!
+//! Pick a machine from the menu, then type transitions one line at a time to see where
+//! they take the machine. Every state is kept in a history, so mistakes can be undone.
+//!
+//! Run it with `cargo run --bin repl`.
+
+use diy_blockchain::c1_state_machine::{
+    p1_switches::{LightSwitch, TwoSwitches, WeirdSwitchMachine},
+    p2_laundry_machine::{ClothesMachine, ClothesState},
+    p3_atm::Atm,
+    p4_accounted_currency::{AccountedCurrency, Balances, Ledger},
+    p5_digital_cash::{DigitalCashSystem, State},
+    StateMachine,
+};
+use std::io::{self, BufRead, Write};
+
+/// A state machine that can be driven from the repl. On top of the machine itself,
+/// the repl needs somewhere to start, a way to read transitions from text, and a
+/// way to show states back to the user.
+trait Repl: StateMachine {
+    /// The state the machine starts in when it is chosen from the menu.
+    fn initial_state() -> Self::State;
+
+    /// Read a transition from a line of user input.
+    fn parse_transition(line: &str) -> Result<Self::Transition, String>;
+
+    /// Describe a state for the user.
+    fn show_state(state: &Self::State) -> String;
+
+    /// A short description of the transitions this machine accepts.
+    fn usage() -> &'static str;
+}
+
+impl Repl for LightSwitch {
+    fn initial_state() -> bool {
+        false
+    }
+
+    fn parse_transition(line: &str) -> Result<(), String> {
+        match line.trim() {
+            "toggle" => Ok(()),
+            other => Err(format!("unknown transition `{other}`")),
+        }
+    }
+
+    fn show_state(state: &bool) -> String {
+        if *state { "on" } else { "off" }.into()
+    }
+
+    fn usage() -> &'static str {
+        "toggle"
+    }
+}
+
+impl Repl for WeirdSwitchMachine {
+    fn initial_state() -> TwoSwitches {
+        TwoSwitches::default()
+    }
+
+    fn parse_transition(line: &str) -> Result<Self::Transition, String> {
+        line.parse()
+    }
+
+    fn show_state(state: &TwoSwitches) -> String {
+        state.to_string()
+    }
+
+    fn usage() -> &'static str {
+        "first | second"
+    }
+}
+
+impl Repl for ClothesMachine {
+    fn initial_state() -> ClothesState {
+        ClothesState::Clean(10)
+    }
+
+    fn parse_transition(line: &str) -> Result<Self::Transition, String> {
+        line.parse()
+    }
+
+    fn show_state(state: &ClothesState) -> String {
+        state.to_string()
+    }
+
+    fn usage() -> &'static str {
+        "wear | wash | dry"
+    }
+}
+
+impl Repl for Atm {
+    fn initial_state() -> Atm {
+        Atm::new(100)
+    }
+
+    fn parse_transition(line: &str) -> Result<Self::Transition, String> {
+        line.parse()
+    }
+
+    fn show_state(state: &Atm) -> String {
+        state.to_string()
+    }
+
+    fn usage() -> &'static str {
+        "swipe <pin> | press <1-4 or enter>"
+    }
+}
+
+impl Repl for AccountedCurrency {
+    fn initial_state() -> Ledger {
+        Ledger::default()
+    }
+
+    fn parse_transition(line: &str) -> Result<Self::Transition, String> {
+        line.parse()
+    }
+
+    fn show_state(state: &Ledger) -> String {
+        let show = |map: &Balances| {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort();
+            let entries: Vec<_> = entries
+                .into_iter()
+                .map(|(user, value)| format!("{user}: {value}"))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        };
+        format!("balances {}, nonces {}", show(&state.balances), show(&state.nonces))
+    }
+
+    fn usage() -> &'static str {
+        "mint <account> <amount> | burn <account> <amount> | transfer <sender> <receiver> <amount> <nonce> [fee <amount>]"
+    }
+}
+
+impl Repl for DigitalCashSystem {
+    fn initial_state() -> State {
+        State::new()
+    }
+
+    fn parse_transition(line: &str) -> Result<Self::Transition, String> {
+        line.parse()
+    }
+
+    fn show_state(state: &State) -> String {
+        state.to_string()
+    }
+
+    fn usage() -> &'static str {
+        "mint <account> <amount> | transfer <owner:amount:serial>... -> <owner:amount:serial>... [fee <amount>]"
+    }
+}
+
+/// Drive the chosen machine until the user quits or input runs out.
+fn run<M: Repl>(input: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<()> {
+    println!("Driving the {}. Transitions: {}", M::human_name(), M::usage());
+    println!("Other commands: undo | history | help | quit");
+
+    let mut history = vec![M::initial_state()];
+    loop {
+        let state = history.last().expect("history always holds the initial state");
+        println!("state: {}", M::show_state(state));
+        print!("> ");
+        io::stdout().flush()?;
+
+        let Some(line) = input.next().transpose()? else {
+            return Ok(());
+        };
+        match line.trim() {
+            "" => {}
+            "quit" | "exit" => return Ok(()),
+            "help" => println!("Transitions: {}", M::usage()),
+            "undo" => {
+                if history.len() > 1 {
+                    history.pop();
+                } else {
+                    println!("nothing to undo");
+                }
+            }
+            "history" => {
+                for (i, state) in history.iter().enumerate() {
+                    println!("{i:>4}: {}", M::show_state(state));
+                }
+            }
+            line => match M::parse_transition(line) {
+                Ok(t) => match M::next_state(state, &t) {
+                    Ok(next) => history.push(next),
+                    Err(e) => println!("transition refused: {e:?}"),
+                },
+                Err(e) => println!("could not parse transition: {e}"),
+            },
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let machines = [
+        LightSwitch::human_name(),
+        WeirdSwitchMachine::human_name(),
+        ClothesMachine::human_name(),
+        Atm::human_name(),
+        AccountedCurrency::human_name(),
+        DigitalCashSystem::human_name(),
+    ];
+    println!("Which state machine would you like to drive?");
+    for (i, name) in machines.iter().enumerate() {
+        println!("  {}) {name}", i + 1);
+    }
+    print!("> ");
+    io::stdout().flush()?;
+
+    let mut input = io::stdin().lock().lines();
+    let Some(choice) = input.next().transpose()? else {
+        return Ok(());
+    };
+    match choice.trim() {
+        "1" => run::<LightSwitch>(&mut input),
+        "2" => run::<WeirdSwitchMachine>(&mut input),
+        "3" => run::<ClothesMachine>(&mut input),
+        "4" => run::<Atm>(&mut input),
+        "5" => run::<AccountedCurrency>(&mut input),
+        "6" => run::<DigitalCashSystem>(&mut input),
+        other => {
+            println!("`{other}` is not on the menu");
+            Ok(())
+        }
+    }
+}
@@ -1,13 +1,15 @@
 //! This module is all about modeling phenomena and systems as state machines. We begin with a few simple
 //! examples, and then proceed to build bigger and more complex state machines all implementing the same simple interface.
 
-mod p1_switches;
-mod p2_laundry_machine;
-mod p3_atm;
-mod p4_accounted_currency;
-mod p5_digital_cash;
+pub mod p1_switches;
+pub mod p2_laundry_machine;
+pub mod p3_atm;
+pub mod p4_accounted_currency;
+pub mod p5_digital_cash;
 mod p6_open_ended;
 
+use crate::{codec::Encode, hash, AccountId, Hash, Keyring};
+
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
     /// The states that can be occupied by this machine
@@ -16,24 +18,144 @@ pub trait StateMachine {
     /// The transitions that can be made between states
     type Transition;
 
-    /// Calculate the resulting state when this state undergoes the given transition
-    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
+    /// The reason a transition could not be applied to a given state
+    type Error: core::fmt::Debug;
+
+    /// Calculate the resulting state when this state undergoes the given transition.
+    /// Returns an error, and leaves the caller's state untouched, when the transition
+    /// is not valid from the starting state.
+    fn next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error>;
 
     /// A human-readable name for this state machine. This may be used in user-facing
-    /// programs such as the repl described below. This is not in any way related to
+    /// programs such as the repl in `src/bin/repl.rs`. This is not in any way related to
     /// the correctness of the state machine.
     fn human_name() -> String {
         "Unnamed state machine".into()
     }
+
+    /// Check a transition that may have arrived ahead of its turn, as far as that is possible
+    /// before the transitions it waits for have been applied. Transaction pools use this to
+    /// hold on to such transitions rather than refuse them. By default no transition waits
+    /// for another, so it has to apply to the given state right away.
+    fn pre_validate(state: &Self::State, t: &Self::Transition) -> Result<(), Self::Error> {
+        Self::next_state(state, t).map(drop)
+    }
+
+    /// The fee that this transition pays to the author of the block that includes it.
+    /// Machines without a currency have no fees.
+    fn fee(_t: &Self::Transition) -> u64 {
+        0
+    }
+
+    /// The account that sent this transition, if it has one. Transaction pools use this to
+    /// share their space fairly between senders.
+    fn sender(_t: &Self::Transition) -> Option<AccountId> {
+        None
+    }
+
+    /// The nonce that the given account's next [`Nonced`] transition has to use on top of the
+    /// given state. Machines whose transitions carry no nonces leave every account at zero.
+    fn nonce(_state: &Self::State, _account: &AccountId) -> u64 {
+        0
+    }
+
+    /// Finish off a block once all of its transitions have been applied. `fees` is the total
+    /// of [`StateMachine::fee`] over the block's transitions. Machines with a currency pay the
+    /// fees and any block reward to the author here. Other machines leave the state alone.
+    ///
+    /// Fails if the author cannot be paid in full, for example because their balance would
+    /// overflow. A block whose payout fails is invalid, just like one with an invalid transition.
+    fn finalize_block(state: Self::State, _author: &AccountId, _fees: u64) -> Result<Self::State, Self::Error> {
+        Ok(state)
+    }
+
+    /// The commitment to a state that goes in block headers. By default this is the hash of
+    /// the whole state. Machines that keep their state in a trie use the trie's root instead,
+    /// which is cheaper to update and lets parts of the state be proven on their own.
+    fn state_root(state: &Self::State) -> Hash
+    where
+        Self::State: Encode,
+    {
+        hash(state)
+    }
 }
 
-/// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
-pub enum User {
-    Alice,
-    Bob,
-    Charlie,
+/// A transition that is signed by an account and numbered with that account's nonce.
+///
+/// Each account numbers the transactions it sends 0, 1, 2 and so on, and the state machine only
+/// accepts the next number in the sequence. That stops the same transaction from being applied
+/// twice, and it tells a transaction pool which transactions have to wait for others.
+pub trait Nonced {
+    /// The sending account and the nonce it used. Transitions that need no replay protection
+    /// return `None`.
+    fn sender_nonce(&self) -> Option<(AccountId, u64)>;
 }
 
-//TODO Some kind of main program that allows users to interact with their state machine in a repl-like way.
-// Might require From<String> implementation for the transition type.
+/// A state machine in which every transition is valid from every state.
+///
+/// Simple machines like switches and laundry never refuse a transition, so they implement
+/// this trait instead, and get a `StateMachine` implementation for free through the blanket
+/// adapter below.
+pub trait InfallibleStateMachine {
+    /// The states that can be occupied by this machine
+    type State;
+
+    /// The transitions that can be made between states
+    type Transition;
+
+    /// Calculate the resulting state when this state undergoes the given transition
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
+
+    /// A human-readable name for this state machine.
+    fn human_name() -> String {
+        "Unnamed state machine".into()
+    }
+}
+
+impl<M: InfallibleStateMachine> StateMachine for M {
+    type State = M::State;
+    type Transition = M::Transition;
+    type Error = core::convert::Infallible;
+
+    fn next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error> {
+        Ok(<M as InfallibleStateMachine>::next_state(starting_state, t))
+    }
+
+    fn human_name() -> String {
+        <M as InfallibleStateMachine>::human_name()
+    }
+}
+
+/// Parse a whitespace-separated amount in one of the transition parsers below.
+fn parse_amount(word: Option<&str>) -> Result<u64, String> {
+    let word = word.ok_or("missing amount")?;
+    word.parse()
+        .map_err(|_| format!("`{word}` is not a valid amount"))
+}
+
+/// Parse the optional `fee <amount>` that may end a transfer in one of the transition parsers
+/// below. A transfer without one pays no fee.
+fn parse_fee<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<u64, String> {
+    match words.next() {
+        None => Ok(0),
+        Some("fee") => parse_amount(words.next()),
+        Some(extra) => Err(format!("unexpected `{extra}` after the transaction")),
+    }
+}
+
+/// Parse a whitespace-separated account in one of the transition parsers below.
+fn parse_account(word: Option<&str>) -> Result<AccountId, String> {
+    word.ok_or("missing account")?.parse()
+}
+
+/// Parse a whitespace-separated development account in one of the transition parsers below.
+/// Only development accounts can sign from the command line, because their keys are public.
+fn parse_signer(word: Option<&str>) -> Result<Keyring, String> {
+    word.ok_or("missing account")?.parse()
+}
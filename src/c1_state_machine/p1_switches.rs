@@ -2,7 +2,8 @@
 //! In these examples, we use actually switch boards as the state machine. The state is,
 //! well, just the state of the switches.
 
-use super::StateMachine;
+use super::InfallibleStateMachine;
+use std::{fmt, str::FromStr};
 
 /// This state machine models a single light switch.
 /// The internal state, a bool, represents whether the switch is on or not.
@@ -10,13 +11,17 @@ pub struct LightSwitch;
 
 /// We model this simple system as a state machine with a single transition - toggling the switch
 /// Because there is only a single kind of transition, we can use a unit struct.
-impl StateMachine for LightSwitch {
+impl InfallibleStateMachine for LightSwitch {
     type State = bool;
     type Transition = ();
 
     fn next_state(starting_state: &bool, _t: &()) -> bool {
         !starting_state
     }
+
+    fn human_name() -> String {
+        "Light Switch".into()
+    }
 }
 
 /// This second  state machine models two light switches with one weird property.
@@ -24,7 +29,8 @@ impl StateMachine for LightSwitch {
 pub struct WeirdSwitchMachine;
 
 /// The state is now two switches instead of one so we use a struct.
-#[derive(PartialEq, Eq, Debug)]
+/// By default both switches are off.
+#[derive(PartialEq, Eq, Debug, Default)]
 pub struct TwoSwitches {
     first_switch: bool,
     second_switch: bool,
@@ -36,8 +42,32 @@ pub enum Toggle {
     SecondSwitch,
 }
 
+impl FromStr for Toggle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "first" | "1" => Ok(Toggle::FirstSwitch),
+            "second" | "2" => Ok(Toggle::SecondSwitch),
+            other => Err(format!("unknown switch `{other}`, expected first or second")),
+        }
+    }
+}
+
+impl fmt::Display for TwoSwitches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        write!(
+            f,
+            "first switch {}, second switch {}",
+            on_off(self.first_switch),
+            on_off(self.second_switch)
+        )
+    }
+}
+
 /// We model this system as a state machine with two possible transitions
-impl StateMachine for WeirdSwitchMachine {
+impl InfallibleStateMachine for WeirdSwitchMachine {
     type State = TwoSwitches;
     type Transition = Toggle;
 
@@ -66,6 +96,10 @@ impl StateMachine for WeirdSwitchMachine {
             }
         }
     }
+
+    fn human_name() -> String {
+        "Weird Switch Machine".into()
+    }
 }
 
 #[test]
@@ -159,3 +193,10 @@ fn sm_1_two_switches_second_goes_off() {
         }
     );
 }
+
+#[test]
+fn sm_1_parse_toggle() {
+    assert!(matches!("first".parse(), Ok(Toggle::FirstSwitch)));
+    assert!(matches!("2".parse(), Ok(Toggle::SecondSwitch)));
+    assert!("third".parse::<Toggle>().is_err());
+}
@@ -2,7 +2,8 @@
 //! ready to be worn again. Or course washing and wearing clothes takes its toll on the clothes, and
 //! eventually they get tattered.
 
-use super::StateMachine;
+use super::InfallibleStateMachine;
+use std::{fmt, str::FromStr};
 
 /// This state machine models the typical life cycle of clothes as they make their way through the laundry
 /// cycle several times before ultimately becoming tattered.
@@ -35,7 +36,31 @@ pub enum ClothesAction {
     Dry,
 }
 
-impl StateMachine for ClothesMachine {
+impl FromStr for ClothesAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "wear" => Ok(ClothesAction::Wear),
+            "wash" => Ok(ClothesAction::Wash),
+            "dry" => Ok(ClothesAction::Dry),
+            other => Err(format!("unknown action `{other}`, expected wear, wash, or dry")),
+        }
+    }
+}
+
+impl fmt::Display for ClothesState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClothesState::Clean(life) => write!(f, "clean, {life} life left"),
+            ClothesState::Dirty(life) => write!(f, "dirty, {life} life left"),
+            ClothesState::Wet(life) => write!(f, "wet, {life} life left"),
+            ClothesState::Tattered => write!(f, "tattered"),
+        }
+    }
+}
+
+impl InfallibleStateMachine for ClothesMachine {
     type State = ClothesState;
     type Transition = ClothesAction;
 
@@ -69,6 +94,10 @@ impl StateMachine for ClothesMachine {
         }
     }
 
+    fn human_name() -> String {
+        "Clothes Machine".into()
+    }
+
 }
 fn decrease_life_and_check_tattered(life: u64, next_state: ClothesState) -> ClothesState {
     if life == 1 {
@@ -245,3 +274,9 @@ fn sm_2_dry_dirty_until_tattered() {
     let expected = ClothesState::Tattered;
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_2_parse_action() {
+    assert!(matches!("Wash".parse(), Ok(ClothesAction::Wash)));
+    assert!("iron".parse::<ClothesAction>().is_err());
+}
@@ -2,7 +2,9 @@
 //! The atm may fail to give you cash if it is empty or you haven't swiped your card, or you have
 //! entered the wrong pin.
 
-use super::StateMachine;
+use super::InfallibleStateMachine;
+use crate::{codec::Encode, Hash};
+use std::{fmt, str::FromStr};
 
 /// The keys on the ATM keypad
 #[derive(Hash, Debug, PartialEq, Eq, Clone)]
@@ -25,15 +27,68 @@ impl Key {
     }
 }
 
+impl Encode for Key {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        let index: u8 = match self {
+            Key::One => 0,
+            Key::Two => 1,
+            Key::Three => 2,
+            Key::Four => 3,
+            Key::Enter => 4,
+        };
+        index.encode_to(dest)
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "1" => Ok(Key::One),
+            "2" => Ok(Key::Two),
+            "3" => Ok(Key::Three),
+            "4" => Ok(Key::Four),
+            "enter" => Ok(Key::Enter),
+            other => Err(format!("unknown key `{other}`, the keypad has 1, 2, 3, 4, and enter")),
+        }
+    }
+}
+
 /// Something you can do to the ATM
+#[derive(Debug, PartialEq, Eq)]
 pub enum Action {
     /// Swipe your card at the ATM. The attached value is the hash of the pin
     /// that should be keyed in on the keypad next.
-    SwipeCard(u64),
+    SwipeCard(Hash),
     /// Press a key on the keypad
     PressKey(Key),
 }
 
+/// Actions are written as `swipe <pin>` or `press <key>`. The key alone is accepted as
+/// shorthand for pressing it. Swiping takes the pin itself, for example `swipe 1234`,
+/// and the card carries that pin's hash.
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("swipe"), Some(pin), None) => {
+                let keys = pin
+                    .chars()
+                    .map(|c| c.to_string().parse())
+                    .collect::<Result<Vec<Key>, _>>()?;
+                Ok(Action::SwipeCard(crate::hash(&keys)))
+            }
+            (Some("press"), Some(key), None) | (Some(key), None, None) => {
+                Ok(Action::PressKey(key.parse()?))
+            }
+            _ => Err("expected `swipe <pin>` or `press <key>`".into()),
+        }
+    }
+}
+
 /// The various states of authentication possible with the ATM
 #[derive(Debug, PartialEq, Eq, Clone)]
 enum Auth {
@@ -41,7 +96,7 @@ enum Auth {
     Waiting,
     /// The user has swiped their card, providing the enclosed PIN hash.
     /// Waiting for the user to key in their pin
-    Authenticating(u64),
+    Authenticating(Hash),
     /// The user has authenticated. Waiting for them to key in the amount
     /// of cash to withdraw
     Authenticated,
@@ -63,7 +118,35 @@ pub struct Atm {
     keystroke_register: Vec<Key>,
 }
 
-impl StateMachine for Atm {
+impl Atm {
+    /// Create an idle ATM holding the given amount of cash.
+    pub fn new(cash_inside: u64) -> Self {
+        Atm {
+            cash_inside,
+            expected_pin_hash: Auth::Waiting,
+            keystroke_register: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Atm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.expected_pin_hash {
+            Auth::Waiting => "waiting for a card",
+            Auth::Authenticating(_) => "waiting for the pin",
+            Auth::Authenticated => "waiting for an amount",
+        };
+        let keys: String = self
+            .keystroke_register
+            .iter()
+            .filter_map(Key::to_int)
+            .map(|digit| digit.to_string())
+            .collect();
+        write!(f, "{} cash inside, {status}, keys pressed: [{keys}]", self.cash_inside)
+    }
+}
+
+impl InfallibleStateMachine for Atm {
     // Notice that we are using the same type for the state as we are using for the machine this time.
     type State = Self;
     type Transition = Action;
@@ -132,6 +215,10 @@ impl StateMachine for Atm {
                 _ => starting_state.clone(),
             }
         }
+
+    fn human_name() -> String {
+        "ATM".into()
+    }
 }
 
 #[test]
@@ -141,10 +228,10 @@ fn sm_3_simple_swipe_card() {
         expected_pin_hash: Auth::Waiting,
         keystroke_register: Vec::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(crate::hash(&1234u64)));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: Vec::new(),
     };
 
@@ -155,13 +242,13 @@ fn sm_3_simple_swipe_card() {
 fn sm_3_swipe_card_again_part_way_through() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: Vec::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(crate::hash(&1234u64)));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: Vec::new(),
     };
 
@@ -169,13 +256,13 @@ fn sm_3_swipe_card_again_part_way_through() {
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: vec![Key::One, Key::Three],
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(crate::hash(&1234u64)));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: vec![Key::One, Key::Three],
     };
 
@@ -203,13 +290,13 @@ fn sm_3_press_key_before_card_swipe() {
 fn sm_3_enter_single_digit_of_pin() {
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: Vec::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::One));
     let expected = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: vec![Key::One],
     };
 
@@ -217,13 +304,13 @@ fn sm_3_enter_single_digit_of_pin() {
 
     let start = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: vec![Key::One],
     };
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Two));
     let expected1 = Atm {
         cash_inside: 10,
-        expected_pin_hash: Auth::Authenticating(1234),
+        expected_pin_hash: Auth::Authenticating(crate::hash(&1234u64)),
         keystroke_register: vec![Key::One, Key::Two],
     };
 
@@ -336,3 +423,14 @@ fn sm_3_withdraw_acceptable_amount() {
 
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_3_parse_actions() {
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+
+    assert_eq!("swipe 1234".parse(), Ok(Action::SwipeCard(crate::hash(&pin))));
+    assert_eq!("press 3".parse(), Ok(Action::PressKey(Key::Three)));
+    assert_eq!("enter".parse(), Ok(Action::PressKey(Key::Enter)));
+    assert!("swipe 1259".parse::<Action>().is_err());
+    assert!("withdraw 10".parse::<Action>().is_err());
+}
@@ -7,357 +7,1060 @@
 //!
 //! In this module we design a state machine that tracks the currency balances of several users.
 //! Each user is associated with an account balance and users are able to send money to other users.
+//!
+//! Balances and nonces live in Merkle-Patricia tries, so the state root is updated a little
+//! after every transition rather than rehashed from scratch, and the balance of a single
+//! account can be proven to someone who only knows the state root.
 
-use super::{StateMachine, User};
-use std::collections::HashMap;
+use super::{parse_account, parse_amount, parse_fee, parse_signer, Nonced, StateMachine};
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature, Trie, TrieProof},
+    hash, AccountId, Hash,
+};
+#[cfg(test)]
+use crate::Keyring;
+use std::str::FromStr;
 
 /// This state machine models a multi-user currency system. It tracks the balance of each
 /// user and allows users to send funds to one another.
+///
+/// Every transfer carries the sender's nonce, so a transfer can only ever be applied once.
+///
+/// Transfers may also pay a fee. At the end of each block the author collects the block's
+/// fees, plus the ledger's block reward, which is freshly minted.
 pub struct AccountedCurrency;
 
 /// The main balances mapping.
 ///
-/// Each entry maps a user id to their corresponding balance.
+/// Each entry maps an account id to its corresponding balance.
 /// There exists an existential deposit of at least 1. That is
 /// to say that an account gets removed from the map entirely
 /// when its balance falls back to 0.
-type Balances = HashMap<User, u64>;
+pub type Balances = Trie<AccountId, u64>;
+
+/// The next nonce of each account, which is the number of transfers it has sent.
+///
+/// Unlike balances, nonces are never removed. If an account's nonce went back to zero when its
+/// balance ran out, its old transfers could be replayed as soon as it was topped up again.
+pub type Nonces = Trie<AccountId, u64>;
+
+/// The full state of the accounted currency.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ledger {
+    pub balances: Balances,
+    pub nonces: Nonces,
+    /// The amount minted for the author of each block, on top of the fees they collect.
+    /// This is part of the genesis configuration.
+    pub block_reward: u64,
+}
+
+impl Ledger {
+    /// The nonce that the given account's next transfer must use.
+    pub fn nonce(&self, account: &AccountId) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+
+    /// The given account's balance, which is zero for accounts that do not exist.
+    pub fn balance(&self, account: &AccountId) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    /// Set the given account's balance, removing the account when the balance is zero.
+    fn set_balance(&mut self, account: AccountId, balance: u64) {
+        if balance == 0 {
+            self.balances.remove(&account);
+        } else {
+            self.balances.insert(account, balance);
+        }
+    }
+
+    /// Prove the given account's balance to someone who only knows the state root.
+    pub fn prove_balance(&self, account: &AccountId) -> BalanceProof {
+        BalanceProof {
+            balances: self.balances.prove(account),
+            balances_root: self.balances.root(),
+            nonces_root: self.nonces.root(),
+            block_reward: self.block_reward,
+        }
+    }
+}
+
+/// The state root of a ledger commits to the roots of its two tries and to the block reward.
+fn ledger_root(balances_root: &Hash, nonces_root: &Hash, block_reward: u64) -> Hash {
+    hash(&(balances_root, nonces_root, block_reward))
+}
+
+/// A proof of one account's balance against a ledger's state root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceProof {
+    balances: TrieProof,
+    /// The rest of the ledger, which is needed to get from the balances root to the state root.
+    balances_root: Hash,
+    nonces_root: Hash,
+    block_reward: u64,
+}
+
+impl BalanceProof {
+    /// Check that the account has the given balance in the ledger with the given state root.
+    pub fn verify(&self, state_root: &Hash, account: &AccountId, balance: u64) -> bool {
+        let balance = (balance > 0).then_some(&balance);
+        ledger_root(&self.balances_root, &self.nonces_root, self.block_reward) == *state_root
+            && self.balances.verify(&self.balances_root, account, balance)
+    }
+}
+
+impl Encode for BalanceProof {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.balances.encode_to(dest);
+        self.balances_root.encode_to(dest);
+        self.nonces_root.encode_to(dest);
+        self.block_reward.encode_to(dest);
+    }
+}
+
+impl Decode for BalanceProof {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(BalanceProof {
+            balances: TrieProof::decode(input)?,
+            balances_root: Hash::decode(input)?,
+            nonces_root: Hash::decode(input)?,
+            block_reward: u64::decode(input)?,
+        })
+    }
+}
+
+/// A ledger with the given balances, in which no account has sent anything yet.
+impl From<Balances> for Ledger {
+    fn from(balances: Balances) -> Self {
+        Ledger {
+            balances,
+            ..Ledger::default()
+        }
+    }
+}
+
+impl Encode for Ledger {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.balances.encode_to(dest);
+        self.nonces.encode_to(dest);
+        self.block_reward.encode_to(dest);
+    }
+}
+
+impl Decode for Ledger {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Ledger {
+            balances: Balances::decode(input)?,
+            nonces: Nonces::decode(input)?,
+            block_reward: u64::decode(input)?,
+        })
+    }
+}
 
 /// The state transitions that users can make in an accounted currency system
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AccountingTransaction {
     /// Create some new money for the given minter in the given amount
-    Mint { minter: User, amount: u64 },
+    Mint { minter: AccountId, amount: u64 },
     /// Destroy some money from the given account in the given amount
     /// If the burn amount exceeds the account balance, burn the entire
     /// amount and remove the account from storage
-    Burn { burner: User, amount: u64 },
-    /// Send some tokens from one account to another
+    Burn { burner: AccountId, amount: u64 },
+    /// Send some tokens from one account to another. The sender must sign the transfer,
+    /// otherwise anyone could spend anyone else's money. The nonce must be the sender's
+    /// next nonce, otherwise anyone could submit the same signed transfer again.
+    ///
+    /// The sender pays the fee on top of the amount, and the block author collects it.
     Transfer {
-        sender: User,
-        receiver: User,
+        sender: AccountId,
+        receiver: AccountId,
         amount: u64,
+        fee: u64,
+        nonce: u64,
+        signature: Signature,
     },
 }
 
+impl AccountingTransaction {
+    /// Build a transfer from the account of the given keypair, signed by it. It pays no fee.
+    pub fn transfer(sender: &Keypair, receiver: AccountId, amount: u64, nonce: u64) -> Self {
+        Self::transfer_with_fee(sender, receiver, amount, 0, nonce)
+    }
+
+    /// Build a transfer that pays the given fee to the block author.
+    pub fn transfer_with_fee(
+        sender: &Keypair,
+        receiver: AccountId,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+    ) -> Self {
+        let sender_id = AccountId::from(sender);
+        let payload = transfer_payload(sender_id, receiver, amount, fee, nonce);
+        AccountingTransaction::Transfer {
+            sender: sender_id,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            signature: sender.sign(&payload),
+        }
+    }
+}
+
+/// The message that a sender signs to authorize a transfer.
+fn transfer_payload(
+    sender: AccountId,
+    receiver: AccountId,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Hash {
+    hash(&(sender, receiver, (amount, fee), nonce))
+}
+
+impl Nonced for AccountingTransaction {
+    fn sender_nonce(&self) -> Option<(AccountId, u64)> {
+        match self {
+            AccountingTransaction::Transfer { sender, nonce, .. } => Some((*sender, *nonce)),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for AccountingTransaction {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            AccountingTransaction::Mint { minter, amount } => {
+                0u8.encode_to(dest);
+                minter.encode_to(dest);
+                amount.encode_to(dest);
+            }
+            AccountingTransaction::Burn { burner, amount } => {
+                1u8.encode_to(dest);
+                burner.encode_to(dest);
+                amount.encode_to(dest);
+            }
+            AccountingTransaction::Transfer {
+                sender,
+                receiver,
+                amount,
+                fee,
+                nonce,
+                signature,
+            } => {
+                2u8.encode_to(dest);
+                sender.encode_to(dest);
+                receiver.encode_to(dest);
+                amount.encode_to(dest);
+                fee.encode_to(dest);
+                nonce.encode_to(dest);
+                signature.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl Decode for AccountingTransaction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(AccountingTransaction::Mint {
+                minter: AccountId::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            1 => Ok(AccountingTransaction::Burn {
+                burner: AccountId::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            2 => Ok(AccountingTransaction::Transfer {
+                sender: AccountId::decode(input)?,
+                receiver: AccountId::decode(input)?,
+                amount: u64::decode(input)?,
+                fee: u64::decode(input)?,
+                nonce: u64::decode(input)?,
+                signature: Signature::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// Transactions are written as `mint <account> <amount>`, `burn <account> <amount>`, or
+/// `transfer <sender> <receiver> <amount> <nonce>`, optionally followed by `fee <amount>`.
+/// Transfers are signed with the sender's
+/// development key, so the sender must be one of the [`Keyring`](crate::Keyring) accounts.
+impl FromStr for AccountingTransaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let transaction = match words.next() {
+            Some("mint") => AccountingTransaction::Mint {
+                minter: parse_account(words.next())?,
+                amount: parse_amount(words.next())?,
+            },
+            Some("burn") => AccountingTransaction::Burn {
+                burner: parse_account(words.next())?,
+                amount: parse_amount(words.next())?,
+            },
+            Some("transfer") => {
+                let sender = parse_signer(words.next())?;
+                let receiver = parse_account(words.next())?;
+                let amount = parse_amount(words.next())?;
+                let nonce = parse_amount(words.next())?;
+                let fee = parse_fee(&mut words)?;
+                AccountingTransaction::transfer_with_fee(sender.pair(), receiver, amount, fee, nonce)
+            }
+            _ => return Err("expected mint, burn, or transfer".into()),
+        };
+        match words.next() {
+            None => Ok(transaction),
+            Some(extra) => Err(format!("unexpected `{extra}` after the transaction")),
+        }
+    }
+}
+
+/// The reasons an accounting transaction may be refused
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccountingError {
+    /// The sender does not have an account, which is to say their balance is zero
+    UnknownSender,
+    /// The sender's balance does not cover the amount being transferred plus the fee
+    InsufficientBalance,
+    /// The transfer was not signed by the sender
+    BadSignature,
+    /// The transfer's nonce has already been used, so it is a replay or has been superseded
+    StaleNonce,
+    /// The transfer's nonce is ahead of the sender's next nonce. Some earlier transfer is missing
+    FutureNonce,
+    /// The credited balance would be larger than the largest amount that can be represented
+    BalanceOverflow,
+}
+
 /// We model this system as a state machine with three possible transitions
 impl StateMachine for AccountedCurrency {
-    type State = Balances;
+    type State = Ledger;
     type Transition = AccountingTransaction;
+    type Error = AccountingError;
 
-    fn next_state(starting_state: &Balances, t: &AccountingTransaction) -> Balances {
+    fn next_state(
+        starting_state: &Ledger,
+        t: &AccountingTransaction,
+    ) -> Result<Ledger, AccountingError> {
+        let mut ledger = starting_state.clone();
         match t {
             AccountingTransaction::Mint { minter, amount } => {
-                let mut new_state = starting_state.clone();
-                if *amount > 0 {
-                    new_state.entry(*minter).and_modify(|balance| *balance += amount).or_insert(*amount);
-                }
-                new_state
+                let balance = ledger
+                    .balance(minter)
+                    .checked_add(*amount)
+                    .ok_or(AccountingError::BalanceOverflow)?;
+                ledger.set_balance(*minter, balance);
             }
             AccountingTransaction::Burn { burner, amount } => {
-                let mut new_state = starting_state.clone();
-                if let Some(balance) = new_state.get_mut(burner) {
-                    if *balance <= *amount {
-                        new_state.remove(burner);
-                    } else {
-                        *balance -= amount;
-                    }
-                }
-                new_state
+                // Burning more than the balance burns all of it. Burnt value is gone either
+                // way, so there is nothing for this to lose.
+                ledger.set_balance(*burner, ledger.balance(burner).saturating_sub(*amount));
             }
             AccountingTransaction::Transfer {
                 sender,
                 receiver,
                 amount,
+                fee,
+                nonce,
+                signature,
             } => {
-                let mut new_state = starting_state.clone();
-                if let Some(sender_balance) = new_state.get_mut(sender) {
-                    if *sender_balance >= *amount {
-                        *sender_balance -= amount;
-                        new_state.entry(*receiver).and_modify(|receiver_balance| *receiver_balance += amount ).or_insert(*amount);
-                    }
+                let payload = transfer_payload(*sender, *receiver, *amount, *fee, *nonce);
+                if !sender.verify(&payload, signature) {
+                    return Err(AccountingError::BadSignature);
+                }
+                let expected_nonce = starting_state.nonce(sender);
+                if *nonce < expected_nonce {
+                    return Err(AccountingError::StaleNonce);
                 }
-                if let Some(sender_balance) = new_state.get(sender) {
-                    if *sender_balance == 0 {
-                        new_state.remove(sender);
-                    }
+                if *nonce > expected_nonce {
+                    return Err(AccountingError::FutureNonce);
                 }
-                new_state
+                ledger.nonces.insert(*sender, expected_nonce + 1);
+                let sender_balance = *ledger
+                    .balances
+                    .get(sender)
+                    .ok_or(AccountingError::UnknownSender)?;
+                let debit = amount
+                    .checked_add(*fee)
+                    .filter(|debit| *debit <= sender_balance)
+                    .ok_or(AccountingError::InsufficientBalance)?;
+                ledger.set_balance(*sender, sender_balance - debit);
+                let receiver_balance = ledger
+                    .balance(receiver)
+                    .checked_add(*amount)
+                    .ok_or(AccountingError::BalanceOverflow)?;
+                ledger.set_balance(*receiver, receiver_balance);
+            }
+        }
+        Ok(ledger)
+    }
+
+    fn human_name() -> String {
+        "Accounted Currency".into()
+    }
+
+    /// A transfer whose nonce is ahead of the sender's is checked as though the transfers
+    /// before it had been applied already, except that they have not spent anything yet.
+    fn pre_validate(state: &Ledger, t: &AccountingTransaction) -> Result<(), AccountingError> {
+        match t {
+            AccountingTransaction::Transfer { sender, nonce, .. } if *nonce > state.nonce(sender) => {
+                let mut ahead = state.clone();
+                ahead.nonces.insert(*sender, *nonce);
+                Self::next_state(&ahead, t).map(drop)
             }
+            _ => Self::next_state(state, t).map(drop),
+        }
+    }
+
+    fn fee(t: &AccountingTransaction) -> u64 {
+        match t {
+            AccountingTransaction::Transfer { fee, .. } => *fee,
+            _ => 0,
         }
     }
+
+    fn sender(t: &AccountingTransaction) -> Option<AccountId> {
+        t.sender_nonce().map(|(sender, _)| sender)
+    }
+
+    fn nonce(ledger: &Ledger, account: &AccountId) -> u64 {
+        ledger.nonce(account)
+    }
+
+    fn finalize_block(mut ledger: Ledger, author: &AccountId, fees: u64) -> Result<Ledger, AccountingError> {
+        let payout = fees
+            .checked_add(ledger.block_reward)
+            .ok_or(AccountingError::BalanceOverflow)?;
+        if payout > 0 {
+            let balance = ledger
+                .balance(author)
+                .checked_add(payout)
+                .ok_or(AccountingError::BalanceOverflow)?;
+            ledger.set_balance(*author, balance);
+        }
+        Ok(ledger)
+    }
+
+    fn state_root(ledger: &Ledger) -> Hash {
+        ledger_root(&ledger.balances.root(), &ledger.nonces.root(), ledger.block_reward)
+    }
 }
 
 #[test]
 fn sm_4_mint_creates_account() {
-    let start = HashMap::new();
+    let start = Ledger::default();
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
-            minter: User::Alice,
+            minter: Keyring::Alice.to_account_id(),
             amount: 100,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_mint_creates_second_account() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
-            minter: User::Bob,
+            minter: Keyring::Bob.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_mint_increases_balance() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
-            minter: User::Alice,
+            minter: Keyring::Alice.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 150)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 150)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_empty_mint() {
-    let start = HashMap::new();
+    let start = Ledger::default();
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
-            minter: User::Alice,
+            minter: Keyring::Alice.to_account_id(),
             amount: 0,
         },
     );
-    let expected = HashMap::new();
+    let expected = Balances::new();
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_simple_burn() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Alice,
+            burner: Keyring::Alice.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 50)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 50)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_burn_no_existential_deposit_left() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Bob,
+            burner: Keyring::Bob.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_non_registered_burner() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Bob,
+            burner: Keyring::Bob.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_burn_more_than_balance() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end2 = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Bob,
+            burner: Keyring::Bob.to_account_id(),
             amount: 100,
         },
     );
-    let expected2 = HashMap::from([(User::Alice, 100)]);
+    let expected2 = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end2, expected2);
+    assert_eq!(end2.map(|ledger| ledger.balances), Ok(expected2));
 }
 
 #[test]
 fn sm_4_empty_burn() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Alice,
+            burner: Keyring::Alice.to_account_id(),
             amount: 0,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_burner_does_not_exist() {
-    let start = HashMap::from([(User::Alice, 100)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
-            burner: User::Bob,
+            burner: Keyring::Bob.to_account_id(),
             amount: 50,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_simple_transfer() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Alice,
-            receiver: User::Bob,
-            amount: 10,
-        },
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0),
     );
-    let expected = HashMap::from([(User::Alice, 90), (User::Bob, 60)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 90), (Keyring::Bob.to_account_id(), 60)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 
-    let start = HashMap::from([(User::Alice, 90), (User::Bob, 60)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 90), (Keyring::Bob.to_account_id(), 60)]));
     let end1 = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Bob,
-            receiver: User::Alice,
-            amount: 50,
-        },
+        &AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Alice.to_account_id(), 50, 0),
     );
-    let expected1 = HashMap::from([(User::Alice, 140), (User::Bob, 10)]);
+    let expected1 = Balances::from([(Keyring::Alice.to_account_id(), 140), (Keyring::Bob.to_account_id(), 10)]);
 
-    assert_eq!(end1, expected1);
+    assert_eq!(end1.map(|ledger| ledger.balances), Ok(expected1));
 }
 
 #[test]
 fn sm_4_send_to_same_user() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Bob,
-            receiver: User::Bob,
-            amount: 10,
-        },
+        &AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Bob.to_account_id(), 10, 0),
     );
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_insufficient_balance_transfer() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Bob,
-            receiver: User::Alice,
-            amount: 60,
-        },
+        &AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Alice.to_account_id(), 60, 0),
     );
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
-
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(AccountingError::InsufficientBalance));
 }
 
 #[test]
 fn sm_4_sender_not_registered() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Charlie,
-            receiver: User::Alice,
-            amount: 50,
-        },
+        &AccountingTransaction::transfer(Keyring::Charlie.pair(), Keyring::Alice.to_account_id(), 50, 0),
     );
-    let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
-
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(AccountingError::UnknownSender));
 }
 
 #[test]
 fn sm_4_receiver_not_registered() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Alice,
-            receiver: User::Charlie,
-            amount: 50,
-        },
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Charlie.to_account_id(), 50, 0),
     );
-    let expected = HashMap::from([(User::Alice, 50), (User::Bob, 50), (User::Charlie, 50)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 50), (Keyring::Bob.to_account_id(), 50), (Keyring::Charlie.to_account_id(), 50)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_sender_to_empty_balance() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Bob,
-            receiver: User::Alice,
-            amount: 50,
-        },
+        &AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Alice.to_account_id(), 50, 0),
     );
-    let expected = HashMap::from([(User::Alice, 150)]);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 150)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
 }
 
 #[test]
 fn sm_4_transfer() {
-    let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 50)]));
     let end = AccountedCurrency::next_state(
         &start,
-        &AccountingTransaction::Transfer {
-            sender: User::Bob,
-            receiver: User::Charlie,
-            amount: 50,
+        &AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Charlie.to_account_id(), 50, 0),
+    );
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Charlie.to_account_id(), 50)]);
+
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
+}
+
+#[test]
+fn sm_4_transfer_not_signed_by_sender_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let forged = AccountingTransaction::Transfer {
+        sender: Keyring::Alice.to_account_id(),
+        receiver: Keyring::Bob.to_account_id(),
+        amount: 100,
+        fee: 0,
+        nonce: 0,
+        signature: Keyring::Bob.pair()
+            .sign(&transfer_payload(Keyring::Alice.to_account_id(), Keyring::Bob.to_account_id(), 100, 0, 0)),
+    };
+
+    assert_eq!(
+        AccountedCurrency::next_state(&start, &forged),
+        Err(AccountingError::BadSignature)
+    );
+}
+
+#[test]
+fn sm_4_transfer_signature_covers_amount() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let AccountingTransaction::Transfer { signature, .. } =
+        AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 1, 0)
+    else {
+        unreachable!()
+    };
+    let tampered = AccountingTransaction::Transfer {
+        sender: Keyring::Alice.to_account_id(),
+        receiver: Keyring::Bob.to_account_id(),
+        amount: 100,
+        fee: 0,
+        nonce: 0,
+        signature,
+    };
+
+    assert_eq!(
+        AccountedCurrency::next_state(&start, &tampered),
+        Err(AccountingError::BadSignature)
+    );
+}
+
+#[test]
+fn sm_4_parse_transactions() {
+    assert_eq!(
+        "mint alice 100".parse(),
+        Ok(AccountingTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 100,
+        })
+    );
+    assert_eq!(
+        "transfer Bob charlie 5 3".parse(),
+        Ok(AccountingTransaction::transfer(Keyring::Bob.pair(), Keyring::Charlie.to_account_id(), 5, 3))
+    );
+    assert_eq!(
+        "transfer bob charlie 5 3 fee 2".parse(),
+        Ok(AccountingTransaction::transfer_with_fee(Keyring::Bob.pair(), Keyring::Charlie.to_account_id(), 5, 2, 3))
+    );
+    assert!("transfer bob charlie 5".parse::<AccountingTransaction>().is_err());
+    assert!("transfer bob charlie 5 3 fee".parse::<AccountingTransaction>().is_err());
+    assert!("transfer bob charlie 5 3 tip 2".parse::<AccountingTransaction>().is_err());
+    assert!("burn alice".parse::<AccountingTransaction>().is_err());
+    assert!("burn mallory 5".parse::<AccountingTransaction>().is_err());
+    assert!("mint alice 5 6".parse::<AccountingTransaction>().is_err());
+}
+
+#[test]
+fn sm_4_transactions_round_trip_through_codec() {
+    let transactions = [
+        AccountingTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 100,
+        },
+        AccountingTransaction::Burn {
+            burner: Keyring::Bob.to_account_id(),
+            amount: 7,
+        },
+        AccountingTransaction::transfer(Keyring::Charlie.pair(), Keyring::Alice.to_account_id(), u64::MAX, 0),
+        AccountingTransaction::transfer_with_fee(Keyring::Dave.pair(), Keyring::Eve.to_account_id(), 3, 1, 9),
+    ];
+    for t in transactions {
+        assert_eq!(AccountingTransaction::decode_all(&t.encode()), Ok(t));
+    }
+
+    let ledger = Ledger {
+        balances: Balances::from([(Keyring::Alice.to_account_id(), 5), (Keyring::Bob.to_account_id(), 10)]),
+        nonces: Nonces::from([(Keyring::Charlie.to_account_id(), 3)]),
+        block_reward: 50,
+    };
+    assert_eq!(Ledger::decode_all(&ledger.encode()), Ok(ledger));
+}
+
+#[test]
+fn sm_4_any_keypair_can_hold_an_account() {
+    let dave = Keypair::from_seed("dave's own secret");
+    let erin = Keypair::from_seed("erin's own secret");
+    let start = Ledger::from(Balances::from([(AccountId::from(&dave), 10)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(&dave, AccountId::from(&erin), 10, 0),
+    );
+    let expected = Balances::from([(AccountId::from(&erin), 10)]);
+
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
+}
+
+#[test]
+fn sm_4_transfer_increments_nonce() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0),
+    )
+    .unwrap();
+
+    assert_eq!(end.nonce(&Keyring::Alice.to_account_id()), 1);
+    assert_eq!(end.nonce(&Keyring::Bob.to_account_id()), 0);
+}
+
+#[test]
+fn sm_4_replayed_transfer_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    let once = AccountedCurrency::next_state(&start, &transfer).unwrap();
+
+    assert_eq!(
+        AccountedCurrency::next_state(&once, &transfer),
+        Err(AccountingError::StaleNonce)
+    );
+}
+
+#[test]
+fn sm_4_out_of_order_transfer_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 1),
+    );
+
+    assert_eq!(end, Err(AccountingError::FutureNonce));
+}
+
+#[test]
+fn sm_4_nonce_survives_emptied_account() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 10)]));
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    let emptied = AccountedCurrency::next_state(&start, &transfer).unwrap();
+    let refilled = AccountedCurrency::next_state(
+        &emptied,
+        &AccountingTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 10,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        AccountedCurrency::next_state(&refilled, &transfer),
+        Err(AccountingError::StaleNonce)
+    );
+}
+
+#[test]
+fn sm_4_only_transfers_are_nonced() {
+    let alice = Keyring::Alice.to_account_id();
+    let mint = AccountingTransaction::Mint {
+        minter: alice,
+        amount: 5,
+    };
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), alice, 5, 7);
+
+    assert_eq!(mint.sender_nonce(), None);
+    assert_eq!(transfer.sender_nonce(), Some((alice, 7)));
+}
+
+#[test]
+fn sm_4_transfer_pays_fee() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let transfer =
+        AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 50, 10, 0);
+    let end = AccountedCurrency::next_state(&start, &transfer);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 40), (Keyring::Bob.to_account_id(), 50)]);
+
+    assert_eq!(AccountedCurrency::fee(&transfer), 10);
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
+}
+
+#[test]
+fn sm_4_transfer_cannot_afford_fee_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 100, 1, 0),
+    );
+
+    assert_eq!(end, Err(AccountingError::InsufficientBalance));
+}
+
+#[test]
+fn sm_4_fee_overflow_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), u64::MAX)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), u64::MAX, 1, 0),
+    );
+
+    assert_eq!(end, Err(AccountingError::InsufficientBalance));
+}
+
+#[test]
+fn sm_4_mint_overflow_fails() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), u64::MAX)]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 1,
         },
     );
-    let expected = HashMap::from([(User::Alice, 100), (User::Charlie, 50)]);
 
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(AccountingError::BalanceOverflow));
+}
+
+#[test]
+fn sm_4_transfer_receiver_overflow_fails() {
+    let start = Ledger::from(Balances::from([
+        (Keyring::Alice.to_account_id(), 100),
+        (Keyring::Bob.to_account_id(), u64::MAX),
+    ]));
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 1, 0),
+    );
+
+    assert_eq!(end, Err(AccountingError::BalanceOverflow));
+}
+
+#[test]
+fn sm_4_transfer_signature_covers_fee() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let AccountingTransaction::Transfer { signature, .. } =
+        AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 1, 0)
+    else {
+        unreachable!()
+    };
+    let tampered = AccountingTransaction::Transfer {
+        sender: Keyring::Alice.to_account_id(),
+        receiver: Keyring::Bob.to_account_id(),
+        amount: 10,
+        fee: 50,
+        nonce: 0,
+        signature,
+    };
+
+    assert_eq!(
+        AccountedCurrency::next_state(&start, &tampered),
+        Err(AccountingError::BadSignature)
+    );
+}
+
+#[test]
+fn sm_4_finalize_block_pays_author() {
+    let start = Ledger {
+        balances: Balances::from([(Keyring::Alice.to_account_id(), 100)]),
+        block_reward: 5,
+        ..Ledger::default()
+    };
+    let end = AccountedCurrency::finalize_block(start, &Keyring::Bob.to_account_id(), 3);
+    let expected = Balances::from([(Keyring::Alice.to_account_id(), 100), (Keyring::Bob.to_account_id(), 8)]);
+
+    assert_eq!(end.map(|ledger| ledger.balances), Ok(expected));
+}
+
+#[test]
+fn sm_4_finalize_empty_block_without_reward_is_noop() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let end = AccountedCurrency::finalize_block(start.clone(), &Keyring::Bob.to_account_id(), 0);
+
+    assert_eq!(end, Ok(start));
+}
+
+#[test]
+fn sm_4_finalize_block_payout_overflow_fails() {
+    let start = Ledger {
+        balances: Balances::from([(Keyring::Bob.to_account_id(), u64::MAX - 5)]),
+        block_reward: 5,
+        ..Ledger::default()
+    };
+    let author = Keyring::Bob.to_account_id();
+
+    assert_eq!(
+        AccountedCurrency::finalize_block(start.clone(), &author, u64::MAX),
+        Err(AccountingError::BalanceOverflow)
+    );
+    assert_eq!(
+        AccountedCurrency::finalize_block(start.clone(), &author, 1),
+        Err(AccountingError::BalanceOverflow)
+    );
+    assert!(AccountedCurrency::finalize_block(start, &author, 0).is_ok());
+}
+
+#[test]
+fn sm_4_balance_proofs_check_against_state_root() {
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let ledger = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 30, 0),
+    )
+    .unwrap();
+    let root = AccountedCurrency::state_root(&ledger);
+    let (alice, bob, charlie) = (
+        Keyring::Alice.to_account_id(),
+        Keyring::Bob.to_account_id(),
+        Keyring::Charlie.to_account_id(),
+    );
+
+    assert_ne!(root, AccountedCurrency::state_root(&start));
+    assert!(ledger.prove_balance(&alice).verify(&root, &alice, 70));
+    assert!(ledger.prove_balance(&bob).verify(&root, &bob, 30));
+    assert!(!ledger.prove_balance(&bob).verify(&root, &bob, 31));
+    assert!(ledger.prove_balance(&charlie).verify(&root, &charlie, 0));
+    assert!(!ledger.prove_balance(&alice).verify(&AccountedCurrency::state_root(&start), &alice, 70));
+
+    let proof = ledger.prove_balance(&bob);
+    assert_eq!(BalanceProof::decode_all(&proof.encode()), Ok(proof));
+}
+
+#[test]
+fn sm_4_state_root_ignores_history() {
+    // Two routes to the same balances and nonces give the same root.
+    let start = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let direct = Ledger {
+        balances: Balances::from([(Keyring::Alice.to_account_id(), 60), (Keyring::Bob.to_account_id(), 40)]),
+        nonces: Nonces::from([(Keyring::Alice.to_account_id(), 1)]),
+        ..Ledger::default()
+    };
+    let end = AccountedCurrency::next_state(
+        &start,
+        &AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 40, 0),
+    )
+    .unwrap();
+
+    assert_eq!(AccountedCurrency::state_root(&end), AccountedCurrency::state_root(&direct));
+}
+
+#[test]
+fn sm_4_transfer_ahead_of_its_turn_is_pre_validated() {
+    let start = Ledger {
+        balances: Balances::from([(Keyring::Alice.to_account_id(), 100)]),
+        nonces: Nonces::from([(Keyring::Alice.to_account_id(), 1)]),
+        ..Ledger::default()
+    };
+    let transfer = |amount, nonce| {
+        AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), amount, nonce)
+    };
+    let mut forged = transfer(10, 3);
+    if let AccountingTransaction::Transfer { amount, .. } = &mut forged {
+        *amount = 90;
+    }
+
+    // A transfer that has to wait for nonces 1 and 2 cannot be applied yet, but may be later.
+    assert_eq!(AccountedCurrency::next_state(&start, &transfer(10, 3)), Err(AccountingError::FutureNonce));
+    assert_eq!(AccountedCurrency::pre_validate(&start, &transfer(10, 3)), Ok(()));
+    assert_eq!(AccountedCurrency::pre_validate(&start, &transfer(10, 1)), Ok(()));
+    // Everything else is still checked.
+    assert_eq!(AccountedCurrency::pre_validate(&start, &transfer(10, 0)), Err(AccountingError::StaleNonce));
+    assert_eq!(AccountedCurrency::pre_validate(&start, &forged), Err(AccountingError::BadSignature));
+    assert_eq!(
+        AccountedCurrency::pre_validate(&start, &transfer(200, 3)),
+        Err(AccountingError::InsufficientBalance)
+    );
 }
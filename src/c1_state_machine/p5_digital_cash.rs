@@ -3,11 +3,19 @@
 //! cash bills. Each bill has an amount and an owner, and can be spent in its entirety.
 //! When a state transition spends bills, new bills are created in lesser or equal amount.
 
-use super::{StateMachine, User};
-use std::collections::HashSet;
+use super::{parse_account, parse_amount, parse_fee, StateMachine};
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature},
+    hash, AccountId, Hash, Keyring,
+};
+use std::{collections::HashSet, fmt, str::FromStr};
 
 /// This state machine models a multi-user currency system. It tracks a set of bills in
 /// circulation, and updates that set when money is transferred.
+///
+/// At the end of each block, the author is paid with a new bill worth the block's fees plus
+/// the block reward.
 pub struct DigitalCashSystem;
 
 /// A single bill in the digital cash system. Each bill has an owner who is allowed to spent
@@ -15,11 +23,53 @@ pub struct DigitalCashSystem;
 /// is unique.
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub struct Bill {
-    owner: User,
+    owner: AccountId,
     amount: u64,
     serial: u64,
 }
 
+impl Encode for Bill {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.owner.encode_to(dest);
+        self.amount.encode_to(dest);
+        self.serial.encode_to(dest);
+    }
+}
+
+impl Decode for Bill {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Bill {
+            owner: AccountId::decode(input)?,
+            amount: u64::decode(input)?,
+            serial: u64::decode(input)?,
+        })
+    }
+}
+
+/// Bills are written as `<owner>:<amount>:<serial>`, for example `alice:20:0`.
+impl FromStr for Bill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let bill = Bill {
+            owner: parse_account(parts.next())?,
+            amount: parse_amount(parts.next())?,
+            serial: parse_amount(parts.next())?,
+        };
+        match parts.next() {
+            None => Ok(bill),
+            Some(_) => Err(format!("`{s}` is not a bill, expected <owner>:<amount>:<serial>")),
+        }
+    }
+}
+
+impl fmt::Display for Bill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.owner, self.amount, self.serial)
+    }
+}
+
 /// The State of a digital cash system. Primarily just the set of currently circulating bills.,
 /// but also a counter for the next serial number.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -29,6 +79,8 @@ pub struct State {
     bills: HashSet<Bill>,
     /// The next serial number to use when a bill is created.
     next_serial: u64,
+    /// The amount minted for the author of each block, on top of the fees they collect.
+    block_reward: u64,
 }
 
 impl State {
@@ -36,9 +88,18 @@ impl State {
         State {
             bills: HashSet::<Bill>::new(),
             next_serial: 0,
+            block_reward: 0,
         }
     }
 
+    pub fn set_block_reward(&mut self, reward: u64) {
+        self.block_reward = reward;
+    }
+
+    pub fn block_reward(&self) -> u64 {
+        self.block_reward
+    }
+
     pub fn set_serial(&mut self, serial: u64) {
         self.next_serial = serial;
     }
@@ -57,6 +118,45 @@ impl State {
     }
 }
 
+impl Encode for State {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.bills.encode_to(dest);
+        self.next_serial.encode_to(dest);
+        self.block_reward.encode_to(dest);
+    }
+}
+
+impl Decode for State {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(State {
+            bills: HashSet::decode(input)?,
+            next_serial: u64::decode(input)?,
+            block_reward: u64::decode(input)?,
+        })
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bills: Vec<_> = self.bills.iter().collect();
+        bills.sort_by_key(|bill| bill.serial);
+        write!(f, "next serial {}, bills: [", self.next_serial)?;
+        for (i, bill) in bills.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{bill}")?;
+        }
+        write!(f, "]")
+    }
+}
+
 impl FromIterator<Bill> for State {
     fn from_iter<I: IntoIterator<Item = Bill>>(iter: I) -> Self {
         let mut state = State::new();
@@ -75,90 +175,278 @@ impl<const N: usize> From<[Bill; N]> for State {
 }
 
 /// The state transitions that users can make in a digital cash system
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CashTransaction {
     /// Mint a single new bill owned by the minter
-    Mint { minter: User, amount: u64 },
+    Mint { minter: AccountId, amount: u64 },
     /// Send some money from some users to other users. The money does not all need
     /// to come from the same user, and it does not all need to go to the same user.
     /// The total amount received must be less than or equal to the amount spent.
     /// The discrepancy between the amount sent and received is destroyed. Therefore,
     /// no dedicated burn transaction is required.
+    ///
+    /// The transfer may also set aside a fee for the block author, which must be covered by
+    /// the spent bills too. Only the remainder after the fee is destroyed.
+    ///
+    /// Each spent bill must be authorized by its owner, so there is one signature per spent
+    /// bill, in the same order as the spends.
     Transfer {
         spends: Vec<Bill>,
         receives: Vec<Bill>,
+        fee: u64,
+        signatures: Vec<Signature>,
     },
 }
 
+impl CashTransaction {
+    /// Build a transfer in which each spent bill is signed by its owner's keypair, taken
+    /// from the given keys. A bill whose owner has no key among them gets an empty signature,
+    /// which will not verify. The transfer pays no fee.
+    pub fn transfer(spends: Vec<Bill>, receives: Vec<Bill>, keys: &[&Keypair]) -> Self {
+        Self::transfer_with_fee(spends, receives, 0, keys)
+    }
+
+    /// Build a signed transfer, like [`CashTransaction::transfer`], that pays the given fee to
+    /// the block author.
+    pub fn transfer_with_fee(
+        spends: Vec<Bill>,
+        receives: Vec<Bill>,
+        fee: u64,
+        keys: &[&Keypair],
+    ) -> Self {
+        let payload = transfer_payload(&spends, &receives, fee);
+        let signatures = spends
+            .iter()
+            .map(|bill| {
+                keys.iter()
+                    .find(|pair| AccountId::from(**pair) == bill.owner)
+                    .map(|pair| pair.sign(&payload))
+                    .unwrap_or_default()
+            })
+            .collect();
+        CashTransaction::Transfer {
+            spends,
+            receives,
+            fee,
+            signatures,
+        }
+    }
+}
+
+/// The message that the owners of the spent bills sign to authorize a transfer.
+fn transfer_payload(spends: &[Bill], receives: &[Bill], fee: u64) -> Hash {
+    hash(&(spends, receives, fee))
+}
+
+impl Encode for CashTransaction {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            CashTransaction::Mint { minter, amount } => {
+                0u8.encode_to(dest);
+                minter.encode_to(dest);
+                amount.encode_to(dest);
+            }
+            CashTransaction::Transfer {
+                spends,
+                receives,
+                fee,
+                signatures,
+            } => {
+                1u8.encode_to(dest);
+                spends.encode_to(dest);
+                receives.encode_to(dest);
+                fee.encode_to(dest);
+                signatures.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl Decode for CashTransaction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(CashTransaction::Mint {
+                minter: AccountId::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            1 => Ok(CashTransaction::Transfer {
+                spends: Vec::decode(input)?,
+                receives: Vec::decode(input)?,
+                fee: u64::decode(input)?,
+                signatures: Vec::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// Transactions are written as `mint <account> <amount>` or as
+/// `transfer <spent bills> -> <received bills>`, with the bills separated by spaces and
+/// optionally followed by `fee <amount>`.
+/// Transfers are signed with the bill owners' development keys, so the owners of spent bills
+/// must be [`Keyring`] accounts.
+impl FromStr for CashTransaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match words.next() {
+            Some("mint") => {
+                let transaction = CashTransaction::Mint {
+                    minter: parse_account(words.next())?,
+                    amount: parse_amount(words.next())?,
+                };
+                match words.next() {
+                    None => Ok(transaction),
+                    Some(extra) => Err(format!("unexpected `{extra}` after the transaction")),
+                }
+            }
+            Some("transfer") => {
+                let words: Vec<_> = words.collect();
+                let arrow = words
+                    .iter()
+                    .position(|word| *word == "->")
+                    .ok_or("expected `->` between the spent and received bills")?;
+                let (words, fee) = match words.iter().position(|word| *word == "fee") {
+                    Some(at) => (&words[..at], parse_fee(words[at..].iter().copied())?),
+                    None => (&words[..], 0),
+                };
+                let parse_bills = |bills: &[&str]| {
+                    bills.iter().map(|bill| bill.parse()).collect::<Result<Vec<Bill>, _>>()
+                };
+                let spends = parse_bills(&words[..arrow])?;
+                let keys = spends
+                    .iter()
+                    .map(|bill| {
+                        Keyring::from_account_id(&bill.owner)
+                            .map(Keyring::pair)
+                            .ok_or_else(|| format!("cannot sign for {}, it is not a development account", bill.owner))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(CashTransaction::transfer_with_fee(
+                    spends,
+                    parse_bills(&words[arrow + 1..])?,
+                    fee,
+                    &keys,
+                ))
+            }
+            _ => Err("expected mint or transfer".into()),
+        }
+    }
+}
+
+/// The reasons a cash transaction may be refused
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CashError {
+    /// The same bill appears more than once among the spends
+    DoubleSpend,
+    /// A serial number is used by more than one bill, or by a bill already in circulation
+    DuplicateSerial,
+    /// Bills must be worth something. Zero-amount bills can be neither spent nor created
+    ZeroAmount,
+    /// A spent bill is not in circulation. It may never have existed or already be spent
+    UnknownBill,
+    /// The receiving bill uses the reserved serial number `u64::MAX`
+    InvalidSerial,
+    /// The bills received, plus the fee, are worth more than the bills spent
+    Overspend,
+    /// A spent bill was not signed for by its owner
+    BadSignature,
+    /// The spent bills are worth more in total than the largest amount that can be represented
+    AmountOverflow,
+}
+
 /// We model this system as a state machine with two possible transitions
 impl StateMachine for DigitalCashSystem {
     type State = State;
     type Transition = CashTransaction;
+    type Error = CashError;
 
-    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Result<Self::State, Self::Error> {
         let mut state = starting_state.clone();
 
         match t {
             CashTransaction::Mint { minter, amount } => {
+                if *amount == 0 {
+                    return Err(CashError::ZeroAmount);
+                }
                 state.add_bill(Bill {
                     owner: *minter,
                     amount: *amount,
                     serial: state.next_serial(),
                 });
             }
-            CashTransaction::Transfer { spends, receives } => {
+            CashTransaction::Transfer {
+                spends,
+                receives,
+                fee,
+                signatures,
+            } => {
+                let payload = transfer_payload(spends, receives, *fee);
+                let authorized = spends.len() == signatures.len()
+                    && spends
+                        .iter()
+                        .zip(signatures)
+                        .all(|(bill, signature)| bill.owner.verify(&payload, signature));
+                if !authorized {
+                    return Err(CashError::BadSignature);
+                }
+
                 let mut new_bills = HashSet::<Bill>::new();
-                let mut total_spent = 0;
-                let mut total_received = 0;
+                let mut total_spent = 0u64;
+                let mut total_received = 0u64;
 
                 let spends_serials: HashSet<_> = spends.iter().map(|bill| bill.serial).collect();
                 if spends_serials.len() != spends.len() {
-                    return state;
+                    return Err(CashError::DoubleSpend);
                 }
                 // check for receives
                 let receives_serials: HashSet<_> = receives.iter().map(|bill| bill.serial).collect();
                 if receives_serials.len() != receives.len() {
-                    return state;
+                    return Err(CashError::DuplicateSerial);
                 }
 
 
                 // check if spends are valid
                 if spends.iter().any(|b| b.amount == 0) {
-                    return state;
+                    return Err(CashError::ZeroAmount);
                 }
 
                 // check if bills are the same
                 for bs in spends.iter() {
                     for br in receives.iter() {
                         if bs.serial == br.serial {
-                            return state;
+                            return Err(CashError::DuplicateSerial);
                         }
                     }
                 }
 
                 for bill in spends {
                     if !state.bills.contains(bill) {
-                        return state;
+                        return Err(CashError::UnknownBill);
                     }
-                    total_spent += bill.amount;
+                    total_spent = total_spent
+                        .checked_add(bill.amount)
+                        .ok_or(CashError::AmountOverflow)?;
                 }
 
                 for bill in receives {
                     if bill.amount == 0 {
-                        return state;
+                        return Err(CashError::ZeroAmount);
                     }
                     if state.bills.contains(bill) {
-                        return state;
+                        return Err(CashError::DuplicateSerial);
                     }
                     if bill.serial == u64::MAX {
-                        return state;
+                        return Err(CashError::InvalidSerial);
                     }
-                    if (total_received + bill.amount) > total_spent {
-                        return state;
-                    }
-                    total_received += bill.amount;
+                    total_received = total_received
+                        .checked_add(bill.amount)
+                        .filter(|total| *total <= total_spent)
+                        .ok_or(CashError::Overspend)?;
                 }
-                if total_spent < total_received {
-                    return state;
+                if total_received.checked_add(*fee).is_none_or(|total| total > total_spent) {
+                    return Err(CashError::Overspend);
                 }
                 for bill in spends {
                     state.bills.remove(bill);
@@ -174,7 +462,32 @@ impl StateMachine for DigitalCashSystem {
             }
         }
 
-        state
+        Ok(state)
+    }
+
+    fn human_name() -> String {
+        "Digital Cash System".into()
+    }
+
+    fn fee(t: &CashTransaction) -> u64 {
+        match t {
+            CashTransaction::Transfer { fee, .. } => *fee,
+            CashTransaction::Mint { .. } => 0,
+        }
+    }
+
+    fn finalize_block(mut state: State, author: &AccountId, fees: u64) -> Result<State, CashError> {
+        let payout = fees
+            .checked_add(state.block_reward)
+            .ok_or(CashError::AmountOverflow)?;
+        if payout > 0 {
+            state.add_bill(Bill {
+                owner: *author,
+                amount: payout,
+                serial: state.next_serial(),
+            });
+        }
+        Ok(state)
     }
 }
 
@@ -184,524 +497,480 @@ fn sm_5_mint_new_cash() {
     let end = DigitalCashSystem::next_state(
         &start,
         &CashTransaction::Mint {
-            minter: User::Alice,
+            minter: Keyring::Alice.to_account_id(),
             amount: 20,
         },
     );
 
     let expected = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Ok(expected));
 }
 
 #[test]
 fn sm_5_overflow_receives_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 42,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 42,
                 serial: 0,
             }],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: u64::MAX,
                     serial: 1,
                 },
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 42,
                     serial: 2,
                 },
             ],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 42,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::Overspend));
 }
 
 #[test]
 fn sm_5_empty_spend_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![],
-            receives: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![],
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 15,
                 serial: 1,
             }],
-        },
+            &[],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::Overspend));
 }
 
 #[test]
 fn sm_5_empty_receive_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-            receives: vec![],
-        },
+            vec![],
+            &[Keyring::Alice.pair()],
+        ),
     );
     let mut expected = State::from([]);
     expected.set_serial(1);
-    assert_eq!(end, expected);
+    assert_eq!(end, Ok(expected));
 }
 
 #[test]
 fn sm_5_output_value_0_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-            receives: vec![Bill {
-                owner: User::Bob,
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
                 amount: 0,
                 serial: 1,
             }],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::ZeroAmount));
 }
 
 #[test]
 fn sm_5_serial_number_already_seen_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-            receives: vec![Bill {
-                owner: User::Alice,
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 18,
                 serial: 0,
             }],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::DuplicateSerial));
 }
 
 #[test]
 fn sm_5_spending_and_receiving_same_bill_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-            receives: vec![Bill {
-                owner: User::Alice,
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::DuplicateSerial));
 }
 
 #[test]
 fn sm_5_receiving_bill_with_incorrect_serial_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 20,
                 serial: 0,
             }],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 10,
                     serial: u64::MAX,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 10,
                     serial: 4000,
                 },
             ],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::InvalidSerial));
 }
 
 #[test]
 fn sm_5_spending_bill_with_incorrect_amount_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 20,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 40,
                 serial: 0,
             }],
-            receives: vec![Bill {
-                owner: User::Bob,
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
                 amount: 40,
                 serial: 1,
             }],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 20,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::UnknownBill));
 }
 
 #[test]
 fn sm_5_spending_same_bill_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 40,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![
+        &CashTransaction::transfer(
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 40,
                     serial: 0,
                 },
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 40,
                     serial: 0,
                 },
             ],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 20,
                     serial: 1,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 20,
                     serial: 2,
                 },
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 40,
                     serial: 3,
                 },
             ],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 40,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::DoubleSpend));
 }
 
 #[test]
 fn sm_5_spending_more_than_bill_fails() {
     let start = State::from([
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 40,
             serial: 0,
         },
         Bill {
-            owner: User::Charlie,
+            owner: Keyring::Charlie.to_account_id(),
             amount: 42,
             serial: 1,
         },
     ]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![
+        &CashTransaction::transfer(
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 40,
                     serial: 0,
                 },
                 Bill {
-                    owner: User::Charlie,
+                    owner: Keyring::Charlie.to_account_id(),
                     amount: 42,
                     serial: 1,
                 },
             ],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 20,
                     serial: 2,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 20,
                     serial: 3,
                 },
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 52,
                     serial: 4,
                 },
             ],
-        },
+            &[Keyring::Alice.pair(), Keyring::Charlie.pair()],
+        ),
     );
-    let expected = State::from([
-        Bill {
-            owner: User::Alice,
-            amount: 40,
-            serial: 0,
-        },
-        Bill {
-            owner: User::Charlie,
-            amount: 42,
-            serial: 1,
-        },
-    ]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::Overspend));
 }
 
 #[test]
 fn sm_5_spending_non_existent_bill_fails() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 32,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
                 amount: 1000,
                 serial: 32,
             }],
-            receives: vec![Bill {
-                owner: User::Bob,
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
                 amount: 1000,
                 serial: 33,
             }],
-        },
+            &[Keyring::Bob.pair()],
+        ),
     );
-    let expected = State::from([Bill {
-        owner: User::Alice,
-        amount: 32,
-        serial: 0,
-    }]);
-    assert_eq!(end, expected);
+    assert_eq!(end, Err(CashError::UnknownBill));
 }
 
 #[test]
 fn sm_5_spending_from_alice_to_all() {
     let start = State::from([Bill {
-        owner: User::Alice,
+        owner: Keyring::Alice.to_account_id(),
         amount: 42,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Alice,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
                 amount: 42,
                 serial: 0,
             }],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 10,
                     serial: 1,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 10,
                     serial: 2,
                 },
                 Bill {
-                    owner: User::Charlie,
+                    owner: Keyring::Charlie.to_account_id(),
                     amount: 10,
                     serial: 3,
                 },
             ],
-        },
+            &[Keyring::Alice.pair()],
+        ),
     );
     let mut expected = State::from([
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 10,
             serial: 1,
         },
         Bill {
-            owner: User::Bob,
+            owner: Keyring::Bob.to_account_id(),
             amount: 10,
             serial: 2,
         },
         Bill {
-            owner: User::Charlie,
+            owner: Keyring::Charlie.to_account_id(),
             amount: 10,
             serial: 3,
         },
     ]);
     expected.set_serial(4);
-    assert_eq!(end, expected);
+    assert_eq!(end, Ok(expected));
 }
 
 #[test]
 fn sm_5_spending_from_bob_to_all() {
     let start = State::from([Bill {
-        owner: User::Bob,
+        owner: Keyring::Bob.to_account_id(),
         amount: 42,
         serial: 0,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Bob,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
                 amount: 42,
                 serial: 0,
             }],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 10,
                     serial: 1,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 10,
                     serial: 2,
                 },
                 Bill {
-                    owner: User::Charlie,
+                    owner: Keyring::Charlie.to_account_id(),
                     amount: 22,
                     serial: 3,
                 },
             ],
-        },
+            &[Keyring::Bob.pair()],
+        ),
     );
     let mut expected = State::from([
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 10,
             serial: 1,
         },
         Bill {
-            owner: User::Bob,
+            owner: Keyring::Bob.to_account_id(),
             amount: 10,
             serial: 2,
         },
         Bill {
-            owner: User::Charlie,
+            owner: Keyring::Charlie.to_account_id(),
             amount: 22,
             serial: 3,
         },
     ]);
     expected.set_serial(4);
-    assert_eq!(end, expected);
+    assert_eq!(end, Ok(expected));
 }
 
 #[test]
 fn sm_5_spending_from_charlie_to_all() {
     let mut start = State::from([
         Bill {
-            owner: User::Charlie,
+            owner: Keyring::Charlie.to_account_id(),
             amount: 68,
             serial: 54,
         },
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 4000,
             serial: 58,
         },
@@ -709,53 +978,312 @@ fn sm_5_spending_from_charlie_to_all() {
     start.set_serial(59);
     let end = DigitalCashSystem::next_state(
         &start,
-        &CashTransaction::Transfer {
-            spends: vec![Bill {
-                owner: User::Charlie,
+        &CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Charlie.to_account_id(),
                 amount: 68,
                 serial: 54,
             }],
-            receives: vec![
+            vec![
                 Bill {
-                    owner: User::Alice,
+                    owner: Keyring::Alice.to_account_id(),
                     amount: 42,
                     serial: 59,
                 },
                 Bill {
-                    owner: User::Bob,
+                    owner: Keyring::Bob.to_account_id(),
                     amount: 5,
                     serial: 60,
                 },
                 Bill {
-                    owner: User::Charlie,
+                    owner: Keyring::Charlie.to_account_id(),
                     amount: 5,
                     serial: 61,
                 },
             ],
-        },
+            &[Keyring::Charlie.pair()],
+        ),
     );
     let mut expected = State::from([
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 4000,
             serial: 58,
         },
         Bill {
-            owner: User::Alice,
+            owner: Keyring::Alice.to_account_id(),
             amount: 42,
             serial: 59,
         },
         Bill {
-            owner: User::Bob,
+            owner: Keyring::Bob.to_account_id(),
             amount: 5,
             serial: 60,
         },
         Bill {
-            owner: User::Charlie,
+            owner: Keyring::Charlie.to_account_id(),
             amount: 5,
             serial: 61,
         },
     ]);
     expected.set_serial(62);
+    assert_eq!(end, Ok(expected));
+}
+
+#[test]
+fn sm_5_mint_zero_amount_fails() {
+    let start = State::new();
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 0,
+        },
+    );
+    assert_eq!(end, Err(CashError::ZeroAmount));
+}
+
+#[test]
+fn sm_5_parse_transactions() {
+    assert_eq!(
+        "mint alice 20".parse(),
+        Ok(CashTransaction::Mint {
+            minter: Keyring::Alice.to_account_id(),
+            amount: 20,
+        })
+    );
+    assert_eq!(
+        "transfer alice:20:0 -> bob:15:1 alice:5:2".parse(),
+        Ok(CashTransaction::transfer(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
+                amount: 20,
+                serial: 0,
+            }],
+            vec![
+                Bill {
+                    owner: Keyring::Bob.to_account_id(),
+                    amount: 15,
+                    serial: 1,
+                },
+                Bill {
+                    owner: Keyring::Alice.to_account_id(),
+                    amount: 5,
+                    serial: 2,
+                },
+            ],
+            &[Keyring::Alice.pair()],
+        ))
+    );
+    assert_eq!(
+        "transfer alice:20:0 -> bob:15:1 fee 3".parse(),
+        Ok(CashTransaction::transfer_with_fee(
+            vec![Bill {
+                owner: Keyring::Alice.to_account_id(),
+                amount: 20,
+                serial: 0,
+            }],
+            vec![Bill {
+                owner: Keyring::Bob.to_account_id(),
+                amount: 15,
+                serial: 1,
+            }],
+            3,
+            &[Keyring::Alice.pair()],
+        ))
+    );
+    assert!("transfer alice:20:0 bob:15:1".parse::<CashTransaction>().is_err());
+    assert!("transfer alice:20:0 -> bob:15:1 fee".parse::<CashTransaction>().is_err());
+    assert!("transfer alice:20 -> bob:15:1".parse::<CashTransaction>().is_err());
+}
+
+#[test]
+fn sm_5_spending_someone_elses_bill_fails() {
+    let alices_bill = Bill {
+        owner: Keyring::Alice.to_account_id(),
+        amount: 20,
+        serial: 0,
+    };
+    let start = State::from([alices_bill.clone()]);
+    let spends = vec![alices_bill];
+    let receives = vec![Bill {
+        owner: Keyring::Bob.to_account_id(),
+        amount: 20,
+        serial: 1,
+    }];
+    let signatures = vec![Keyring::Bob.pair().sign(&transfer_payload(&spends, &receives, 0))];
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: spends.clone(),
+            receives: receives.clone(),
+            fee: 0,
+            signatures,
+        },
+    );
+    assert_eq!(end, Err(CashError::BadSignature));
+
+    // A transfer with the signature missing altogether is no better.
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends,
+            receives,
+            fee: 0,
+            signatures: vec![],
+        },
+    );
+    assert_eq!(end, Err(CashError::BadSignature));
+}
+
+#[test]
+fn sm_5_types_round_trip_through_codec() {
+    let bill = Bill {
+        owner: Keyring::Alice.to_account_id(),
+        amount: 20,
+        serial: 0,
+    };
+    assert_eq!(Bill::decode_all(&bill.encode()), Ok(bill.clone()));
+
+    let transactions = [
+        CashTransaction::Mint {
+            minter: Keyring::Bob.to_account_id(),
+            amount: 5,
+        },
+        CashTransaction::transfer(
+            vec![bill.clone()],
+            vec![
+                Bill {
+                    owner: Keyring::Bob.to_account_id(),
+                    amount: 15,
+                    serial: 1,
+                },
+                Bill {
+                    owner: Keyring::Charlie.to_account_id(),
+                    amount: 5,
+                    serial: 2,
+                },
+            ],
+            &[Keyring::Alice.pair()],
+        ),
+    ];
+    for t in transactions {
+        assert_eq!(CashTransaction::decode_all(&t.encode()), Ok(t));
+    }
+
+    let mut state = State::from([bill]);
+    state.set_block_reward(10);
+    assert_eq!(State::decode_all(&state.encode()), Ok(state));
+}
+
+#[test]
+fn sm_5_transfer_pays_fee() {
+    let bill = Bill {
+        owner: Keyring::Alice.to_account_id(),
+        amount: 20,
+        serial: 0,
+    };
+    let start = State::from([bill.clone()]);
+    let received = Bill {
+        owner: Keyring::Bob.to_account_id(),
+        amount: 15,
+        serial: 1,
+    };
+    let transfer =
+        CashTransaction::transfer_with_fee(vec![bill], vec![received.clone()], 5, &[Keyring::Alice.pair()]);
+    let end = DigitalCashSystem::next_state(&start, &transfer);
+    let mut expected = State::from([received]);
+    expected.set_serial(2);
+
+    assert_eq!(DigitalCashSystem::fee(&transfer), 5);
+    assert_eq!(end, Ok(expected));
+}
+
+#[test]
+fn sm_5_transfer_cannot_afford_fee_fails() {
+    let bill = Bill {
+        owner: Keyring::Alice.to_account_id(),
+        amount: 20,
+        serial: 0,
+    };
+    let start = State::from([bill.clone()]);
+    let receives = vec![Bill {
+        owner: Keyring::Bob.to_account_id(),
+        amount: 15,
+        serial: 1,
+    }];
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::transfer_with_fee(vec![bill], receives, 6, &[Keyring::Alice.pair()]),
+    );
+
+    assert_eq!(end, Err(CashError::Overspend));
+}
+
+#[test]
+fn sm_5_spent_total_overflow_fails() {
+    let spends = vec![
+        Bill {
+            owner: Keyring::Alice.to_account_id(),
+            amount: u64::MAX,
+            serial: 0,
+        },
+        Bill {
+            owner: Keyring::Alice.to_account_id(),
+            amount: 1,
+            serial: 1,
+        },
+    ];
+    let start = State::from_iter(spends.clone());
+    let receives = vec![Bill {
+        owner: Keyring::Bob.to_account_id(),
+        amount: 1,
+        serial: 2,
+    }];
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::transfer(spends, receives, &[Keyring::Alice.pair(), Keyring::Alice.pair()]),
+    );
+
+    assert_eq!(end, Err(CashError::AmountOverflow));
+}
+
+#[test]
+fn sm_5_finalize_block_pays_author() {
+    let mut start = State::from([Bill {
+        owner: Keyring::Alice.to_account_id(),
+        amount: 20,
+        serial: 0,
+    }]);
+    start.set_block_reward(10);
+    let end = DigitalCashSystem::finalize_block(start.clone(), &Keyring::Bob.to_account_id(), 5).unwrap();
+
+    let mut expected = State::from([
+        Bill {
+            owner: Keyring::Alice.to_account_id(),
+            amount: 20,
+            serial: 0,
+        },
+        Bill {
+            owner: Keyring::Bob.to_account_id(),
+            amount: 15,
+            serial: 1,
+        },
+    ]);
+    expected.set_block_reward(10);
     assert_eq!(end, expected);
+
+    // With no fees and no reward there is nothing to pay.
+    start.set_block_reward(0);
+    assert_eq!(
+        DigitalCashSystem::finalize_block(start.clone(), &Keyring::Bob.to_account_id(), 0),
+        Ok(start.clone())
+    );
+
+    // The fees and the reward together must fit in a bill.
+    start.set_block_reward(10);
+    assert_eq!(
+        DigitalCashSystem::finalize_block(start, &Keyring::Bob.to_account_id(), u64::MAX),
+        Err(CashError::AmountOverflow)
+    );
 }
@@ -29,8 +29,9 @@ pub enum Transition {}
 impl StateMachine for State {
     type State = State;
     type Transition = Transition;
+    type Error = ();
 
-    fn next_state(_starting: &Self::State, _t: &Self::Transition) -> Self::State {
+    fn next_state(_starting: &Self::State, _t: &Self::Transition) -> Result<Self::State, Self::Error> {
         todo!()
     }
 }
@@ -3,11 +3,7 @@
 //! start with that.
 //!
 
-use crate::hash;
-
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type Hash = u64;
+use crate::{codec::Encode, hash, Hash};
 
 /// The most basic blockchain header possible. We learned its basic structure from lecture.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -20,13 +16,23 @@ pub struct Header {
     consensus_digest: (),
 }
 
+impl Encode for Header {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.extrinsics_root.encode_to(dest);
+        self.state_root.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
 // Here are the methods for creating a new header and verifying headers.
 // It is your job to write them.
 impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         Header{
-            parent: 0,
+            parent: Hash::default(),
             height: 0,
             extrinsics_root: (),
             state_root: (),
@@ -98,7 +104,7 @@ fn bc_1_genesis_block_height() {
 #[test]
 fn bc_1_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == Hash::default());
 }
 
 #[test]
@@ -148,7 +154,7 @@ fn bc_1_cant_verify_invalid_parent() {
     // not to give away the solution to writing that function.
     let g = Header::genesis();
     let mut b1 = g.child();
-    b1.parent = 10;
+    b1.parent = hash(&10u64);
 
     assert!(!g.verify_sub_chain(&[b1]))
 }
@@ -7,11 +7,7 @@
 //! In the coming parts of this tutorial, we will expand this to be more real-world like and
 //! use some real batching.
 
-use crate::hash;
-
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type Hash = u64;
+use crate::{codec::Encode, hash, Hash};
 
 /// The header is now expanded to contain an extrinsic and a state. Note that we are not
 /// using roots yet, but rather directly embedding some minimal extrinsic and state info
@@ -26,13 +22,23 @@ pub struct Header {
     consensus_digest: (),
 }
 
+impl Encode for Header {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.extrinsic.encode_to(dest);
+        self.state.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
 // Here are the methods for creating new header and verifying headers.
 // It is your job to write them.
 impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         Header{
-            parent: 0,
+            parent: Hash::default(),
             height: 0,
             extrinsic: 0,
             state: 0,
@@ -157,7 +163,7 @@ fn bc_2_genesis_block_height() {
 #[test]
 fn bc_2_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == Hash::default());
 }
 
 #[test]
@@ -223,7 +229,7 @@ fn bc_2_verify_three_blocks() {
 fn bc_2_cant_verify_invalid_parent() {
     let g = Header::genesis();
     let mut b1 = g.child(5);
-    b1.parent = 10;
+    b1.parent = hash(&10u64);
 
     assert!(!g.verify_sub_chain(&[b1]));
 }
@@ -4,16 +4,12 @@
 //! 1. Rules to throttle authoring. In this case we will use a simple PoW.
 //! 2. Arbitrary / Political rules. Here we will implement two alternate validity rules
 
-use crate::hash;
-
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type Hash = u64;
+use crate::{codec::Encode, hash, Hash};
 
 /// In this lesson we are introducing proof of work onto our blocks. We need a hash threshold.
 /// You may change this as you see fit, and I encourage you to experiment. Probably best to start
 /// high so we aren't wasting time mining. I'll start with 1 in 100 blocks being valid.
-const THRESHOLD: u64 = u64::MAX  ;
+const THRESHOLD: Hash = Hash::MAX;
 
 /// In this lesson we introduce the concept of a contentious hard fork. The fork will happen at
 /// this block height.
@@ -32,13 +28,23 @@ pub struct Header {
     consensus_digest: u64,
 }
 
+impl Encode for Header {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.extrinsic.encode_to(dest);
+        self.state.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
 // Here are the methods for creating new header and verifying headers.
 // It is your job to write them.
 impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         Header{
-            parent: 0,
+            parent: Hash::default(),
             height: 0,
             extrinsic: 0,
             state: 0,
@@ -153,7 +159,7 @@ fn bc_3_genesis_block_height() {
 #[test]
 fn bc_3_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == Hash::default());
 }
 
 #[test]
@@ -236,7 +242,7 @@ fn bc_3_verify_three_blocks() {
 fn bc_3_cant_verify_invalid_parent() {
     let g = Header::genesis();
     let mut b1 = g.child(5);
-    b1.parent = 10;
+    b1.parent = hash(&10u64);
 
     assert!(!g.verify_sub_chain(&[b1]));
 }
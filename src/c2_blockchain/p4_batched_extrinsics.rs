@@ -1,8 +1,11 @@
 //! Until now, each block has contained just a single extrinsic. Really we would prefer to batch them.
 //! Now, we stop relying solely on headers, and instead, create complete blocks.
 
-use crate::hash;
-type Hash = u64;
+use crate::{
+    codec::Encode,
+    crypto::{merkle_root, MerkleProof},
+    hash, Hash,
+};
 
 /// The header no longer contains an extrinsic directly. Rather a vector of extrinsics will be stored in
 /// the block body. We are still storing the state in the header for now. This will change in an upcoming
@@ -13,12 +16,23 @@ pub struct Header {
     height: u64,
     // We now switch from storing an extrinsic directly, to storing an extrinsic root.
     // This is basically a concise cryptographic commitment to the complete list of extrinsics.
-    // For example, a hash or a Merkle root.
+    // For example, a hash or a Merkle root. We use a Merkle root so that individual extrinsics
+    // can be proven to be in the block without the rest of the body.
     extrinsics_root: Hash,
     state: u64,
     pub consensus_digest: u64,
 }
 
+impl Encode for Header {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.extrinsics_root.encode_to(dest);
+        self.state.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
 // Methods for creating and verifying headers.
 //
 // With the extrinsics no longer stored in the header, we can no longer do
@@ -29,9 +43,9 @@ impl Header {
     /// Returns a new valid genesis header.
     pub fn genesis() -> Self {
         Header{
-            parent: 0,
+            parent: Hash::default(),
             height: 0,
-            extrinsics_root: hash(&Vec::<u64>::new()),
+            extrinsics_root: merkle_root::<u64>(&[]),
             state: 0,
             consensus_digest: 0,
         }
@@ -62,6 +76,13 @@ impl Header {
         self.height + 1 == child.height && hash(self) == child.parent
     }
 
+    /// Check a proof that the given extrinsic is in the body of the block with this header.
+    ///
+    /// This only needs the header, which is what makes it useful to light clients.
+    pub fn verify_extrinsic(&self, extrinsic: &u64, proof: &MerkleProof) -> bool {
+        proof.verify(&self.extrinsics_root, extrinsic)
+    }
+
     /// Verify that all the given headers form a valid chain from this header to the tip.
     ///
     /// We can now trivially write the old verification function in terms of the new one.
@@ -71,15 +92,10 @@ impl Header {
     ///  * with tail recursion
     fn verify_sub_chain(&self, chain: &[Header]) -> bool {
         // use verify_child
-        chain.iter().fold(Some(self.clone()), |prev, h| {
-            prev.and_then(|prev| {
-                if prev.verify_child(h) {
-                    Some(h.clone())
-                } else {
-                    None
-                }
-            })
-        }).is_some()
+        chain
+            .iter()
+            .try_fold(self.clone(), |prev, h| prev.verify_child(h).then(|| h.clone()))
+            .is_some()
 
     }
 }
@@ -108,7 +124,7 @@ impl Block {
     /// Create and return a valid child block.
     /// The extrinsics are batched now, so we need to execute each of them.
     pub fn child(&self, extrinsics: Vec<u64>) -> Self {
-        let extrinsics_root = hash(&extrinsics);
+        let extrinsics_root = merkle_root(&extrinsics);
         let state = self.header.state + extrinsics.iter().sum::<u64>();
         let header = self.header.child(extrinsics_root, state);
 
@@ -122,20 +138,21 @@ impl Block {
     ///
     /// We need to verify the headers as well as execute all transactions and check the final state.
     pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
-        chain.iter().fold(Some(self.clone()), |prev, b| {
-            prev.and_then(|prev| {
-                if prev.header.verify_child(&b.header) {
-                    let state = prev.header.state + b.body.iter().sum::<u64>();
-                    if state == b.header.state {
-                        Some(b.clone())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+        chain
+            .iter()
+            .try_fold(self.clone(), |prev, b| {
+                let valid = prev.header.verify_child(&b.header)
+                    && merkle_root(&b.body) == b.header.extrinsics_root
+                    && prev.header.state + b.body.iter().sum::<u64>() == b.header.state;
+                valid.then(|| b.clone())
             })
-        }).is_some()
+            .is_some()
+    }
+
+    /// Prove that the extrinsic at the given index is in this block, so that it can be
+    /// checked against the header alone.
+    pub fn extrinsic_proof(&self, index: usize) -> Option<MerkleProof> {
+        MerkleProof::new(&self.body, index)
     }
 }
 
@@ -149,7 +166,7 @@ impl Block {
 /// Notice that you do not need the entire parent block to do this. You only need the header.
 fn build_invalid_child_block_with_valid_header(parent: &Header) -> Block {
     Block{
-        header: parent.child(hash(&[1, 2, 3]), 6),
+        header: parent.child(merkle_root(&[1u64, 2, 3]), 6),
         body: vec![],
     }
 }
@@ -158,8 +175,8 @@ fn build_invalid_child_block_with_valid_header(parent: &Header) -> Block {
 fn bc_4_genesis_header() {
     let g = Header::genesis();
     assert_eq!(g.height, 0);
-    assert_eq!(g.parent, 0);
-    assert_eq!(g.extrinsics_root, hash(&Vec::<u64>::new()));
+    assert_eq!(g.parent, Hash::default());
+    assert_eq!(g.extrinsics_root, merkle_root::<u64>(&[]));
     assert_eq!(g.state, 0);
 }
 
@@ -235,9 +252,9 @@ fn bc_4_verify_three_blocks() {
 fn bc_4_invalid_header_does_not_check() {
     let g = Header::genesis();
     let h1 = Header {
-        parent: 0,
+        parent: Hash::default(),
         height: 100,
-        extrinsics_root: 0,
+        extrinsics_root: Hash::default(),
         state: 100,
         consensus_digest: 0,
     };
@@ -277,3 +294,24 @@ fn bc_4_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&[b1]));
 }
+
+#[test]
+fn bc_4_extrinsic_inclusion_proof() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![4, 5, 6]);
+    let proof = b1.extrinsic_proof(1).unwrap();
+
+    assert!(b1.header.verify_extrinsic(&5, &proof));
+    assert!(!b1.header.verify_extrinsic(&4, &proof));
+    assert!(!g.header.verify_extrinsic(&5, &proof));
+    assert_eq!(b1.extrinsic_proof(3), None);
+}
+
+#[test]
+fn bc_4_block_with_wrong_extrinsics_root_does_not_check() {
+    let b0 = Block::genesis();
+    let mut b1 = b0.child(vec![1, 2]);
+    b1.body = vec![2, 1];
+
+    assert!(!b0.verify_sub_chain(&[b1]));
+}
@@ -8,9 +8,9 @@
 //! we will import them from the previous lesson.
 
 use super::p4_batched_extrinsics::{Block, Header};
-use crate::hash;
+use crate::{hash, Hash};
 
-const THRESHOLD: u64 = u64::MAX / 1000;
+const THRESHOLD: Hash = Hash::MAX.divided_by(1000);
 
 /// Judge which blockchain is "best" when there are multiple candidates. There are several
 /// meaningful notions of "best" which is why this is a trait instead of just a
@@ -82,13 +82,25 @@ impl ForkChoice for LongestChainRule {
 /// nonces. Modeling the amount of work required to achieve a particular hash
 /// is out of scope for this exercise, so we will use the not-really-right-but
 /// conceptually-good-enough formula `work = THRESHOLD - block_hash`
+///
+/// Our hashes are 256 bits wide, so we only subtract the leading 64 bits of each.
+/// That is plenty of precision to tell blocks apart.
 pub struct HeaviestChainRule;
 
+impl HeaviestChainRule {
+    fn work(header: &Header) -> u128 {
+        THRESHOLD
+            .leading_u64()
+            .saturating_sub(hash(header).leading_u64())
+            .into()
+    }
+}
+
 /// Mutates a block (and its embedded header) to contain more PoW difficulty.
 /// This will be useful for exploring the heaviest chain rule. The expected
 /// usage is that you create a block using the normal `Block.child()` method
 /// and then pass the block to this helper for additional mining.
-fn mine_extra_hard(block: &mut Block, threshold: u64) {
+fn mine_extra_hard(block: &mut Block, threshold: Hash) {
     while hash(&block.header) >= threshold {
         block.header.consensus_digest += 1;
     }
@@ -97,8 +109,8 @@ fn mine_extra_hard(block: &mut Block, threshold: u64) {
 
 impl ForkChoice for HeaviestChainRule {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
-        let work_1 = chain_1.iter().map(|h| THRESHOLD.checked_sub(hash(h)).unwrap_or(0)).sum::<u64>();
-        let work_2 = chain_2.iter().map(|h| THRESHOLD.checked_sub(hash(h)).unwrap_or(0)).sum::<u64>();
+        let work_1 = chain_1.iter().map(Self::work).sum::<u128>();
+        let work_2 = chain_2.iter().map(Self::work).sum::<u128>();
         work_1 > work_2
     }
 
@@ -132,8 +144,8 @@ pub struct MostBlocksWithEvenHash;
 
 impl ForkChoice for MostBlocksWithEvenHash {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
-        let work_1 = chain_1.iter().filter(|h| hash(h) % 2 == 0 ).count();
-        let work_2 = chain_2.iter().filter(|h| hash(h) % 2 == 0 ).count();
+        let work_1 = chain_1.iter().filter(|h| hash(h).is_even()).count();
+        let work_2 = chain_2.iter().filter(|h| hash(h).is_even()).count();
         work_1 > work_2
 
     }
@@ -167,35 +179,32 @@ impl ForkChoice for MostBlocksWithEvenHash {
 /// 2. The suffix chain which is longer (non-overlapping with the common prefix)
 /// 3. The suffix chain with more work (non-overlapping with the common prefix)
 fn create_fork_one_side_longer_other_side_heavier() -> (Vec<Header>, Vec<Header>, Vec<Header>) {
-    let mut common_chain = vec![Header::genesis()];
-    let mut longer_chain = Vec::new();
-    let mut heavier_chain = Vec::new();
-
-    let mut i = 2;
-    let mut h = common_chain.last().unwrap().clone();
-    loop {
-        h = h.child(hash(&[i]), i);
-        if hash(&h) % 2 == 0 {
-            break;
+    let g = Header::genesis();
+    let b1 = g.child(hash(&[1]), 1);
+    let common_chain = vec![g, b1];
+
+    // The longer side is made entirely of blocks that did no work at all.
+    let mut longer_chain: Vec<Header> = Vec::new();
+    let mut parent = common_chain.last().unwrap().clone();
+    for i in 0..4 {
+        let mut h = parent.child(hash(&[i]), i);
+        while hash(&h) < THRESHOLD {
+            h.consensus_digest += 1;
         }
-        common_chain.push(h.clone());
-        i += 1;
+        longer_chain.push(h.clone());
+        parent = h;
     }
-    // heaviest chain
-    let mut h = common_chain.last().unwrap().clone();
-    for i in 0..1500 {
-        h = h.child(hash(&[i]), i);
-        if hash(&h) > THRESHOLD {
-            break;
+
+    // The heavier side is shorter, but each block is mined below the threshold.
+    let mut heavier_chain: Vec<Header> = Vec::new();
+    let mut parent = common_chain.last().unwrap().clone();
+    for i in 0..2 {
+        let mut h = parent.child(hash(&[i]), i);
+        while hash(&h) >= THRESHOLD {
+            h.consensus_digest += 1;
         }
         heavier_chain.push(h.clone());
-    }
-
-    // longest chain
-    let mut h = common_chain.last().unwrap().clone();
-    for i in 0..1500 {
-        h = h.child(hash(&[i]), i);
-        longer_chain.push(h.clone());
+        parent = h;
     }
 
     (common_chain, longer_chain, heavier_chain)
@@ -226,7 +235,7 @@ fn bc_5_mine_to_custom_difficulty() {
     // We want the custom threshold to be high enough that we don't take forever mining
     // but low enough that it is unlikely we accidentally meet it with the normal
     // block creation function
-    let custom_threshold = u64::max_value() / 1000;
+    let custom_threshold = Hash::MAX.divided_by(1000);
     mine_extra_hard(&mut b1, custom_threshold);
 
     assert!(hash(&b1.header) < custom_threshold);
@@ -266,33 +275,33 @@ fn bc_5_heaviest_chain() {
 fn bc_5_most_even_blocks() {
     let g = Header::genesis();
 
-    let mut h_a1 = g.child(2, 0);
+    let mut h_a1 = g.child(hash(&[2]), 0);
     for i in 0..u64::max_value() {
-        h_a1 = g.child(2, i);
-        if hash(&h_a1) % 2 == 0 {
+        h_a1 = g.child(hash(&[2]), i);
+        if hash(&h_a1).is_even() {
             break;
         }
     }
-    let mut h_a2 = g.child(2, 0);
+    let mut h_a2 = g.child(hash(&[2]), 0);
     for i in 0..u64::max_value() {
-        h_a2 = h_a1.child(2, i);
-        if hash(&h_a2) % 2 == 0 {
+        h_a2 = h_a1.child(hash(&[2]), i);
+        if hash(&h_a2).is_even() {
             break;
         }
     }
     let chain_1 = &[g.clone(), h_a1, h_a2];
 
-    let mut h_b1 = g.child(2, 0);
+    let mut h_b1 = g.child(hash(&[2]), 0);
     for i in 0..u64::max_value() {
-        h_b1 = g.child(2, i);
-        if hash(&h_b1) % 2 != 0 {
+        h_b1 = g.child(hash(&[2]), i);
+        if !hash(&h_b1).is_even() {
             break;
         }
     }
-    let mut h_b2 = g.child(2, 0);
+    let mut h_b2 = g.child(hash(&[2]), 0);
     for i in 0..u64::max_value() {
-        h_b2 = h_b1.child(2, i);
-        if hash(&h_b2) % 2 != 0 {
+        h_b2 = h_b1.child(hash(&[2]), i);
+        if !hash(&h_b2).is_even() {
             break;
         }
     }
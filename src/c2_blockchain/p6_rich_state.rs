@@ -11,8 +11,7 @@
 //! This notion of state may sound familiar from our previous work on state machines. Indeed this
 //! naming coincidence foreshadows a key abstraction that we will make in a coming chapter.
 
-type Hash = u64;
-use crate::hash;
+use crate::{codec::Encode, hash, Hash};
 
 /// In this section we will use sum and product together to be our state. While this is only a doubling of state size
 /// remember that in real world blockchains, the state is often really really large.
@@ -37,6 +36,23 @@ pub struct Header {
     consensus_digest: u64,
 }
 
+impl Encode for State {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.sum.encode_to(dest);
+        self.product.encode_to(dest);
+    }
+}
+
+impl Encode for Header {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.extrinsics_root.encode_to(dest);
+        self.state_root.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
 // Methods for creating and verifying headers.
 //
 // We already moved the execution logic to the block level in the last section.
@@ -126,7 +142,7 @@ fn bc_6_genesis_header() {
     let state = State { sum: 6, product: 9 };
     let g = Header::genesis(hash(&state));
     assert_eq!(g.height, 0);
-    assert_eq!(g.parent, 0);
+    assert_eq!(g.parent, Hash::default());
     assert_eq!(g.extrinsics_root, hash(&Vec::<u64>::new()));
     assert_eq!(g.state_root, hash(&state));
 }
@@ -223,9 +239,9 @@ fn bc_6_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 };
     let g = Header::genesis(hash(&state));
     let h1 = Header {
-        parent: 0,
+        parent: Hash::default(),
         height: 100,
-        extrinsics_root: 0,
+        extrinsics_root: Hash::default(),
         state_root: hash(&(State { sum: 0, product: 0 })),
         consensus_digest: 0,
     };
@@ -8,12 +8,27 @@ mod p3_poa; // exercise: dictator is a special case of poa. Create dictator in t
 mod p4_even_only;
 mod p5_interleave;
 mod p6_forking;
+mod p7_difficulty_adjustment;
+mod p8_mining;
+mod p9_slot_clock;
+mod p10_stake_lottery;
 
 // Re-export some individual consensus engines so they can be be re-used in the Client chapter.
 pub use p1_pow::Pow;
 pub use p3_poa::SimplePoa;
+pub use p8_mining::{Miner, MiningJob, ProofOfWork};
+pub use p9_slot_clock::{SlotClock, SlotConsensus};
+// The client's tests drive slot-based authoring with these.
+#[cfg(test)]
+pub use p3_poa::PoaRoundRobinBySlot;
+#[cfg(test)]
+pub use p9_slot_clock::{ManualSlotClock, SystemSlotClock};
 
-type Hash = u64;
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature},
+    hash, AccountId, Hash,
+};
 
 /// A Block Header similar to prior chapters of this tutorial.
 ///
@@ -23,20 +38,79 @@ type Hash = u64;
 /// Consensus engines do not know or care about the blockchain's state machine,
 /// which means they can operate entirely at the header level. They never need to touch
 /// the complete blocks.
+///
+/// The header also names the block's author. This is the account that collects the block's
+/// fees and reward. It is independent of the consensus digest, which may not identify anyone,
+/// for example in proof of work.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Header<Digest> {
-    parent: Hash,
-    height: u64,
-    state_root: Hash,
-    extrinsics_root: Hash,
-    consensus_digest: Digest,
+    pub(crate) parent: Hash,
+    pub(crate) height: u64,
+    pub(crate) state_root: Hash,
+    pub(crate) extrinsics_root: Hash,
+    pub(crate) author: AccountId,
+    pub(crate) consensus_digest: Digest,
+}
+
+impl<Digest: Encode> Encode for Header<Digest> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.parent.encode_to(dest);
+        self.height.encode_to(dest);
+        self.state_root.encode_to(dest);
+        self.extrinsics_root.encode_to(dest);
+        self.author.encode_to(dest);
+        self.consensus_digest.encode_to(dest);
+    }
+}
+
+impl<Digest> Header<Digest> {
+    /// This header with its consensus digest stripped off. This is the partial header that
+    /// the consensus engine was given to seal.
+    pub(crate) fn unsealed(&self) -> Header<()> {
+        Header {
+            parent: self.parent,
+            height: self.height,
+            state_root: self.state_root,
+            extrinsics_root: self.extrinsics_root,
+            author: self.author,
+            consensus_digest: (),
+        }
+    }
+}
+
+impl Header<()> {
+    /// Attach the given consensus digest to this partial header.
+    pub(crate) fn with_digest<Digest>(self, consensus_digest: Digest) -> Header<Digest> {
+        Header {
+            parent: self.parent,
+            height: self.height,
+            state_root: self.state_root,
+            extrinsics_root: self.extrinsics_root,
+            author: self.author,
+            consensus_digest,
+        }
+    }
 }
+
+impl<Digest: Decode> Decode for Header<Digest> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Header {
+            parent: Hash::decode(input)?,
+            height: u64::decode(input)?,
+            state_root: Hash::decode(input)?,
+            extrinsics_root: Hash::decode(input)?,
+            author: AccountId::decode(input)?,
+            consensus_digest: Digest::decode(input)?,
+        })
+    }
+}
+
 /// A Consensus Engine. Responsible for Sealing blocks and verifying their seals
 ///
 /// Consensus exists independently of execution logic, and therefore operates
 /// only on the block headers.
 pub trait Consensus {
-    type Digest: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash;
+    type Digest: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash + Encode + Decode;
 
     /// Validates that a header is valid according to consensus rules. This
     /// function checks ONLY consensus-related aspects such as the signature
@@ -64,7 +138,6 @@ pub trait Consensus {
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>>;
-    // NOTE TO SELF. For slot-based PoA etc, just look at the system time. It's what real-world aura does
 
     /// Verify that all the given headers are valid according to the consensus rules.
     ///
@@ -76,7 +149,13 @@ pub trait Consensus {
         parent_digest: &Self::Digest,
         chain: &[Header<Self::Digest>],
     ) -> bool {
-        
+        chain
+            .iter()
+            .try_fold(parent_digest.clone(), |parent_digest, header| {
+                self.validate(&parent_digest, header)
+                    .then(|| header.consensus_digest.clone())
+            })
+            .is_some()
     }
 
     /// A human-readable name for this engine. This may be used in user-facing
@@ -103,11 +182,98 @@ impl Consensus for () {
     }
 }
 
-/// A set of consensus authority accounts that can be used in
-/// identity-based consensus algorithms.
+/// The consensus digest used by identity-based engines. It names an authority and carries
+/// their signature over the hash of the partial header, that is the header before the seal
+/// was attached. Signing the partial header rather than the full one is what lets the
+/// signature live inside the header it signs.
 #[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
-pub enum ConsensusAuthority {
-    Alice,
-    Bob,
-    Charlie,
+pub struct AuthoritySeal {
+    pub(crate) authority: AccountId,
+    pub(crate) signature: Signature,
+}
+
+impl AuthoritySeal {
+    /// Sign the given partial header with the given authority's keypair.
+    pub fn sign(authority: &Keypair, partial_header: &Header<()>) -> Self {
+        AuthoritySeal {
+            authority: authority.into(),
+            signature: authority.sign(&hash(partial_header)),
+        }
+    }
+
+    /// Check that this seal's authority really did sign the given header.
+    pub fn verify<Digest>(&self, header: &Header<Digest>) -> bool {
+        self.authority
+            .verify(&hash(&header.unsealed()), &self.signature)
+    }
+}
+
+/// Genesis headers are never sealed, but they still need a digest. This placeholder is
+/// never a valid seal.
+impl Default for AuthoritySeal {
+    fn default() -> Self {
+        AuthoritySeal {
+            authority: AccountId::default(),
+            signature: Signature::default(),
+        }
+    }
+}
+
+impl Encode for AuthoritySeal {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.authority.encode_to(dest);
+        self.signature.encode_to(dest);
+    }
+}
+
+impl Decode for AuthoritySeal {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(AuthoritySeal {
+            authority: AccountId::decode(input)?,
+            signature: Signature::decode(input)?,
+        })
+    }
+}
+
+#[test]
+fn header_round_trips_through_codec() {
+    let header = Header {
+        parent: crate::hash(&1u64),
+        height: 2,
+        state_root: crate::hash(&3u64),
+        extrinsics_root: crate::hash(&4u64),
+        author: crate::Keyring::Dave.to_account_id(),
+        consensus_digest: 7u64,
+    };
+
+    assert_eq!(Header::decode_all(&header.encode()), Ok(header));
+}
+
+#[test]
+fn authority_seal_signs_the_partial_header() {
+    let partial = Header {
+        parent: Hash::default(),
+        height: 1,
+        state_root: crate::hash(&1u64),
+        extrinsics_root: crate::hash(&2u64),
+        author: AccountId::default(),
+        consensus_digest: (),
+    };
+    let seal = AuthoritySeal::sign(crate::Keyring::Bob.pair(), &partial);
+    let header = partial.with_digest(seal);
+
+    assert!(seal.verify(&header));
+    assert_eq!(AuthoritySeal::decode_all(&seal.encode()), Ok(seal));
+
+    // Claiming somebody else signed it does not work.
+    let impostor = AuthoritySeal {
+        authority: crate::Keyring::Alice.to_account_id(),
+        ..seal
+    };
+    assert!(!impostor.verify(&header));
+
+    // Nor does moving the seal to a different header.
+    let mut other = header.clone();
+    other.height = 2;
+    assert!(!seal.verify(&other));
 }
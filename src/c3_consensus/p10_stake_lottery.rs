@@ -0,0 +1,346 @@
+//! The authority engines so far give every authority the same share of the blocks. In Proof
+//! of Stake, authorities put up tokens as a bond, and an authority with twice the stake should
+//! author twice as many blocks.
+//!
+//! BABE and Ouroboros Praos do this with a private lottery. In every slot, each authority
+//! evaluates a VRF on the slot number. If the output falls below a threshold proportional to
+//! its stake, it has won the slot and may author a block. The VRF proof goes in the digest so
+//! that every other node can check the win.
+//!
+//! Because every authority draws independently, a slot may have no winner at all, in which
+//! case no block is authored, or several winners, in which case the chain briefly forks and
+//! fork choice settles it. Nobody knows who won a slot until the winners reveal themselves,
+//! which makes it hard for an attacker to target the next author.
+
+use std::collections::BTreeMap;
+
+use super::{p9_slot_clock::SystemSlotClock, Consensus, Header, SlotClock, SlotConsensus};
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature, VrfProof},
+    hash, AccountId, Hash,
+};
+
+/// How much each authority has staked. Accounts with no stake cannot win any slot.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StakingTable {
+    stakes: BTreeMap<AccountId, u64>,
+}
+
+impl StakingTable {
+    pub fn stake_of(&self, who: &AccountId) -> u64 {
+        self.stakes.get(who).copied().unwrap_or(0)
+    }
+
+    pub fn set_stake(&mut self, who: AccountId, stake: u64) {
+        self.stakes.insert(who, stake);
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.stakes
+            .values()
+            .fold(0, |total, stake| total.saturating_add(*stake))
+    }
+}
+
+impl FromIterator<(AccountId, u64)> for StakingTable {
+    fn from_iter<I: IntoIterator<Item = (AccountId, u64)>>(iter: I) -> Self {
+        StakingTable {
+            stakes: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<const N: usize> From<[(AccountId, u64); N]> for StakingTable {
+    fn from(stakes: [(AccountId, u64); N]) -> Self {
+        stakes.into_iter().collect()
+    }
+}
+
+/// The digest of a block authored by a lottery winner. It carries the VRF output and proof
+/// that show the author won the slot, and the author's signature over the partial header.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct LotteryDigest {
+    pub(crate) slot: u64,
+    pub(crate) authority: AccountId,
+    pub(crate) vrf_output: Hash,
+    pub(crate) vrf_proof: VrfProof,
+    pub(crate) signature: Signature,
+}
+
+impl LotteryDigest {
+    /// The message an author signs. It covers the slot and the VRF output as well as the
+    /// partial header, so that a winning ticket cannot be moved to another block.
+    fn message(partial_header: &Header<()>, slot: u64, vrf_output: &Hash) -> Hash {
+        hash(&(partial_header, slot, vrf_output))
+    }
+}
+
+impl Encode for LotteryDigest {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.slot.encode_to(dest);
+        self.authority.encode_to(dest);
+        self.vrf_output.encode_to(dest);
+        self.vrf_proof.encode_to(dest);
+        self.signature.encode_to(dest);
+    }
+}
+
+impl Decode for LotteryDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(LotteryDigest {
+            slot: u64::decode(input)?,
+            authority: AccountId::decode(input)?,
+            vrf_output: Hash::decode(input)?,
+            vrf_proof: VrfProof::decode(input)?,
+            signature: Signature::decode(input)?,
+        })
+    }
+}
+
+/// A Proof of Stake engine in which authorities win slots in a private, stake-weighted lottery.
+pub struct StakeLottery<K = SystemSlotClock> {
+    pub staking: StakingTable,
+    /// The chance, as a numerator and denominator, that an authority holding all of the stake
+    /// wins a given slot. Everyone else's chance is scaled down by their share of the stake.
+    pub winning_chance: (u64, u64),
+    pub signer: Option<Keypair>,
+    pub clock: K,
+    /// How many slots ahead of this node's clock a block may be.
+    pub allowed_drift: u64,
+}
+
+impl<K> StakeLottery<K> {
+    /// The VRF input for a slot. Every authority draws on the same input, but with their own
+    /// key, so their outputs are independent.
+    fn lottery_input(slot: u64) -> Hash {
+        hash(&("stake lottery", slot))
+    }
+
+    /// The VRF output that the given authority must come in under to win a slot.
+    pub fn threshold(&self, authority: &AccountId) -> Hash {
+        let total = self.staking.total_stake();
+        if total == 0 {
+            return Hash::default();
+        }
+        let (numerator, denominator) = self.winning_chance;
+        Hash::MAX
+            .scaled(self.staking.stake_of(authority), total)
+            .scaled(numerator, denominator)
+    }
+
+    /// Draw this node's ticket for the given slot. Returns the VRF output and proof if it wins.
+    pub fn claim(&self, slot: u64) -> Option<(Hash, VrfProof)> {
+        let signer = self.signer.as_ref()?;
+        let threshold = self.threshold(&signer.into());
+        // Nothing is below zero, so there is no point drawing without stake.
+        if threshold == Hash::default() {
+            return None;
+        }
+        let (output, proof) = signer.vrf_sign(&Self::lottery_input(slot));
+        (output < threshold).then_some((output, proof))
+    }
+}
+
+impl<K: SlotClock> Consensus for StakeLottery<K> {
+    type Digest = LotteryDigest;
+
+    /// Check that the slot moves forward and is not too far in the future, that the VRF proof
+    /// is valid and wins the slot for the authority's stake, and that the authority signed the
+    /// header.
+    fn validate(&self, parent_digest: &LotteryDigest, header: &Header<LotteryDigest>) -> bool {
+        let digest = &header.consensus_digest;
+        let input = Self::lottery_input(digest.slot);
+        let message = LotteryDigest::message(&header.unsealed(), digest.slot, &digest.vrf_output);
+        digest.slot > parent_digest.slot
+            && digest.slot <= self.clock.current_slot().saturating_add(self.allowed_drift)
+            && digest
+                .authority
+                .public()
+                .vrf_verify(&input, &digest.vrf_proof)
+                == Some(digest.vrf_output)
+            && digest.vrf_output < self.threshold(&digest.authority)
+            && digest.authority.verify(&message, &digest.signature)
+    }
+
+    /// Seal in the current slot, if it comes after the parent's and this node won it.
+    fn seal(
+        &self,
+        parent_digest: &LotteryDigest,
+        partial_header: Header<()>,
+    ) -> Option<Header<LotteryDigest>> {
+        let slot = self.clock.current_slot();
+        if slot <= parent_digest.slot {
+            return None;
+        }
+        let (vrf_output, vrf_proof) = self.claim(slot)?;
+        let signer = self.signer.as_ref()?;
+        let message = LotteryDigest::message(&partial_header, slot, &vrf_output);
+        let digest = LotteryDigest {
+            slot,
+            authority: signer.into(),
+            vrf_output,
+            vrf_proof,
+            signature: signer.sign(&message),
+        };
+        Some(partial_header.with_digest(digest))
+    }
+
+    fn human_name() -> String {
+        "Stake Lottery".into()
+    }
+}
+
+impl<K: SlotClock> SlotConsensus for StakeLottery<K> {
+    type Clock = K;
+
+    fn clock(&self) -> &K {
+        &self.clock
+    }
+
+    fn slot(digest: &LotteryDigest) -> u64 {
+        digest.slot
+    }
+
+    fn claims_slot(&self, slot: u64) -> bool {
+        self.claim(slot).is_some()
+    }
+}
+
+#[cfg(test)]
+use super::ManualSlotClock;
+#[cfg(test)]
+use crate::Keyring;
+
+#[cfg(test)]
+fn partial_header() -> Header<()> {
+    Header {
+        parent: Hash::default(),
+        height: 1,
+        state_root: hash(&1u64),
+        extrinsics_root: hash(&2u64),
+        author: AccountId::default(),
+        consensus_digest: (),
+    }
+}
+
+/// Alice has a quarter of the stake and Bob has the rest. An authority with all of the stake
+/// would win every slot.
+#[cfg(test)]
+fn lottery(signer: Option<Keyring>, clock: &ManualSlotClock) -> StakeLottery<ManualSlotClock> {
+    StakeLottery {
+        staking: StakingTable::from([
+            (Keyring::Alice.to_account_id(), 1),
+            (Keyring::Bob.to_account_id(), 3),
+        ]),
+        winning_chance: (1, 1),
+        signer: signer.map(|signer| signer.pair().clone()),
+        clock: clock.clone(),
+        allowed_drift: 1,
+    }
+}
+
+/// The first slot from the given one in which the engine's signer wins or loses, as asked.
+#[cfg(test)]
+fn first_slot(engine: &StakeLottery<ManualSlotClock>, from: u64, wins: bool) -> u64 {
+    (from..)
+        .find(|slot| engine.claims_slot(*slot) == wins)
+        .unwrap()
+}
+
+#[test]
+fn lottery_digest_round_trips_through_codec() {
+    let (vrf_output, vrf_proof) = Keyring::Alice.pair().vrf_sign(&hash(&3u64));
+    let digest = LotteryDigest {
+        slot: 3,
+        authority: Keyring::Alice.to_account_id(),
+        vrf_output,
+        vrf_proof,
+        signature: Signature([7; 64]),
+    };
+
+    assert_eq!(LotteryDigest::decode_all(&digest.encode()), Ok(digest));
+}
+
+#[test]
+fn stake_lottery_winner_seals_valid_block() {
+    let clock = ManualSlotClock::new(1);
+    let alice = lottery(Some(Keyring::Alice), &clock);
+    let observer = lottery(None, &clock);
+    let parent = LotteryDigest::default();
+
+    clock.set_slot(first_slot(&alice, 1, false));
+    assert_eq!(alice.seal(&parent, partial_header()), None);
+    clock.set_slot(first_slot(&alice, 1, true));
+    let sealed = alice.seal(&parent, partial_header()).unwrap();
+    assert!(observer.validate(&parent, &sealed));
+
+    // The win cannot be reused in a later slot or by someone else.
+    let mut moved = sealed.clone();
+    moved.consensus_digest.slot += 1;
+    assert!(!observer.validate(&parent, &moved));
+    let mut stolen = sealed.clone();
+    stolen.consensus_digest.authority = Keyring::Bob.to_account_id();
+    assert!(!observer.validate(&parent, &stolen));
+    let mut resigned = sealed.clone();
+    resigned.height = 2;
+    assert!(!observer.validate(&parent, &resigned));
+}
+
+#[test]
+fn stake_lottery_rejects_losers_and_future_slots() {
+    let clock = ManualSlotClock::new(0);
+    let alice = lottery(Some(Keyring::Alice), &clock);
+    let parent = LotteryDigest::default();
+
+    // A losing ticket, honestly drawn and signed, does not win the slot.
+    let losing_slot = first_slot(&alice, 1, false);
+    let (vrf_output, vrf_proof) = Keyring::Alice
+        .pair()
+        .vrf_sign(&StakeLottery::<ManualSlotClock>::lottery_input(losing_slot));
+    let message = LotteryDigest::message(&partial_header(), losing_slot, &vrf_output);
+    let loser = partial_header().with_digest(LotteryDigest {
+        slot: losing_slot,
+        authority: Keyring::Alice.to_account_id(),
+        vrf_output,
+        vrf_proof,
+        signature: Keyring::Alice.pair().sign(&message),
+    });
+    clock.set_slot(losing_slot);
+    assert!(!alice.validate(&parent, &loser));
+
+    // A winning ticket from too far in the future is not accepted until its time comes.
+    let winning_slot = first_slot(&alice, 10, true);
+    clock.set_slot(winning_slot);
+    let winner = alice.seal(&parent, partial_header()).unwrap();
+    clock.set_slot(winning_slot - 2);
+    assert!(!alice.validate(&parent, &winner));
+    clock.set_slot(winning_slot - 1);
+    assert!(alice.validate(&parent, &winner));
+
+    // Without stake, nobody wins.
+    let charlie = StakeLottery {
+        signer: Some(Keyring::Charlie.pair().clone()),
+        ..lottery(None, &clock)
+    };
+    assert!((0..20).all(|slot| !charlie.claims_slot(slot)));
+}
+
+#[test]
+fn stake_lottery_wins_are_proportional_to_stake() {
+    let clock = ManualSlotClock::new(0);
+    let alice = lottery(Some(Keyring::Alice), &clock);
+    let bob = lottery(Some(Keyring::Bob), &clock);
+    let draws: Vec<(bool, bool)> = (1..=100)
+        .map(|slot| (alice.claims_slot(slot), bob.claims_slot(slot)))
+        .collect();
+    let alice_wins = draws.iter().filter(|(alice_won, _)| *alice_won).count();
+    let bob_wins = draws.iter().filter(|(_, bob_won)| *bob_won).count();
+
+    // Expected: 25 wins for Alice and 75 for Bob.
+    assert!((12..=38).contains(&alice_wins), "alice won {alice_wins}");
+    assert!((62..=88).contains(&bob_wins), "bob won {bob_wins}");
+    // Some slots go to nobody and some to both.
+    assert!(draws.contains(&(false, false)));
+    assert!(draws.contains(&(true, true)));
+}
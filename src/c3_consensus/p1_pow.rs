@@ -5,13 +5,21 @@
 //! generic consensus framework that we will use throughout the rest of the chapter.
 
 use crate::hash;
-use super::{Consensus, Hash, Header};
+use super::{Consensus, Hash, Header, Miner, ProofOfWork};
 
 /// A Proof of Work consensus engine. This is the same consensus logic that we
 /// implemented in the previous chapter. Here we simply re-implement it in the
 /// consensus framework that will be used throughout this chapter.
+#[derive(Clone)]
 pub struct Pow {
-    threshold: u64,
+    threshold: Hash,
+}
+
+impl Pow {
+    /// A PoW engine that accepts headers whose hash is below the given threshold.
+    pub fn new(threshold: Hash) -> Self {
+        Pow { threshold }
+    }
 }
 
 impl Consensus for Pow {
@@ -27,28 +35,31 @@ impl Consensus for Pow {
 
     /// Mine a new PoW seal for the partial header provided.
     /// This does not rely on the parent digest at all.
-    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
-        for nonce in 0..u64::MAX {
-            let header_hash = hash(&partial_header);
-            if header_hash < self.threshold {
-                return Some(Header {
-                    parent: partial_header.parent,
-                    height: partial_header.height,
-                    extrinsics_root: partial_header.extrinsics_root,
-                    state_root: partial_header.state_root,
-                    consensus_digest: nonce,
-                });
-            }
-        }
-        None
+    fn seal(&self, parent_digest: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        Miner::new(1).mine(self, parent_digest, partial_header)
+    }
+}
+
+/// The whole digest is the nonce.
+impl ProofOfWork for Pow {
+    fn prepare(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        Some(partial_header.with_digest(0))
+    }
+
+    fn set_nonce(header: &mut Header<Self::Digest>, nonce: u64) {
+        header.consensus_digest = nonce;
+    }
+
+    fn meets_target(&self, header: &Header<Self::Digest>) -> bool {
+        hash(header) < self.threshold
     }
 }
 
 /// Create a PoW consensus engine that has a difficulty threshold such that roughly 1 in 100 blocks
-/// with randomly drawn nonces will be valid. That is: the threshold should be u64::max_value() / 100.
+/// with randomly drawn nonces will be valid. That is: the threshold should be `Hash::MAX` / 100.
 pub fn moderate_difficulty_pow() -> Pow {
     Pow {
-        threshold: u64::MAX / 100,
+        threshold: Hash::MAX.divided_by(100),
     }
 }
 
@@ -56,6 +67,6 @@ pub fn moderate_difficulty_pow() -> Pow {
 /// consensus implementation for `()` from the module level.
 pub fn trivial_always_valid_pow() -> Pow {
     Pow {
-        threshold: u64::MAX,
+        threshold: Hash::MAX,
     }
 }
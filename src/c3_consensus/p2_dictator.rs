@@ -1,39 +1,92 @@
 //! The Dictator consensus engine considers any header valid as long as it is signed by the dictator.
-//! The "signature" is made by simply attaching one of the Consensus Authorities from the root of this module.
+//! The signature is an [`AuthoritySeal`]: the dictator's Schnorr signature over the partial header.
 //!
 //! Notice that there is no iterating or "searching" for a correct seal. This save a lot of computation and
-//! a lot of energy.
-//!
-//! Throughout this chapter we will avoid performing _Actual_ cryptographic calculations because they
-//! require a crypto library which and overcoming its own learning curve, plus they distract from the
-//! underlying consensus-related logic. Instead, we just use the `ConsensusAuthority` enum from the module root.
+//! a lot of energy. Signing costs a couple of elliptic curve multiplications, which is nothing compared
+//! to grinding nonces.
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{AuthoritySeal, Consensus, Header};
+#[cfg(test)]
+use crate::Keyring;
+use crate::{crypto::Keypair, AccountId};
 /// Dictator consensus is an identity-based consensus algorithm. It specifies a single dictator
 /// identity who is the only identity authorized to sign valid blocks. Any block signed by the
 /// dictator is valid (at the consensus level), and any block not signed by the dictator is invalid.
 struct DictatorConsensus {
-    dictator: ConsensusAuthority,
+    dictator: AccountId,
+    /// This node's own keypair. Only the dictator's node holds the dictator's key, so only it can seal.
+    signer: Option<Keypair>,
 }
 
 impl Consensus for DictatorConsensus {
-    type Digest = ConsensusAuthority;
+    type Digest = AuthoritySeal;
 
     /// Check that the header is signed by the dictator
-    fn validate(&self, dictator: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        let seal = &header.consensus_digest;
+        seal.authority == self.dictator && seal.verify(header)
+    }
 
-        header.consensus_digest == *dictator
+    /// Sign the given partial header by the dictator
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        let signer = self
+            .signer
+            .as_ref()
+            .filter(|pair| AccountId::from(*pair) == self.dictator)?;
+        let seal = AuthoritySeal::sign(signer, &partial_header);
+        Some(partial_header.with_digest(seal))
+    }
+}
 
+#[cfg(test)]
+fn partial_header() -> Header<()> {
+    Header {
+        parent: crate::Hash::default(),
+        height: 1,
+        state_root: crate::hash(&1u64),
+        extrinsics_root: crate::hash(&2u64),
+        author: AccountId::default(),
+        consensus_digest: (),
     }
+}
 
-    /// Sign the given partial header by the dictator
-    fn seal(&self, dictator: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
-        Some(Header {
-            parent: partial_header.parent,
-            height: partial_header.height,
-            extrinsics_root: partial_header.extrinsics_root,
-            state_root: partial_header.state_root,
-            consensus_digest: *dictator,
-        })
+#[cfg(test)]
+fn bob_rules(signer: Keyring) -> DictatorConsensus {
+    DictatorConsensus {
+        dictator: Keyring::Bob.to_account_id(),
+        signer: Some(signer.pair().clone()),
     }
 }
+
+#[test]
+fn dictator_seals_are_valid() {
+    let engine = bob_rules(Keyring::Bob);
+    let header = engine
+        .seal(&AuthoritySeal::default(), partial_header())
+        .unwrap();
+
+    assert!(engine.validate(&AuthoritySeal::default(), &header));
+}
+
+#[test]
+fn dictator_rejects_other_signers() {
+    let engine = bob_rules(Keyring::Alice);
+    let partial = partial_header();
+    let header = partial
+        .clone()
+        .with_digest(AuthoritySeal::sign(Keyring::Alice.pair(), &partial));
+
+    assert_eq!(engine.seal(&AuthoritySeal::default(), partial), None);
+    assert!(!engine.validate(&AuthoritySeal::default(), &header));
+}
+
+#[test]
+fn dictator_rejects_tampered_header() {
+    let engine = bob_rules(Keyring::Bob);
+    let mut header = engine
+        .seal(&AuthoritySeal::default(), partial_header())
+        .unwrap();
+    header.state_root = crate::hash(&3u64);
+
+    assert!(!engine.validate(&AuthoritySeal::default(), &header));
+}
@@ -9,26 +9,43 @@
 //! Even when using the Proof of Stake configuration, the underlying consensus logic is identical to
 //! the proof of authority we are writing here.
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{
+    p9_slot_clock::SystemSlotClock, AuthoritySeal, Consensus, Header, SlotClock, SlotConsensus,
+};
+#[cfg(test)]
+use super::ManualSlotClock;
+#[cfg(test)]
+use crate::Keyring;
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature},
+    hash, AccountId,
+};
 
 /// A Proof of Authority consensus engine. If any of the authorities have signed the block, it is valid.
 pub struct SimplePoa {
-    pub authorities: Vec<ConsensusAuthority>,
+    pub authorities: Vec<AccountId>,
+    /// This node's own keypair, if it has one. Nodes that are not authorities can still
+    /// validate blocks, they just cannot seal them.
+    pub signer: Option<Keypair>,
 }
 
 impl Consensus for SimplePoa {
-    type Digest = ConsensusAuthority;
+    type Digest = AuthoritySeal;
 
-    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 1")
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        let seal = &header.consensus_digest;
+        self.authorities.contains(&seal.authority) && seal.verify(header)
     }
 
-    fn seal(
-        &self,
-        parent_digest: &Self::Digest,
-        partial_header: Header<()>,
-    ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 2")
+    /// Any authority may sign, so we can seal whenever our own key belongs to one of them.
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        let signer = self
+            .signer
+            .as_ref()
+            .filter(|pair| self.authorities.contains(&AccountId::from(*pair)))?;
+        let seal = AuthoritySeal::sign(signer, &partial_header);
+        Some(partial_header.with_digest(seal))
     }
 }
 
@@ -36,22 +53,34 @@ impl Consensus for SimplePoa {
 /// As ever, the genesis block does not require a seal. After that the authorities take turns
 /// in order.
 struct PoaRoundRobinByHeight {
-    authorities: Vec<ConsensusAuthority>,
+    authorities: Vec<AccountId>,
+    signer: Option<Keypair>,
+}
+
+impl PoaRoundRobinByHeight {
+    /// The authority whose turn it is to sign at the given height. Block 1 goes to the first authority.
+    fn expected_author(&self, height: u64) -> Option<AccountId> {
+        let turn = height.checked_sub(1)? % self.authorities.len() as u64;
+        self.authorities.get(turn as usize).copied()
+    }
 }
 
 impl Consensus for PoaRoundRobinByHeight {
-    type Digest = ConsensusAuthority;
+    type Digest = AuthoritySeal;
 
-    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 3")
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        let seal = &header.consensus_digest;
+        self.expected_author(header.height) == Some(seal.authority) && seal.verify(header)
     }
 
-    fn seal(
-        &self,
-        parent_digest: &Self::Digest,
-        partial_header: Header<()>,
-    ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 4")
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        let author = self.expected_author(partial_header.height)?;
+        let signer = self
+            .signer
+            .as_ref()
+            .filter(|pair| AccountId::from(*pair) == author)?;
+        let seal = AuthoritySeal::sign(signer, &partial_header);
+        Some(partial_header.with_digest(seal))
     }
 }
 
@@ -63,31 +92,307 @@ impl Consensus for PoaRoundRobinByHeight {
 ///
 /// A common PoA scheme that works around these weaknesses is to divide time into slots, and then do a round robin
 /// by slot instead of by height
-struct PoaRoundRobinBySlot {
-    authorities: Vec<ConsensusAuthority>,
+///
+/// The engine reads the current slot from its clock. It only seals in the current slot, and only if that
+/// slot is its own. It rejects blocks whose slot is more than `allowed_drift` slots ahead of its clock.
+pub struct PoaRoundRobinBySlot<K = SystemSlotClock> {
+    pub authorities: Vec<AccountId>,
+    pub signer: Option<Keypair>,
+    pub clock: K,
+    /// How many slots ahead of this node's clock a block may be, to allow for clocks that disagree a little.
+    pub allowed_drift: u64,
 }
 
 /// A digest used for PoaRoundRobinBySlot. The digest contains the slot number as well as the signature.
 /// In addition to checking that the right signer has signed for the slot, you must check that the slot is
 /// always strictly increasing. But remember that slots may be skipped.
+///
+/// The signature covers the partial header together with the slot, so a seal cannot be moved to a
+/// different slot.
 #[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
-struct SlotDigest {
-    slot: u64,
-    signature: ConsensusAuthority,
+pub struct SlotDigest {
+    pub(crate) slot: u64,
+    pub(crate) authority: AccountId,
+    pub(crate) signature: Signature,
+}
+
+impl SlotDigest {
+    fn sign(slot: u64, authority: &Keypair, partial_header: &Header<()>) -> Self {
+        SlotDigest {
+            slot,
+            authority: authority.into(),
+            signature: authority.sign(&hash(&(partial_header, slot))),
+        }
+    }
+
+    fn verify(&self, header: &Header<Self>) -> bool {
+        self.authority
+            .verify(&hash(&(header.unsealed(), self.slot)), &self.signature)
+    }
+}
+
+/// The genesis digest sits in slot zero and is never checked.
+impl Default for SlotDigest {
+    fn default() -> Self {
+        SlotDigest {
+            slot: 0,
+            authority: AccountId::default(),
+            signature: Signature::default(),
+        }
+    }
+}
+
+impl Encode for SlotDigest {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.slot.encode_to(dest);
+        self.authority.encode_to(dest);
+        self.signature.encode_to(dest);
+    }
+}
+
+impl Decode for SlotDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SlotDigest {
+            slot: u64::decode(input)?,
+            authority: AccountId::decode(input)?,
+            signature: Signature::decode(input)?,
+        })
+    }
 }
 
-impl Consensus for PoaRoundRobinBySlot {
+impl<K> PoaRoundRobinBySlot<K> {
+    fn slot_author(&self, slot: u64) -> Option<AccountId> {
+        let turn = slot.checked_rem(self.authorities.len() as u64)?;
+        self.authorities.get(turn as usize).copied()
+    }
+}
+
+impl<K: SlotClock> Consensus for PoaRoundRobinBySlot<K> {
     type Digest = SlotDigest;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 5")
+        let digest = &header.consensus_digest;
+        digest.slot > parent_digest.slot
+            && digest.slot <= self.clock.current_slot().saturating_add(self.allowed_drift)
+            && self.slot_author(digest.slot) == Some(digest.authority)
+            && digest.verify(header)
     }
 
+    /// Seal in the current slot, if it is ours and comes after the parent's.
     fn seal(
         &self,
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 6")
+        let slot = self.clock.current_slot();
+        if slot <= parent_digest.slot || !self.claims_slot(slot) {
+            return None;
+        }
+        let signer = self.signer.as_ref()?;
+        let digest = SlotDigest::sign(slot, signer, &partial_header);
+        Some(partial_header.with_digest(digest))
+    }
+}
+
+impl<K: SlotClock> SlotConsensus for PoaRoundRobinBySlot<K> {
+    type Clock = K;
+
+    fn clock(&self) -> &K {
+        &self.clock
+    }
+
+    fn slot(digest: &SlotDigest) -> u64 {
+        digest.slot
     }
+
+    fn claims_slot(&self, slot: u64) -> bool {
+        self.signer
+            .as_ref()
+            .is_some_and(|signer| self.slot_author(slot) == Some(AccountId::from(signer)))
+    }
+}
+
+#[test]
+fn slot_digest_round_trips_through_codec() {
+    let digest = SlotDigest {
+        slot: 1_000_000,
+        authority: Keyring::Charlie.to_account_id(),
+        signature: Signature([7; 64]),
+    };
+
+    assert_eq!(SlotDigest::decode_all(&digest.encode()), Ok(digest));
+}
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: crate::Hash::default(),
+        height,
+        state_root: crate::hash(&1u64),
+        extrinsics_root: crate::hash(&2u64),
+        author: AccountId::default(),
+        consensus_digest: (),
+    }
+}
+
+#[cfg(test)]
+fn alice_and_bob() -> Vec<AccountId> {
+    vec![Keyring::Alice.to_account_id(), Keyring::Bob.to_account_id()]
+}
+
+#[test]
+fn simple_poa_accepts_any_authority_in_the_set() {
+    let engine = SimplePoa {
+        authorities: alice_and_bob(),
+        signer: Some(Keyring::Bob.pair().clone()),
+    };
+    let parent = AuthoritySeal::default();
+    let sealed = engine.seal(&parent, partial_header(1)).unwrap();
+    let partial = partial_header(1);
+    let by_alice = partial
+        .clone()
+        .with_digest(AuthoritySeal::sign(Keyring::Alice.pair(), &partial));
+    let by_charlie = partial
+        .clone()
+        .with_digest(AuthoritySeal::sign(Keyring::Charlie.pair(), &partial));
+
+    assert!(engine.validate(&parent, &sealed));
+    assert!(engine.validate(&parent, &by_alice));
+    assert!(!engine.validate(&parent, &by_charlie));
+}
+
+#[test]
+fn simple_poa_only_authorities_can_seal() {
+    let engine = SimplePoa {
+        authorities: alice_and_bob(),
+        signer: Some(Keyring::Charlie.pair().clone()),
+    };
+
+    assert_eq!(
+        engine.seal(&AuthoritySeal::default(), partial_header(1)),
+        None
+    );
+}
+
+#[test]
+fn simple_poa_rejects_forged_seal() {
+    let engine = SimplePoa {
+        authorities: alice_and_bob(),
+        signer: Some(Keyring::Alice.pair().clone()),
+    };
+    let parent = AuthoritySeal::default();
+    let forged = partial_header(1).with_digest(AuthoritySeal {
+        authority: Keyring::Alice.to_account_id(),
+        signature: Signature::default(),
+    });
+    let mut moved = engine.seal(&parent, partial_header(1)).unwrap();
+    moved.height = 2;
+
+    assert!(!engine.validate(&parent, &forged));
+    assert!(!engine.validate(&parent, &moved));
+}
+
+#[test]
+fn round_robin_by_height_takes_turns() {
+    let engine_for = |signer: Keyring| PoaRoundRobinByHeight {
+        authorities: alice_and_bob(),
+        signer: Some(signer.pair().clone()),
+    };
+    let (alice, bob) = (engine_for(Keyring::Alice), engine_for(Keyring::Bob));
+    let parent = AuthoritySeal::default();
+
+    for (height, author, other) in [(1, &alice, &bob), (2, &bob, &alice), (3, &alice, &bob)] {
+        let header = author.seal(&parent, partial_header(height)).unwrap();
+        assert!(other.validate(&parent, &header));
+        assert_eq!(other.seal(&parent, partial_header(height)), None);
+    }
+
+    let partial = partial_header(2);
+    let out_of_turn = partial
+        .clone()
+        .with_digest(AuthoritySeal::sign(Keyring::Alice.pair(), &partial));
+    assert!(!bob.validate(&parent, &out_of_turn));
+}
+
+#[cfg(test)]
+fn slot_engine(signer: Option<Keyring>, clock: &ManualSlotClock) -> PoaRoundRobinBySlot<ManualSlotClock> {
+    PoaRoundRobinBySlot {
+        authorities: alice_and_bob(),
+        signer: signer.map(|signer| signer.pair().clone()),
+        clock: clock.clone(),
+        allowed_drift: 1,
+    }
+}
+
+#[test]
+fn round_robin_by_slot_seals_only_in_own_slot() {
+    let clock = ManualSlotClock::new(1);
+    let engine = slot_engine(Some(Keyring::Alice), &clock);
+    let parent = SlotDigest::default();
+
+    // Slot 1 is Bob's, so Alice has to wait for slot 2.
+    assert!(!engine.claims_slot(1));
+    assert_eq!(engine.seal(&parent, partial_header(1)), None);
+    clock.advance(1);
+    let sealed = engine.seal(&parent, partial_header(1)).unwrap();
+    assert_eq!(sealed.consensus_digest.slot, 2);
+    assert!(engine.validate(&parent, &sealed));
+
+    // A child cannot go in the same slot as its parent.
+    assert_eq!(engine.seal(&sealed.consensus_digest, partial_header(2)), None);
+}
+
+#[test]
+fn round_robin_by_slot_allows_skipped_slots() {
+    let clock = ManualSlotClock::new(3);
+    let engine = slot_engine(None, &clock);
+    let parent = SlotDigest::default();
+
+    let partial = partial_header(1);
+    let skipped = partial
+        .clone()
+        .with_digest(SlotDigest::sign(3, Keyring::Bob.pair(), &partial));
+    assert!(engine.validate(&parent, &skipped));
+}
+
+#[test]
+fn round_robin_by_slot_rejects_wrong_author_or_stale_slot() {
+    let engine = slot_engine(None, &ManualSlotClock::new(10));
+    let partial = partial_header(1);
+    let wrong_author =
+        partial
+            .clone()
+            .with_digest(SlotDigest::sign(2, Keyring::Bob.pair(), &partial));
+    let stale = partial
+        .clone()
+        .with_digest(SlotDigest::sign(4, Keyring::Alice.pair(), &partial));
+    let mut moved = stale.clone();
+    moved.consensus_digest.slot = 6;
+    let slot_four = SlotDigest {
+        slot: 4,
+        ..Default::default()
+    };
+
+    assert!(!engine.validate(&SlotDigest::default(), &wrong_author));
+    assert!(!engine.validate(&slot_four, &stale));
+    assert!(!engine.validate(&SlotDigest::default(), &moved));
+}
+
+#[test]
+fn round_robin_by_slot_rejects_blocks_from_the_future() {
+    let clock = ManualSlotClock::new(2);
+    let engine = slot_engine(None, &clock);
+    let partial = partial_header(1);
+    let early = partial
+        .clone()
+        .with_digest(SlotDigest::sign(3, Keyring::Bob.pair(), &partial));
+    let too_early = partial
+        .clone()
+        .with_digest(SlotDigest::sign(4, Keyring::Alice.pair(), &partial));
+
+    // One slot of drift is allowed.
+    assert!(engine.validate(&SlotDigest::default(), &early));
+    assert!(!engine.validate(&SlotDigest::default(), &too_early));
+    clock.advance(1);
+    assert!(engine.validate(&SlotDigest::default(), &too_early));
 }
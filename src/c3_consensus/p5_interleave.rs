@@ -8,14 +8,40 @@
 /// Odd blocks are PoW
 /// Even blocks are PoA
 struct AlternatingPowPoa;
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{AuthoritySeal, Consensus, Header};
+use crate::codec::{Decode, DecodeError, Encode};
 
 /// In order to implement a consensus that can be sealed with either work or a signature,
 /// we will need an enum that wraps the two individual digest types.
 #[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
 enum PowOrPoaDigest {
     Pow(u64),
-    Poa(ConsensusAuthority),
+    Poa(AuthoritySeal),
+}
+
+impl Encode for PowOrPoaDigest {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            PowOrPoaDigest::Pow(nonce) => {
+                0u8.encode_to(dest);
+                nonce.encode_to(dest);
+            }
+            PowOrPoaDigest::Poa(seal) => {
+                1u8.encode_to(dest);
+                seal.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl Decode for PowOrPoaDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(PowOrPoaDigest::Pow(u64::decode(input)?)),
+            1 => Ok(PowOrPoaDigest::Poa(AuthoritySeal::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
 }
 
 impl From<u64> for PowOrPoaDigest {
@@ -32,13 +58,13 @@ impl TryFrom<PowOrPoaDigest> for u64 {
     }
 }
 
-impl From<ConsensusAuthority> for PowOrPoaDigest {
-    fn from(_: ConsensusAuthority) -> Self {
+impl From<AuthoritySeal> for PowOrPoaDigest {
+    fn from(_: AuthoritySeal) -> Self {
         todo!("Exercise 3")
     }
 }
 
-impl TryFrom<PowOrPoaDigest> for ConsensusAuthority {
+impl TryFrom<PowOrPoaDigest> for AuthoritySeal {
     type Error = ();
 
     fn try_from(_: PowOrPoaDigest) -> Result<Self, Self::Error> {
@@ -61,3 +87,14 @@ impl Consensus for AlternatingPowPoa {
         todo!("Exercise 6")
     }
 }
+
+#[test]
+fn pow_or_poa_digest_round_trips_through_codec() {
+    for digest in [
+        PowOrPoaDigest::Pow(42),
+        PowOrPoaDigest::Poa(AuthoritySeal::default()),
+    ] {
+        assert_eq!(PowOrPoaDigest::decode_all(&digest.encode()), Ok(digest));
+    }
+    assert_eq!(PowOrPoaDigest::decode_all(&[2]), Err(DecodeError::InvalidTag(2)));
+}
@@ -6,9 +6,15 @@
 //! be enforced before or after the fork, but rather delegates to existing consensus engines
 //! for that. Here we simply write the logic for detecting whether we are before or after the fork.
 
+// The unfinished exercises below return `todo!()` from functions returning `impl Consensus`,
+// which leaves the compiler to pick a concrete engine type on its own.
+#![allow(dependency_on_unit_never_type_fallback)]
+
 use std::marker::PhantomData;
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{Consensus, Header};
+use crate::AccountId;
+use crate::codec::{Decode, Encode};
 
 /// A Higher-order consensus engine that represents a change from one set of consensus rules (Before) to
 /// another set (After) at a specific block height
@@ -20,7 +26,7 @@ struct Forked<D, Before, After> {
 
 impl<D, B, A> Consensus for Forked<D, B, A>
 where
-    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash,
+    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash + Encode + Decode,
     B: Consensus,
     A: Consensus,
     B::Digest: Into<D>,
@@ -45,8 +51,8 @@ where
 /// Given the initial authorities, the authorities after the fork, and the height at which the fork occurs.
 fn change_authorities(
     fork_height: u64,
-    initial_authorities: Vec<ConsensusAuthority>,
-    final_authorities: Vec<ConsensusAuthority>,
+    initial_authorities: Vec<AccountId>,
+    final_authorities: Vec<AccountId>,
 ) -> impl Consensus {
     todo!("Exercise 3")
 }
@@ -80,7 +86,7 @@ fn even_after_given_height<Original: Consensus>(fork_height: u64) -> impl Consen
 fn pow_to_poa(
     fork_height: u64,
     difficulty: u64,
-    authorities: Vec<ConsensusAuthority>,
+    authorities: Vec<AccountId>,
 ) -> impl Consensus {
     todo!("Exercise 6")
 }
@@ -0,0 +1,325 @@
+//! The proof of work engine we wrote at the start of this chapter has a fixed threshold. On a
+//! real network, miners come and go, and the total hash rate changes by orders of magnitude
+//! over the life of a chain. With a fixed threshold, blocks would come faster and faster as
+//! hardware improves.
+//!
+//! Nakamoto's answer is to let the chain measure its own speed. Every block records when it
+//! was mined and the target it was mined against. Every `retarget_interval` blocks, the target
+//! is scaled by how long the last window of blocks actually took, compared to how long it
+//! should have taken. The adjustment is clamped so that a few bad timestamps cannot swing the
+//! difficulty wildly.
+//!
+//! Everything a validator needs is in the parent's digest: the target, the parent's timestamp,
+//! and the timestamp of the first block in the current window.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Consensus, Header, Miner, ProofOfWork};
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    hash, Hash,
+};
+
+/// The consensus digest of a block mined under [`RetargetingPow`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PowDigest {
+    /// When the block was mined, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The block's hash must be below this.
+    pub target: Hash,
+    /// The timestamp of the first block in the current retarget window.
+    pub window_start: u64,
+    /// Varied by the miner until the block's hash is below the target.
+    pub nonce: u64,
+}
+
+impl Encode for PowDigest {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.timestamp.encode_to(dest);
+        self.target.encode_to(dest);
+        self.window_start.encode_to(dest);
+        self.nonce.encode_to(dest);
+    }
+}
+
+impl Decode for PowDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(PowDigest {
+            timestamp: u64::decode(input)?,
+            target: Hash::decode(input)?,
+            window_start: u64::decode(input)?,
+            nonce: u64::decode(input)?,
+        })
+    }
+}
+
+/// The parameters of the difficulty adjustment. They are fixed for the life of a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetargetConfig {
+    /// The target of the first block after genesis.
+    pub initial_target: Hash,
+    /// The number of blocks in each retarget window. Must be at least two, so that a window
+    /// spans some time.
+    pub retarget_interval: u64,
+    /// The desired number of seconds between blocks.
+    pub block_time: u64,
+    /// The most the target may grow or shrink by, as a factor, in one retarget.
+    pub max_adjustment: u64,
+    /// How many seconds ahead of the validator's clock a timestamp may be.
+    pub max_future_drift: u64,
+}
+
+/// The current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// A proof of work engine whose target adjusts to keep blocks coming at a steady rate.
+#[derive(Clone)]
+pub struct RetargetingPow {
+    config: RetargetConfig,
+    /// Where the engine gets the time from, for stamping new blocks and rejecting blocks
+    /// from the future.
+    clock: fn() -> u64,
+}
+
+impl RetargetingPow {
+    pub fn new(config: RetargetConfig) -> Self {
+        Self::with_clock(config, unix_time)
+    }
+
+    /// An engine that reads the time from the given clock instead of the system clock.
+    pub fn with_clock(config: RetargetConfig, clock: fn() -> u64) -> Self {
+        assert!(
+            config.retarget_interval >= 2,
+            "a retarget window needs at least two blocks"
+        );
+        assert!(
+            config.block_time >= 1,
+            "the block time must be at least one second"
+        );
+        assert!(
+            config.max_adjustment >= 1,
+            "the adjustment factor must be at least one"
+        );
+        RetargetingPow { config, clock }
+    }
+
+    /// Whether the block at the given height is the first of a retarget window.
+    fn starts_window(&self, height: u64) -> bool {
+        (height - 1).is_multiple_of(self.config.retarget_interval)
+    }
+
+    /// The target that the block at the given height must be mined against, given its
+    /// parent's digest.
+    pub fn next_target(&self, parent_digest: &PowDigest, height: u64) -> Hash {
+        if height <= 1 {
+            return self.config.initial_target;
+        }
+        if !self.starts_window(height) {
+            return parent_digest.target;
+        }
+        // The parent ended the last window. A window of n blocks spans n - 1 block times.
+        let expected = (self.config.retarget_interval - 1) * self.config.block_time;
+        let actual = parent_digest
+            .timestamp
+            .saturating_sub(parent_digest.window_start);
+        let max = self.config.max_adjustment;
+        let (numerator, denominator) = if actual.saturating_mul(max) < expected {
+            (1, max)
+        } else if actual > expected.saturating_mul(max) {
+            (max, 1)
+        } else {
+            (actual, expected)
+        };
+        parent_digest.target.scaled(numerator, denominator)
+    }
+
+    /// The window start that the block at the given height must record.
+    fn window_start(&self, parent_digest: &PowDigest, height: u64, timestamp: u64) -> u64 {
+        if self.starts_window(height) {
+            timestamp
+        } else {
+            parent_digest.window_start
+        }
+    }
+}
+
+impl Consensus for RetargetingPow {
+    type Digest = PowDigest;
+
+    /// Check that the header's timestamp moves forward and is not in the future, that its
+    /// target and window start follow from the parent's digest, and that its hash is below
+    /// its target.
+    fn validate(&self, parent_digest: &PowDigest, header: &Header<PowDigest>) -> bool {
+        let digest = &header.consensus_digest;
+        if header.height == 0 {
+            return false;
+        }
+        digest.timestamp > parent_digest.timestamp
+            && digest.timestamp <= (self.clock)().saturating_add(self.config.max_future_drift)
+            && digest.target == self.next_target(parent_digest, header.height)
+            && digest.window_start
+                == self.window_start(parent_digest, header.height, digest.timestamp)
+            && self.meets_target(header)
+    }
+
+    /// Stamp the header with the current time, or just after the parent's if the clock is
+    /// behind, and mine it against the retargeted threshold.
+    fn seal(
+        &self,
+        parent_digest: &PowDigest,
+        partial_header: Header<()>,
+    ) -> Option<Header<PowDigest>> {
+        Miner::new(1).mine(self, parent_digest, partial_header)
+    }
+
+    fn human_name() -> String {
+        "Retargeting Proof of Work".into()
+    }
+}
+
+impl ProofOfWork for RetargetingPow {
+    /// Fill in everything but the nonce, as described on [`RetargetingPow::seal`].
+    fn prepare(
+        &self,
+        parent_digest: &PowDigest,
+        partial_header: Header<()>,
+    ) -> Option<Header<PowDigest>> {
+        let height = partial_header.height;
+        if height == 0 {
+            return None;
+        }
+        let timestamp = (self.clock)().max(parent_digest.timestamp + 1);
+        Some(partial_header.with_digest(PowDigest {
+            timestamp,
+            target: self.next_target(parent_digest, height),
+            window_start: self.window_start(parent_digest, height, timestamp),
+            nonce: 0,
+        }))
+    }
+
+    fn set_nonce(header: &mut Header<PowDigest>, nonce: u64) {
+        header.consensus_digest.nonce = nonce;
+    }
+
+    fn meets_target(&self, header: &Header<PowDigest>) -> bool {
+        hash(header) < header.consensus_digest.target
+    }
+}
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: Hash::default(),
+        height,
+        state_root: hash(&1u64),
+        extrinsics_root: hash(&2u64),
+        author: crate::AccountId::default(),
+        consensus_digest: (),
+    }
+}
+
+/// Windows of four blocks, ten seconds apart, with an easy initial target.
+#[cfg(test)]
+fn test_config() -> RetargetConfig {
+    RetargetConfig {
+        initial_target: Hash::MAX.divided_by(4),
+        retarget_interval: 4,
+        block_time: 10,
+        max_adjustment: 4,
+        max_future_drift: 60,
+    }
+}
+
+#[test]
+fn pow_digest_round_trips_through_codec() {
+    let digest = PowDigest {
+        timestamp: 1_000,
+        target: Hash::MAX.divided_by(7),
+        window_start: 970,
+        nonce: 42,
+    };
+
+    assert_eq!(PowDigest::decode_all(&digest.encode()), Ok(digest));
+}
+
+#[test]
+fn retargeting_pow_seals_a_valid_chain() {
+    let engine = RetargetingPow::with_clock(test_config(), || 1_000);
+    let mut parent = PowDigest::default();
+    for height in 1..=9 {
+        let header = engine.seal(&parent, partial_header(height)).unwrap();
+
+        assert!(engine.validate(&parent, &header));
+        assert!(header.consensus_digest.timestamp > parent.timestamp);
+        parent = header.consensus_digest;
+    }
+}
+
+#[test]
+fn retargeting_pow_adjusts_target_to_block_times() {
+    let engine = RetargetingPow::new(test_config());
+    let target = Hash::MAX.divided_by(1_000);
+    // The parent ends a window whose four blocks should have spanned thirty seconds.
+    let ended_window = |seconds: u64| PowDigest {
+        timestamp: 100 + seconds,
+        target,
+        window_start: 100,
+        nonce: 0,
+    };
+
+    assert_eq!(engine.next_target(&ended_window(30), 5), target);
+    assert_eq!(
+        engine.next_target(&ended_window(15), 5),
+        target.scaled(1, 2)
+    );
+    assert_eq!(
+        engine.next_target(&ended_window(60), 5),
+        target.scaled(2, 1)
+    );
+    // Adjustments are clamped to a factor of four either way.
+    assert_eq!(engine.next_target(&ended_window(0), 5), target.scaled(1, 4));
+    assert_eq!(
+        engine.next_target(&ended_window(1_000), 5),
+        target.scaled(4, 1)
+    );
+    // Within a window the target does not change.
+    assert_eq!(engine.next_target(&ended_window(1_000), 4), target);
+    assert_eq!(
+        engine.next_target(&ended_window(1_000), 1),
+        test_config().initial_target
+    );
+}
+
+#[test]
+fn retargeting_pow_rejects_bad_digests() {
+    let engine = RetargetingPow::with_clock(test_config(), || 1_000);
+    let parent = engine
+        .seal(&PowDigest::default(), partial_header(1))
+        .unwrap()
+        .consensus_digest;
+    let valid = engine.seal(&parent, partial_header(2)).unwrap();
+    assert!(engine.validate(&parent, &valid));
+
+    // Tampering with any part of the digest is caught, whether or not the work still happens
+    // to be below the target.
+    let mut easier = valid.clone();
+    easier.consensus_digest.target = Hash::MAX;
+    assert!(!engine.validate(&parent, &easier));
+
+    let mut stale = valid.clone();
+    stale.consensus_digest.timestamp = parent.timestamp;
+    assert!(!engine.validate(&parent, &stale));
+
+    let mut moved_window = valid.clone();
+    moved_window.consensus_digest.window_start += 1;
+    assert!(!engine.validate(&parent, &moved_window));
+
+    let future_engine = RetargetingPow::with_clock(test_config(), || 5_000);
+    let from_the_future = future_engine.seal(&parent, partial_header(2)).unwrap();
+    assert!(!engine.validate(&parent, &from_the_future));
+    assert!(future_engine.validate(&parent, &from_the_future));
+}
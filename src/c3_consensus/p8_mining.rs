@@ -0,0 +1,315 @@
+//! Sealing a proof of work block means trying nonces until one of them happens to give a hash
+//! below the target. Each attempt is independent of the others, so the search splits neatly
+//! across threads: with `n` workers, worker `i` tries nonces `i`, `i + n`, `i + 2n`, and so on.
+//! The first worker to find a solution tells the others to stop.
+//!
+//! A miner on a live network also needs to give up. When a better block arrives from a peer,
+//! the block being mined no longer extends the best chain, and every further hash spent on it
+//! is wasted. So mining can run in the background as a [`MiningJob`] that can be cancelled at
+//! any time, and that reports how fast it is hashing.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use super::{Consensus, Header};
+
+/// How many nonces a worker tries between checking whether it should stop. Checking after
+/// every hash would have the workers fighting over the shared counters.
+const BATCH_SIZE: u64 = 256;
+
+/// A consensus engine whose seal is found by searching for a nonce.
+pub trait ProofOfWork: Consensus {
+    /// Attach a digest to the partial header with everything but the nonce filled in. Returns
+    /// None if no valid digest can be built on the given parent.
+    fn prepare(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>>;
+
+    /// Set the nonce in a prepared header.
+    fn set_nonce(header: &mut Header<Self::Digest>, nonce: u64);
+
+    /// Whether the header's hash is below the target it must meet.
+    fn meets_target(&self, header: &Header<Self::Digest>) -> bool;
+}
+
+/// Searches for proof of work seals on a fixed number of threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Miner {
+    threads: usize,
+}
+
+impl Miner {
+    /// A miner with the given number of worker threads.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads >= 1, "a miner needs at least one thread");
+        Miner { threads }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Seal the partial header, blocking until a solution is found. Returns None if the
+    /// engine cannot build a digest on this parent, or if every nonce fails.
+    ///
+    /// A single-threaded miner searches on the calling thread.
+    pub fn mine<C>(
+        &self,
+        engine: &C,
+        parent_digest: &C::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<C::Digest>>
+    where
+        C: ProofOfWork + Sync,
+        C::Digest: Send,
+    {
+        let header = engine.prepare(parent_digest, partial_header)?;
+        let stop = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        if self.threads == 1 {
+            return search(engine, header, 0, 1, &stop, &hashes);
+        }
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads as u64)
+                .map(|first| {
+                    let header = header.clone();
+                    let (stop, hashes) = (&stop, &hashes);
+                    scope.spawn(move || {
+                        search(engine, header, first, self.threads as u64, stop, hashes)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining workers do not panic"))
+                .next()
+        })
+    }
+
+    /// Start sealing the partial header in the background. Returns None if the engine cannot
+    /// build a digest on this parent.
+    pub fn start<C>(
+        &self,
+        engine: Arc<C>,
+        parent_digest: &C::Digest,
+        partial_header: Header<()>,
+    ) -> Option<MiningJob<C::Digest>>
+    where
+        C: ProofOfWork + Send + Sync + 'static,
+        C::Digest: Send + 'static,
+    {
+        let header = engine.prepare(parent_digest, partial_header)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let hashes = Arc::new(AtomicU64::new(0));
+        let (sender, found) = mpsc::channel();
+        let workers = (0..self.threads as u64)
+            .map(|first| {
+                let (engine, header, sender) = (engine.clone(), header.clone(), sender.clone());
+                let (stop, hashes) = (stop.clone(), hashes.clone());
+                let step = self.threads as u64;
+                thread::spawn(move || {
+                    if let Some(sealed) = search(&*engine, header, first, step, &stop, &hashes) {
+                        // The job may have been dropped already, in which case nobody wants it.
+                        let _ = sender.send(sealed);
+                    }
+                })
+            })
+            .collect();
+        Some(MiningJob {
+            stop,
+            hashes,
+            started: Instant::now(),
+            found,
+            workers,
+        })
+    }
+}
+
+/// A miner with one thread per available core.
+impl Default for Miner {
+    fn default() -> Self {
+        Miner::new(thread::available_parallelism().map_or(1, |threads| threads.get()))
+    }
+}
+
+/// Try the nonces `first`, `first + step`, `first + 2 * step`, and so on, until one meets the
+/// target, the nonces run out, or another worker raises the stop flag.
+fn search<C: ProofOfWork>(
+    engine: &C,
+    mut header: Header<C::Digest>,
+    first: u64,
+    step: u64,
+    stop: &AtomicBool,
+    hashes: &AtomicU64,
+) -> Option<Header<C::Digest>> {
+    let mut nonce = Some(first);
+    let mut tried = 0;
+    while let Some(current) = nonce {
+        if tried == BATCH_SIZE {
+            hashes.fetch_add(tried, Ordering::Relaxed);
+            tried = 0;
+            if stop.load(Ordering::Relaxed) {
+                return None;
+            }
+        }
+        C::set_nonce(&mut header, current);
+        tried += 1;
+        if engine.meets_target(&header) {
+            hashes.fetch_add(tried, Ordering::Relaxed);
+            stop.store(true, Ordering::Relaxed);
+            return Some(header);
+        }
+        nonce = current.checked_add(step);
+    }
+    hashes.fetch_add(tried, Ordering::Relaxed);
+    None
+}
+
+/// A seal being searched for in the background. Dropping the job cancels it.
+pub struct MiningJob<Digest> {
+    /// Raised to tell the workers to stop.
+    stop: Arc<AtomicBool>,
+    /// The number of hashes the workers have computed so far.
+    hashes: Arc<AtomicU64>,
+    started: Instant,
+    /// Where a worker sends the sealed header when it finds one.
+    found: Receiver<Header<Digest>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<Digest> MiningJob<Digest> {
+    /// Tell the workers to stop. They notice within a few hundred hashes.
+    pub fn cancel(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether every worker has stopped, because a seal was found, the job was cancelled, or
+    /// the nonces ran out.
+    pub fn is_finished(&self) -> bool {
+        self.workers.iter().all(JoinHandle::is_finished)
+    }
+
+    /// The sealed header, if a worker has found one. Does not block.
+    pub fn try_result(&self) -> Option<Header<Digest>> {
+        self.found.try_recv().ok()
+    }
+
+    /// Block until a seal is found or the job stops without one.
+    pub fn wait(self) -> Option<Header<Digest>> {
+        self.found.recv().ok()
+    }
+
+    /// The number of hashes computed so far, across all workers.
+    pub fn hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    /// The average number of hashes per second since the job started.
+    pub fn hash_rate(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.hashes() as f64 / elapsed
+    }
+}
+
+impl<Digest> Drop for MiningJob<Digest> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+use super::{
+    p7_difficulty_adjustment::{PowDigest, RetargetConfig, RetargetingPow},
+    Pow,
+};
+#[cfg(test)]
+use crate::{hash, Hash};
+
+#[cfg(test)]
+fn partial_header() -> Header<()> {
+    Header {
+        parent: Hash::default(),
+        height: 1,
+        state_root: hash(&1u64),
+        extrinsics_root: hash(&2u64),
+        author: crate::AccountId::default(),
+        consensus_digest: (),
+    }
+}
+
+/// An engine whose target no hash can meet, so that mining only ever stops when cancelled.
+#[cfg(test)]
+fn impossible_pow() -> Arc<RetargetingPow> {
+    let config = RetargetConfig {
+        initial_target: Hash::default(),
+        retarget_interval: 4,
+        block_time: 10,
+        max_adjustment: 4,
+        max_future_drift: 60,
+    };
+    Arc::new(RetargetingPow::with_clock(config, || 1_000))
+}
+
+#[test]
+fn miner_finds_valid_seal_on_several_threads() {
+    let engine = Pow::new(Hash::MAX.divided_by(1_000));
+    for threads in [1, 4] {
+        let header = Miner::new(threads)
+            .mine(&engine, &0, partial_header())
+            .unwrap();
+
+        assert!(engine.validate(&0, &header));
+        assert_eq!(header.unsealed(), partial_header());
+    }
+}
+
+#[test]
+fn miner_runs_in_background_until_found() {
+    let engine = Arc::new(Pow::new(Hash::MAX.divided_by(1_000)));
+    let job = Miner::new(3)
+        .start(engine.clone(), &0, partial_header())
+        .unwrap();
+
+    let header = job.wait().unwrap();
+    assert!(engine.validate(&0, &header));
+}
+
+#[test]
+fn mining_job_can_be_cancelled() {
+    let job = Miner::new(2)
+        .start(impossible_pow(), &PowDigest::default(), partial_header())
+        .unwrap();
+    assert!(job.try_result().is_none());
+
+    job.cancel();
+    assert_eq!(job.wait(), None);
+}
+
+#[test]
+fn mining_job_reports_hash_rate() {
+    let job = Miner::new(2)
+        .start(impossible_pow(), &PowDigest::default(), partial_header())
+        .unwrap();
+    while job.hashes() < 10 * BATCH_SIZE {
+        thread::yield_now();
+    }
+
+    assert!(job.hash_rate() > 0.0);
+    assert!(!job.is_finished());
+    job.cancel();
+    while !job.is_finished() {
+        thread::yield_now();
+    }
+}
@@ -0,0 +1,139 @@
+//! Slot-based engines such as Aura and BABE divide time into fixed-length slots and give each
+//! slot to an author. For that to work, every node needs to agree, roughly, on what slot it is
+//! now. Real networks simply read the system clock and count slots since the Unix epoch.
+//! Clocks drift, so validators allow blocks to arrive a little early, but a block claiming a
+//! slot far in the future is rejected. Otherwise an author could claim every future slot at
+//! once.
+//!
+//! Reading the system clock in tests makes them slow and flaky, so the clock is a trait. Tests
+//! use a manual clock that only moves when told to.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::Consensus;
+
+/// Tells slot-based engines what slot it is.
+pub trait SlotClock {
+    /// The slot that is under way right now.
+    fn current_slot(&self) -> u64;
+
+    /// How long until the next slot begins.
+    fn time_until_next_slot(&self) -> Duration;
+}
+
+/// A consensus engine that gives each slot to an author.
+pub trait SlotConsensus: Consensus {
+    type Clock: SlotClock;
+
+    /// The clock this engine seals and validates against.
+    fn clock(&self) -> &Self::Clock;
+
+    /// The slot a header with the given digest was sealed in.
+    fn slot(digest: &Self::Digest) -> u64;
+
+    /// Whether this node may author a block in the given slot.
+    fn claims_slot(&self, slot: u64) -> bool;
+}
+
+/// A clock that reads the system time, with slots counted from the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SystemSlotClock {
+    slot_duration: Duration,
+}
+
+impl SystemSlotClock {
+    pub fn new(slot_duration: Duration) -> Self {
+        assert!(
+            !slot_duration.is_zero(),
+            "slots must have a positive duration"
+        );
+        SystemSlotClock { slot_duration }
+    }
+
+    /// The time since the Unix epoch, in whole slots and the time into the current slot.
+    fn since_epoch(&self) -> (u64, Duration) {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let slot = elapsed.as_nanos() / self.slot_duration.as_nanos();
+        let into_slot = elapsed.as_nanos() % self.slot_duration.as_nanos();
+        (slot as u64, Duration::from_nanos(into_slot as u64))
+    }
+}
+
+impl SlotClock for SystemSlotClock {
+    fn current_slot(&self) -> u64 {
+        self.since_epoch().0
+    }
+
+    fn time_until_next_slot(&self) -> Duration {
+        self.slot_duration - self.since_epoch().1
+    }
+}
+
+/// A clock that stays in the same slot until it is moved. Clones share the same slot, so a
+/// test can keep one copy and hand another to a consensus engine.
+#[derive(Clone, Debug, Default)]
+pub struct ManualSlotClock {
+    slot: Arc<AtomicU64>,
+}
+
+impl ManualSlotClock {
+    pub fn new(slot: u64) -> Self {
+        ManualSlotClock {
+            slot: Arc::new(AtomicU64::new(slot)),
+        }
+    }
+
+    pub fn set_slot(&self, slot: u64) {
+        self.slot.store(slot, Ordering::Relaxed);
+    }
+
+    /// Move the clock forward by the given number of slots.
+    pub fn advance(&self, slots: u64) {
+        self.slot.fetch_add(slots, Ordering::Relaxed);
+    }
+}
+
+impl SlotClock for ManualSlotClock {
+    fn current_slot(&self) -> u64 {
+        self.slot.load(Ordering::Relaxed)
+    }
+
+    /// The next slot only begins when the clock is moved, so there is never anything to wait
+    /// for.
+    fn time_until_next_slot(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[test]
+fn system_slot_clock_counts_slots_since_epoch() {
+    let clock = SystemSlotClock::new(Duration::from_secs(6));
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let slot = clock.current_slot();
+
+    assert!(slot == seconds / 6 || slot == seconds / 6 + 1);
+    assert!(clock.time_until_next_slot() <= Duration::from_secs(6));
+}
+
+#[test]
+fn manual_slot_clock_is_shared_between_clones() {
+    let clock = ManualSlotClock::new(5);
+    let handed_out = clock.clone();
+    clock.advance(2);
+    assert_eq!(handed_out.current_slot(), 7);
+
+    handed_out.set_slot(3);
+    assert_eq!(clock.current_slot(), 3);
+    assert_eq!(clock.time_until_next_slot(), Duration::ZERO);
+}
@@ -15,9 +15,18 @@
 use crate::{
     c1_state_machine::StateMachine,
     c3_consensus::{Consensus, Header},
+    AccountId, Hash,
 };
 use p1_data_structure::Block;
 use p3_fork_choice::ForkChoice;
+use p8_state_pruning::PruningMode;
+use p15_grandpa::Justification;
+use p16_reorgs::ChainEvent;
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    sync::mpsc::Sender,
+};
 
 mod p1_data_structure;
 mod p2_importing_blocks;
@@ -25,8 +34,23 @@ mod p3_fork_choice;
 mod p4_transaction_pool;
 mod p5_authoring_blocks;
 mod p6_finality;
+mod p7_block_store;
+mod p8_state_pruning;
+mod p9_mock_network;
+mod p10_tcp_network;
+mod p11_chain_sync;
+mod p12_light_client;
+mod p13_background_mining;
+mod p14_slot_authoring;
+mod p15_grandpa;
+mod p16_reorgs;
 
-type Hash = u64;
+// What it takes to run a node outside of this crate, as the `node` binary does.
+pub use p10_tcp_network::TcpNode;
+pub use p2_importing_blocks::ImportBlock;
+pub use p3_fork_choice::LongestChain;
+pub use p4_transaction_pool::{BoundedPool, PoolLimits, SimplePool};
+pub use p7_block_store::{FileStore, MemoryStore};
 
 /// A client represents one view of an evolving blockchain network. It knows of blocks,
 /// forks, state, and it also pools transactions waiting to be included in upcoming blocks.
@@ -37,31 +61,51 @@ type Hash = u64;
 /// * consensus system - It can use any consensus engine that implements our trait.
 /// * Fork Choice - It can use any fork choice we discussed and more. This is explored shortly.
 /// * Transaction Pool - It can use any logic for queueing and prioritizing incoming future transactions.
+/// * Block Store - It can keep its blocks in memory, on disk, or anywhere else that implements our trait.
 ///
 /// As you work through the sections in this chapter, you will add features to the client
 /// by implementing more and more methods on it.
 /// 
 /// In practice the trait bounds here will always be the same:
-/// C: Client
+/// C: Consensus
 /// SM: StateMachine
 /// FC: ForkChoice<C>
 /// P: TransactionPool<SM>
+/// S: BlockStore<C, SM>
 /// 
-/// But we leave them unconstrained here to avoid repeating many where clauses throughout the section.
+/// The client stores blocks and states, so the struct itself needs the first two. We leave the
+/// others unconstrained here to avoid repeating many where clauses throughout the section.
 /// Instead we bind them on impl blocks.
-pub struct FullClient<C, SM, FC, P>
+pub struct FullClient<C: Consensus, SM: StateMachine, FC, P, S = MemoryStore<C, SM>>
 {
     /// The consensus engine used by this client.
     consensus_engine: C,
-    /// The state machine used by this client.
-    state_machine: SM,
+    /// The state machine used by this client. State machines are only ever used through
+    /// their associated functions, so there is no value to store.
+    state_machine: PhantomData<SM>,
     /// The fork choice strategy used by this client.
     fork_choice: FC,
     /// The transaction pool used by this client.
     transaction_pool: P,
-
-    // TODO: You are free to add more fields here, and you will probably need to.
-    // Please document them as you add them.
+    /// Every block this client has imported.
+    blocks: S,
+    /// The state after executing each imported block, except those that have been pruned.
+    states: HashMap<Hash, SM::State>,
+    /// Which states to keep in memory. The rest are recomputed from the blocks when needed.
+    pruning: PruningMode,
+    /// The hashes of the imported blocks that have no imported children yet.
+    leaves: HashSet<Hash>,
+    /// The hash of the genesis block that this client was started with.
+    genesis: Hash,
+    /// The hash of the last block that was finalized. This starts out as the genesis block.
+    finalized: Hash,
+    /// The proofs of finality for the blocks that the voters finalized.
+    justifications: HashMap<Hash, Justification>,
+    /// The account that collects the fees and rewards for blocks this client authors.
+    author: AccountId,
+    /// Where to send a notification each time the best block changes.
+    subscribers: Vec<Sender<ChainEvent>>,
+    /// The best block's state with the pool's transactions that apply to it, against which
+    /// new transactions are checked. It is worked out again whenever the best block changes.
+    pending_state: Option<SM::State>,
 }
-
-//TODO Consider exploring LightClient as well. It may import headers but not blocks for example.
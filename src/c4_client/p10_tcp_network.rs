@@ -0,0 +1,529 @@
+//! The mock network let us watch clients cooperate inside one process. Now we connect real
+//! nodes over TCP, so that several of them can run side by side on one machine.
+//!
+//! The wire protocol is deliberately small. Every message is one frame: a four byte little
+//! endian length followed by the message in our canonical encoding. When two nodes connect,
+//! each sends a handshake naming its genesis block, and nodes on different chains hang up on
+//! each other. Then they tell each other about their best blocks. After that, new blocks are
+//! announced by hash, and fetched by whoever does not have them yet. Transactions are simply
+//! broadcast.
+//!
+//! A block whose parent is unknown is handed to the chain sync, which asks the sender for the
+//! missing ancestors. Peers that send invalid blocks are dropped.
+//!
+//! A node never blocks on the network. All of its sockets are non-blocking, and the node
+//! only does network work when it is polled.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use super::{
+    p11_chain_sync::ChainSync, p1_data_structure::BlockError, p2_importing_blocks::ImportBlock,
+    p4_transaction_pool::TransactionPool, p7_block_store::BlockStore, p9_mock_network::PeerId,
+    Block, Consensus, ForkChoice, FullClient, Hash, MemoryStore, StateMachine,
+};
+use crate::codec::{Decode, DecodeError, Encode};
+
+/// Frames larger than this are refused, so a peer cannot make us buffer without limit.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// The messages of the wire protocol.
+#[derive(Debug)]
+pub enum WireMessage<C: Consensus, SM: StateMachine> {
+    /// The first message on every connection, in both directions.
+    Handshake { genesis: Hash },
+    /// The sender's best block, sent right after the handshake.
+    Status { best: Hash, height: u64 },
+    /// The sender has a new block with this hash.
+    Announce(Hash),
+    /// Please send the block with this hash.
+    GetBlock(Hash),
+    /// A block, in response to a request.
+    Block(Block<C, SM>),
+    /// A transaction that the sender accepted into its pool.
+    Transaction(SM::Transition),
+}
+
+impl<C: Consensus, SM: StateMachine> Encode for WireMessage<C, SM>
+where
+    SM::Transition: Encode,
+{
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            WireMessage::Handshake { genesis } => {
+                0u8.encode_to(dest);
+                genesis.encode_to(dest);
+            }
+            WireMessage::Status { best, height } => {
+                1u8.encode_to(dest);
+                best.encode_to(dest);
+                height.encode_to(dest);
+            }
+            WireMessage::Announce(block_hash) => {
+                2u8.encode_to(dest);
+                block_hash.encode_to(dest);
+            }
+            WireMessage::GetBlock(block_hash) => {
+                3u8.encode_to(dest);
+                block_hash.encode_to(dest);
+            }
+            WireMessage::Block(block) => {
+                4u8.encode_to(dest);
+                block.encode_to(dest);
+            }
+            WireMessage::Transaction(t) => {
+                5u8.encode_to(dest);
+                t.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Decode for WireMessage<C, SM>
+where
+    SM::Transition: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(WireMessage::Handshake {
+                genesis: Hash::decode(input)?,
+            }),
+            1 => Ok(WireMessage::Status {
+                best: Hash::decode(input)?,
+                height: u64::decode(input)?,
+            }),
+            2 => Ok(WireMessage::Announce(Hash::decode(input)?)),
+            3 => Ok(WireMessage::GetBlock(Hash::decode(input)?)),
+            4 => Ok(WireMessage::Block(Block::decode(input)?)),
+            5 => Ok(WireMessage::Transaction(SM::Transition::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// One connection to another node.
+struct Peer {
+    id: PeerId,
+    stream: TcpStream,
+    /// Bytes received that do not make up a complete frame yet.
+    inbox: Vec<u8>,
+    /// Bytes waiting for the socket to accept them.
+    outbox: Vec<u8>,
+    /// Whether the peer's handshake has arrived and names our genesis block. Nothing else
+    /// is sent to or accepted from a peer until then.
+    ready: bool,
+    /// Set when the chain sync finds that this peer sent an invalid chain.
+    misbehaved: bool,
+}
+
+impl Peer {
+    fn new(id: PeerId, stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Peer {
+            id,
+            stream,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+            ready: false,
+            misbehaved: false,
+        })
+    }
+
+    fn send<M: Encode>(&mut self, message: &M) {
+        let payload = message.encode();
+        self.outbox.extend((payload.len() as u32).to_le_bytes());
+        self.outbox.extend(payload);
+    }
+
+    /// Write as much of the outbox as the socket takes without blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outbox.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read whatever the socket has for us, and split off the complete frames. Fails when
+    /// the connection is closed or the peer sends a frame that is too large.
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.inbox.extend(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let mut frames = Vec::new();
+        while let Some(len_bytes) = self.inbox.get(..4) {
+            let len = u32::from_le_bytes(len_bytes.try_into().expect("four bytes")) as usize;
+            if len > MAX_FRAME_LEN {
+                return Err(io::Error::new(ErrorKind::InvalidData, "frame is too large"));
+            }
+            if self.inbox.len() < 4 + len {
+                break;
+            }
+            frames.push(self.inbox[4..4 + len].to_vec());
+            self.inbox.drain(..4 + len);
+        }
+        Ok(frames)
+    }
+}
+
+/// A full client connected to other nodes over TCP.
+pub struct TcpNode<C: Consensus, SM: StateMachine, FC, P, S = MemoryStore<C, SM>> {
+    client: FullClient<C, SM, FC, P, S>,
+    listener: TcpListener,
+    peers: Vec<Peer>,
+    next_peer_id: PeerId,
+    sync: ChainSync<C, SM>,
+    /// The number of connections that were dropped, including new ones that failed to set up.
+    dropped: usize,
+}
+
+impl<C, SM, FC, P, S> TcpNode<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode + Decode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Start a node that listens for peers on the given address. Use port zero to let the
+    /// operating system pick a free port, and [`TcpNode::local_addr`] to find out which.
+    pub fn bind(client: FullClient<C, SM, FC, P, S>, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpNode {
+            client,
+            listener,
+            peers: Vec::new(),
+            next_peer_id: 0,
+            sync: ChainSync::default(),
+            dropped: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn client(&self) -> &FullClient<C, SM, FC, P, S> {
+        &self.client
+    }
+
+    pub fn sync(&self) -> &ChainSync<C, SM> {
+        &self.sync
+    }
+
+    /// The number of peers that have completed the handshake.
+    pub fn peer_count(&self) -> usize {
+        self.peers.iter().filter(|peer| peer.ready).count()
+    }
+
+    /// The number of connections this node has dropped so far, because they failed, broke the
+    /// protocol, or could not be set up in the first place.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped
+    }
+
+    /// Open a connection to another node. The handshake completes as both nodes are polled.
+    pub fn connect(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        self.add_peer(stream)
+    }
+
+    /// Submit a transaction to the client's pool, and broadcast it if the pool accepts it.
+    pub fn submit_transaction(&mut self, t: SM::Transition) -> bool {
+        let accepted = self.client.submit_transaction(t.clone());
+        if accepted {
+            self.broadcast(&WireMessage::Transaction(t), None);
+        }
+        accepted
+    }
+
+    /// Author a block from the pool on top of the best block, and announce it.
+    pub fn author_block(&mut self) -> Result<Hash, BlockError<SM::Error>> {
+        let block_hash = self.client.author_and_import_automatic_block()?;
+        self.broadcast(&WireMessage::Announce(block_hash), None);
+        Ok(block_hash)
+    }
+
+    /// Do all the network work that is possible without blocking: accept new connections,
+    /// handle every complete message that has arrived, and send what is waiting to be sent.
+    /// Peers whose connection fails or who break the protocol are dropped. So is a new
+    /// connection that cannot be set up, without holding up the other peers. Every dropped
+    /// connection is counted in [`TcpNode::dropped_count`].
+    ///
+    /// Fails only if the listening socket itself fails.
+    pub fn poll(&mut self) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if self.add_peer(stream).is_err() {
+                        self.dropped += 1;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut i = 0;
+        while i < self.peers.len() {
+            let alive = match self.peers[i].receive() {
+                Ok(frames) => frames.iter().all(|frame| self.handle(i, frame)),
+                Err(_) => false,
+            };
+            if alive && self.peers[i].flush().is_ok() {
+                i += 1;
+            } else {
+                self.peers.swap_remove(i);
+                self.dropped += 1;
+            }
+        }
+        let connected = self.peers.len();
+        self.peers.retain(|peer| !peer.misbehaved);
+        self.dropped += connected - self.peers.len();
+        Ok(())
+    }
+
+    fn add_peer(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut peer = Peer::new(self.next_peer_id, stream)?;
+        self.next_peer_id += 1;
+        peer.send(&WireMessage::<C, SM>::Handshake {
+            genesis: self.client.genesis(),
+        });
+        peer.flush()?;
+        self.peers.push(peer);
+        Ok(())
+    }
+
+    /// Handle one frame from the given peer. Returns false if the peer broke the protocol
+    /// and should be dropped.
+    fn handle(&mut self, from: usize, frame: &[u8]) -> bool {
+        let Ok(message) = WireMessage::<C, SM>::decode_all(frame) else {
+            return false;
+        };
+        if !self.peers[from].ready {
+            return match message {
+                WireMessage::Handshake { genesis } if genesis == self.client.genesis() => {
+                    let best = self.client.best_block();
+                    let height = self.client.get_block(best).map_or(0, |b| b.header().height);
+                    let peer = &mut self.peers[from];
+                    peer.ready = true;
+                    peer.send(&WireMessage::<C, SM>::Status { best, height });
+                    true
+                }
+                _ => false,
+            };
+        }
+        match message {
+            WireMessage::Handshake { .. } => return false,
+            WireMessage::Status { best, .. } | WireMessage::Announce(best) => {
+                if self.sync.is_bad(best) {
+                    return false;
+                }
+                if self.sync.should_request(&self.client, best) {
+                    self.peers[from].send(&WireMessage::<C, SM>::GetBlock(best));
+                }
+            }
+            WireMessage::GetBlock(block_hash) => {
+                if let Some(block) = self.client.get_block(block_hash) {
+                    self.peers[from].send(&WireMessage::Block(block));
+                }
+            }
+            WireMessage::Block(block) => {
+                let sender = self.peers[from].id;
+                let outcome = self.sync.block_received(&mut self.client, sender, block);
+                for block_hash in outcome.imported {
+                    self.broadcast(&WireMessage::Announce(block_hash), Some(from));
+                }
+                if let Some(missing) = outcome.request {
+                    self.peers[from].send(&WireMessage::<C, SM>::GetBlock(missing));
+                }
+                for peer in &mut self.peers {
+                    peer.misbehaved |= outcome.misbehaving.contains(&peer.id);
+                }
+                return !self.peers[from].misbehaved;
+            }
+            WireMessage::Transaction(t) => {
+                if self.client.submit_transaction(t.clone()) {
+                    self.broadcast(&WireMessage::Transaction(t), Some(from));
+                }
+            }
+        }
+        true
+    }
+
+    /// Queue a message for every ready peer, except the one it came from.
+    fn broadcast(&mut self, message: &WireMessage<C, SM>, except: Option<usize>) {
+        for (i, peer) in self.peers.iter_mut().enumerate() {
+            if peer.ready && Some(i) != except {
+                peer.send(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c4_client::{p3_fork_choice::LongestChain, p4_transaction_pool::SimplePool},
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerNode = TcpNode<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A node on a loopback port whose genesis ledger gives Alice the given balance.
+#[cfg(test)]
+fn ledger_node(author: Keyring, alice_balance: u64) -> LedgerNode {
+    let genesis_state = Ledger::from(Balances::from([(
+        Keyring::Alice.to_account_id(),
+        alice_balance,
+    )]));
+    let mut client = FullClient::new(
+        (),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap();
+    client.set_author(author.to_account_id());
+    TcpNode::bind(client, "127.0.0.1:0").unwrap()
+}
+
+/// Poll all the nodes until the condition holds. Returns false if it still does not hold
+/// after a few seconds.
+#[cfg(test)]
+fn poll_until(
+    nodes: &mut [&mut LedgerNode],
+    condition: impl Fn(&[&mut LedgerNode]) -> bool,
+) -> bool {
+    for _ in 0..1000 {
+        if condition(nodes) {
+            return true;
+        }
+        for node in nodes.iter_mut() {
+            node.poll().unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+    condition(nodes)
+}
+
+#[test]
+fn cl_10_handshake_checks_genesis() {
+    let mut a = ledger_node(Keyring::Alice, 100);
+    let mut b = ledger_node(Keyring::Bob, 100);
+    let mut other_chain = ledger_node(Keyring::Charlie, 50);
+    b.connect(a.local_addr().unwrap()).unwrap();
+    other_chain.connect(a.local_addr().unwrap()).unwrap();
+
+    assert!(poll_until(
+        &mut [&mut a, &mut b, &mut other_chain],
+        |nodes| {
+            nodes[0].peers.len() == 1 && nodes[1].peer_count() == 1 && nodes[2].peers.is_empty()
+        }
+    ));
+    assert_eq!(a.peer_count(), 1);
+    assert_eq!(other_chain.peer_count(), 0);
+    assert_eq!(a.dropped_count(), 1);
+    assert_eq!(b.dropped_count(), 0);
+    assert_eq!(other_chain.dropped_count(), 1);
+}
+
+#[test]
+fn cl_10_status_exchange_fetches_best_block() {
+    let mut a = ledger_node(Keyring::Alice, 100);
+    let mut b = ledger_node(Keyring::Bob, 100);
+    let b1 = a.author_block().unwrap();
+
+    b.connect(a.local_addr().unwrap()).unwrap();
+
+    assert!(poll_until(&mut [&mut a, &mut b], |nodes| nodes[1]
+        .client()
+        .best_block()
+        == b1));
+}
+
+#[test]
+fn cl_10_new_node_syncs_whole_chain() {
+    let mut a = ledger_node(Keyring::Alice, 100);
+    let mut b = ledger_node(Keyring::Bob, 100);
+    for _ in 0..5 {
+        a.author_block().unwrap();
+    }
+    let best = a.client().best_block();
+
+    b.connect(a.local_addr().unwrap()).unwrap();
+
+    assert!(poll_until(&mut [&mut a, &mut b], |nodes| nodes[1]
+        .client()
+        .best_block()
+        == best));
+    assert_eq!(b.sync().orphan_count(), 0);
+}
+
+#[test]
+fn cl_10_blocks_are_relayed() {
+    let mut a = ledger_node(Keyring::Alice, 100);
+    let mut b = ledger_node(Keyring::Bob, 100);
+    let mut c = ledger_node(Keyring::Charlie, 100);
+    b.connect(a.local_addr().unwrap()).unwrap();
+    c.connect(b.local_addr().unwrap()).unwrap();
+    assert!(poll_until(&mut [&mut a, &mut b, &mut c], |nodes| nodes[1]
+        .peer_count()
+        == 2
+        && nodes[2].peer_count() == 1));
+
+    // A and C are not connected, so the block has to go through B.
+    let b1 = a.author_block().unwrap();
+
+    assert!(poll_until(&mut [&mut a, &mut b, &mut c], |nodes| nodes[2]
+        .client()
+        .best_block()
+        == b1));
+}
+
+#[test]
+fn cl_10_transactions_are_broadcast() {
+    let mut a = ledger_node(Keyring::Alice, 100);
+    let mut b = ledger_node(Keyring::Bob, 100);
+    b.connect(a.local_addr().unwrap()).unwrap();
+    assert!(poll_until(&mut [&mut a, &mut b], |nodes| nodes[0]
+        .peer_count()
+        == 1
+        && nodes[1].peer_count() == 1));
+    let t =
+        AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 5, 0);
+
+    assert!(a.submit_transaction(t.clone()));
+
+    assert!(poll_until(&mut [&mut a, &mut b], |nodes| nodes[1]
+        .client()
+        .pool_contains(t.clone())));
+    let b1 = b.author_block().unwrap();
+    assert!(poll_until(&mut [&mut a, &mut b], |nodes| nodes[0]
+        .client()
+        .best_block()
+        == b1));
+    assert_eq!(a.client().pool_size(), 0);
+}
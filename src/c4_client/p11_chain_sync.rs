@@ -0,0 +1,503 @@
+//! A client can only import a block whose parent it already has. A node that joins the network
+//! late, or that missed a few announcements, hears about blocks far ahead of its own chain.
+//! In this section we teach it to catch up.
+//!
+//! Blocks whose parent is unknown are kept aside as orphans, and the missing parent is
+//! requested from the peer that sent them. Walking backwards like this eventually reaches a
+//! block the client knows, and then the whole chain of orphans is imported in order.
+//!
+//! Orphans cannot be checked until their ancestors arrive, so a malicious peer could send us
+//! any number of them. The orphan buffer is therefore limited, and the oldest orphans are
+//! forgotten when it fills up. A peer that sends a block that turns out to be invalid is
+//! reported as misbehaving. Blocks building on it are thrown away too, but the peers that
+//! passed those on are not blamed, since it was not their block that failed.
+//!
+//! The sync remembers bad blocks, so it can refuse them without executing them again. That
+//! memory is limited as well, and the blocks that were seen least recently are forgotten first.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    p1_data_structure::BlockError, p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore, p9_mock_network::PeerId, Block, Consensus, ForkChoice, FullClient,
+    Hash, StateMachine,
+};
+use crate::codec::Encode;
+
+/// How much memory the orphan buffer, and the memory of bad blocks, may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrphanLimits {
+    /// The most orphans kept at once.
+    pub max_blocks: usize,
+    /// The most encoded bytes of orphans kept at once.
+    pub max_bytes: usize,
+    /// The most bad blocks remembered at once.
+    pub max_bad_blocks: usize,
+}
+
+impl Default for OrphanLimits {
+    fn default() -> Self {
+        OrphanLimits {
+            max_blocks: 1024,
+            max_bytes: 16 * 1024 * 1024,
+            max_bad_blocks: 4096,
+        }
+    }
+}
+
+/// What came of handing a block to the sync.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncOutcome {
+    /// The blocks that were imported, parents before children. Besides the given block, this
+    /// includes any orphans that could be imported because of it.
+    pub imported: Vec<Hash>,
+    /// A missing ancestor that should be requested from the peer that sent the block.
+    pub request: Option<Hash>,
+    /// Peers that sent invalid blocks.
+    pub misbehaving: Vec<PeerId>,
+}
+
+/// A block waiting for its parent.
+struct Orphan<C: Consensus, SM: StateMachine> {
+    block: Block<C, SM>,
+    from: PeerId,
+    size: usize,
+    arrival: u64,
+}
+
+/// The hashes of bad blocks, up to a limit. When it is reached, the block that was least
+/// recently marked or looked up is forgotten.
+struct BadBlocks {
+    capacity: usize,
+    /// Whether each block is invalid itself, rather than only building on an invalid block,
+    /// and when it was last used.
+    blocks: HashMap<Hash, (bool, u64)>,
+    /// The blocks by when they were last used, least recent first.
+    uses: BTreeMap<u64, Hash>,
+    next_use: u64,
+}
+
+impl BadBlocks {
+    fn new(capacity: usize) -> Self {
+        BadBlocks {
+            capacity,
+            blocks: HashMap::new(),
+            uses: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    fn contains(&self, block_hash: Hash) -> bool {
+        self.blocks.contains_key(&block_hash)
+    }
+
+    /// Look up a block, counting this as a use. Returns whether the block is invalid itself,
+    /// or None if it is not known to be bad.
+    fn touch(&mut self, block_hash: Hash) -> Option<bool> {
+        let (invalid, last_use) = self.blocks.get_mut(&block_hash)?;
+        self.uses.remove(last_use);
+        *last_use = self.next_use;
+        self.uses.insert(self.next_use, block_hash);
+        self.next_use += 1;
+        Some(*invalid)
+    }
+
+    fn insert(&mut self, block_hash: Hash, invalid: bool) {
+        if self.touch(block_hash).is_some() {
+            return;
+        }
+        self.blocks.insert(block_hash, (invalid, self.next_use));
+        self.uses.insert(self.next_use, block_hash);
+        self.next_use += 1;
+        while self.blocks.len() > self.capacity {
+            let Some((_, least_recent)) = self.uses.pop_first() else {
+                break;
+            };
+            self.blocks.remove(&least_recent);
+        }
+    }
+}
+
+/// Fetches missing ancestors and imports them in order.
+pub struct ChainSync<C: Consensus, SM: StateMachine> {
+    limits: OrphanLimits,
+    orphans: HashMap<Hash, Orphan<C, SM>>,
+    /// The orphans waiting for each parent.
+    children: HashMap<Hash, Vec<Hash>>,
+    /// The orphans in the order they arrived, so the oldest can be evicted first.
+    arrivals: BTreeMap<u64, Hash>,
+    next_arrival: u64,
+    bytes: usize,
+    /// Blocks known to be invalid, and the blocks building on them.
+    bad_blocks: BadBlocks,
+}
+
+impl<C: Consensus, SM: StateMachine> Default for ChainSync<C, SM> {
+    fn default() -> Self {
+        Self::new(OrphanLimits::default())
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> ChainSync<C, SM> {
+    pub fn new(limits: OrphanLimits) -> Self {
+        ChainSync {
+            limits,
+            orphans: HashMap::new(),
+            children: HashMap::new(),
+            arrivals: BTreeMap::new(),
+            next_arrival: 0,
+            bytes: 0,
+            bad_blocks: BadBlocks::new(limits.max_bad_blocks),
+        }
+    }
+
+    /// The number of blocks waiting for their parents.
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// The encoded size of all the blocks waiting for their parents.
+    pub fn orphan_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn is_orphan(&self, block_hash: Hash) -> bool {
+        self.orphans.contains_key(&block_hash)
+    }
+
+    /// Whether the block is known to be invalid, or to build on an invalid block.
+    pub fn is_bad(&self, block_hash: Hash) -> bool {
+        self.bad_blocks.contains(block_hash)
+    }
+
+    fn remove_orphan(&mut self, block_hash: Hash) -> Option<Orphan<C, SM>> {
+        let orphan = self.orphans.remove(&block_hash)?;
+        self.arrivals.remove(&orphan.arrival);
+        self.bytes -= orphan.size;
+        let parent_hash = orphan.block.header().parent;
+        if let Some(siblings) = self.children.get_mut(&parent_hash) {
+            siblings.retain(|sibling| *sibling != block_hash);
+            if siblings.is_empty() {
+                self.children.remove(&parent_hash);
+            }
+        }
+        Some(orphan)
+    }
+
+    /// Mark an invalid block bad, and throw away every orphan that builds on it, marking
+    /// those bad as well.
+    fn reject(&mut self, block_hash: Hash) {
+        self.bad_blocks.insert(block_hash, true);
+        let mut queue = vec![block_hash];
+        while let Some(bad) = queue.pop() {
+            for child in self.children.get(&bad).cloned().unwrap_or_default() {
+                if self.remove_orphan(child).is_some() {
+                    self.bad_blocks.insert(child, false);
+                    queue.push(child);
+                }
+            }
+        }
+    }
+
+    /// Follow the orphans back from the given block to the first ancestor that has not
+    /// arrived yet.
+    fn missing_ancestor(&self, mut block_hash: Hash) -> Hash {
+        while let Some(orphan) = self.orphans.get(&block_hash) {
+            block_hash = orphan.block.header().parent;
+        }
+        block_hash
+    }
+}
+
+impl<C, SM> ChainSync<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+{
+    /// Whether a block that a peer told us about should be requested from it. That is the
+    /// case when we have never seen it.
+    pub fn should_request<FC, P, S>(
+        &self,
+        client: &FullClient<C, SM, FC, P, S>,
+        block_hash: Hash,
+    ) -> bool
+    where
+        FC: ForkChoice<C>,
+        P: TransactionPool<SM>,
+        S: BlockStore<C, SM>,
+    {
+        !client.blocks.contains(&block_hash)
+            && !self.is_orphan(block_hash)
+            && !self.is_bad(block_hash)
+    }
+
+    /// Handle a block that arrived from a peer.
+    ///
+    /// If its parent is known, the block is imported, followed by every orphan that descends
+    /// from it. Otherwise it becomes an orphan, and the outcome names the ancestor that should
+    /// be requested next.
+    ///
+    /// A block that the client fails to store is not held against it or its sender. It is
+    /// dropped, and may be fetched again later.
+    pub fn block_received<FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        from: PeerId,
+        block: Block<C, SM>,
+    ) -> SyncOutcome
+    where
+        FC: ForkChoice<C>,
+        P: TransactionPool<SM>,
+        S: BlockStore<C, SM>,
+    {
+        let mut outcome = SyncOutcome::default();
+        let block_hash = block.hash();
+        let parent_hash = block.header().parent;
+        if client.blocks.contains(&block_hash) || self.is_orphan(block_hash) {
+            return outcome;
+        }
+        if let Some(invalid) = self.bad_blocks.touch(block_hash) {
+            if invalid {
+                outcome.misbehaving.push(from);
+            }
+            return outcome;
+        }
+        if self.bad_blocks.touch(parent_hash).is_some() {
+            self.bad_blocks.insert(block_hash, false);
+            return outcome;
+        }
+
+        if !client.blocks.contains(&parent_hash) {
+            self.insert_orphan(block, from);
+            // The block may have been evicted straight away if it is too big to keep.
+            if self.is_orphan(block_hash) {
+                outcome.request = Some(self.missing_ancestor(parent_hash));
+            }
+            return outcome;
+        }
+
+        let mut ready = vec![(block, from)];
+        while let Some((block, from)) = ready.pop() {
+            let block_hash = block.hash();
+            match client.try_import_block(block) {
+                Ok(()) => {
+                    outcome.imported.push(block_hash);
+                    for child in self.children.get(&block_hash).cloned().unwrap_or_default() {
+                        if let Some(orphan) = self.remove_orphan(child) {
+                            ready.push((orphan.block, orphan.from));
+                        }
+                    }
+                }
+                // Our own store failed, so the block says nothing about its sender. Its
+                // orphaned descendants stay, waiting for it to be fetched again.
+                Err(BlockError::StoreFailed) => (),
+                Err(_) => {
+                    outcome.misbehaving.push(from);
+                    self.reject(block_hash);
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Keep a block until its parent arrives, evicting the oldest orphans if the limits are
+    /// exceeded.
+    fn insert_orphan(&mut self, block: Block<C, SM>, from: PeerId) {
+        let block_hash = block.hash();
+        let arrival = self.next_arrival;
+        self.next_arrival += 1;
+        let size = block.encode().len();
+        self.bytes += size;
+        self.children
+            .entry(block.header().parent)
+            .or_default()
+            .push(block_hash);
+        self.arrivals.insert(arrival, block_hash);
+        self.orphans.insert(
+            block_hash,
+            Orphan {
+                block,
+                from,
+                size,
+                arrival,
+            },
+        );
+
+        while self.orphans.len() > self.limits.max_blocks || self.bytes > self.limits.max_bytes {
+            let Some((_, &oldest)) = self.arrivals.first_key_value() else {
+                break;
+            };
+            self.remove_orphan(oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+#[cfg(test)]
+fn ledger_client() -> LedgerClient {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(
+        (),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap()
+}
+
+/// A client with a chain of the given length in which Alice pays Bob once per block. Returns
+/// the client and the blocks, genesis excluded.
+#[cfg(test)]
+fn ledger_chain(length: u64) -> (LedgerClient, Vec<Block<(), AccountedCurrency>>) {
+    let mut client = ledger_client();
+    let mut parent = client.genesis();
+    let mut blocks = Vec::new();
+    for nonce in 0..length {
+        let transfer = AccountingTransaction::transfer(
+            Keyring::Alice.pair(),
+            Keyring::Bob.to_account_id(),
+            1,
+            nonce,
+        );
+        parent = client
+            .author_and_import_manual_block(vec![transfer], parent)
+            .unwrap();
+        blocks.push(client.get_block(parent).unwrap());
+    }
+    (client, blocks)
+}
+
+#[test]
+fn cl_11_orphans_are_imported_once_ancestors_arrive() {
+    let (_, blocks) = ledger_chain(3);
+    let mut client = ledger_client();
+    let mut sync = ChainSync::default();
+
+    let outcome = sync.block_received(&mut client, 0, blocks[2].clone());
+    assert_eq!(outcome.request, Some(blocks[1].hash()));
+    let outcome = sync.block_received(&mut client, 0, blocks[1].clone());
+    assert_eq!(outcome.request, Some(blocks[0].hash()));
+    assert_eq!(sync.orphan_count(), 2);
+    assert!(!sync.should_request(&client, blocks[2].hash()));
+
+    let outcome = sync.block_received(&mut client, 0, blocks[0].clone());
+
+    let hashes: Vec<Hash> = blocks.iter().map(Block::hash).collect();
+    assert_eq!(
+        outcome,
+        SyncOutcome {
+            imported: hashes,
+            ..SyncOutcome::default()
+        }
+    );
+    assert_eq!(client.best_block(), blocks[2].hash());
+    assert_eq!(sync.orphan_count(), 0);
+    assert_eq!(sync.orphan_bytes(), 0);
+}
+
+#[test]
+fn cl_11_orphan_limits_evict_oldest() {
+    let (_, blocks) = ledger_chain(4);
+    let mut client = ledger_client();
+    let mut sync = ChainSync::new(OrphanLimits {
+        max_blocks: 2,
+        ..OrphanLimits::default()
+    });
+
+    for block in blocks[1..].iter().rev() {
+        sync.block_received(&mut client, 0, block.clone());
+    }
+    assert_eq!(sync.orphan_count(), 2);
+    assert!(!sync.is_orphan(blocks[3].hash()));
+
+    let outcome = sync.block_received(&mut client, 0, blocks[0].clone());
+    assert_eq!(outcome.imported.len(), 3);
+    assert_eq!(client.best_block(), blocks[2].hash());
+
+    let mut tiny = ChainSync::new(OrphanLimits {
+        max_bytes: 10,
+        ..OrphanLimits::default()
+    });
+    let outcome = tiny.block_received(&mut ledger_client(), 0, blocks[3].clone());
+    assert_eq!(outcome.request, None);
+    assert_eq!(tiny.orphan_count(), 0);
+}
+
+#[test]
+fn cl_11_peers_serving_invalid_chains_are_detected() {
+    let (source, blocks) = ledger_chain(1);
+    // This block claims Bob already holds the money, so its state root is wrong.
+    let wrong_state = Ledger::from(Balances::from([(Keyring::Bob.to_account_id(), 100)]));
+    let bad = blocks[0]
+        .child(&(), &wrong_state, vec![], Keyring::Alice.to_account_id())
+        .unwrap();
+    let bad_child = bad
+        .child(&(), &wrong_state, vec![], Keyring::Alice.to_account_id())
+        .unwrap();
+    let mut client = ledger_client();
+    let mut sync = ChainSync::default();
+    sync.block_received(&mut client, 0, blocks[0].clone());
+
+    let outcome = sync.block_received(&mut client, 2, bad_child.clone());
+    assert_eq!(outcome.request, Some(bad.hash()));
+    let outcome = sync.block_received(&mut client, 1, bad.clone());
+
+    // Only the peer whose block failed is to blame, not the one that passed on its child.
+    assert_eq!(outcome.imported, vec![]);
+    assert_eq!(outcome.misbehaving, vec![1]);
+    assert_eq!(sync.orphan_count(), 0);
+    assert!(!sync.should_request(&client, bad_child.hash()));
+    assert_eq!(client.best_block(), source.best_block());
+
+    // The bad chain is refused without executing anything when it comes again, and whoever
+    // sends the invalid block itself is caught.
+    let outcome = sync.block_received(&mut client, 3, bad_child);
+    assert_eq!(outcome, SyncOutcome::default());
+    let outcome = sync.block_received(&mut client, 3, bad);
+    assert_eq!(outcome.misbehaving, vec![3]);
+}
+
+#[test]
+fn cl_11_bad_blocks_are_forgotten_least_recent_first() {
+    let (_, blocks) = ledger_chain(1);
+    let wrong_state = Ledger::from(Balances::from([(Keyring::Bob.to_account_id(), 100)]));
+    let bad: Vec<_> = [Keyring::Alice, Keyring::Bob, Keyring::Charlie]
+        .iter()
+        .map(|author| {
+            blocks[0]
+                .child(&(), &wrong_state, vec![], author.to_account_id())
+                .unwrap()
+        })
+        .collect();
+    let mut client = ledger_client();
+    let mut sync = ChainSync::new(OrphanLimits {
+        max_bad_blocks: 2,
+        ..OrphanLimits::default()
+    });
+    sync.block_received(&mut client, 0, blocks[0].clone());
+
+    sync.block_received(&mut client, 1, bad[0].clone());
+    sync.block_received(&mut client, 1, bad[1].clone());
+    // Seeing the first bad block again makes the second the least recently used.
+    sync.block_received(&mut client, 1, bad[0].clone());
+    sync.block_received(&mut client, 1, bad[2].clone());
+
+    assert!(sync.is_bad(bad[0].hash()));
+    assert!(!sync.is_bad(bad[1].hash()));
+    assert!(sync.is_bad(bad[2].hash()));
+}
@@ -0,0 +1,295 @@
+//! A full client keeps every block body and executes every transaction. That is too much work
+//! for a phone or a browser. A light client follows the chain by importing headers only.
+//!
+//! Consensus engines operate entirely at the header level, so a light client can check seals
+//! and ancestry just as well as a full client can. What it cannot do is check execution. It
+//! trusts that the best chain, as chosen by consensus and fork choice, executed correctly.
+//!
+//! To answer questions about the chain's contents, the light client asks a full node for a
+//! proof and checks it against the roots in a header it already trusts. The full node does
+//! not need to be trusted, because a bad proof does not match the root.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    p2_importing_blocks::ImportBlock, p3_fork_choice::ForkChoice,
+    p4_transaction_pool::TransactionPool, p7_block_store::BlockStore, Consensus, FullClient, Hash,
+    Header, StateMachine,
+};
+use crate::{codec::Encode, crypto::MerkleProof, hash};
+
+/// A client that imports headers but never bodies or states.
+pub struct LightClient<C: Consensus, FC> {
+    /// The consensus engine used to check seals.
+    consensus_engine: C,
+    /// The fork choice strategy used by this client.
+    fork_choice: FC,
+    /// Every header this client has imported.
+    headers: HashMap<Hash, Header<C::Digest>>,
+    /// The hashes of the imported headers that have no imported children yet.
+    leaves: HashSet<Hash>,
+    /// The hash of the genesis header that this client was started with.
+    genesis: Hash,
+}
+
+impl<C, FC> LightClient<C, FC>
+where
+    C: Consensus,
+    C::Digest: Clone + Encode,
+    FC: ForkChoice<C>,
+{
+    /// Create a light client that trusts the given genesis header. It can be taken from any
+    /// full client on the same chain, or built from the genesis state with `Block::genesis`.
+    pub fn new(
+        consensus_engine: C,
+        mut fork_choice: FC,
+        genesis_header: Header<C::Digest>,
+    ) -> Self {
+        let genesis = hash(&genesis_header);
+        fork_choice.import_hook(genesis_header.clone());
+        LightClient {
+            consensus_engine,
+            fork_choice,
+            headers: HashMap::from([(genesis, genesis_header)]),
+            leaves: HashSet::from([genesis]),
+            genesis,
+        }
+    }
+
+    pub fn genesis(&self) -> Hash {
+        self.genesis
+    }
+
+    /// Attempt to import a header. It is imported when its parent is known, it correctly
+    /// extends its parent, and its seal is valid. Importing a header that is already known
+    /// succeeds without doing anything.
+    pub fn import_header(&mut self, header: Header<C::Digest>) -> bool {
+        let header_hash = hash(&header);
+        if self.headers.contains_key(&header_hash) {
+            return true;
+        }
+        let Some(parent) = self.headers.get(&header.parent) else {
+            return false;
+        };
+        if !parent.verify_child(&header)
+            || !self
+                .consensus_engine
+                .validate(&parent.consensus_digest, &header)
+        {
+            return false;
+        }
+
+        self.leaves.remove(&header.parent);
+        self.leaves.insert(header_hash);
+        self.fork_choice.import_hook(header.clone());
+        self.headers.insert(header_hash, header);
+        true
+    }
+
+    /// Retrieve an imported header. Returns None if the header is not known.
+    pub fn get_header(&self, block_hash: Hash) -> Option<&Header<C::Digest>> {
+        self.headers.get(&block_hash)
+    }
+
+    /// The best block according to the fork choice, or genesis if the fork choice has no
+    /// opinion.
+    pub fn best_header(&self) -> Hash {
+        self.fork_choice.best_block().unwrap_or(self.genesis)
+    }
+
+    /// Check whether a given block is a leaf. Returns None if the block is not known.
+    pub fn is_leaf(&self, block_hash: Hash) -> Option<bool> {
+        self.headers
+            .contains_key(&block_hash)
+            .then(|| self.leaves.contains(&block_hash))
+    }
+
+    pub fn all_leaves(&self) -> Vec<Hash> {
+        self.leaves.iter().copied().collect()
+    }
+
+    /// Check a proof, supplied by a full node, that the given extrinsic is in the body of the
+    /// given block. Fails if the block's header is not known.
+    pub fn verify_extrinsic<T: Encode>(
+        &self,
+        block_hash: Hash,
+        extrinsic: &T,
+        proof: &MerkleProof,
+    ) -> bool {
+        self.headers
+            .get(&block_hash)
+            .is_some_and(|header| header.verify_extrinsic(extrinsic, proof))
+    }
+
+    /// Check that the given state, supplied by a full node, is the state after the given
+    /// block. Fails if the block's header is not known.
+    ///
+    /// This needs the whole state. State machines that keep their state in a trie can offer
+    /// proofs of parts of it instead, to be checked against the header's state root.
+    pub fn verify_state<SM>(&self, block_hash: Hash, state: &SM::State) -> bool
+    where
+        SM: StateMachine,
+        SM::State: Encode,
+    {
+        self.headers
+            .get(&block_hash)
+            .is_some_and(|header| header.state_root == SM::state_root(state))
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// The header of an imported block, for handing to a light client.
+    pub fn get_header(&self, block_hash: Hash) -> Option<Header<C::Digest>> {
+        self.get_block(block_hash)
+            .map(|block| block.header().clone())
+    }
+
+    /// Prove to a light client that the extrinsic at the given index is in the given block.
+    /// Returns None if the block is not known or has no extrinsic at that index.
+    pub fn extrinsic_proof(&self, block_hash: Hash, index: usize) -> Option<MerkleProof> {
+        self.get_block(block_hash)?.extrinsic_proof(index)
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c3_consensus::SimplePoa,
+    c4_client::{
+        p3_fork_choice::LongestChain, p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type PoaClient =
+    FullClient<SimplePoa, AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A full client whose only authority is Alice, signing as the given account.
+#[cfg(test)]
+fn alice_poa_client(signer: Keyring) -> PoaClient {
+    let engine = SimplePoa {
+        authorities: vec![Keyring::Alice.to_account_id()],
+        signer: Some(signer.pair().clone()),
+    };
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(
+        engine,
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap()
+}
+
+/// A light client following the same chain as the given full client.
+#[cfg(test)]
+fn light_client_for(full: &PoaClient) -> LightClient<SimplePoa, LongestChain> {
+    let engine = SimplePoa {
+        authorities: vec![Keyring::Alice.to_account_id()],
+        signer: None,
+    };
+    LightClient::new(
+        engine,
+        LongestChain::default(),
+        full.get_header(full.genesis()).unwrap(),
+    )
+}
+
+#[cfg(test)]
+fn alice_transfer(nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(
+        Keyring::Alice.pair(),
+        Keyring::Bob.to_account_id(),
+        10,
+        nonce,
+    )
+}
+
+#[test]
+fn cl_12_light_client_follows_best_header() {
+    let mut full = alice_poa_client(Keyring::Alice);
+    let genesis = full.genesis();
+    let a1 = full
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+    let a2 = full.author_and_import_manual_block(vec![], a1).unwrap();
+    let b1 = full
+        .author_and_import_manual_block(vec![alice_transfer(0)], genesis)
+        .unwrap();
+    let mut light = light_client_for(&full);
+
+    assert!(!light.import_header(full.get_header(a2).unwrap()));
+    for block_hash in [a1, a2, b1] {
+        assert!(light.import_header(full.get_header(block_hash).unwrap()));
+    }
+
+    assert_eq!(light.genesis(), genesis);
+    assert_eq!(light.best_header(), a2);
+    assert_eq!(light.is_leaf(a1), Some(false));
+    assert_eq!(light.is_leaf(b1), Some(true));
+    assert_eq!(light.is_leaf(Hash::default()), None);
+    assert_eq!(light.all_leaves().len(), 2);
+}
+
+#[test]
+fn cl_12_light_client_rejects_bad_headers() {
+    let mut full = alice_poa_client(Keyring::Alice);
+    let a1 = full
+        .author_and_import_manual_block(vec![], full.genesis())
+        .unwrap();
+    let mut light = light_client_for(&full);
+
+    let mut wrong_height = full.get_header(a1).unwrap();
+    wrong_height.height = 5;
+    assert!(!light.import_header(wrong_height));
+
+    // Bob is not an authority on this chain, so his seals are worthless.
+    let mut rogue = alice_poa_client(Keyring::Bob);
+    rogue
+        .consensus_engine
+        .authorities
+        .push(Keyring::Bob.to_account_id());
+    let r1 = rogue
+        .author_and_import_manual_block(vec![], rogue.genesis())
+        .unwrap();
+    assert!(!light.import_header(rogue.get_header(r1).unwrap()));
+
+    assert_eq!(light.best_header(), light.genesis());
+}
+
+#[test]
+fn cl_12_light_client_checks_proofs_from_full_node() {
+    let mut full = alice_poa_client(Keyring::Alice);
+    let included = [alice_transfer(0), alice_transfer(1)];
+    let b1 = full
+        .author_and_import_manual_block(included.to_vec(), full.genesis())
+        .unwrap();
+    let mut light = light_client_for(&full);
+    assert!(light.import_header(full.get_header(b1).unwrap()));
+
+    let proof = full.extrinsic_proof(b1, 1).unwrap();
+    assert!(light.verify_extrinsic(b1, &included[1], &proof));
+    assert!(!light.verify_extrinsic(b1, &alice_transfer(2), &proof));
+    assert!(!light.verify_extrinsic(full.genesis(), &included[1], &proof));
+    assert!(full.extrinsic_proof(b1, 2).is_none());
+
+    let state = full.get_state(b1).unwrap();
+    assert!(light.verify_state::<AccountedCurrency>(b1, &state));
+    assert!(!light.verify_state::<AccountedCurrency>(full.genesis(), &state));
+
+    let bob = Keyring::Bob.to_account_id();
+    let state_root = light.get_header(b1).unwrap().state_root;
+    assert!(state.prove_balance(&bob).verify(&state_root, &bob, 20));
+    assert!(!state.prove_balance(&bob).verify(&state_root, &bob, 30));
+}
@@ -0,0 +1,316 @@
+//! Authoring a proof of work block with `author_and_import_automatic_block` blocks the client
+//! until a seal is found. Meanwhile no blocks are imported, so the client cannot notice that a
+//! peer has already extended the chain, and may keep mining on a parent that is no longer best.
+//!
+//! Here the client hands the search to a background [`Miner`] instead, and keeps working. Each
+//! time the client polls the pending block, it checks whether the best block has moved on. If
+//! it has, the search is cancelled and the block's transactions go back into the pool, ready
+//! to be mined again on the new best block.
+
+use std::sync::Arc;
+
+use super::{
+    p1_data_structure::{Block, BlockError},
+    p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore,
+    Consensus, ForkChoice, FullClient, Hash, StateMachine,
+};
+use crate::{
+    c3_consensus::{Miner, MiningJob, ProofOfWork},
+    codec::Encode,
+};
+
+/// A block whose seal is being searched for in the background.
+pub struct PendingBlock<C: Consensus, SM: StateMachine> {
+    /// The block that was best when mining started.
+    parent: Hash,
+    body: Vec<SM::Transition>,
+    job: MiningJob<C::Digest>,
+}
+
+impl<C: Consensus, SM: StateMachine> PendingBlock<C, SM> {
+    pub fn parent(&self) -> Hash {
+        self.parent
+    }
+
+    /// The number of hashes computed so far.
+    pub fn hashes(&self) -> u64 {
+        self.job.hashes()
+    }
+
+    /// The average number of hashes per second since mining started.
+    pub fn hash_rate(&self) -> f64 {
+        self.job.hash_rate()
+    }
+}
+
+/// What became of a pending block when it was polled.
+pub enum MiningStatus<C: Consensus, SM: StateMachine> {
+    /// No seal has been found yet, and the block still extends the best block.
+    Mining(PendingBlock<C, SM>),
+    /// A seal was found and the block was imported. Its hash is included.
+    Imported(Hash),
+    /// The best block moved on, or the search gave up. Mining was stopped and the block's
+    /// transactions were returned to the pool.
+    Stale,
+    /// A seal was found, but the block could not be imported for the given reason, for
+    /// example because it could not be stored. The block's transactions were returned to the pool.
+    Failed(BlockError<SM::Error>),
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: ProofOfWork + Clone + Send + Sync + 'static,
+    C::Digest: Send + 'static,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Start mining a block with the transactions from the pool on top of the "best" block.
+    /// The pool is drained as in `author_and_import_automatic_block`, but the seal is searched
+    /// for in the background by the given miner.
+    pub fn start_mining(
+        &mut self,
+        miner: &Miner,
+    ) -> Result<PendingBlock<C, SM>, BlockError<SM::Error>> {
+        let parent_hash = self.best_block();
+        let parent = self
+            .blocks
+            .get(&parent_hash)
+            .expect("the best block is always known");
+        let body = self.drain_pool_onto(parent_hash);
+        let pre_state = self
+            .state_at(parent_hash)
+            .expect("the best block's state can always be recomputed");
+        let partial_header = match parent.unsealed_child(&pre_state, &body, self.author) {
+            Ok(partial_header) => partial_header,
+            Err(e) => {
+                self.requeue(body);
+                return Err(e);
+            }
+        };
+        let engine = Arc::new(self.consensus_engine.clone());
+        let Some(job) = miner.start(engine, &parent.header().consensus_digest, partial_header)
+        else {
+            self.requeue(body);
+            return Err(BlockError::SealFailed);
+        };
+        Ok(PendingBlock {
+            parent: parent_hash,
+            body,
+            job,
+        })
+    }
+
+    /// Check on a pending block, importing it if its seal has been found.
+    ///
+    /// Mining is cancelled as soon as the best block is no longer the pending block's parent,
+    /// even if a seal has been found, because the block would only start a fork.
+    pub fn poll_mining(&mut self, pending: PendingBlock<C, SM>) -> MiningStatus<C, SM> {
+        if self.best_block() != pending.parent {
+            pending.job.cancel();
+            self.requeue(pending.body);
+            return MiningStatus::Stale;
+        }
+        // Check this first, so that a seal sent just before the workers finished is not missed.
+        let finished = pending.job.is_finished();
+        let Some(header) = pending.job.try_result() else {
+            if finished {
+                self.requeue(pending.body);
+                return MiningStatus::Stale;
+            }
+            return MiningStatus::Mining(pending);
+        };
+        let block = Block::from_parts(header, pending.body.clone());
+        let block_hash = block.hash();
+        match self.try_import_block(block) {
+            Ok(()) => MiningStatus::Imported(block_hash),
+            Err(e) => {
+                self.requeue(pending.body);
+                MiningStatus::Failed(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{
+        AccountedCurrency, AccountingError, AccountingTransaction, Balances, Ledger,
+    },
+    c3_consensus::Pow,
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool, p5_authoring_blocks::FullDisk,
+        p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type PowClient = FullClient<Pow, AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A client on a chain where Alice has some money, and where roughly one in a hundred hashes
+/// is a valid seal.
+#[cfg(test)]
+fn pow_client() -> PowClient {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(
+        Pow::new(Hash::MAX.divided_by(100)),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn alice_transfer(nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(
+        Keyring::Alice.pair(),
+        Keyring::Bob.to_account_id(),
+        10,
+        nonce,
+    )
+}
+
+#[test]
+fn cl_13_background_mining_imports_block() {
+    let mut client = pow_client();
+    assert!(client.submit_transaction(alice_transfer(0)));
+
+    let mut pending = client.start_mining(&Miner::new(2)).unwrap();
+    assert_eq!(pending.parent(), client.genesis());
+    assert_eq!(client.pool_size(), 0);
+    let block_hash = loop {
+        match client.poll_mining(pending) {
+            MiningStatus::Mining(still_mining) => pending = still_mining,
+            MiningStatus::Imported(block_hash) => break block_hash,
+            MiningStatus::Stale | MiningStatus::Failed(_) => panic!("nothing else extended the chain"),
+        }
+        std::thread::yield_now();
+    };
+
+    assert_eq!(client.best_block(), block_hash);
+    assert_eq!(
+        client.get_block(block_hash).unwrap().body(),
+        &[alice_transfer(0)]
+    );
+}
+
+#[test]
+fn cl_13_new_best_block_makes_mining_stale() {
+    let mut client = pow_client();
+    assert!(client.submit_transaction(alice_transfer(0)));
+    let pending = client.start_mining(&Miner::new(2)).unwrap();
+
+    // A block from elsewhere becomes best before the pending block is polled.
+    let other = client
+        .author_and_import_manual_block(vec![], client.genesis())
+        .unwrap();
+    assert_eq!(client.best_block(), other);
+
+    assert!(matches!(client.poll_mining(pending), MiningStatus::Stale));
+    assert!(client.pool_contains(alice_transfer(0)));
+    assert_eq!(client.best_block(), other);
+
+    // Mining again picks the transaction up on top of the new best block.
+    let pending = client.start_mining(&Miner::new(2)).unwrap();
+    assert_eq!(pending.parent(), other);
+}
+
+#[test]
+fn cl_13_pending_block_reports_progress() {
+    // No hash is below a threshold of zero, so the search goes on until it is cancelled.
+    let mut client = FullClient::<_, AccountedCurrency, _, _, _>::new(
+        Pow::new(Hash::default()),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        Ledger::default(),
+    )
+    .unwrap();
+    let mut pending = client.start_mining(&Miner::new(2)).unwrap();
+
+    while pending.hashes() == 0 {
+        std::thread::yield_now();
+    }
+    assert!(pending.hash_rate() > 0.0);
+    let hashes = pending.hashes();
+    pending = match client.poll_mining(pending) {
+        MiningStatus::Mining(still_mining) => still_mining,
+        _ => panic!("no seal can be found"),
+    };
+    while pending.hashes() == hashes {
+        std::thread::yield_now();
+    }
+    // Dropping the pending block cancels the search.
+}
+
+#[test]
+fn cl_13_block_that_cannot_be_built_keeps_its_transactions() {
+    // Bob authors, but his balance has no room left for the block reward.
+    let genesis_state = Ledger {
+        balances: Balances::from([
+            (Keyring::Alice.to_account_id(), 100),
+            (Keyring::Bob.to_account_id(), u64::MAX),
+        ]),
+        block_reward: 1,
+        ..Ledger::default()
+    };
+    let mut client = FullClient::<_, AccountedCurrency, _, _, _>::new(
+        Pow::new(Hash::MAX.divided_by(100)),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap();
+    client.set_author(Keyring::Bob.to_account_id());
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Charlie.to_account_id(), 10, 0);
+    assert!(client.submit_transaction(transfer.clone()));
+
+    assert!(matches!(
+        client.start_mining(&Miner::new(2)),
+        Err(BlockError::InvalidPayout(AccountingError::BalanceOverflow))
+    ));
+    assert!(client.pool_contains(transfer));
+}
+
+#[test]
+fn cl_13_sealed_block_that_cannot_be_stored_keeps_its_transactions() {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let store = FullDisk {
+        store: MemoryStore::new(),
+        full: false,
+    };
+    let mut client = FullClient::new(
+        Pow::new(Hash::MAX.divided_by(100)),
+        LongestChain::default(),
+        SimplePool::new(),
+        store,
+        genesis_state,
+    )
+    .unwrap();
+    assert!(client.submit_transaction(alice_transfer(0)));
+    let mut pending = client.start_mining(&Miner::new(2)).unwrap();
+    // The disk fills up while the seal is being searched for.
+    client.blocks.full = true;
+
+    let failure = loop {
+        match client.poll_mining(pending) {
+            MiningStatus::Mining(still_mining) => pending = still_mining,
+            MiningStatus::Failed(e) => break e,
+            _ => panic!("the block is sealed but cannot be stored"),
+        }
+        std::thread::yield_now();
+    };
+
+    assert_eq!(failure, BlockError::StoreFailed);
+    assert!(client.pool_contains(alice_transfer(0)));
+    assert_eq!(client.best_block(), client.genesis());
+}
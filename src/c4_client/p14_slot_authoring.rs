@@ -0,0 +1,197 @@
+//! With a slot-based consensus engine, authoring is driven by the clock rather than by work.
+//! At the start of every slot, the client checks whether the slot is its own. If it is, it
+//! builds a block on the best block and seals it in that slot. If not, it waits for the next
+//! slot. A slot is only ever used once, so the client never builds two blocks in the same
+//! slot, even when it is polled several times during it.
+
+use std::thread;
+
+use super::{
+    p1_data_structure::BlockError, p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore, ForkChoice, FullClient, Hash, StateMachine,
+};
+use crate::{
+    c3_consensus::{SlotClock, SlotConsensus},
+    codec::Encode,
+};
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: SlotConsensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Author a block with the transactions from the pool on top of the best block, if the
+    /// current slot belongs to this client and the best block is from an earlier slot.
+    ///
+    /// Returns None when there is nothing to author in this slot.
+    pub fn author_in_current_slot(&mut self) -> Option<Result<Hash, BlockError<SM::Error>>> {
+        let slot = self.consensus_engine.clock().current_slot();
+        let best = self
+            .blocks
+            .get(&self.best_block())
+            .expect("the best block is always known");
+        if slot <= C::slot(&best.header().consensus_digest)
+            || !self.consensus_engine.claims_slot(slot)
+        {
+            return None;
+        }
+        Some(self.author_and_import_automatic_block())
+    }
+
+    /// Follow the clock for the given number of slots, authoring in each slot that belongs
+    /// to this client, and return the hashes of the blocks authored.
+    ///
+    /// A block that cannot be sealed, for example because its slot ended while it was being
+    /// built, is skipped. Its transactions go back into the pool for a later slot.
+    pub fn run_slot_authoring(&mut self, slots: u64) -> Vec<Hash> {
+        let mut authored = Vec::new();
+        for _ in 0..slots {
+            if let Some(Ok(block_hash)) = self.author_in_current_slot() {
+                authored.push(block_hash);
+            }
+            thread::sleep(self.consensus_engine.clock().time_until_next_slot());
+        }
+        authored
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{
+        AccountedCurrency, AccountingTransaction, Balances, Ledger,
+    },
+    c3_consensus::{ManualSlotClock, PoaRoundRobinBySlot, SystemSlotClock},
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    AccountId, Keyring,
+};
+
+#[cfg(test)]
+type SlotClient<K> = FullClient<
+    PoaRoundRobinBySlot<K>,
+    AccountedCurrency,
+    LongestChain,
+    SimplePool<AccountedCurrency>,
+>;
+
+/// A client for the given authorities, signing as the given account and reading the time from
+/// the given clock. Blocks may be one slot ahead of the clock.
+#[cfg(test)]
+fn slot_client<K: SlotClock>(
+    authorities: Vec<AccountId>,
+    signer: Keyring,
+    clock: K,
+) -> SlotClient<K> {
+    let engine = PoaRoundRobinBySlot {
+        authorities,
+        signer: Some(signer.pair().clone()),
+        clock,
+        allowed_drift: 1,
+    };
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(
+        engine,
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap()
+}
+
+/// A clock that moves on to the next slot every time it is read, as if building a block took
+/// a whole slot.
+#[cfg(test)]
+struct TickingClock(std::sync::atomic::AtomicU64);
+
+#[cfg(test)]
+impl SlotClock for TickingClock {
+    fn current_slot(&self) -> u64 {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn time_until_next_slot(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+fn alice_and_bob() -> Vec<AccountId> {
+    vec![Keyring::Alice.to_account_id(), Keyring::Bob.to_account_id()]
+}
+
+#[test]
+fn cl_14_authors_only_in_own_slots() {
+    let clock = ManualSlotClock::new(1);
+    let mut alice = slot_client(alice_and_bob(), Keyring::Alice, clock.clone());
+    let transfer =
+        AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    assert!(alice.submit_transaction(transfer.clone()));
+
+    // Slot 1 is Bob's.
+    assert_eq!(alice.author_in_current_slot(), None);
+    clock.advance(1);
+    let a1 = alice.author_in_current_slot().unwrap().unwrap();
+    assert_eq!(alice.get_block(a1).unwrap().body(), &[transfer]);
+    // Slot 2 is used up.
+    assert_eq!(alice.author_in_current_slot(), None);
+    clock.advance(1);
+    assert_eq!(alice.author_in_current_slot(), None);
+    clock.advance(1);
+    let a2 = alice.author_in_current_slot().unwrap().unwrap();
+
+    assert_eq!(alice.get_header(a2).unwrap().parent, a1);
+    assert_eq!(alice.best_block(), a2);
+}
+
+#[test]
+fn cl_14_blocks_from_future_slots_are_rejected() {
+    let bobs_clock = ManualSlotClock::new(11);
+    let mut bob = slot_client(alice_and_bob(), Keyring::Bob, bobs_clock);
+    let b1 = bob.author_in_current_slot().unwrap().unwrap();
+    let block = bob.get_block(b1).unwrap();
+
+    // Alice's clock is far behind Bob's, so his block looks like it comes from the future.
+    let alices_clock = ManualSlotClock::new(2);
+    let mut alice = slot_client(alice_and_bob(), Keyring::Alice, alices_clock.clone());
+    assert!(!alice.import_block(block.clone()));
+    // Within the allowed drift of one slot, it is fine.
+    alices_clock.set_slot(10);
+    assert!(alice.import_block(block));
+    assert_eq!(alice.best_block(), b1);
+}
+
+#[test]
+fn cl_14_authoring_loop_follows_system_clock() {
+    let clock = SystemSlotClock::new(std::time::Duration::from_millis(20));
+    let mut alice = slot_client(vec![Keyring::Alice.to_account_id()], Keyring::Alice, clock);
+
+    let authored = alice.run_slot_authoring(3);
+
+    assert_eq!(authored.len(), 3);
+    assert_eq!(alice.best_block(), authored[2]);
+    assert_eq!(alice.get_header(authored[2]).unwrap().height, 3);
+}
+
+#[test]
+fn cl_14_block_that_misses_its_slot_keeps_its_transactions() {
+    // Slot 2 is Alice's, but by the time her block is sealed, slot 3 has begun and it is Bob's.
+    let clock = TickingClock(2.into());
+    let mut alice = slot_client(alice_and_bob(), Keyring::Alice, clock);
+    let transfer =
+        AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    assert!(alice.submit_transaction(transfer.clone()));
+
+    assert_eq!(alice.run_slot_authoring(1), vec![]);
+    // The clock was read once to claim the slot and once more to seal the block.
+    assert_eq!(alice.consensus_engine.clock().current_slot(), 4);
+    assert_eq!(alice.best_block(), alice.genesis());
+    assert!(alice.pool_contains(transfer));
+}
@@ -0,0 +1,639 @@
+//! Manual finality trusts whoever runs the node. Real chains finalize blocks with a BFT game
+//! among a known set of voters, in the style of Polkadot's GRANDPA.
+//!
+//! Voting happens in rounds, and each round has two steps.
+//!
+//! 1. Every voter prevotes for the head of its best chain.
+//! 2. Once a voter has seen prevotes from more than two thirds of the voters, it precommits
+//!    for the highest block that more than two thirds of those prevotes agree on. A vote for a
+//!    block also counts as a vote for each of its ancestors, so this is the highest block that
+//!    a supermajority has built on.
+//!
+//! When more than two thirds of the precommits agree on a block in the same way, that block
+//! is final. The precommits themselves are the proof. They are kept as a justification next
+//! to the finalized block, so that anyone who knows the voter set can check it later.
+//!
+//! As long as fewer than a third of the voters are dishonest, two conflicting blocks can never
+//! both gather a supermajority, so finality is never reverted. In return, finality stalls
+//! while more than a third of the voters are offline. Block production carries on regardless.
+//!
+//! This is a simplified GRANDPA. It has no timeouts, no primary proposer, and no handling of
+//! equivocations beyond counting only the first vote of each voter.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use super::{
+    p4_transaction_pool::TransactionPool, p7_block_store::BlockStore, Consensus, ForkChoice,
+    FullClient, Hash, StateMachine,
+};
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{Keypair, Signature},
+    hash, AccountId,
+};
+
+/// The accounts that vote on finality.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoterSet {
+    voters: Vec<AccountId>,
+}
+
+impl VoterSet {
+    pub fn new(voters: Vec<AccountId>) -> Self {
+        assert!(!voters.is_empty(), "a voter set cannot be empty");
+        VoterSet { voters }
+    }
+
+    pub fn contains(&self, who: &AccountId) -> bool {
+        self.voters.contains(who)
+    }
+
+    /// The fewest votes that are more than two thirds of the set.
+    pub fn threshold(&self) -> usize {
+        self.voters.len() * 2 / 3 + 1
+    }
+}
+
+/// The two steps of a round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+impl Encode for VoteKind {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            VoteKind::Prevote => 0u8.encode_to(dest),
+            VoteKind::Precommit => 1u8.encode_to(dest),
+        }
+    }
+}
+
+impl Decode for VoteKind {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(VoteKind::Prevote),
+            1 => Ok(VoteKind::Precommit),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// A voter's signed vote for a block, and so for all of the block's ancestors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SignedVote {
+    pub(crate) round: u64,
+    pub(crate) kind: VoteKind,
+    pub(crate) target: Hash,
+    pub(crate) voter: AccountId,
+    pub(crate) signature: Signature,
+}
+
+impl SignedVote {
+    /// The message a voter signs. It names the round and the step, so that a vote cannot be
+    /// replayed in another round or as the other kind of vote.
+    fn message(round: u64, kind: VoteKind, target: Hash) -> Hash {
+        hash(&(round, kind, target))
+    }
+
+    pub fn sign(voter: &Keypair, round: u64, kind: VoteKind, target: Hash) -> Self {
+        SignedVote {
+            round,
+            kind,
+            target,
+            voter: voter.into(),
+            signature: voter.sign(&Self::message(round, kind, target)),
+        }
+    }
+
+    /// Check that the voter really cast this vote.
+    pub fn verify(&self) -> bool {
+        self.voter.verify(
+            &Self::message(self.round, self.kind, self.target),
+            &self.signature,
+        )
+    }
+}
+
+impl Encode for SignedVote {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.round.encode_to(dest);
+        self.kind.encode_to(dest);
+        self.target.encode_to(dest);
+        self.voter.encode_to(dest);
+        self.signature.encode_to(dest);
+    }
+}
+
+impl Decode for SignedVote {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SignedVote {
+            round: u64::decode(input)?,
+            kind: VoteKind::decode(input)?,
+            target: Hash::decode(input)?,
+            voter: AccountId::decode(input)?,
+            signature: Signature::decode(input)?,
+        })
+    }
+}
+
+/// Proof that a block is final: precommits from more than two thirds of the voters, all in the
+/// same round, for the block or its descendants.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Justification {
+    pub(crate) round: u64,
+    pub(crate) target: Hash,
+    pub(crate) precommits: Vec<SignedVote>,
+}
+
+impl Justification {
+    /// The block this justification finalizes.
+    pub fn target(&self) -> Hash {
+        self.target
+    }
+}
+
+impl Encode for Justification {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.round.encode_to(dest);
+        self.target.encode_to(dest);
+        self.precommits.encode_to(dest);
+    }
+}
+
+impl Decode for Justification {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Justification {
+            round: u64::decode(input)?,
+            target: Hash::decode(input)?,
+            precommits: Vec::decode(input)?,
+        })
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// The justification of a block that was finalized by the voters. Blocks that were
+    /// finalized by hand, or only implicitly as the ancestor of a justified block, have none.
+    pub fn justification(&self, block_hash: Hash) -> Option<&Justification> {
+        self.justifications.get(&block_hash)
+    }
+
+    /// Check that the justification holds precommits from more than two thirds of the given
+    /// voters, each valid, all in the justification's round, and each for its target or a
+    /// known descendant of it.
+    pub fn verify_justification(&self, voters: &VoterSet, justification: &Justification) -> bool {
+        let mut seen = HashSet::new();
+        justification.precommits.iter().all(|vote| {
+            vote.round == justification.round
+                && vote.kind == VoteKind::Precommit
+                && voters.contains(&vote.voter)
+                && seen.insert(vote.voter)
+                && vote.verify()
+                && self.is_descendant(vote.target, justification.target)
+        }) && seen.len() >= voters.threshold()
+    }
+
+    /// Finalize the block named by the given justification, and keep the justification
+    /// alongside it. Fails if the justification is not valid for the given voters, or if its
+    /// target does not descend from the last finalized block.
+    pub fn import_justification(
+        &mut self,
+        voters: &VoterSet,
+        justification: Justification,
+    ) -> bool {
+        self.verify_justification(voters, &justification)
+            && self.finalize(justification.target, Some(justification))
+    }
+
+    /// The highest block that at least `threshold` of the votes are for, counting a vote for
+    /// a block as a vote for each of its ancestors. Only votes for known descendants of the
+    /// last finalized block count. Returns None if no block has enough votes.
+    fn vote_ghost(&self, targets: &[Hash], threshold: usize) -> Option<Hash> {
+        let mut support: HashMap<Hash, (u64, usize)> = HashMap::new();
+        for target in targets {
+            if !self.is_descendant(*target, self.finalized) {
+                continue;
+            }
+            let mut current = *target;
+            loop {
+                let header = self
+                    .blocks
+                    .get(&current)
+                    .expect("descendants of the finalized block are known")
+                    .header()
+                    .clone();
+                support.entry(current).or_insert((header.height, 0)).1 += 1;
+                if current == self.finalized {
+                    break;
+                }
+                current = header.parent;
+            }
+        }
+        support
+            .into_iter()
+            .filter(|(_, (_, votes))| *votes >= threshold)
+            .max_by_key(|(_, (height, _))| *height)
+            .map(|(block_hash, _)| block_hash)
+    }
+}
+
+/// One voter taking part in finality, connected to the other voters by channels.
+pub struct GrandpaVoter {
+    keypair: Keypair,
+    voters: VoterSet,
+    round: u64,
+    /// The first vote of each kind from each voter in the current round.
+    votes: HashMap<(AccountId, VoteKind), SignedVote>,
+    /// Votes for later rounds, kept until this voter gets there.
+    future_votes: Vec<SignedVote>,
+    inbox: Receiver<SignedVote>,
+    peers: Vec<Sender<SignedVote>>,
+}
+
+impl GrandpaVoter {
+    /// Create a voter for each of the given keypairs, each connected to all of the others.
+    /// Voters in the set that are not given a keypair here are offline.
+    pub fn connected(keypairs: &[Keypair], voters: &VoterSet) -> Vec<Self> {
+        let (senders, inboxes): (Vec<_>, Vec<_>) = keypairs.iter().map(|_| mpsc::channel()).unzip();
+        keypairs
+            .iter()
+            .zip(inboxes)
+            .enumerate()
+            .map(|(i, (keypair, inbox))| GrandpaVoter {
+                keypair: keypair.clone(),
+                voters: voters.clone(),
+                round: 0,
+                votes: HashMap::new(),
+                future_votes: Vec::new(),
+                inbox,
+                peers: senders
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, sender)| sender.clone())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn id(&self) -> AccountId {
+        AccountId::from(&self.keypair)
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// Take the current round as far as possible on top of the given client's chain.
+    ///
+    /// The voter reads the votes waiting in its inbox, prevotes for the client's best block,
+    /// precommits once it has seen enough prevotes, and once it has seen enough precommits,
+    /// finalizes their block in the client and moves on to the next round. Each vote is only
+    /// cast once per round.
+    ///
+    /// Returns the block that was newly finalized, if any.
+    pub fn step<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+    ) -> Option<Hash>
+    where
+        C: Consensus,
+        SM: StateMachine,
+        SM::State: Clone,
+        SM::Transition: Clone,
+        FC: ForkChoice<C>,
+        P: TransactionPool<SM>,
+        S: BlockStore<C, SM>,
+    {
+        while let Ok(vote) = self.inbox.try_recv() {
+            self.receive(vote);
+        }
+        let threshold = self.voters.threshold();
+
+        if !self.votes.contains_key(&(self.id(), VoteKind::Prevote)) {
+            let best = client.best_block();
+            let finalized = client.finalized_block();
+            let head = if client.is_descendant(best, finalized) {
+                best
+            } else {
+                finalized
+            };
+            self.cast(VoteKind::Prevote, head);
+        }
+
+        let prevotes = self.targets(VoteKind::Prevote);
+        if !self.votes.contains_key(&(self.id(), VoteKind::Precommit))
+            && prevotes.len() >= threshold
+        {
+            if let Some(ghost) = client.vote_ghost(&prevotes, threshold) {
+                self.cast(VoteKind::Precommit, ghost);
+            }
+        }
+
+        let precommits = self.targets(VoteKind::Precommit);
+        if precommits.len() < threshold {
+            return None;
+        }
+        let target = client.vote_ghost(&precommits, threshold)?;
+        let finalized = target != client.finalized_block() && {
+            let justification = Justification {
+                round: self.round,
+                target,
+                precommits: self
+                    .votes
+                    .values()
+                    .filter(|vote| {
+                        vote.kind == VoteKind::Precommit
+                            && client.is_descendant(vote.target, target)
+                    })
+                    .copied()
+                    .collect(),
+            };
+            client.import_justification(&self.voters, justification)
+        };
+        self.next_round();
+        finalized.then_some(target)
+    }
+
+    /// Record a vote from another voter, if it is valid and not stale.
+    fn receive(&mut self, vote: SignedVote) {
+        if vote.round < self.round || !self.voters.contains(&vote.voter) || !vote.verify() {
+            return;
+        }
+        if vote.round > self.round {
+            self.future_votes.push(vote);
+            return;
+        }
+        self.votes.entry((vote.voter, vote.kind)).or_insert(vote);
+    }
+
+    /// Sign a vote in the current round, count it, and send it to every peer.
+    fn cast(&mut self, kind: VoteKind, target: Hash) {
+        let vote = SignedVote::sign(&self.keypair, self.round, kind, target);
+        self.votes.insert((vote.voter, kind), vote);
+        for peer in &self.peers {
+            // A peer that has shut down does not need our votes.
+            let _ = peer.send(vote);
+        }
+    }
+
+    /// The targets of the current round's votes of the given kind.
+    fn targets(&self, kind: VoteKind) -> Vec<Hash> {
+        self.votes
+            .values()
+            .filter(|vote| vote.kind == kind)
+            .map(|vote| vote.target)
+            .collect()
+    }
+
+    fn next_round(&mut self) {
+        self.round += 1;
+        self.votes.clear();
+        for vote in std::mem::take(&mut self.future_votes) {
+            self.receive(vote);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, Ledger},
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+#[cfg(test)]
+fn ledger_client() -> LedgerClient {
+    FullClient::new(
+        (),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        Ledger::default(),
+    )
+    .unwrap()
+}
+
+/// Alice, Bob, Charlie, and Dave vote. Three of them make a supermajority.
+#[cfg(test)]
+fn four_voters() -> VoterSet {
+    VoterSet::new(
+        [
+            Keyring::Alice,
+            Keyring::Bob,
+            Keyring::Charlie,
+            Keyring::Dave,
+        ]
+        .map(|voter| voter.to_account_id())
+        .to_vec(),
+    )
+}
+
+#[cfg(test)]
+fn keypairs(voters: &[Keyring]) -> Vec<Keypair> {
+    voters.iter().map(|voter| voter.pair().clone()).collect()
+}
+
+/// Author a chain of empty blocks of the given length on top of the given parent.
+#[cfg(test)]
+fn chain_from(client: &mut LedgerClient, parent: Hash, length: usize) -> Vec<Hash> {
+    let mut hashes = Vec::new();
+    let mut parent = parent;
+    for _ in 0..length {
+        parent = client
+            .author_and_import_manual_block(vec![], parent)
+            .unwrap();
+        hashes.push(parent);
+    }
+    hashes
+}
+
+/// Import the given blocks, in order, from one client into another.
+#[cfg(test)]
+fn copy_blocks(from: &LedgerClient, to: &mut LedgerClient, blocks: &[Hash]) {
+    for block_hash in blocks {
+        assert!(to.import_block(from.get_block(*block_hash).unwrap()));
+    }
+}
+
+/// Let every voter step on its own client, over and over.
+#[cfg(test)]
+fn run_voters(voters: &mut [GrandpaVoter], clients: &mut [LedgerClient], steps: usize) {
+    for _ in 0..steps {
+        for (voter, client) in voters.iter_mut().zip(clients.iter_mut()) {
+            voter.step(client);
+        }
+    }
+}
+
+#[test]
+fn cl_15_voters_finalize_best_chain() {
+    let mut source = ledger_client();
+    let genesis = source.genesis();
+    let chain = chain_from(&mut source, genesis, 3);
+    let mut clients: Vec<_> = (0..4).map(|_| ledger_client()).collect();
+    for client in &mut clients {
+        copy_blocks(&source, client, &chain);
+    }
+    let voter_set = four_voters();
+    let mut voters = GrandpaVoter::connected(
+        &keypairs(&[
+            Keyring::Alice,
+            Keyring::Bob,
+            Keyring::Charlie,
+            Keyring::Dave,
+        ]),
+        &voter_set,
+    );
+
+    run_voters(&mut voters, &mut clients, 3);
+
+    for client in &clients {
+        assert_eq!(client.finalized_block(), chain[2]);
+        let justification = client.justification(chain[2]).unwrap();
+        assert!(client.verify_justification(&voter_set, justification));
+        assert!(justification.precommits.len() >= 3);
+        assert_eq!(client.justification(chain[1]), None);
+    }
+    assert!(voters.iter().all(|voter| voter.round() >= 1));
+}
+
+#[test]
+fn cl_15_finality_needs_more_than_two_thirds() {
+    let mut source = ledger_client();
+    let genesis = source.genesis();
+    let chain = chain_from(&mut source, genesis, 2);
+    let mut clients: Vec<_> = (0..3).map(|_| ledger_client()).collect();
+    for client in &mut clients {
+        copy_blocks(&source, client, &chain);
+    }
+
+    // Two of four voters are not enough.
+    let mut voters =
+        GrandpaVoter::connected(&keypairs(&[Keyring::Alice, Keyring::Bob]), &four_voters());
+    run_voters(&mut voters, &mut clients, 5);
+    assert!(clients
+        .iter()
+        .all(|client| client.finalized_block() == client.genesis()));
+
+    // Three are.
+    let mut voters = GrandpaVoter::connected(
+        &keypairs(&[Keyring::Alice, Keyring::Bob, Keyring::Charlie]),
+        &four_voters(),
+    );
+    run_voters(&mut voters, &mut clients, 5);
+    assert!(clients
+        .iter()
+        .all(|client| client.finalized_block() == chain[1]));
+}
+
+#[test]
+fn cl_15_split_votes_finalize_common_ancestor() {
+    let mut source = ledger_client();
+    let genesis = source.genesis();
+    let common = chain_from(&mut source, genesis, 1);
+    let fork_a = chain_from(&mut source, common[0], 2);
+    source.set_author(Keyring::Ferdie.to_account_id());
+    let fork_b = chain_from(&mut source, common[0], 2);
+    // Alice and Bob see fork B first, and Charlie and Dave see fork A first.
+    let mut clients: Vec<_> = (0..4).map(|_| ledger_client()).collect();
+    for (i, client) in clients.iter_mut().enumerate() {
+        let (first, second) = if i < 2 {
+            (&fork_b, &fork_a)
+        } else {
+            (&fork_a, &fork_b)
+        };
+        copy_blocks(&source, client, &common);
+        copy_blocks(&source, client, first);
+        copy_blocks(&source, client, second);
+    }
+    let mut voters = GrandpaVoter::connected(
+        &keypairs(&[
+            Keyring::Alice,
+            Keyring::Bob,
+            Keyring::Charlie,
+            Keyring::Dave,
+        ]),
+        &four_voters(),
+    );
+
+    run_voters(&mut voters, &mut clients, 3);
+
+    assert!(clients
+        .iter()
+        .all(|client| client.finalized_block() == common[0]));
+}
+
+#[test]
+fn cl_15_bad_justifications_are_rejected() {
+    let mut client = ledger_client();
+    let genesis = client.genesis();
+    let chain = chain_from(&mut client, genesis, 2);
+    let voter_set = four_voters();
+    let precommit = |voter: Keyring, round: u64, target: Hash| {
+        SignedVote::sign(voter.pair(), round, VoteKind::Precommit, target)
+    };
+    let justification = |precommits: Vec<SignedVote>| Justification {
+        round: 0,
+        target: chain[0],
+        precommits,
+    };
+
+    let too_few = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+    ]);
+    let outsider = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+        precommit(Keyring::Eve, 0, chain[0]),
+    ]);
+    let repeated = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+        precommit(Keyring::Bob, 0, chain[0]),
+    ]);
+    let wrong_round = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+        precommit(Keyring::Charlie, 1, chain[0]),
+    ]);
+    let not_a_descendant = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+        precommit(Keyring::Charlie, 0, client.genesis()),
+    ]);
+    for bad in [too_few, outsider, repeated, wrong_round, not_a_descendant] {
+        assert!(!client.import_justification(&voter_set, bad));
+    }
+    assert_eq!(client.finalized_block(), client.genesis());
+
+    let good = justification(vec![
+        precommit(Keyring::Alice, 0, chain[1]),
+        precommit(Keyring::Bob, 0, chain[0]),
+        precommit(Keyring::Charlie, 0, chain[0]),
+    ]);
+    assert_eq!(Justification::decode_all(&good.encode()), Ok(good.clone()));
+    assert!(client.import_justification(&voter_set, good.clone()));
+    assert_eq!(client.finalized_block(), chain[0]);
+    assert_eq!(client.justification(chain[0]), Some(&good));
+}
@@ -0,0 +1,348 @@
+//! Most of the time a new best block simply extends the old one. Sometimes, though, another
+//! fork overtakes the one the client was following, and the best block jumps across. This is
+//! called a re-org. The blocks between the common ancestor and the old best block are
+//! retracted, and the blocks between the common ancestor and the new best block are enacted.
+//!
+//! The transactions in the retracted blocks have not really happened anymore, so the client
+//! puts them back into its pool, where they wait to be included again. A pool that orders
+//! transactions by nonce is first told the nonces on the new best chain, which may be lower
+//! than on the old one. Transactions in the enacted blocks have happened, so they are removed
+//! from the pool. Any other transaction may have been invalidated on the new chain, so the
+//! whole pool is checked again against the new best state. Other components, such as wallets
+//! and block explorers, also need to know when the best block moves, so the client tells its
+//! subscribers about every change.
+
+use std::sync::mpsc::{channel, Receiver};
+
+use super::{
+    p4_transaction_pool::TransactionPool, p7_block_store::BlockStore, Block, Consensus, ForkChoice,
+    FullClient, Hash, StateMachine,
+};
+
+/// A notification sent to the client's subscribers whenever its best block changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The best block moved onto one of its own descendants, which is now the best block.
+    NewBest(Hash),
+    /// The best block moved to another fork. The retracted blocks are listed newest first,
+    /// in the order they are undone, and the enacted blocks oldest first, in the order they
+    /// are applied. Neither includes the common ancestor. The last enacted block is the new
+    /// best block.
+    Reorg {
+        retracted: Vec<Hash>,
+        enacted: Vec<Hash>,
+    },
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C>,
+{
+    /// Start receiving a [`ChainEvent`] every time the best block changes. Dropping the
+    /// receiver unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// The route between two known blocks: the blocks to retract from the first, newest
+    /// first, and the blocks to enact to reach the second, oldest first. Their common
+    /// ancestor is in neither list.
+    pub(super) fn tree_route(&self, from: Hash, to: Hash) -> (Vec<Hash>, Vec<Hash>) {
+        let height_and_parent = |block_hash: Hash| {
+            let block = self
+                .blocks
+                .get(&block_hash)
+                .expect("both ends of a route are known, and so are their ancestors");
+            (block.header().height, block.header().parent)
+        };
+        let (mut from, mut to) = (from, to);
+        let (mut retracted, mut enacted) = (Vec::new(), Vec::new());
+        while from != to {
+            let (from_height, from_parent) = height_and_parent(from);
+            let (to_height, to_parent) = height_and_parent(to);
+            if from_height >= to_height {
+                retracted.push(from);
+                from = from_parent;
+            } else {
+                enacted.push(to);
+                to = to_parent;
+            }
+        }
+        enacted.reverse();
+        (retracted, enacted)
+    }
+
+    /// Bring the pool and the subscribers up to date after the best block may have moved away
+    /// from the given block. Every block on the route between the two must still be known.
+    pub(super) fn follow_best(&mut self, old_best: Hash) {
+        if self.best_block() == old_best {
+            return;
+        }
+        let (retracted, enacted) = self.tree_route(old_best, self.best_block());
+        let retracted = retracted
+            .iter()
+            .map(|block_hash| {
+                self.blocks
+                    .get(block_hash)
+                    .expect("retracted blocks are known")
+            })
+            .collect();
+        self.reorganize(retracted, enacted);
+    }
+
+    /// Bring the pool's nonces up to date with the new best state, requeue the transactions
+    /// from the retracted blocks, given newest first, and remove the transactions in the
+    /// enacted blocks from the pool. Then evict the transactions that do
+    /// not apply to the new best state, and tell the subscribers.
+    ///
+    /// The retracted blocks are passed whole because they may already have been discarded,
+    /// when finality moves past them.
+    pub(super) fn reorganize(&mut self, retracted: Vec<Block<C, SM>>, enacted: Vec<Hash>) {
+        let enacted_blocks: Vec<_> = enacted
+            .iter()
+            .map(|block_hash| self.blocks.get(block_hash).expect("enacted blocks are known"))
+            .collect();
+        // The pool has to know the new best state's nonces before the retracted transactions
+        // go back in, or it refuses them as already used.
+        let best_state = self
+            .state_at(self.best_block())
+            .expect("the best block's state can always be recomputed");
+        let touched: Vec<_> = retracted
+            .iter()
+            .chain(&enacted_blocks)
+            .flat_map(|block| block.body().iter().cloned())
+            .collect();
+        self.transaction_pool
+            .resync_nonces(&touched, |account| SM::nonce(&best_state, account));
+
+        // Requeue the oldest transactions first, so that they keep their order in the pool.
+        for block in retracted.iter().rev() {
+            for t in block.body() {
+                self.transaction_pool.try_insert(t.clone());
+            }
+        }
+        for block in &enacted_blocks {
+            for t in block.body() {
+                self.transaction_pool.remove(t.clone());
+            }
+        }
+        self.pending_state = Some(self.revalidate_pool());
+
+        let event = if retracted.is_empty() {
+            ChainEvent::NewBest(self.best_block())
+        } else {
+            ChainEvent::Reorg {
+                retracted: retracted.iter().map(Block::hash).collect(),
+                enacted,
+            }
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{
+        AccountedCurrency, AccountingTransaction, Balances, Ledger,
+    },
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::{NoncePool, SimplePool},
+        p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+#[cfg(test)]
+fn ledger_client() -> LedgerClient {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(
+        (),
+        LongestChain::default(),
+        SimplePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn alice_transfer(nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(
+        Keyring::Alice.pair(),
+        Keyring::Bob.to_account_id(),
+        10,
+        nonce,
+    )
+}
+
+/// Author a fork with the given bodies on top of genesis on another client, and import its
+/// blocks into the given client. Returns the hashes of the new blocks.
+#[cfg(test)]
+fn import_fork<P: TransactionPool<AccountedCurrency>>(
+    client: &mut FullClient<(), AccountedCurrency, LongestChain, P>,
+    bodies: Vec<Vec<AccountingTransaction>>,
+) -> Vec<Hash> {
+    let mut author = ledger_client();
+    author.set_author(Keyring::Charlie.to_account_id());
+    let mut parent = author.genesis();
+    let mut fork = Vec::new();
+    for body in bodies {
+        parent = author.author_and_import_manual_block(body, parent).unwrap();
+        assert!(client.import_block(author.get_block(parent).unwrap()));
+        fork.push(parent);
+    }
+    fork
+}
+
+#[test]
+fn cl_16_tree_route_goes_through_common_ancestor() {
+    let mut client = ledger_client();
+    let genesis = client.genesis();
+    let b1 = client
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+    let b2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+    let fork = import_fork(&mut client, vec![vec![], vec![], vec![]]);
+
+    assert_eq!(client.tree_route(b2, fork[2]), (vec![b2, b1], fork.clone()));
+    assert_eq!(
+        client.tree_route(fork[1], b2),
+        (vec![fork[1], fork[0]], vec![b1, b2])
+    );
+    assert_eq!(client.tree_route(b1, b2), (vec![], vec![b2]));
+    assert_eq!(client.tree_route(b2, b2), (vec![], vec![]));
+}
+
+#[test]
+fn cl_16_extending_best_notifies_subscribers() {
+    let mut client = ledger_client();
+    let events = client.subscribe();
+    let genesis = client.genesis();
+    let b1 = client
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+    // A shorter fork does not move the best block, so it is not announced.
+    import_fork(&mut client, vec![vec![]]);
+
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![ChainEvent::NewBest(b1)]
+    );
+}
+
+#[test]
+fn cl_16_reorg_requeues_retracted_transactions() {
+    let mut client = ledger_client();
+    let events = client.subscribe();
+    let genesis = client.genesis();
+    let b1 = client
+        .author_and_import_manual_block(vec![alice_transfer(0), alice_transfer(1)], genesis)
+        .unwrap();
+    events.try_iter().for_each(drop);
+
+    let fork = import_fork(&mut client, vec![vec![alice_transfer(0)], vec![]]);
+
+    assert_eq!(client.best_block(), fork[1]);
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![ChainEvent::Reorg {
+            retracted: vec![b1],
+            enacted: fork,
+        }]
+    );
+    // The first transfer is in the new best chain, but the second one has to be included again.
+    assert!(!client.pool_contains(alice_transfer(0)));
+    assert!(client.pool_contains(alice_transfer(1)));
+    assert_eq!(client.pool_size(), 1);
+}
+
+#[test]
+fn cl_16_reorg_requeues_into_nonce_pool() {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client = FullClient::<_, AccountedCurrency, _, _, _>::new(
+        (),
+        LongestChain::default(),
+        NoncePool::new(),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap();
+    assert!(client.submit_transaction(alice_transfer(0)));
+    assert!(client.submit_transaction(alice_transfer(1)));
+    client.author_and_import_automatic_block().unwrap();
+    assert_eq!(client.transaction_pool.next_nonce(&Keyring::Alice.to_account_id()), 2);
+
+    import_fork(&mut client, vec![vec![alice_transfer(0)], vec![]]);
+
+    // The pool went back to Alice's nonce on the new chain before her transfers were requeued,
+    // so her second one is ready to be included again rather than refused as already used.
+    assert_eq!(client.transaction_pool.future_size(), 0);
+    assert!(!client.pool_contains(alice_transfer(0)));
+    assert!(client.pool_contains(alice_transfer(1)));
+    let b3 = client.author_and_import_automatic_block().unwrap();
+    assert_eq!(client.get_block(b3).unwrap().body(), &[alice_transfer(1)]);
+}
+
+#[test]
+fn cl_16_finality_reorg_requeues_discarded_transactions() {
+    let mut client = ledger_client();
+    let genesis = client.genesis();
+    let b1 = client
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+    let fork = import_fork(
+        &mut client,
+        vec![vec![alice_transfer(0)], vec![alice_transfer(1)]],
+    );
+    assert_eq!(client.best_block(), fork[1]);
+    assert_eq!(client.pool_size(), 0);
+    let events = client.subscribe();
+
+    // Finalizing b1 discards the fork, and with it the best block.
+    assert!(client.manually_finalize_block(b1));
+
+    assert_eq!(client.best_block(), b1);
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![ChainEvent::Reorg {
+            retracted: vec![fork[1], fork[0]],
+            enacted: vec![b1],
+        }]
+    );
+    assert!(client.pool_contains(alice_transfer(0)));
+    assert!(client.pool_contains(alice_transfer(1)));
+}
+
+#[test]
+fn cl_16_dropped_subscribers_are_forgotten() {
+    let mut client = ledger_client();
+    let kept = client.subscribe();
+    drop(client.subscribe());
+    let genesis = client.genesis();
+    let b1 = client
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+
+    assert_eq!(client.subscribers.len(), 1);
+    assert_eq!(kept.try_recv(), Ok(ChainEvent::NewBest(b1)));
+}
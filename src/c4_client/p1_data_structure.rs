@@ -16,81 +16,419 @@
 //!
 //! This abstraction is the key idea behind blockchain _frameworks_ like Substrate or the Cosmos SDK.
 
-use super::{Consensus, ForkChoice, Header, StateMachine};
+use super::{
+    p7_block_store::BlockStore, p8_state_pruning::PruningMode, Consensus, ForkChoice, Header,
+    StateMachine,
+};
 
 use super::FullClient;
-type Hash = u64;
+use crate::{
+    codec::{Decode, DecodeError, Encode},
+    crypto::{merkle_root, MerkleProof},
+    hash, AccountId, Hash,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    marker::PhantomData,
+};
 
 impl<Digest> Header<Digest> {
     /// Returns a new valid genesis header.
-    fn genesis(genesis_state_root: Hash) -> Self {
-        Header{
-
+    fn genesis(genesis_state_root: Hash) -> Self
+    where
+        Digest: Default,
+    {
+        Header {
+            parent: Hash::default(),
+            height: 0,
+            state_root: genesis_state_root,
+            extrinsics_root: merkle_root::<()>(&[]),
+            author: AccountId::default(),
+            consensus_digest: Digest::default(),
         }
     }
 
     /// Create and return a valid child header.
-    fn child(&self, state_root: Hash, extrinsics_root: Hash) -> Self {
-        Header{
-            parent: self.parent,
+    ///
+    /// The child is returned without a consensus digest so that it can be
+    /// handed straight to a consensus engine for sealing.
+    fn child(&self, state_root: Hash, extrinsics_root: Hash, author: AccountId) -> Header<()>
+    where
+        Digest: Encode,
+    {
+        Header {
+            parent: hash(self),
             height: self.height + 1,
-            extrinsics_root,
             state_root,
-            consensus_digest: 0,
+            extrinsics_root,
+            author,
+            consensus_digest: (),
         }
     }
 
     /// Verify a single child header.
-    fn verify_child(&self, child: &Self) -> bool {
-        todo!("Exercise 3")
+    pub(super) fn verify_child(&self, child: &Self) -> bool
+    where
+        Digest: Encode,
+    {
+        self.height + 1 == child.height && hash(self) == child.parent
     }
 
     /// Verify that all the given headers form a valid chain from this header to the tip.
-    fn verify_sub_chain(&self, chain: &[Self]) -> bool {
-        todo!("Exercise 4")
+    fn verify_sub_chain(&self, chain: &[Self]) -> bool
+    where
+        Digest: Encode,
+    {
+        let mut parent = self;
+        for header in chain {
+            if !parent.verify_child(header) {
+                return false;
+            }
+            parent = header;
+        }
+        true
     }
+
+    /// Check a proof that the given extrinsic is in the body of the block with this header.
+    pub fn verify_extrinsic<T: Encode>(&self, extrinsic: &T, proof: &MerkleProof) -> bool {
+        proof.verify(&self.extrinsics_root, extrinsic)
+    }
+}
+
+/// The reasons a block may fail to be built, verified, or imported.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockError<E> {
+    /// The header does not correctly extend its parent's header.
+    InvalidHeader,
+    /// The extrinsics root in the header does not commit to the body.
+    InvalidExtrinsicsRoot,
+    /// The extrinsic at the given index could not be applied. The state machine's
+    /// reason for refusing it is included.
+    InvalidExtrinsic(usize, E),
+    /// The fees of the extrinsics add up to more than can be represented.
+    FeeOverflow,
+    /// The state machine could not pay the fees and the block reward to the block's author.
+    /// Its reason is included.
+    InvalidPayout(E),
+    /// The state root in the header does not match the post state.
+    InvalidStateRoot,
+    /// The consensus engine could not seal the block.
+    SealFailed,
+    /// The block's parent is not known to the client.
+    UnknownParent,
+    /// The block's parent is below the last finalized block, so the block would revert it.
+    ConflictsWithFinality,
+    /// The consensus engine does not accept the block's seal.
+    InvalidSeal,
+    /// The block may well be valid, but the client could not write it to its block store.
+    StoreFailed,
 }
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Block<C: Consensus, SM: StateMachine> {
     header: Header<C::Digest>,
     body: Vec<SM::Transition>,
 }
 
+// Implemented by hand because deriving would needlessly require the engine and the state
+// machine themselves to be `Clone`.
+impl<C: Consensus, SM: StateMachine> Clone for Block<C, SM>
+where
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        Block {
+            header: self.header.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
 impl<C: Consensus, SM: StateMachine> Block<C, SM> {
+    /// Execute this block's body on top of its parent's post state, and pay its author. This
+    /// does not check that the result matches the state root.
+    pub fn execute(&self, pre_state: &SM::State) -> Result<SM::State, BlockError<SM::Error>>
+    where
+        SM::State: Clone,
+    {
+        execute::<SM>(pre_state, &self.body, &self.header.author)
+    }
+
+    pub fn header(&self) -> &Header<C::Digest> {
+        &self.header
+    }
+
+    pub fn body(&self) -> &[SM::Transition] {
+        &self.body
+    }
+
+    /// The hash of this block, which is the hash of its header.
+    pub fn hash(&self) -> Hash {
+        hash(&self.header)
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Encode for Block<C, SM>
+where
+    SM::Transition: Encode,
+{
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.header.encode_to(dest);
+        self.body.encode_to(dest);
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Decode for Block<C, SM>
+where
+    SM::Transition: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Header::decode(input)?,
+            body: Vec::decode(input)?,
+        })
+    }
+}
+
+impl<C, SM> Block<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Encode,
+{
     /// Returns a new valid genesis block. By convention this block has no extrinsics.
-    pub fn genesis(genesis_state: &SM::State) -> Self {
-        todo!("Exercise 5")
+    pub fn genesis(genesis_state: &SM::State) -> Self
+    where
+        C::Digest: Default,
+    {
+        Block {
+            header: Header::genesis(SM::state_root(genesis_state)),
+            body: Vec::new(),
+        }
+    }
+
+    /// Create and return a valid child block sealed by the given consensus engine. The given
+    /// author collects the block's fees and reward.
+    ///
+    /// Fails if any of the extrinsics cannot be applied, in which case the offending
+    /// extrinsic's index and the state machine's error are reported.
+    pub fn child(
+        &self,
+        consensus_engine: &C,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        author: AccountId,
+    ) -> Result<Self, BlockError<SM::Error>> {
+        let partial_header = self.unsealed_child(pre_state, &extrinsics, author)?;
+        let header = consensus_engine
+            .seal(&self.header.consensus_digest, partial_header)
+            .ok_or(BlockError::SealFailed)?;
+
+        Ok(Block::from_parts(header, extrinsics))
+    }
+
+    /// Execute the given extrinsics on top of this block and return the child's partial
+    /// header, ready for a consensus engine to seal.
+    pub(super) fn unsealed_child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: &[SM::Transition],
+        author: AccountId,
+    ) -> Result<Header<()>, BlockError<SM::Error>> {
+        let post_state = execute::<SM>(pre_state, extrinsics, &author)?;
+        Ok(self
+            .header
+            .child(SM::state_root(&post_state), merkle_root(extrinsics), author))
     }
 
-    /// Create and return a valid child block.
-    pub fn child(&self, pre_state: &SM::State, extrinsics: Vec<u8>) -> Self {
-        todo!("Exercise 6")
+    /// Put a sealed header together with the body it commits to.
+    pub(super) fn from_parts(header: Header<C::Digest>, body: Vec<SM::Transition>) -> Self {
+        Block { header, body }
+    }
+
+    /// Verify a single child block, returning its post state when it is valid.
+    ///
+    /// This checks ancestry, the extrinsics root, execution, and the state root. It
+    /// does not check the consensus seal. That is the consensus engine's job.
+    pub fn verify_child(
+        &self,
+        pre_state: &SM::State,
+        child: &Self,
+    ) -> Result<SM::State, BlockError<SM::Error>> {
+        if !self.header.verify_child(&child.header) {
+            return Err(BlockError::InvalidHeader);
+        }
+        if merkle_root(&child.body) != child.header.extrinsics_root {
+            return Err(BlockError::InvalidExtrinsicsRoot);
+        }
+        let post_state = execute::<SM>(pre_state, &child.body, &child.header.author)?;
+        if SM::state_root(&post_state) != child.header.state_root {
+            return Err(BlockError::InvalidStateRoot);
+        }
+        Ok(post_state)
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     pub fn verify_sub_chain(&self, pre_state: &SM::State, chain: &[Self]) -> bool {
-        todo!("Exercise 7")
+        if SM::state_root(pre_state) != self.header.state_root {
+            return false;
+        }
+        let mut parent = self;
+        let mut state = None;
+        for block in chain {
+            match parent.verify_child(state.as_ref().unwrap_or(pre_state), block) {
+                Ok(post_state) => state = Some(post_state),
+                Err(_) => return false,
+            }
+            parent = block;
+        }
+        true
+    }
+
+    /// Prove that the extrinsic at the given index is in this block, so that it can be
+    /// checked against the header alone.
+    pub fn extrinsic_proof(&self, index: usize) -> Option<MerkleProof> {
+        MerkleProof::new(&self.body, index)
     }
 }
 
+/// Apply the given extrinsics to the pre state one after another, then pay the block's fees
+/// and reward to its author.
+fn execute<SM: StateMachine>(
+    pre_state: &SM::State,
+    extrinsics: &[SM::Transition],
+    author: &AccountId,
+) -> Result<SM::State, BlockError<SM::Error>>
+where
+    SM::State: Clone,
+{
+    let post_state = extrinsics
+        .iter()
+        .enumerate()
+        .try_fold(pre_state.clone(), |state, (i, t)| {
+            SM::next_state(&state, t).map_err(|e| BlockError::InvalidExtrinsic(i, e))
+        })?;
+    let fees = extrinsics
+        .iter()
+        .try_fold(0u64, |fees, t| fees.checked_add(SM::fee(t)))
+        .ok_or(BlockError::FeeOverflow)?;
+    SM::finalize_block(post_state, author, fees).map_err(BlockError::InvalidPayout)
+}
+
 /// Create and return a block chain that is n blocks long starting from the given genesis state.
 /// The blocks should not contain any transactions.
 fn create_empty_chain<C: Consensus, SM: StateMachine>(
+    consensus_engine: &C,
     n: u64,
     genesis_state: &SM::State,
-) -> Vec<Block<C, SM>> {
-    todo!("Exercise 8")
+) -> Vec<Block<C, SM>>
+where
+    C::Digest: Default,
+    SM::State: Clone + Encode,
+    SM::Transition: Encode,
+{
+    let mut chain = vec![Block::<C, SM>::genesis(genesis_state)];
+    for _ in 0..n {
+        let child = chain
+            .last()
+            .expect("chain always contains genesis")
+            .child(consensus_engine, genesis_state, Vec::new(), AccountId::default())
+            .expect("empty blocks can always be built with a trivial consensus engine");
+        chain.push(child);
+    }
+    chain
 }
 
 // To wrap this section up, we will implement the first two simple methods on our client.
 // These methods simply create a new instance of the client initialized with a proper
 // genesis block.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P>
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
 where
+    C: Consensus,
+    C::Digest: Default,
     SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Encode,
+    FC: ForkChoice<C>,
+    S: BlockStore<C, SM>,
 {
-    fn new(genesis_state: SM::State) -> Self {
-        todo!("Exercise 9")
+    /// Create a client that keeps its blocks in the given store.
+    ///
+    /// An empty store is initialized with the genesis block. A store that already holds a
+    /// chain, for example from before a restart, is reopened. Its blocks are replayed on top
+    /// of the genesis state to rebuild the states, the leaves, and the fork choice, and the
+    /// finality recorded in its metadata is restored.
+    ///
+    /// Fails if the store cannot be written, if it holds a chain with a different genesis,
+    /// or if one of its blocks does not correctly extend its parent.
+    pub fn new(
+        consensus_engine: C,
+        fork_choice: FC,
+        transaction_pool: P,
+        block_store: S,
+        genesis_state: SM::State,
+    ) -> io::Result<Self> {
+        let genesis = Block::genesis(&genesis_state);
+        let genesis_hash = genesis.hash();
+        let mut client = FullClient {
+            consensus_engine,
+            state_machine: PhantomData,
+            fork_choice,
+            transaction_pool,
+            blocks: block_store,
+            states: HashMap::from([(genesis_hash, genesis_state)]),
+            pruning: PruningMode::Archive,
+            leaves: HashSet::from([genesis_hash]),
+            genesis: genesis_hash,
+            finalized: genesis_hash,
+            justifications: HashMap::new(),
+            author: AccountId::default(),
+            subscribers: Vec::new(),
+            pending_state: None,
+        };
+
+        let stored = client.blocks.hashes();
+        match stored.first() {
+            None => client.blocks.insert(genesis)?,
+            Some(first) if *first == genesis_hash => (),
+            Some(_) => return Err(invalid_store("the block store holds a chain with a different genesis")),
+        }
+        for block_hash in stored.iter().skip(1) {
+            let block = client
+                .blocks
+                .get(block_hash)
+                .ok_or_else(|| invalid_store("the block store lost a block it listed"))?;
+            let parent_hash = block.header().parent;
+            let (Some(parent), Some(pre_state)) = (client.blocks.get(&parent_hash), client.states.get(&parent_hash))
+            else {
+                return Err(invalid_store("the block store holds a block before its parent"));
+            };
+            let post_state = parent
+                .verify_child(pre_state, &block)
+                .map_err(|e| invalid_store(&format!("the block store holds an invalid block: {e:?}")))?;
+            client.record_block(block, post_state)?;
+        }
+        client.restore_finality()?;
+        Ok(client)
+    }
+}
+
+pub(super) fn invalid_store(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> FullClient<C, SM, FC, P, S> {
+    /// Set the account that collects the fees and rewards for blocks this client authors.
+    /// Until this is called, they go to the default account, which nobody can spend from.
+    pub fn set_author(&mut self, author: AccountId) {
+        self.author = author;
+    }
+
+    /// The hash of the genesis block.
+    pub fn genesis(&self) -> Hash {
+        self.genesis
     }
 }
 
@@ -98,15 +436,146 @@ where
 // Depending on the state machine definition there may not _be_ a default
 // genesis state. There is only a default client when there is also a
 // default genesis state.
-impl<C, SM, FC, P> Default for FullClient<C, SM, FC, P>
+impl<C, SM, FC, P, S> Default for FullClient<C, SM, FC, P, S>
 where
-    C: Consensus,
+    C: Consensus + Default,
+    C::Digest: Default,
     SM: StateMachine,
-    FC: ForkChoice<C>,
+    SM::State: Clone + Default + Encode,
+    SM::Transition: Encode,
+    FC: ForkChoice<C> + Default,
+    P: Default,
+    S: BlockStore<C, SM> + Default,
 {
     fn default() -> Self {
-        todo!("Exerise 10")
+        Self::new(C::default(), FC::default(), P::default(), S::default(), SM::State::default())
+            .expect("a fresh block store accepts the genesis block")
+    }
+}
+
+/// A tiny fallible state machine used to exercise the block logic. It is a counter that
+/// refuses to be decremented below zero.
+#[cfg(test)]
+struct Counter;
+
+#[cfg(test)]
+impl StateMachine for Counter {
+    type State = u64;
+    type Transition = i64;
+    type Error = ();
+
+    fn next_state(starting_state: &u64, t: &i64) -> Result<u64, ()> {
+        starting_state.checked_add_signed(*t).ok_or(())
     }
 }
 
-//TODO tests
+#[test]
+fn cl_1_empty_chain_is_valid() {
+    let chain = create_empty_chain::<(), Counter>(&(), 3, &0);
+
+    assert_eq!(chain.len(), 4);
+    assert!(chain[0].verify_sub_chain(&0, &chain[1..]));
+}
+
+#[test]
+fn cl_1_child_block_executes_extrinsics() {
+    let g = Block::<(), Counter>::genesis(&0);
+    let b1 = g.child(&(), &0, vec![5, -2], AccountId::default()).unwrap();
+
+    assert_eq!(b1.header.height, 1);
+    assert_eq!(b1.header.state_root, hash(&3u64));
+    assert_eq!(g.verify_child(&0, &b1), Ok(3));
+}
+
+#[test]
+fn cl_1_child_block_rejects_invalid_extrinsic() {
+    let g = Block::<(), Counter>::genesis(&0);
+
+    assert_eq!(
+        g.child(&(), &0, vec![5, -6], AccountId::default()).err(),
+        Some(BlockError::InvalidExtrinsic(1, ()))
+    );
+}
+
+#[test]
+fn cl_1_verify_rejects_invalid_extrinsic() {
+    let g = Block::<(), Counter>::genesis(&0);
+    let mut b1 = g.child(&(), &0, vec![5], AccountId::default()).unwrap();
+    b1.body = vec![-5];
+    b1.header.extrinsics_root = merkle_root(&b1.body);
+
+    assert_eq!(
+        g.verify_child(&0, &b1),
+        Err(BlockError::InvalidExtrinsic(0, ()))
+    );
+    assert!(!g.verify_sub_chain(&0, &[b1]));
+}
+
+#[test]
+fn cl_1_verify_rejects_wrong_state_root() {
+    let g = Block::<(), Counter>::genesis(&0);
+    let mut b1 = g.child(&(), &0, vec![5], AccountId::default()).unwrap();
+    b1.header.state_root = hash(&6u64);
+
+    assert_eq!(g.verify_child(&0, &b1), Err(BlockError::InvalidStateRoot));
+}
+
+#[test]
+fn cl_1_block_round_trips_through_codec() {
+    let g = Block::<(), Counter>::genesis(&0);
+    let b1 = g.child(&(), &0, vec![5, -2], AccountId::default()).unwrap();
+    let decoded = Block::<(), Counter>::decode_all(&b1.encode()).unwrap();
+
+    assert_eq!(decoded.header, b1.header);
+    assert_eq!(decoded.body, b1.body);
+}
+
+#[test]
+fn cl_1_extrinsic_inclusion_proof() {
+    let g = Block::<(), Counter>::genesis(&0);
+    let b1 = g.child(&(), &0, vec![5, -2, 7], AccountId::default()).unwrap();
+    let proof = b1.extrinsic_proof(2).unwrap();
+
+    assert!(b1.header.verify_extrinsic(&7i64, &proof));
+    assert!(!b1.header.verify_extrinsic(&-2i64, &proof));
+}
+
+#[test]
+fn cl_1_child_block_pays_author() {
+    use crate::{
+        c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+        Keyring,
+    };
+
+    let genesis_state = Ledger {
+        balances: Balances::from([(Keyring::Alice.to_account_id(), 100)]),
+        block_reward: 10,
+        ..Ledger::default()
+    };
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_state);
+    let transfer =
+        AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 50, 5, 0);
+    let b1 = g
+        .child(&(), &genesis_state, vec![transfer], Keyring::Charlie.to_account_id())
+        .unwrap();
+    let post_state = g.verify_child(&genesis_state, &b1).unwrap();
+
+    assert_eq!(b1.header.author, Keyring::Charlie.to_account_id());
+    assert_eq!(
+        post_state.balances,
+        Balances::from([
+            (Keyring::Alice.to_account_id(), 45),
+            (Keyring::Bob.to_account_id(), 50),
+            (Keyring::Charlie.to_account_id(), 15),
+        ])
+    );
+
+    // Claiming somebody else's reward changes the post state, so it does not verify.
+    let mut stolen = b1.clone();
+    stolen.header.author = Keyring::Dave.to_account_id();
+    assert_eq!(
+        g.verify_child(&genesis_state, &stolen),
+        Err(BlockError::InvalidStateRoot)
+    );
+}
+
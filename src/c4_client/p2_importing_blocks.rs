@@ -1,7 +1,12 @@
 //! We being implementing our client with the most fundamental task, which is importing
 //! blocks and headers. Full clients import entire blocks while light clients only import headers.
 
-use super::{Block, Consensus, FullClient, StateMachine};
+use super::{
+    p1_data_structure::BlockError, p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore, Block, Consensus, ForkChoice, FullClient, Hash, StateMachine,
+};
+use crate::codec::Encode;
+use std::io;
 
 /// A trait that represents the ability to import complete blocks of the chain.
 ///
@@ -14,48 +19,129 @@ pub trait ImportBlock<C: Consensus, SM: StateMachine> {
 
     /// Retrieve the full body of an imported block.
     /// Returns None if the block is not known.
-    fn get_block(&self, block_hash: u64) -> Option<Block<C, SM>>;
+    fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>>;
 
     /// Retrieve the state associated with a given block.
     /// Returns None if the block is not known.
-    fn get_state(&self, block_hash: u64) -> Option<SM::State>;
+    fn get_state(&self, block_hash: Hash) -> Option<SM::State>;
 
     /// Check whether a given block is a leaf (aka tip) of the chain.
     /// A leaf block has no known children.
     /// Returns None if the block is not known.
-    fn is_leaf(&self, block_hash: u64) -> Option<bool>;
+    fn is_leaf(&self, block_hash: Hash) -> Option<bool>;
 
     /// Get a list of all the leaf nodes in the chain.
-    fn all_leaves(&self) -> Vec<u64>;
+    fn all_leaves(&self) -> Vec<Hash>;
 }
 
-impl<C, SM, FC, P> ImportBlock<C, SM> for FullClient<C, SM, FC, P>
+impl<C, SM, FC, P, S> ImportBlock<C, SM> for FullClient<C, SM, FC, P, S>
     where
     C: Consensus,
     SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
 {
-    fn import_block(&mut self, _: Block<C, SM>) -> bool {
-        todo!("Exercise 1")
+    /// A block is imported when its parent is known, its seal is valid, and it correctly
+    /// extends its parent. A block that does not descend from the last finalized block is
+    /// rejected, because following it would revert a finalized block.
+    ///
+    /// If the new block changes the best block, the pool and the subscribers are brought up
+    /// to date, as described in the re-org section. Only then are its transactions removed
+    /// from the pool. A block on a fork that is not best leaves the pool alone, because its
+    /// transactions have not happened on the best chain.
+    /// Importing a block that is already known succeeds without doing anything.
+    ///
+    /// A block that cannot be written to the block store is not imported.
+    fn import_block(&mut self, block: Block<C, SM>) -> bool {
+        self.try_import_block(block).is_ok()
     }
 
-    fn get_block(&self, block_hash: u64) -> Option<Block<C, SM>> {
-        todo!("Exercise 2")
+    fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>> {
+        self.blocks.get(&block_hash)
     }
 
-    fn get_state(&self, block_hash: u64) -> Option<<SM as StateMachine>::State> {
-        todo!("Exercise 3")
+    fn get_state(&self, block_hash: Hash) -> Option<<SM as StateMachine>::State> {
+        self.state_at(block_hash)
     }
 
-    fn is_leaf(&self, block_hash: u64) -> Option<bool> {
-        todo!("Exercise 4")
+    fn is_leaf(&self, block_hash: Hash) -> Option<bool> {
+        self.blocks
+            .contains(&block_hash)
+            .then(|| self.leaves.contains(&block_hash))
     }
 
-    fn all_leaves(&self) -> Vec<u64> {
-        todo!("Exercise 5")
+    fn all_leaves(&self) -> Vec<Hash> {
+        self.leaves.iter().copied().collect()
     }
 }
 
-// TODO Write these tests.
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Import a block exactly like `import_block`, but report why it was not imported. That
+    /// tells a block that is invalid apart from one the client merely failed to store.
+    pub fn try_import_block(&mut self, block: Block<C, SM>) -> Result<(), BlockError<SM::Error>> {
+        if self.blocks.contains(&block.hash()) {
+            return Ok(());
+        }
+        let parent_hash = block.header().parent;
+        let (Some(parent), Some(pre_state)) = (self.blocks.get(&parent_hash), self.state_at(parent_hash)) else {
+            return Err(BlockError::UnknownParent);
+        };
+        if !self.extends_finalized(parent.header().height) {
+            return Err(BlockError::ConflictsWithFinality);
+        }
+        if !self
+            .consensus_engine
+            .validate(&parent.header().consensus_digest, block.header())
+        {
+            return Err(BlockError::InvalidSeal);
+        }
+        let post_state = parent.verify_child(&pre_state, &block)?;
+
+        let old_best = self.best_block();
+        self.record_block(block, post_state)
+            .map_err(|_| BlockError::StoreFailed)?;
+        self.follow_best(old_best);
+        Ok(())
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    FC: ForkChoice<C>,
+    S: BlockStore<C, SM>,
+{
+    /// Add a block that is already known to be valid, along with its post state. The block is
+    /// written to the store first, so nothing else changes if that fails. Afterwards, states
+    /// that have fallen out of the pruning window are dropped.
+    pub(super) fn record_block(&mut self, block: Block<C, SM>, post_state: SM::State) -> io::Result<()> {
+        let block_hash = block.hash();
+        let header = block.header().clone();
+        self.blocks.insert(block)?;
+        self.leaves.remove(&header.parent);
+        self.leaves.insert(block_hash);
+        self.states.insert(block_hash, post_state);
+        self.fork_choice.import_hook(header);
+        self.prune_states();
+        Ok(())
+    }
+}
+
+// TODO Write more of these tests.
 
 // Test ideas:
 // import valid block
@@ -75,3 +161,139 @@ impl<C, SM, FC, P> ImportBlock<C, SM> for FullClient<C, SM, FC, P>
 // Import a forked chain and make sure both leaves' statuses are right.
 
 // Same previous 4 scenarios except with the `all_leaves` method.
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c3_consensus::SimplePoa,
+    c4_client::{p3_fork_choice::LongestChain, p4_transaction_pool::SimplePool, p7_block_store::MemoryStore},
+    Keyring,
+};
+
+#[cfg(test)]
+type PoaClient = FullClient<SimplePoa, AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A client whose only authority is Alice, signing with the given key if any.
+#[cfg(test)]
+fn alice_poa_client(signer: Option<Keyring>) -> PoaClient {
+    let engine = SimplePoa {
+        authorities: vec![Keyring::Alice.to_account_id()],
+        signer: signer.map(|k| k.pair().clone()),
+    };
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new(engine, LongestChain::default(), SimplePool::new(), MemoryStore::new(), genesis_state).unwrap()
+}
+
+#[cfg(test)]
+fn alice_transfer(nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, nonce)
+}
+
+#[test]
+fn cl_2_genesis_is_only_leaf() {
+    let client = alice_poa_client(None);
+    let genesis = client.genesis();
+
+    assert!(client.get_block(genesis).is_some());
+    assert_eq!(client.is_leaf(genesis), Some(true));
+    assert_eq!(client.all_leaves(), vec![genesis]);
+    assert_eq!(client.is_leaf(Hash::default()), None);
+    assert!(client.get_block(Hash::default()).is_none());
+}
+
+#[test]
+fn cl_2_import_valid_block() {
+    let mut author = alice_poa_client(Some(Keyring::Alice));
+    let b1 = author
+        .author_and_import_manual_block(vec![alice_transfer(0)], author.genesis())
+        .unwrap();
+
+    let mut follower = alice_poa_client(None);
+    let genesis = follower.genesis();
+    assert!(follower.import_block(author.get_block(b1).unwrap()));
+
+    assert_eq!(follower.get_state(b1), author.get_state(b1));
+    assert_eq!(follower.is_leaf(genesis), Some(false));
+    assert_eq!(follower.all_leaves(), vec![b1]);
+}
+
+#[test]
+fn cl_2_import_block_with_unknown_parent_fails() {
+    let mut author = alice_poa_client(Some(Keyring::Alice));
+    let b1 = author.author_and_import_manual_block(vec![], author.genesis()).unwrap();
+    let b2 = author.author_and_import_manual_block(vec![], b1).unwrap();
+
+    let mut follower = alice_poa_client(None);
+    assert!(!follower.import_block(author.get_block(b2).unwrap()));
+    assert!(follower.get_block(b2).is_none());
+}
+
+#[test]
+fn cl_2_import_block_with_invalid_seal_fails() {
+    // Bob is not an authority on the follower's chain, so his seals are worthless there.
+    let mut rogue = alice_poa_client(Some(Keyring::Bob));
+    rogue.consensus_engine.authorities.push(Keyring::Bob.to_account_id());
+    let b1 = rogue.author_and_import_manual_block(vec![], rogue.genesis()).unwrap();
+
+    let mut follower = alice_poa_client(None);
+    assert!(!follower.import_block(rogue.get_block(b1).unwrap()));
+}
+
+#[test]
+fn cl_2_import_forks_tracks_leaves() {
+    let mut author = alice_poa_client(Some(Keyring::Alice));
+    let genesis = author.genesis();
+    let a1 = author.author_and_import_manual_block(vec![], genesis).unwrap();
+    let a2 = author.author_and_import_manual_block(vec![], a1).unwrap();
+    let b1 = author
+        .author_and_import_manual_block(vec![alice_transfer(0)], genesis)
+        .unwrap();
+
+    let mut leaves = author.all_leaves();
+    leaves.sort();
+    let mut expected = vec![a2, b1];
+    expected.sort();
+
+    assert_eq!(leaves, expected);
+    assert_eq!(author.is_leaf(a1), Some(false));
+    assert_eq!(author.is_leaf(genesis), Some(false));
+}
+
+#[test]
+fn cl_2_importing_best_block_removes_its_transactions_from_pool() {
+    let mut author = alice_poa_client(Some(Keyring::Alice));
+    let b1 = author
+        .author_and_import_manual_block(vec![alice_transfer(0)], author.genesis())
+        .unwrap();
+
+    let mut follower = alice_poa_client(None);
+    assert!(follower.submit_transaction(alice_transfer(0)));
+    assert!(follower.submit_transaction(alice_transfer(1)));
+    assert!(follower.import_block(author.get_block(b1).unwrap()));
+
+    assert!(!follower.pool_contains(alice_transfer(0)));
+    assert_eq!(follower.pool_size(), 1);
+}
+
+#[test]
+fn cl_2_importing_fork_block_keeps_its_transactions_in_pool() {
+    let mut author = alice_poa_client(Some(Keyring::Alice));
+    let genesis = author.genesis();
+    let a1 = author
+        .author_and_import_manual_block(vec![], genesis)
+        .unwrap();
+    let a2 = author.author_and_import_manual_block(vec![], a1).unwrap();
+    let b1 = author
+        .author_and_import_manual_block(vec![alice_transfer(0)], genesis)
+        .unwrap();
+
+    let mut follower = alice_poa_client(None);
+    assert!(follower.submit_transaction(alice_transfer(0)));
+    for block_hash in [a1, a2, b1] {
+        assert!(follower.import_block(author.get_block(block_hash).unwrap()));
+    }
+
+    // The transfer only happened on the shorter fork, so it still waits to be included.
+    assert_eq!(follower.best_block(), a2);
+    assert!(follower.pool_contains(alice_transfer(0)));
+}
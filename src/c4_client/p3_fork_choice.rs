@@ -4,8 +4,11 @@
 //! The concepts are identical here, but now that we have a client tracking a proper block database,
 //! we can explore more advanced fork choice algorithms. In particular, we can now explore GHOST.
 
-use super::{Header, FullClient, Consensus};
-use crate::c3_consensus::{Pow, SimplePoa, ConsensusAuthority};
+use super::{Header, FullClient, Consensus, Hash, StateMachine};
+use crate::{
+    c3_consensus::{AuthoritySeal, Pow, SimplePoa},
+    hash,
+};
 
 /// A means for a blockchain client to decide which chain is best among the many
 /// that it potentially knows about.
@@ -15,90 +18,180 @@ use crate::c3_consensus::{Pow, SimplePoa, ConsensusAuthority};
 ///
 /// Some implementations are light and just make a quick comparison, like the longest chain rule.
 /// Others are more complex and associate additional logic with block import, like GHOST.
+///
+/// The fork choice learns about blocks only through [`ForkChoice::import_hook`], so it must
+/// remember whatever it needs to pick the best one later. When a block is finalized, the client
+/// discards every block that conflicts with it and tells the fork choice through
+/// [`ForkChoice::finality_hook`], so a finalized block is never reverted.
 pub trait ForkChoice<C: Consensus> {
     /// Return the hash of the best block currently known according to this fork choice rule.
-    fn best_block(&self, header: Header<C::Digest>) -> Option<u64>;
+    /// Returns None if no blocks have been imported yet.
+    fn best_block(&self) -> Option<Hash>;
 
     /// Perform some bookkeeping activities when importing a new block.
     fn import_hook(&mut self, header: Header<C::Digest>);
+
+    /// Forget every block except the newly finalized one and its descendants. The descendants
+    /// are ordered by height, and blocks at the same height in the order they were imported.
+    /// From now on, the best block must be one of these blocks.
+    fn finality_hook(&mut self, finalized: Header<C::Digest>, descendants: Vec<Header<C::Digest>>);
 }
 
-/// The chain with the highest block height is the best
+/// The chain with the highest block height is the best. When two chains are equally long,
+/// the one that was seen first stays best.
+#[derive(Default)]
 pub struct LongestChain {
-    // You may add fields here if you need to.
+    /// The height and hash of the best block seen so far.
+    best: Option<(u64, Hash)>,
 }
 
 impl<C: Consensus> ForkChoice<C> for LongestChain {
-    fn best_block(&self, header: Header<C::Digest>) -> Option<u64> {
-        todo!("Exercise 1")
+    fn best_block(&self) -> Option<Hash> {
+        self.best.map(|(_, best)| best)
     }
 
     fn import_hook(&mut self, header: Header<C::Digest>) {
-        todo!("Exercise 2")
+        if self.best.is_none_or(|(height, _)| header.height > height) {
+            self.best = Some((header.height, hash(&header)));
+        }
+    }
+
+    /// Replaying the remaining blocks picks the same best block as before, unless that block
+    /// was discarded. Ties still go to the block that was imported first.
+    fn finality_hook(&mut self, finalized: Header<C::Digest>, descendants: Vec<Header<C::Digest>>) {
+        rebuild::<C, _>(self, finalized, descendants);
+    }
+}
+
+/// Start the given fork choice over and import the finalized block and its descendants again.
+/// A fork choice only learns about blocks through its import hook, so this leaves it exactly
+/// as if the discarded blocks had never been imported.
+fn rebuild<C: Consensus, FC: ForkChoice<C> + Default>(
+    fork_choice: &mut FC,
+    finalized: Header<C::Digest>,
+    descendants: Vec<Header<C::Digest>>,
+) {
+    *fork_choice = FC::default();
+    for header in std::iter::once(finalized).chain(descendants) {
+        fork_choice.import_hook(header);
     }
 }
 
 /// The chain with the most accumulated proof of work is the best.
 /// This fork choice rule only makes sense with the PoW consensus engine
 /// and the generics reflect that.
+#[derive(Default)]
 pub struct HeaviestChain {
     // You may add fields here if you need to.
 }
 
 impl ForkChoice<Pow> for HeaviestChain {
-    fn best_block(&self, header: Header<u64>) -> Option<u64> {
+    fn best_block(&self) -> Option<Hash> {
         todo!("Exercise 3")
     }
 
     fn import_hook(&mut self, header: Header<u64>) {
         todo!("Exercise 4")
     }
+
+    fn finality_hook(&mut self, finalized: Header<u64>, descendants: Vec<Header<u64>>) {
+        rebuild(self, finalized, descendants);
+    }
 }
 
 /// The chain with the most signatures from the Alice authority is the best.
 /// This fork choice rule only makes sense with the PoA consensus engine
 /// and the generics reflect that.
+#[derive(Default)]
 pub struct MostAliceSigs {
     // You may add fields here if you need to.
 }
 
 impl ForkChoice<SimplePoa> for MostAliceSigs {
-    fn best_block(&self, header: Header<ConsensusAuthority>) -> Option<u64> {
+    fn best_block(&self) -> Option<Hash> {
         todo!("Exercise 5")
     }
 
-    fn import_hook(&mut self, header: Header<ConsensusAuthority>) {
+    fn import_hook(&mut self, header: Header<AuthoritySeal>) {
         todo!("Exercise 6")
     }
+
+    fn finality_hook(&mut self, finalized: Header<AuthoritySeal>, descendants: Vec<Header<AuthoritySeal>>) {
+        rebuild(self, finalized, descendants);
+    }
 }
 
 /// In the Greedy Heaviest Observed Subtree rule, the fork choice is iterative.
 /// You start from the genesis block, and at each fork, you choose the side of the fork
 /// that has the most accumulated proof of work on _all_ of its descendants.
+#[derive(Default)]
 pub struct Ghost {
     // You may add fields here if you need to.
 }
 
 impl ForkChoice<Pow> for Ghost {
-    fn best_block(&self, header: Header<u64>) -> Option<u64> {
+    fn best_block(&self) -> Option<Hash> {
         todo!("Exercise 7")
     }
 
     fn import_hook(&mut self, header: Header<u64>) {
         todo!("Exercise 8")
     }
+
+    fn finality_hook(&mut self, finalized: Header<u64>, descendants: Vec<Header<u64>>) {
+        rebuild(self, finalized, descendants);
+    }
 }
 
 // Finally, we will provide a convenience method directly on our client that simply calls
 // into the corresponding method on the ForkChoice rule. You may need to add some trait
 // bounds to make this work.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P> {
-    /// Return the hash of the best block currently known to the client
-    fn best_block(&self) -> u64 {
-        todo!("Exercise 9")
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C>,
+{
+    /// Return the hash of the best block currently known to the client. Before any blocks
+    /// are imported, that is the genesis block.
+    pub fn best_block(&self) -> Hash {
+        self.fork_choice.best_block().unwrap_or(self.genesis)
     }
 }
 
 //TODO lots of tests for all the algos.
 // Especially a subtle one in Ghost, where importing a new
 // header causes a re-org to a different header than the one that was imported.
+
+#[cfg(test)]
+fn header_at(height: u64, salt: u64) -> Header<()> {
+    Header {
+        parent: Hash::default(),
+        height,
+        state_root: hash(&salt),
+        extrinsics_root: Hash::default(),
+        author: crate::AccountId::default(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cl_3_longest_chain_picks_highest_block() {
+    let mut fork_choice = LongestChain::default();
+    assert_eq!(ForkChoice::<()>::best_block(&fork_choice), None);
+
+    for header in [header_at(1, 0), header_at(3, 0), header_at(2, 0)] {
+        ForkChoice::<()>::import_hook(&mut fork_choice, header);
+    }
+
+    assert_eq!(ForkChoice::<()>::best_block(&fork_choice), Some(hash(&header_at(3, 0))));
+}
+
+#[test]
+fn cl_3_longest_chain_keeps_first_seen_on_tie() {
+    let mut fork_choice = LongestChain::default();
+    ForkChoice::<()>::import_hook(&mut fork_choice, header_at(2, 0));
+    ForkChoice::<()>::import_hook(&mut fork_choice, header_at(2, 1));
+
+    assert_eq!(ForkChoice::<()>::best_block(&fork_choice), Some(hash(&header_at(2, 0))));
+}
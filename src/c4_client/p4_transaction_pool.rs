@@ -4,14 +4,26 @@
 //! are queued before they are inserted into blocks.
 //! 
 //! Maintaining a transaction pool includes:
-//! * Accepting transactions from users
+//! * Accepting transactions from users, as long as they apply on top of the best block, now
+//!   or once the transactions they wait for have arrived
 //! * Removing transactions that are included in blocks as they are imported
 //! * Making the current transactions available for a block authoring process
-//! * Re-queueing transactions from orphaned blocks when re-orgs happen (This one happens IRL; might not cover it in BFS; TBD)
+//! * Re-queueing transactions from orphaned blocks when re-orgs happen (covered in the re-org section)
+//! * Evicting transactions that no longer apply once the best block changes
+//! * Keeping the pool within its memory limits
+//!
+//! The pool itself never sees any state. It is the client that checks transactions against
+//! the best block's state, and tells the pool which ones to keep.
 
-use std::{collections::VecDeque, marker::PhantomData};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
-use super::{FullClient, StateMachine};
+use super::{p7_block_store::BlockStore, Consensus, ForkChoice, FullClient, StateMachine};
+use crate::{c1_state_machine::Nonced, codec::Encode, hash, AccountId, Hash};
 
 /// An abstraction over the notion of transaction pool.
 pub trait TransactionPool<SM: StateMachine> {
@@ -34,55 +46,174 @@ pub trait TransactionPool<SM: StateMachine> {
     /// The notion of next is opaque and implementation dependent.
     /// Different chains prioritize transactions differently, usually by economic means.
     fn next_from_pool(&mut self) -> Option<SM::Transition>;
+
+    /// Keep only the transactions for which `keep` returns true. They are visited in the order
+    /// that [`TransactionPool::next_from_pool`] would hand them out. Transactions that would
+    /// not be handed out yet are neither visited nor removed.
+    ///
+    /// Retaining removes nothing but what `keep` rejects, so calling it again visits the same
+    /// transactions in the same order.
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, keep: F);
+
+    /// Catch up with a change of best block, for pools that order transactions by nonce.
+    /// `touched` holds the transactions of the blocks that were retracted and enacted on the
+    /// way, whose senders are the only accounts whose nonces can have changed. `chain_nonce`
+    /// gives an account's nonce on top of the new best block.
+    ///
+    /// Pools that know nothing about nonces have nothing to catch up with.
+    fn resync_nonces<F: Fn(&AccountId) -> u64>(&mut self, _touched: &[SM::Transition], _chain_nonce: F) {}
 }
 
 
 // First we add some new user-facing methods to the client.
 // These are basically wrappers around methods that the pool itself provides.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P>           
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>           
     where
+    C: Consensus,
     SM: StateMachine,
+    SM::State: Clone,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
 {
     /// Submit a transaction to the client's transaction pool to hopefully
-    /// be included in a future block.
-    pub fn submit_transaction(&mut self, t: SM::Transition) {
-        todo!("Exercise 1")
+    /// be included in a future block. Returns whether the pool accepted it.
+    ///
+    /// The transaction is checked first, on top of the best block's state and the transactions
+    /// already waiting in the pool. A transaction that can never apply is refused, so that it
+    /// does not take up space in the pool or in the blocks this client authors. One that has
+    /// to wait for transactions that have not arrived yet, such as a transfer with a future
+    /// nonce, is accepted, as far as [`StateMachine::pre_validate`] can tell it is valid.
+    ///
+    /// Only the new transaction is checked. The state after the ones already waiting is kept
+    /// from one submission to the next, and the whole pool is only checked again when the best
+    /// block changes.
+    pub fn submit_transaction(&mut self, t: SM::Transition) -> bool {
+        let pending_state = self
+            .pending_state
+            .take()
+            .unwrap_or_else(|| self.revalidate_pool());
+        let post_state = SM::next_state(&pending_state, &t);
+        let accepted = SM::pre_validate(&pending_state, &t).is_ok() && self.transaction_pool.try_insert(t);
+        // A transaction that has to wait for others is not applied until the pool is checked
+        // again, when it may apply.
+        self.pending_state = Some(match post_state {
+            Ok(post_state) if accepted => post_state,
+            _ => pending_state,
+        });
+        accepted
     }
 
+    /// Check the queued transactions against the best block's state, and evict every one that
+    /// can no longer apply. Returns the state after the transactions that apply now.
+    ///
+    /// The pool's order need not be an order the transactions apply in. A pool sorted by fee
+    /// hands out a sender's second transfer first if it pays more than the first one. So the
+    /// pool is visited again and again, each time applying the transactions that follow on
+    /// from the ones applied before, until nothing more applies. Of the transactions left
+    /// over, only the ones that may apply later are kept.
+    pub(super) fn revalidate_pool(&mut self) -> SM::State {
+        let mut state = self
+            .state_at(self.best_block())
+            .expect("the best block's state can always be recomputed");
+        // Whether each transaction, by its position in the pool's order, has been applied.
+        let mut applied = Vec::new();
+        loop {
+            let mut progress = false;
+            let mut position = 0;
+            self.transaction_pool.retain(|t| {
+                if position == applied.len() {
+                    applied.push(false);
+                }
+                if !applied[position] {
+                    if let Ok(post_state) = SM::next_state(&state, t) {
+                        state = post_state;
+                        applied[position] = true;
+                        progress = true;
+                    }
+                }
+                position += 1;
+                true
+            });
+            if !progress {
+                break;
+            }
+        }
+        let mut position = 0;
+        self.transaction_pool.retain(|t| {
+            let keep = applied[position] || SM::pre_validate(&state, t).is_ok();
+            position += 1;
+            keep
+        });
+        state
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+    where
+    C: Consensus,
+    SM: StateMachine,
+    P: TransactionPool<SM>,
+{
+
     /// Get the total number of transactions in the node's
     /// transaction pool.
     pub fn pool_size(&self) -> usize {
-        todo!("Exercise 2")
+        self.transaction_pool.size()
     }
 
     /// Check whether a a given transaction is in the client's transaction pool.
     pub fn pool_contains(&self, t: SM::Transition) -> bool {
-        todo!("Exercise 3")
+        self.transaction_pool.contains(t)
     }
 }
 
 /// A simple state machine that is just a first-in-first-out queue.
 pub struct SimplePool<SM: StateMachine>(VecDeque<SM::Transition>);
 
-impl<SM: StateMachine> TransactionPool<SM> for SimplePool<SM> {
+impl<SM: StateMachine> SimplePool<SM> {
+    pub fn new() -> Self {
+        SimplePool(VecDeque::new())
+    }
+}
+
+impl<SM: StateMachine> Default for SimplePool<SM> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SM: StateMachine> TransactionPool<SM> for SimplePool<SM>
+where
+    SM::Transition: PartialEq,
+{
+    /// Transactions are queued unless the very same transaction is already waiting.
     fn try_insert(&mut self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        if self.0.contains(&t) {
+            return false;
+        }
+        self.0.push_back(t);
+        true
     }
 
     fn remove(&mut self, t: <SM as StateMachine>::Transition) {
-        todo!()
+        self.0.retain(|queued| *queued != t);
     }
 
     fn size(&self) -> usize {
-        todo!()
+        self.0.len()
     }
 
     fn contains(&self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        self.0.contains(&t)
     }
 
     fn next_from_pool(&mut self) -> Option<<SM as StateMachine>::Transition> {
-        todo!()
+        self.0.pop_front()
+    }
+
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, mut keep: F) {
+        self.0.retain(|t| keep(t));
     }
 }
 
@@ -93,38 +224,74 @@ impl<SM: StateMachine> TransactionPool<SM> for SimplePool<SM> {
 /// 
 /// This is where the blockspace market takes place. A lot of interesting game theory
 /// happens here.
+///
+/// Transactions with equal priority are provided in the order they arrived.
 pub struct PriorityPool<T, P: Fn(T) -> u64> {
     /// A means of determining a transaction's priority
     prioritizer: P,
     /// The minimum priority that will be accepted. Any transaction with a
     /// priority below this value will be rejected.
     minimum_priority: u64,
-    ph_data: PhantomData<T>
+    /// The queued transactions, keyed so that the highest priority comes first, and
+    /// arrival order breaks ties.
+    queue: BTreeMap<(Reverse<u64>, u64), T>,
+    /// The arrival number to give the next transaction.
+    next_arrival: u64,
+}
+
+impl<T, P: Fn(T) -> u64> PriorityPool<T, P> {
+    pub fn new(prioritizer: P, minimum_priority: u64) -> Self {
+        PriorityPool {
+            prioritizer,
+            minimum_priority,
+            queue: BTreeMap::new(),
+            next_arrival: 0,
+        }
+    }
+}
+
+impl<T> PriorityPool<T, fn(T) -> u64> {
+    /// A pool that prioritizes transactions by the fee they pay to the block author, as
+    /// reported by the state machine. Transactions paying less than `minimum_fee` are refused.
+    pub fn by_fee<SM: StateMachine<Transition = T>>(minimum_fee: u64) -> Self {
+        Self::new(|t| SM::fee(&t), minimum_fee)
+    }
 }
 
 impl<SM, P> TransactionPool<SM> for PriorityPool<SM::Transition, P>
 where
     SM: StateMachine,
+    SM::Transition: Clone + PartialEq,
     P: Fn(SM::Transition) -> u64
 {
     fn try_insert(&mut self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        let priority = (self.prioritizer)(t.clone());
+        if priority < self.minimum_priority || self.queue.values().any(|queued| *queued == t) {
+            return false;
+        }
+        self.queue.insert((Reverse(priority), self.next_arrival), t);
+        self.next_arrival += 1;
+        true
     }
 
     fn remove(&mut self, t: <SM as StateMachine>::Transition) {
-        todo!()
+        self.queue.retain(|_, queued| *queued != t);
     }
 
     fn size(&self) -> usize {
-        todo!()
+        self.queue.len()
     }
 
     fn contains(&self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        self.queue.values().any(|queued| *queued == t)
     }
 
     fn next_from_pool(&mut self) -> Option<<SM as StateMachine>::Transition> {
-        todo!()
+        self.queue.pop_first().map(|(_, t)| t)
+    }
+
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, mut keep: F) {
+        self.queue.retain(|_, t| keep(t));
     }
 }
 
@@ -161,6 +328,394 @@ where
     fn next_from_pool(&mut self) -> Option<<SM as StateMachine>::Transition> {
         todo!()
     }
+
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, keep: F) {
+        todo!()
+    }
+}
+
+/// A transaction pool that respects the order of each sender's nonces.
+///
+/// A transaction whose nonce is its sender's next one is _ready_, and ready transactions are
+/// handed out in the order they became ready. A transaction further ahead than that would be
+/// refused by the state machine, so it is held back as a _future_ transaction until the ones
+/// before it arrive. Then it is promoted to ready. Transactions without a nonce are ready
+/// straight away.
+///
+/// The pool cannot see the chain state, so it assumes every account starts at nonce zero.
+/// The client keeps it up to date through [`TransactionPool::resync_nonces`] whenever its
+/// best block changes. Used on its own, it can be told with [`NoncePool::set_chain_nonce`].
+pub struct NoncePool<T> {
+    /// The nonce that each sender's next ready transaction will have. This counts the
+    /// transactions already on chain as well as the ones that are ready here.
+    next_nonce: HashMap<AccountId, u64>,
+    /// Transactions that can be applied now, in the order they became ready.
+    ready: VecDeque<T>,
+    /// Transactions waiting for an earlier nonce from the same sender, keyed by nonce.
+    future: HashMap<AccountId, BTreeMap<u64, T>>,
+}
+
+impl<T: Nonced> NoncePool<T> {
+    pub fn new() -> Self {
+        NoncePool {
+            next_nonce: HashMap::new(),
+            ready: VecDeque::new(),
+            future: HashMap::new(),
+        }
+    }
+
+    /// The next nonce expected from the given sender.
+    pub fn next_nonce(&self, sender: &AccountId) -> u64 {
+        self.next_nonce.get(sender).copied().unwrap_or(0)
+    }
+
+    /// Tell the pool that the given account's nonce on chain is now `nonce`, for example
+    /// because a block including some of its transactions was imported. Transactions with
+    /// older nonces can never be applied, so they are dropped.
+    pub fn set_chain_nonce(&mut self, sender: AccountId, nonce: u64) {
+        let is_stale = |t: &T| matches!(t.sender_nonce(), Some((s, n)) if s == sender && n < nonce);
+        self.ready.retain(|t| !is_stale(t));
+        if let Some(waiting) = self.future.get_mut(&sender) {
+            *waiting = waiting.split_off(&nonce);
+        }
+        if self.next_nonce(&sender) < nonce {
+            self.next_nonce.insert(sender, nonce);
+        }
+        self.promote(sender);
+    }
+
+    /// Move the sender's future transactions to ready for as long as their nonces follow on.
+    fn promote(&mut self, sender: AccountId) {
+        let Some(waiting) = self.future.get_mut(&sender) else {
+            return;
+        };
+        let mut next = self.next_nonce.get(&sender).copied().unwrap_or(0);
+        while let Some(t) = waiting.remove(&next) {
+            self.ready.push_back(t);
+            next += 1;
+        }
+        if waiting.is_empty() {
+            self.future.remove(&sender);
+        }
+        self.next_nonce.insert(sender, next);
+    }
+
+    /// The number of transactions that are held back waiting for earlier nonces.
+    pub fn future_size(&self) -> usize {
+        self.future.values().map(BTreeMap::len).sum()
+    }
+}
+
+impl<T: Nonced> Default for NoncePool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SM> TransactionPool<SM> for NoncePool<SM::Transition>
+where
+    SM: StateMachine,
+    SM::Transition: Nonced + PartialEq,
+{
+    fn try_insert(&mut self, t: SM::Transition) -> bool {
+        let Some((sender, nonce)) = t.sender_nonce() else {
+            if self.ready.contains(&t) {
+                return false;
+            }
+            self.ready.push_back(t);
+            return true;
+        };
+        if nonce < self.next_nonce(&sender) {
+            return false;
+        }
+        let waiting = self.future.entry(sender).or_default();
+        if waiting.contains_key(&nonce) {
+            return false;
+        }
+        waiting.insert(nonce, t);
+        self.promote(sender);
+        true
+    }
+
+    fn remove(&mut self, t: SM::Transition) {
+        if let Some(i) = self.ready.iter().position(|r| *r == t) {
+            self.ready.remove(i);
+        } else if let Some((sender, nonce)) = t.sender_nonce() {
+            if let Some(waiting) = self.future.get_mut(&sender) {
+                if waiting.get(&nonce) == Some(&t) {
+                    waiting.remove(&nonce);
+                }
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.ready.len() + self.future_size()
+    }
+
+    fn contains(&self, t: SM::Transition) -> bool {
+        self.ready.contains(&t)
+            || t.sender_nonce().is_some_and(|(sender, nonce)| {
+                self.future
+                    .get(&sender)
+                    .and_then(|waiting| waiting.get(&nonce))
+                    == Some(&t)
+            })
+    }
+
+    fn next_from_pool(&mut self) -> Option<SM::Transition> {
+        self.ready.pop_front()
+    }
+
+    /// Only ready transactions are visited. Future transactions cannot be checked until the
+    /// ones before them are ready.
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, mut keep: F) {
+        self.ready.retain(|t| keep(t));
+    }
+
+    /// Every sender the pool knows of is reset to its nonce on chain, which may be lower than
+    /// before if the best block moved to another fork. Then the queued transactions are sorted
+    /// into ready and future again, and the ones with nonces already used on chain are dropped.
+    fn resync_nonces<F: Fn(&AccountId) -> u64>(&mut self, touched: &[SM::Transition], chain_nonce: F) {
+        let senders: HashSet<AccountId> = self
+            .next_nonce
+            .keys()
+            .copied()
+            .chain(touched.iter().filter_map(|t| t.sender_nonce().map(|(sender, _)| sender)))
+            .collect();
+        self.next_nonce = senders
+            .into_iter()
+            .map(|sender| (sender, chain_nonce(&sender)))
+            .collect();
+        let queued: Vec<_> = self
+            .ready
+            .drain(..)
+            .chain(self.future.drain().flat_map(|(_, waiting)| waiting.into_values()))
+            .collect();
+        for t in queued {
+            TransactionPool::<SM>::try_insert(self, t);
+        }
+    }
+}
+
+/// How much a [`BoundedPool`] may hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolLimits {
+    /// The most transactions the pool holds at once.
+    pub max_count: usize,
+    /// The most bytes that the queued transactions may take up together, when encoded.
+    pub max_bytes: usize,
+    /// The most transactions the pool holds from any one sender.
+    pub max_per_sender: usize,
+    /// How long a transaction may wait in the pool before it is dropped.
+    pub max_age: Duration,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        PoolLimits {
+            max_count: 8192,
+            max_bytes: 20 * 1024 * 1024,
+            max_per_sender: 64,
+            max_age: Duration::from_secs(3 * 60 * 60),
+        }
+    }
+}
+
+/// A transaction waiting in a [`BoundedPool`], along with what the pool needs to account for it.
+struct BoundedEntry<T> {
+    t: T,
+    hash: Hash,
+    bytes: usize,
+    sender: Option<AccountId>,
+    arrived: Instant,
+}
+
+/// A priority pool that fits in a fixed amount of memory.
+///
+/// Like [`PriorityPool`], it hands out the highest priority first, and the earliest arrival
+/// among equals. When it is full, a new transaction pushes out the lowest priority ones to
+/// make room, but only if it has a strictly higher priority than all of them. Otherwise the
+/// new transaction is refused. A sender that already has its share of the pool cannot add
+/// more until some of its transactions leave, so no single account can crowd out the rest.
+/// Senders are told apart by [`StateMachine::sender`]. Transactions without one are not
+/// capped.
+///
+/// Transactions that have waited longer than the maximum age are dropped the next time a
+/// transaction is added or taken out. Until then, they still count towards its size.
+pub struct BoundedPool<T, P: Fn(T) -> u64> {
+    /// A means of determining a transaction's priority
+    prioritizer: P,
+    limits: PoolLimits,
+    /// The queued transactions, keyed so that the highest priority comes first, and
+    /// arrival order breaks ties.
+    queue: BTreeMap<(Reverse<u64>, u64), BoundedEntry<T>>,
+    /// The key of each queued transaction, by the transaction's hash.
+    by_hash: HashMap<Hash, (Reverse<u64>, u64)>,
+    /// The key of each queued transaction, by its arrival number, so oldest first.
+    by_arrival: BTreeMap<u64, (Reverse<u64>, u64)>,
+    /// The arrival number to give the next transaction.
+    next_arrival: u64,
+    /// The total encoded size of the queued transactions.
+    bytes: usize,
+    /// The number of queued transactions from each sender.
+    per_sender: HashMap<AccountId, usize>,
+}
+
+impl<T, P: Fn(T) -> u64> BoundedPool<T, P> {
+    pub fn new(prioritizer: P, limits: PoolLimits) -> Self {
+        BoundedPool {
+            prioritizer,
+            limits,
+            queue: BTreeMap::new(),
+            by_hash: HashMap::new(),
+            by_arrival: BTreeMap::new(),
+            next_arrival: 0,
+            bytes: 0,
+            per_sender: HashMap::new(),
+        }
+    }
+
+    /// The total encoded size of the transactions in the pool.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Take a transaction out of the pool, keeping the indexes and counts up to date.
+    fn take(&mut self, key: &(Reverse<u64>, u64)) -> Option<T> {
+        let entry = self.queue.remove(key)?;
+        self.by_hash.remove(&entry.hash);
+        self.by_arrival.remove(&key.1);
+        self.bytes -= entry.bytes;
+        if let Some(sender) = entry.sender {
+            let count = self.per_sender.entry(sender).or_default();
+            *count -= 1;
+            if *count == 0 {
+                self.per_sender.remove(&sender);
+            }
+        }
+        Some(entry.t)
+    }
+
+    /// Drop every transaction that has waited longer than the maximum age. They arrived
+    /// before all the others, so only the oldest few are looked at.
+    fn expire(&mut self) {
+        while let Some((_, key)) = self.by_arrival.first_key_value() {
+            let key = *key;
+            if self.queue[&key].arrived.elapsed() <= self.limits.max_age {
+                break;
+            }
+            self.take(&key);
+        }
+    }
+
+    /// The keys of the lowest priority transactions that would have to be evicted to make
+    /// room for a new one of the given priority and size. Returns None if there is no way to
+    /// make room without evicting a transaction of the same or higher priority.
+    fn make_room(&self, priority: u64, bytes: usize) -> Option<Vec<(Reverse<u64>, u64)>> {
+        let mut evicted = Vec::new();
+        let (mut count, mut total_bytes) = (self.queue.len(), self.bytes);
+        let mut lowest_first = self.queue.iter().rev();
+        while count + 1 > self.limits.max_count || total_bytes + bytes > self.limits.max_bytes {
+            let (key, entry) = lowest_first.next()?;
+            if key.0 .0 >= priority {
+                return None;
+            }
+            evicted.push(*key);
+            count -= 1;
+            total_bytes -= entry.bytes;
+        }
+        Some(evicted)
+    }
+}
+
+impl<T> BoundedPool<T, fn(T) -> u64> {
+    /// A pool that prioritizes transactions by the fee they pay to the block author, as
+    /// reported by the state machine.
+    pub fn by_fee<SM: StateMachine<Transition = T>>(limits: PoolLimits) -> Self {
+        Self::new(|t| SM::fee(&t), limits)
+    }
+}
+
+impl<SM, P> TransactionPool<SM> for BoundedPool<SM::Transition, P>
+where
+    SM: StateMachine,
+    SM::Transition: Clone + Encode,
+    P: Fn(SM::Transition) -> u64,
+{
+    fn try_insert(&mut self, t: SM::Transition) -> bool {
+        self.expire();
+        let hash = hash(&t);
+        if self.by_hash.contains_key(&hash) {
+            return false;
+        }
+        let sender = SM::sender(&t);
+        if sender.is_some_and(|sender| {
+            self.per_sender.get(&sender).copied().unwrap_or(0) >= self.limits.max_per_sender
+        }) {
+            return false;
+        }
+        let priority = (self.prioritizer)(t.clone());
+        let bytes = t.encode().len();
+        let Some(evicted) = self.make_room(priority, bytes) else {
+            return false;
+        };
+        for key in evicted {
+            self.take(&key);
+        }
+
+        if let Some(sender) = sender {
+            *self.per_sender.entry(sender).or_default() += 1;
+        }
+        self.bytes += bytes;
+        let key = (Reverse(priority), self.next_arrival);
+        let entry = BoundedEntry {
+            t,
+            hash,
+            bytes,
+            sender,
+            arrived: Instant::now(),
+        };
+        self.queue.insert(key, entry);
+        self.by_hash.insert(hash, key);
+        self.by_arrival.insert(self.next_arrival, key);
+        self.next_arrival += 1;
+        true
+    }
+
+    fn remove(&mut self, t: SM::Transition) {
+        if let Some(key) = self.by_hash.get(&hash(&t)).copied() {
+            self.take(&key);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn contains(&self, t: SM::Transition) -> bool {
+        self.by_hash.contains_key(&hash(&t))
+    }
+
+    fn next_from_pool(&mut self) -> Option<SM::Transition> {
+        self.expire();
+        let key = *self.queue.keys().next()?;
+        self.take(&key)
+    }
+
+    /// Old transactions are not expired here, so that retaining twice in a row visits the same
+    /// transactions.
+    fn retain<F: FnMut(&SM::Transition) -> bool>(&mut self, mut keep: F) {
+        let dropped: Vec<_> = self
+            .queue
+            .iter()
+            .filter(|(_, entry)| !keep(&entry.t))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in dropped {
+            self.take(&key);
+        }
+    }
 }
 
 //TODO tests
@@ -190,4 +745,399 @@ where
 
 
 // More tests for block importing to make sure that transactions that are imported
-// to the chain are correctly removed from the pool.
\ No newline at end of file
+// to the chain are correctly removed from the pool.
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::{
+        p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+        p5_digital_cash::{CashTransaction, DigitalCashSystem},
+    },
+    c4_client::{p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain, p7_block_store::MemoryStore},
+    Keyring,
+};
+
+#[cfg(test)]
+fn transfer(from: Keyring, nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(from.pair(), Keyring::Charlie.to_account_id(), 1, nonce)
+}
+
+#[cfg(test)]
+fn fee_transfer(from: Keyring, fee: u64, nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer_with_fee(from.pair(), Keyring::Charlie.to_account_id(), 1, fee, nonce)
+}
+
+#[cfg(test)]
+fn drain(pool: &mut NoncePool<AccountingTransaction>) -> Vec<AccountingTransaction> {
+    std::iter::from_fn(|| TransactionPool::<AccountedCurrency>::next_from_pool(pool)).collect()
+}
+
+#[test]
+fn cl_4_nonce_pool_holds_future_transactions() {
+    let mut pool = NoncePool::new();
+    let insert = |pool: &mut NoncePool<_>, t| TransactionPool::<AccountedCurrency>::try_insert(pool, t);
+
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 2)));
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 1)));
+    assert_eq!(pool.future_size(), 2);
+    assert_eq!(TransactionPool::<AccountedCurrency>::next_from_pool(&mut pool), None);
+
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 0)));
+    assert_eq!(pool.future_size(), 0);
+    assert_eq!(
+        drain(&mut pool),
+        vec![
+            transfer(Keyring::Alice, 0),
+            transfer(Keyring::Alice, 1),
+            transfer(Keyring::Alice, 2),
+        ]
+    );
+}
+
+#[test]
+fn cl_4_nonce_pool_keeps_senders_independent() {
+    let mut pool = NoncePool::new();
+    let insert = |pool: &mut NoncePool<_>, t| TransactionPool::<AccountedCurrency>::try_insert(pool, t);
+    let mint = AccountingTransaction::Mint {
+        minter: Keyring::Bob.to_account_id(),
+        amount: 5,
+    };
+
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 1)));
+    assert!(insert(&mut pool, transfer(Keyring::Bob, 0)));
+    assert!(insert(&mut pool, mint.clone()));
+    assert!(!insert(&mut pool, mint.clone()));
+
+    assert_eq!(TransactionPool::<AccountedCurrency>::size(&pool), 3);
+    assert_eq!(drain(&mut pool), vec![transfer(Keyring::Bob, 0), mint]);
+}
+
+#[test]
+fn cl_4_nonce_pool_rejects_stale_and_duplicate_nonces() {
+    let mut pool = NoncePool::new();
+    let insert = |pool: &mut NoncePool<_>, t| TransactionPool::<AccountedCurrency>::try_insert(pool, t);
+
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 0)));
+    assert!(!insert(&mut pool, transfer(Keyring::Alice, 0)));
+    assert!(insert(&mut pool, transfer(Keyring::Alice, 3)));
+    assert!(!insert(&mut pool, transfer(Keyring::Alice, 3)));
+
+    pool.set_chain_nonce(Keyring::Bob.to_account_id(), 4);
+    assert!(!insert(&mut pool, transfer(Keyring::Bob, 3)));
+    assert!(insert(&mut pool, transfer(Keyring::Bob, 4)));
+}
+
+#[test]
+fn cl_4_nonce_pool_follows_chain_nonce() {
+    let mut pool = NoncePool::new();
+    let insert = |pool: &mut NoncePool<_>, t| TransactionPool::<AccountedCurrency>::try_insert(pool, t);
+    for nonce in [0, 1, 3] {
+        assert!(insert(&mut pool, transfer(Keyring::Alice, nonce)));
+    }
+
+    // Somebody else's block included Alice's first three transactions.
+    pool.set_chain_nonce(Keyring::Alice.to_account_id(), 3);
+
+    assert!(!TransactionPool::<AccountedCurrency>::contains(&pool, transfer(Keyring::Alice, 0)));
+    assert_eq!(pool.next_nonce(&Keyring::Alice.to_account_id()), 4);
+    assert_eq!(drain(&mut pool), vec![transfer(Keyring::Alice, 3)]);
+}
+
+
+#[cfg(test)]
+fn transfer_with_fee(from: Keyring, fee: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer_with_fee(from.pair(), Keyring::Charlie.to_account_id(), 1, fee, 0)
+}
+
+#[test]
+fn cl_4_simple_pool_is_first_in_first_out() {
+    let mut pool = SimplePool::<AccountedCurrency>::new();
+
+    assert!(pool.try_insert(transfer(Keyring::Alice, 0)));
+    assert!(pool.try_insert(transfer(Keyring::Bob, 0)));
+    assert!(!pool.try_insert(transfer(Keyring::Alice, 0)));
+    pool.remove(transfer(Keyring::Bob, 0));
+    assert!(pool.try_insert(transfer(Keyring::Dave, 0)));
+
+    assert_eq!(pool.size(), 2);
+    assert_eq!(pool.next_from_pool(), Some(transfer(Keyring::Alice, 0)));
+    assert_eq!(pool.next_from_pool(), Some(transfer(Keyring::Dave, 0)));
+    assert_eq!(pool.next_from_pool(), None);
+}
+
+#[test]
+fn cl_4_priority_pool_orders_by_fee() {
+    let mut pool = PriorityPool::by_fee::<AccountedCurrency>(0);
+    let mut insert = |t| TransactionPool::<AccountedCurrency>::try_insert(&mut pool, t);
+
+    assert!(insert(transfer_with_fee(Keyring::Alice, 1)));
+    assert!(insert(transfer_with_fee(Keyring::Bob, 5)));
+    assert!(insert(transfer_with_fee(Keyring::Dave, 1)));
+    assert!(insert(transfer_with_fee(Keyring::Eve, 3)));
+    assert!(!insert(transfer_with_fee(Keyring::Eve, 3)));
+
+    let drained: Vec<_> =
+        std::iter::from_fn(|| TransactionPool::<AccountedCurrency>::next_from_pool(&mut pool)).collect();
+    assert_eq!(
+        drained,
+        vec![
+            transfer_with_fee(Keyring::Bob, 5),
+            transfer_with_fee(Keyring::Eve, 3),
+            transfer_with_fee(Keyring::Alice, 1),
+            transfer_with_fee(Keyring::Dave, 1),
+        ]
+    );
+}
+
+#[test]
+fn cl_4_priority_pool_refuses_low_fees() {
+    let mut pool = PriorityPool::by_fee::<AccountedCurrency>(2);
+
+    assert!(!TransactionPool::<AccountedCurrency>::try_insert(&mut pool, transfer_with_fee(Keyring::Alice, 1)));
+    assert!(TransactionPool::<AccountedCurrency>::try_insert(&mut pool, transfer_with_fee(Keyring::Bob, 2)));
+    assert_eq!(TransactionPool::<AccountedCurrency>::size(&pool), 1);
+}
+
+#[cfg(test)]
+type LedgerClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A client on a chain where only Alice has money.
+#[cfg(test)]
+fn ledger_client() -> LedgerClient {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    FullClient::new((), LongestChain::default(), SimplePool::new(), MemoryStore::new(), genesis_state).unwrap()
+}
+
+#[test]
+fn cl_4_client_refuses_transactions_that_do_not_apply() {
+    let mut client = ledger_client();
+    let overdraft = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 500, 1);
+
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 0)));
+    assert!(!client.submit_transaction(transfer(Keyring::Alice, 0)));
+    // Nonce 0 is taken by the transaction in the pool, and Bob has nothing to send.
+    assert!(!client.submit_transaction(AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 1, 0)));
+    assert!(!client.submit_transaction(transfer(Keyring::Bob, 0)));
+    assert!(!client.submit_transaction(overdraft));
+    // Nonce 2 has to wait for nonce 1, but it may apply once that arrives.
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 2)));
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 1)));
+    assert_eq!(client.pool_size(), 3);
+}
+
+#[test]
+fn cl_4_submissions_are_checked_against_the_new_best_state() {
+    let mut client = ledger_client();
+    let mut other = ledger_client();
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 0)));
+
+    // Alice spends everything she has in someone else's block.
+    let spend_all = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Dave.to_account_id(), 100, 0);
+    let b1 = other.author_and_import_manual_block(vec![spend_all], other.genesis()).unwrap();
+    assert!(client.import_block(other.get_block(b1).unwrap()));
+
+    // Her transfers are now checked against her empty balance, not the one she had before.
+    assert_eq!(client.pool_size(), 0);
+    assert!(!client.submit_transaction(transfer(Keyring::Alice, 1)));
+    assert!(client.submit_transaction(transfer(Keyring::Dave, 0)));
+}
+
+#[test]
+fn cl_4_revalidation_follows_nonces_rather_than_fees() {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let pool = PriorityPool::by_fee::<AccountedCurrency>(0);
+    let mut client =
+        FullClient::<_, AccountedCurrency, _, _, _>::new((), LongestChain::default(), pool, MemoryStore::new(), genesis_state)
+            .unwrap();
+
+    // The later transfer pays more, so the pool hands it out before the one it depends on.
+    assert!(client.submit_transaction(fee_transfer(Keyring::Alice, 1, 0)));
+    assert!(client.submit_transaction(fee_transfer(Keyring::Alice, 5, 1)));
+    assert!(client.submit_transaction(fee_transfer(Keyring::Alice, 3, 2)));
+    assert_eq!(client.pool_size(), 3);
+
+    let b1 = client.author_and_import_automatic_block().unwrap();
+    assert_eq!(
+        client.get_block(b1).unwrap().body(),
+        &[fee_transfer(Keyring::Alice, 1, 0), fee_transfer(Keyring::Alice, 5, 1), fee_transfer(Keyring::Alice, 3, 2)]
+    );
+}
+
+#[test]
+fn cl_4_client_holds_transactions_ahead_of_their_turn() {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client =
+        FullClient::<_, AccountedCurrency, _, _, _>::new((), LongestChain::default(), NoncePool::new(), MemoryStore::new(), genesis_state)
+            .unwrap();
+
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 1)));
+    assert_eq!(client.transaction_pool.future_size(), 1);
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 0)));
+    assert_eq!(client.transaction_pool.future_size(), 0);
+
+    let b1 = client.author_and_import_automatic_block().unwrap();
+    assert_eq!(
+        client.get_block(b1).unwrap().body(),
+        &[transfer(Keyring::Alice, 0), transfer(Keyring::Alice, 1)]
+    );
+}
+
+#[test]
+fn cl_4_pool_is_revalidated_when_best_block_changes() {
+    let mut client = ledger_client();
+    let mut other = ledger_client();
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 0)));
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 1)));
+    assert!(client.submit_transaction(AccountingTransaction::Mint {
+        minter: Keyring::Bob.to_account_id(),
+        amount: 5,
+    }));
+
+    // Alice spends her first nonce on a different transfer in someone else's block.
+    let spend = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Dave.to_account_id(), 3, 0);
+    let b1 = other.author_and_import_manual_block(vec![spend], other.genesis()).unwrap();
+    assert!(client.import_block(other.get_block(b1).unwrap()));
+
+    // Her first queued transfer can never be applied now. The second one follows on from the
+    // new block, and the mint applies anywhere.
+    assert_eq!(client.best_block(), b1);
+    assert!(!client.pool_contains(transfer(Keyring::Alice, 0)));
+    assert!(client.pool_contains(transfer(Keyring::Alice, 1)));
+    assert_eq!(client.pool_size(), 2);
+}
+
+#[test]
+fn cl_4_nonce_pool_follows_nonces_in_imported_blocks() {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client =
+        FullClient::<_, AccountedCurrency, _, _, _>::new((), LongestChain::default(), NoncePool::new(), MemoryStore::new(), genesis_state)
+            .unwrap();
+    let mut other = ledger_client();
+
+    // Alice's first transfer reaches the client in someone else's block, never in its pool.
+    let b1 = other.author_and_import_manual_block(vec![transfer(Keyring::Alice, 0)], other.genesis()).unwrap();
+    assert!(client.import_block(other.get_block(b1).unwrap()));
+    assert_eq!(client.transaction_pool.next_nonce(&Keyring::Alice.to_account_id()), 1);
+
+    // So her next transfer is ready straight away, rather than waiting for nonce zero.
+    assert!(client.submit_transaction(transfer(Keyring::Alice, 1)));
+    assert_eq!(client.transaction_pool.future_size(), 0);
+    let b2 = client.author_and_import_automatic_block().unwrap();
+    assert_eq!(client.get_block(b2).unwrap().body(), &[transfer(Keyring::Alice, 1)]);
+}
+
+#[cfg(test)]
+type BoundedFeePool = BoundedPool<AccountingTransaction, fn(AccountingTransaction) -> u64>;
+
+#[cfg(test)]
+fn bounded_insert(pool: &mut BoundedFeePool, t: AccountingTransaction) -> bool {
+    TransactionPool::<AccountedCurrency>::try_insert(pool, t)
+}
+
+#[cfg(test)]
+fn bounded_drain(pool: &mut BoundedFeePool) -> Vec<AccountingTransaction> {
+    std::iter::from_fn(|| TransactionPool::<AccountedCurrency>::next_from_pool(pool)).collect()
+}
+
+#[test]
+fn cl_4_bounded_pool_evicts_lowest_priority_when_full() {
+    let limits = PoolLimits { max_count: 2, ..PoolLimits::default() };
+    let mut pool = BoundedPool::by_fee::<AccountedCurrency>(limits);
+
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 1, 0)));
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Bob, 5, 0)));
+    // Paying the same as the cheapest transaction is not enough to push it out.
+    assert!(!bounded_insert(&mut pool, fee_transfer(Keyring::Dave, 1, 0)));
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Eve, 3, 0)));
+
+    assert!(!TransactionPool::<AccountedCurrency>::contains(&pool, fee_transfer(Keyring::Alice, 1, 0)));
+    assert_eq!(
+        bounded_drain(&mut pool),
+        vec![fee_transfer(Keyring::Bob, 5, 0), fee_transfer(Keyring::Eve, 3, 0)]
+    );
+}
+
+#[test]
+fn cl_4_bounded_pool_limits_encoded_size() {
+    let size = fee_transfer(Keyring::Alice, 1, 0).encode().len();
+    let mint = AccountingTransaction::Mint {
+        minter: Keyring::Dave.to_account_id(),
+        amount: 5,
+    };
+    let limits = PoolLimits { max_bytes: 2 * size, ..PoolLimits::default() };
+    let mut pool = BoundedPool::by_fee::<AccountedCurrency>(limits);
+
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 2, 0)));
+    assert!(bounded_insert(&mut pool, mint.clone()));
+    // The mint is smaller than a transfer, but it pays nothing, so it makes room for Bob.
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Bob, 1, 0)));
+    assert!(!TransactionPool::<AccountedCurrency>::contains(&pool, mint));
+    assert!(!bounded_insert(&mut pool, fee_transfer(Keyring::Dave, 1, 0)));
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Eve, 3, 0)));
+
+    assert_eq!(pool.bytes(), 2 * size);
+    assert_eq!(
+        bounded_drain(&mut pool),
+        vec![fee_transfer(Keyring::Eve, 3, 0), fee_transfer(Keyring::Alice, 2, 0)]
+    );
+    assert_eq!(pool.bytes(), 0);
+}
+
+#[test]
+fn cl_4_bounded_pool_caps_each_sender() {
+    let limits = PoolLimits { max_per_sender: 2, ..PoolLimits::default() };
+    let mut pool = BoundedPool::by_fee::<AccountedCurrency>(limits);
+    let mint = |amount| AccountingTransaction::Mint {
+        minter: Keyring::Alice.to_account_id(),
+        amount,
+    };
+
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 5, 0)));
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 5, 1)));
+    assert!(!bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 5, 2)));
+    // Other senders are unaffected, and so are transactions without a sender.
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Bob, 1, 0)));
+    assert!(bounded_insert(&mut pool, mint(1)));
+    assert!(bounded_insert(&mut pool, mint(2)));
+    assert!(bounded_insert(&mut pool, mint(3)));
+
+    // Once one of Alice's transactions leaves the pool, she may send another.
+    assert_eq!(
+        TransactionPool::<AccountedCurrency>::next_from_pool(&mut pool),
+        Some(fee_transfer(Keyring::Alice, 5, 0))
+    );
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 5, 2)));
+}
+
+#[test]
+fn cl_4_bounded_pool_expires_old_transactions() {
+    let limits = PoolLimits { max_age: Duration::from_millis(20), ..PoolLimits::default() };
+    let mut pool = BoundedPool::by_fee::<AccountedCurrency>(limits);
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Alice, 5, 0)));
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(bounded_insert(&mut pool, fee_transfer(Keyring::Bob, 1, 0)));
+
+    assert_eq!(TransactionPool::<AccountedCurrency>::size(&pool), 1);
+    assert_eq!(bounded_drain(&mut pool), vec![fee_transfer(Keyring::Bob, 1, 0)]);
+}
+
+#[test]
+fn cl_4_bounded_pool_takes_transactions_without_senders() {
+    let limits = PoolLimits { max_per_sender: 1, ..PoolLimits::default() };
+    let mut pool = BoundedPool::by_fee::<DigitalCashSystem>(limits);
+    let mint = |amount| CashTransaction::Mint {
+        minter: Keyring::Alice.to_account_id(),
+        amount,
+    };
+    let insert = |pool: &mut BoundedPool<_, _>, t| TransactionPool::<DigitalCashSystem>::try_insert(pool, t);
+
+    assert!(insert(&mut pool, mint(1)));
+    assert!(insert(&mut pool, mint(2)));
+    assert!(!insert(&mut pool, mint(2)));
+    TransactionPool::<DigitalCashSystem>::remove(&mut pool, mint(1));
+
+    assert!(!TransactionPool::<DigitalCashSystem>::contains(&pool, mint(1)));
+    assert!(TransactionPool::<DigitalCashSystem>::contains(&pool, mint(2)));
+    assert_eq!(TransactionPool::<DigitalCashSystem>::size(&pool), 1);
+}
@@ -1,24 +1,282 @@
 //! We are now ready to give out client the ability to author blocks.
 //! Clients that perform this task are usually known as "miners", "authors", or "authorities".
+//!
+//! Every block this client authors names the client's author account, set with
+//! `FullClient::set_author`, so that account collects the block's fees and reward.
 
-use super::{FullClient, StateMachine};
+use super::{
+    p1_data_structure::BlockError, p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore, Consensus, ForkChoice, FullClient, Hash, StateMachine,
+};
+use crate::codec::Encode;
 
 // You may need to add trait bounds to make this work.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P>
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
     where
+    C: Consensus,
     SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
 {
     /// Author a new block with the given transactions on top of the given parent
     /// and import the new block into the local database.
-    pub fn author_and_import_manual_block(&mut self, transactions: Vec<SM::Transition>, parent_hash: u64) {
-        todo!("Exercise 1")
+    ///
+    /// Returns the new block's hash, or the reason it could not be built or imported.
+    pub fn author_and_import_manual_block(
+        &mut self,
+        transactions: Vec<SM::Transition>,
+        parent_hash: Hash,
+    ) -> Result<Hash, BlockError<SM::Error>> {
+        let (Some(parent), Some(pre_state)) = (self.blocks.get(&parent_hash), self.state_at(parent_hash)) else {
+            return Err(BlockError::UnknownParent);
+        };
+        if !self.extends_finalized(parent.header().height) {
+            return Err(BlockError::ConflictsWithFinality);
+        }
+        let block = parent.child(&self.consensus_engine, &pre_state, transactions, self.author)?;
+        let block_hash = block.hash();
+        self.try_import_block(block)?;
+        Ok(block_hash)
     }
 
     /// Author a new block with the transactions from the pool on top of the "best" block
     /// and import the new block into the local database.
-    pub fn author_and_import_automatic_block(&self) {
-        todo!("Exercise 2")
+    ///
+    /// The whole pool is drained into the block, in an order in which the transactions apply.
+    /// Any transaction that cannot be applied on top of the others is dropped, unless it may
+    /// apply later, in which case it goes back into the pool.
+    ///
+    /// If the block cannot be sealed or stored, its transactions go back into the pool too.
+    pub fn author_and_import_automatic_block(&mut self) -> Result<Hash, BlockError<SM::Error>> {
+        let parent_hash = self.best_block();
+        let transactions = self.drain_pool_onto(parent_hash);
+        let authored = self.author_and_import_manual_block(transactions.clone(), parent_hash);
+        if authored.is_err() {
+            self.requeue(transactions);
+        }
+        authored
+    }
+
+    /// Drain the whole pool, keeping the transactions that apply on top of the given block's
+    /// state. As when the pool is revalidated, the transactions are gone through again and
+    /// again, in the order the pool provides, until no more of them apply. That way a
+    /// transaction may follow one that the pool handed out after it. Of the transactions left
+    /// over, the ones that may apply later are put back into the pool, and the rest are dropped.
+    pub(super) fn drain_pool_onto(&mut self, parent_hash: Hash) -> Vec<SM::Transition> {
+        let mut state = self
+            .state_at(parent_hash)
+            .expect("blocks are only authored on known parents");
+        let mut waiting: Vec<_> = std::iter::from_fn(|| self.transaction_pool.next_from_pool()).collect();
+        let mut transactions = Vec::new();
+        loop {
+            let applied = transactions.len();
+            for t in std::mem::take(&mut waiting) {
+                match SM::next_state(&state, &t) {
+                    Ok(post_state) => {
+                        state = post_state;
+                        transactions.push(t);
+                    }
+                    Err(_) => waiting.push(t),
+                }
+            }
+            if transactions.len() == applied {
+                break;
+            }
+        }
+        for t in waiting {
+            if SM::pre_validate(&state, &t).is_ok() {
+                self.transaction_pool.try_insert(t);
+            }
+        }
+        transactions
+    }
+
+    /// Put the transactions of a block that will never be imported back into the pool.
+    pub(super) fn requeue(&mut self, transactions: Vec<SM::Transition>) {
+        for t in transactions {
+            self.transaction_pool.try_insert(t);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c3_consensus::SimplePoa,
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain, p4_transaction_pool::PriorityPool,
+        p7_block_store::MemoryStore, Block,
+    },
+    Keyring,
+};
+#[cfg(test)]
+use std::io;
+
+#[cfg(test)]
+type FeeClient = FullClient<(), AccountedCurrency, LongestChain, PriorityPool<AccountingTransaction, fn(AccountingTransaction) -> u64>>;
+
+/// A client authoring as Charlie on a chain where Alice and Bob have some money and each
+/// block is worth a reward of 10.
+#[cfg(test)]
+fn charlie_client() -> FeeClient {
+    let genesis_state = Ledger {
+        balances: Balances::from([
+            (Keyring::Alice.to_account_id(), 100),
+            (Keyring::Bob.to_account_id(), 100),
+        ]),
+        block_reward: 10,
+        ..Ledger::default()
+    };
+    let pool = PriorityPool::by_fee::<AccountedCurrency>(0);
+    let mut client = FullClient::new((), LongestChain::default(), pool, MemoryStore::new(), genesis_state).unwrap();
+    client.set_author(Keyring::Charlie.to_account_id());
+    client
+}
+
+#[cfg(test)]
+fn charlies_balance(client: &FeeClient, block: Hash) -> Option<u64> {
+    let state = client.get_state(block)?;
+    state.balances.get(&Keyring::Charlie.to_account_id()).copied()
+}
+
+#[test]
+fn cl_5_manual_block_pays_author() {
+    let mut client = charlie_client();
+    let transfer =
+        AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 4, 0);
+    let b1 = client
+        .author_and_import_manual_block(vec![transfer], client.genesis())
+        .unwrap();
+
+    assert_eq!(charlies_balance(&client, b1), Some(14));
+    assert_eq!(client.best_block(), b1);
+}
+
+#[test]
+fn cl_5_manual_block_on_unknown_parent_fails() {
+    let mut client = charlie_client();
+
+    assert_eq!(
+        client.author_and_import_manual_block(vec![], Hash::default()),
+        Err(BlockError::UnknownParent)
+    );
+}
+
+/// A memory store whose disk can fill up, after which it refuses new blocks.
+#[cfg(test)]
+pub(super) struct FullDisk<C: Consensus> {
+    pub(super) store: MemoryStore<C, AccountedCurrency>,
+    pub(super) full: bool,
+}
+
+#[cfg(test)]
+impl<C: Consensus> BlockStore<C, AccountedCurrency> for FullDisk<C> {
+    fn insert(&mut self, block: Block<C, AccountedCurrency>) -> io::Result<()> {
+        if self.full {
+            return Err(io::Error::other("the disk is full"));
+        }
+        self.store.insert(block)
+    }
+
+    fn get(&self, block_hash: &Hash) -> Option<Block<C, AccountedCurrency>> {
+        self.store.get(block_hash)
+    }
+
+    fn contains(&self, block_hash: &Hash) -> bool {
+        self.store.contains(block_hash)
+    }
+
+    fn hashes_at_height(&self, height: u64) -> Vec<Hash> {
+        self.store.hashes_at_height(height)
+    }
+
+    fn hashes(&self) -> Vec<Hash> {
+        self.store.hashes()
+    }
+
+    fn remove(&mut self, block_hashes: &[Hash]) -> io::Result<()> {
+        self.store.remove(block_hashes)
+    }
+
+    fn metadata(&self) -> Option<Vec<u8>> {
+        self.store.metadata()
+    }
+
+    fn set_metadata(&mut self, metadata: Vec<u8>) -> io::Result<()> {
+        self.store.set_metadata(metadata)
     }
 }
 
-//TODO tests
\ No newline at end of file
+#[test]
+fn cl_5_manual_block_that_cannot_be_stored_fails() {
+    let store = FullDisk {
+        store: MemoryStore::new(),
+        full: false,
+    };
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client =
+        FullClient::new((), LongestChain::default(), PriorityPool::by_fee::<AccountedCurrency>(0), store, genesis_state)
+            .unwrap();
+    client.blocks.full = true;
+
+    assert_eq!(
+        client.author_and_import_manual_block(vec![], client.genesis()),
+        Err(BlockError::StoreFailed)
+    );
+    assert_eq!(client.best_block(), client.genesis());
+
+    // An automatic block's transactions are not lost with it.
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    assert!(client.submit_transaction(transfer.clone()));
+    assert_eq!(client.author_and_import_automatic_block(), Err(BlockError::StoreFailed));
+    assert!(client.pool_contains(transfer));
+}
+
+#[test]
+fn cl_5_automatic_block_that_cannot_be_sealed_keeps_its_transactions() {
+    // This node validates Alice's blocks, but has no key to seal any of its own.
+    let engine = SimplePoa {
+        authorities: vec![Keyring::Alice.to_account_id()],
+        signer: None,
+    };
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client = FullClient::<_, AccountedCurrency, _, _, _>::new(
+        engine,
+        LongestChain::default(),
+        PriorityPool::by_fee::<AccountedCurrency>(0),
+        MemoryStore::new(),
+        genesis_state,
+    )
+    .unwrap();
+    let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 0);
+    assert!(client.submit_transaction(transfer.clone()));
+
+    assert_eq!(client.author_and_import_automatic_block(), Err(BlockError::SealFailed));
+    assert_eq!(client.pool_size(), 1);
+    assert!(client.pool_contains(transfer));
+}
+
+#[test]
+fn cl_5_automatic_block_drains_pool_and_pays_author() {
+    let mut client = charlie_client();
+    let cheap = AccountingTransaction::transfer_with_fee(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, 1, 0);
+    let pricey = AccountingTransaction::transfer_with_fee(Keyring::Bob.pair(), Keyring::Alice.to_account_id(), 10, 3, 0);
+    // Dave has no money, so his transfer cannot be applied and the pool refuses it.
+    let broke = AccountingTransaction::transfer_with_fee(Keyring::Dave.pair(), Keyring::Alice.to_account_id(), 10, 9, 0);
+    for t in [cheap.clone(), pricey.clone()] {
+        assert!(client.submit_transaction(t));
+    }
+    assert!(!client.submit_transaction(broke));
+
+    let b1 = client.author_and_import_automatic_block().unwrap();
+    let b2 = client.author_and_import_automatic_block().unwrap();
+
+    assert_eq!(client.get_block(b1).unwrap().body(), &[pricey, cheap]);
+    assert_eq!(client.pool_size(), 0);
+    assert_eq!(charlies_balance(&client, b1), Some(14));
+    assert_eq!(charlies_balance(&client, b2), Some(24));
+    assert_eq!(client.best_block(), b2);
+}
@@ -1,18 +1,312 @@
 //! Sometimes you want a block to never be reverted.
 //! In practice this is usually implemented by some kind of BFT based consensus game.
-//! We will model a very simple alternative where node operators manually request finality.
+//! We will start with a very simple alternative where node operators manually request finality.
 //! 
-//! Although we elide the details of the game itself, this model still allows us to explore
-//! the consequences of having some blocks that are never reverted.
+//! Although we elide the details of the game itself here, this model still allows us to explore
+//! the consequences of having some blocks that are never reverted. The game itself comes later,
+//! in the GRANDPA section, and finalizes blocks through the same method.
+//!
+//! The first consequence is that forks which branch off below the finalized block are dead.
+//! No block on them can ever become best, so the client discards them as soon as finality
+//! passes them by, and it rejects any block that would start a new one.
 
-use super::FullClient;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
 
-impl<C, SM, FC, P> FullClient<C, SM, FC, P> {
+use super::{
+    p1_data_structure::invalid_store, p4_transaction_pool::TransactionPool,
+    p7_block_store::BlockStore, Consensus, ForkChoice, FullClient, Hash, Header, Justification,
+    StateMachine,
+};
+use crate::codec::{Decode, Encode};
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
     /// Mark the given block as final so that it will never be reverted.
     /// Returns whether or not the block was known and marked successfully.
-    pub fn manually_finalize_block(&mut self, block_hash: u64) -> bool {
-        todo!("Exercise 1")
+    ///
+    /// Finality only ever moves forward, so the block must be the last finalized block or
+    /// one of its descendants.
+    ///
+    /// Every block that is neither an ancestor nor a descendant of the newly finalized block
+    /// is discarded, and the fork choice only considers the blocks that remain.
+    ///
+    /// When the best block is on a discarded fork, this is a re-org like any other, and the
+    /// transactions on that fork go back into the pool.
+    ///
+    /// The finalized block is recorded in the block store's metadata before anything is
+    /// discarded, so it is restored when the client reopens the store. If that record cannot
+    /// be written, or the stale blocks cannot be removed from the store, nothing is finalized
+    /// for now. In the second case the record is already written, so the client finishes the
+    /// job when it next opens the store.
+    pub fn manually_finalize_block(&mut self, block_hash: Hash) -> bool {
+        self.finalize(block_hash, None)
+    }
+
+    /// Finalize the given block as described on `manually_finalize_block`, and keep the
+    /// justification that proves it final, if there is one.
+    pub(super) fn finalize(&mut self, block_hash: Hash, justification: Option<Justification>) -> bool {
+        if !self.is_descendant(block_hash, self.finalized) {
+            return false;
+        }
+        let mut justifications = self.justifications.clone();
+        if let Some(justification) = justification {
+            justifications.insert(block_hash, justification);
+        }
+        if self.blocks.set_metadata((block_hash, &justifications).encode()).is_err() {
+            return false;
+        }
+
+        let (descendants, stale) = self.split_forks(block_hash);
+        // Read the blocks between the old best block and the finalized chain while they are
+        // still stored. They are all discarded if the first of them is.
+        let old_best = self.best_block();
+        let discarded_best_fork: Option<Vec<_>> = stale.contains(&old_best).then(|| {
+            let (retracted, _) = self.tree_route(old_best, block_hash);
+            retracted
+                .iter()
+                .map(|block_hash| self.blocks.get(block_hash).expect("retracted blocks are known"))
+                .collect()
+        });
+        if self.blocks.remove(&stale).is_err() {
+            return false;
+        }
+        self.justifications = justifications;
+        for block_hash in &stale {
+            self.leaves.remove(block_hash);
+            self.states.remove(block_hash);
+        }
+        self.finalized = block_hash;
+        let finalized = self
+            .blocks
+            .get(&block_hash)
+            .expect("the finalized block is known")
+            .header()
+            .clone();
+        self.fork_choice.finality_hook(finalized, descendants);
+        match discarded_best_fork {
+            Some(retracted) => {
+                let fork_point = retracted.last().expect("the old best block is retracted").header().parent;
+                let (_, enacted) = self.tree_route(fork_point, self.best_block());
+                self.reorganize(retracted, enacted);
+            }
+            None => self.follow_best(old_best),
+        }
+        self.prune_states();
+        true
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C>,
+    S: BlockStore<C, SM>,
+{
+    /// Sort the blocks above the last finalized block by whether they survive the given block
+    /// being finalized. Returns the headers of the given block's descendants, ordered by height
+    /// and then by import order, and the hashes of the blocks that conflict with it.
+    ///
+    /// Forks that branched off below the last finalized block were discarded when it was
+    /// finalized, so only the blocks above it need to be looked at.
+    fn split_forks(&self, block_hash: Hash) -> (Vec<Header<C::Digest>>, Vec<Hash>) {
+        let header_of = |block_hash: &Hash| {
+            self.blocks
+                .get(block_hash)
+                .expect("the store lists only blocks it holds")
+                .header()
+                .clone()
+        };
+        let finalized_height = header_of(&block_hash).height;
+        let mut route = HashSet::new();
+        let mut current = block_hash;
+        while current != self.finalized {
+            route.insert(current);
+            current = header_of(&current).parent;
+        }
+
+        let mut kept = HashSet::from([block_hash]);
+        let mut descendants = Vec::new();
+        let mut stale = Vec::new();
+        for height in header_of(&self.finalized).height + 1.. {
+            let hashes = self.blocks.hashes_at_height(height);
+            if hashes.is_empty() {
+                break;
+            }
+            for block_hash in hashes {
+                if height <= finalized_height {
+                    if !route.contains(&block_hash) {
+                        stale.push(block_hash);
+                    }
+                    continue;
+                }
+                let header = header_of(&block_hash);
+                if kept.contains(&header.parent) {
+                    kept.insert(block_hash);
+                    descendants.push(header);
+                } else {
+                    stale.push(block_hash);
+                }
+            }
+        }
+        (descendants, stale)
+    }
+
+    /// Restore the finality recorded in the block store's metadata, if there is any. This is
+    /// done once the stored blocks have been replayed, when the client opens its store.
+    pub(super) fn restore_finality(&mut self) -> io::Result<()> {
+        let Some(metadata) = self.blocks.metadata() else {
+            return Ok(());
+        };
+        let (finalized, justifications) = <(Hash, HashMap<Hash, Justification>)>::decode_all(&metadata)
+            .map_err(|e| invalid_store(&format!("the block store holds unreadable metadata: {e:?}")))?;
+        let Some(finalized_block) = self.blocks.get(&finalized) else {
+            return Err(invalid_store("the block store lost the finalized block"));
+        };
+
+        // Finality is recorded before the blocks that conflict with it are removed, so a node
+        // that died in between still has some of them. They are discarded now.
+        let (descendants, stale) = self.split_forks(finalized);
+        self.blocks.remove(&stale)?;
+        for block_hash in &stale {
+            self.leaves.remove(block_hash);
+            self.states.remove(block_hash);
+        }
+        self.finalized = finalized;
+        self.justifications = justifications;
+        self.fork_choice
+            .finality_hook(finalized_block.header().clone(), descendants);
+        Ok(())
+    }
+
+    /// Whether a known block at the given height is the last finalized block or one of its
+    /// descendants. Blocks that conflict with finality are discarded when it moves, so any
+    /// block that is not below the finalized block must descend from it.
+    pub(super) fn extends_finalized(&self, height: u64) -> bool {
+        self.blocks
+            .get(&self.finalized)
+            .is_some_and(|finalized| height >= finalized.header().height)
+    }
+
+    /// The hash of the last block that was finalized.
+    pub fn finalized_block(&self) -> Hash {
+        self.finalized
     }
+
+    /// Check whether the given block is the given ancestor or one of its descendants.
+    /// Unknown blocks descend from nothing.
+    pub(super) fn is_descendant(&self, block_hash: Hash, ancestor: Hash) -> bool {
+        let Some(ancestor_height) = self.blocks.get(&ancestor).map(|b| b.header().height) else {
+            return false;
+        };
+        let mut current = block_hash;
+        while current != ancestor {
+            match self.blocks.get(&current) {
+                Some(block) if block.header().height > ancestor_height => {
+                    current = block.header().parent;
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, Ledger},
+    c4_client::{
+        p1_data_structure::BlockError, p2_importing_blocks::ImportBlock,
+        p3_fork_choice::LongestChain, p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    AccountId,
+};
+
+#[cfg(test)]
+fn ledger_client() -> FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>> {
+    FullClient::new((), LongestChain::default(), SimplePool::new(), MemoryStore::new(), Ledger::default()).unwrap()
+}
+
+#[test]
+fn cl_6_finality_only_moves_forward() {
+    let mut client = ledger_client();
+    let genesis = client.genesis();
+    let b1 = client.author_and_import_manual_block(vec![], genesis).unwrap();
+    let b2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+    client.set_author(AccountId::from(crate::Keyring::Bob));
+    let fork = client.author_and_import_manual_block(vec![], b1).unwrap();
+
+    assert_eq!(client.finalized_block(), genesis);
+    assert!(client.manually_finalize_block(b1));
+    assert!(!client.manually_finalize_block(genesis));
+    assert!(client.manually_finalize_block(b2));
+    assert!(!client.manually_finalize_block(fork));
+    assert!(!client.manually_finalize_block(Hash::default()));
+    assert_eq!(client.finalized_block(), b2);
 }
 
-//TODO tests
\ No newline at end of file
+#[test]
+fn cl_6_finality_discards_conflicting_forks() {
+    let mut client = ledger_client();
+    let genesis = client.genesis();
+    let b1 = client.author_and_import_manual_block(vec![], genesis).unwrap();
+    let b2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+    let b3 = client.author_and_import_manual_block(vec![], b2).unwrap();
+    client.set_author(AccountId::from(crate::Keyring::Bob));
+    let early_fork = client.author_and_import_manual_block(vec![], genesis).unwrap();
+    let f2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+    let f3 = client.author_and_import_manual_block(vec![], f2).unwrap();
+    let f4 = client.author_and_import_manual_block(vec![], f3).unwrap();
+    let late_fork = client.author_and_import_manual_block(vec![], b2).unwrap();
+    assert_eq!(client.best_block(), f4);
+
+    assert!(client.manually_finalize_block(b2));
+
+    // The longer fork reverted b2, so the best block falls back to the finalized chain.
+    assert_eq!(client.best_block(), b3);
+    for discarded in [early_fork, f2, f3, f4] {
+        assert!(client.get_block(discarded).is_none());
+        assert_eq!(client.is_leaf(discarded), None);
+    }
+    let mut leaves = client.all_leaves();
+    leaves.sort();
+    let mut expected = vec![b3, late_fork];
+    expected.sort();
+    assert_eq!(leaves, expected);
+    assert!(client.get_block(b1).is_some());
+}
+
+#[test]
+fn cl_6_blocks_conflicting_with_finality_are_rejected() {
+    let mut client = ledger_client();
+    let mut other = ledger_client();
+    let genesis = client.genesis();
+    let b1 = client.author_and_import_manual_block(vec![], genesis).unwrap();
+    let b2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+    other.set_author(AccountId::from(crate::Keyring::Bob));
+    let fork = other.author_and_import_manual_block(vec![], genesis).unwrap();
+    let fork_child = other.author_and_import_manual_block(vec![], fork).unwrap();
+    assert!(client.manually_finalize_block(b1));
+
+    assert!(!client.import_block(other.get_block(fork).unwrap()));
+    assert!(!client.import_block(other.get_block(fork_child).unwrap()));
+    assert_eq!(
+        client.author_and_import_manual_block(vec![], genesis),
+        Err(BlockError::ConflictsWithFinality)
+    );
+    // Building on the finalized block itself is fine.
+    let sibling = client.author_and_import_manual_block(vec![], b1).unwrap();
+    assert_eq!(client.get_header(sibling).unwrap().parent, b1);
+    assert_eq!(client.best_block(), b2);
+}
@@ -0,0 +1,684 @@
+//! So far our client has kept every block in memory. That is fine for experiments, but a real
+//! node must not lose its chain when it restarts. In this section we make the client's block
+//! database pluggable, and write a backend that keeps the blocks on disk.
+//!
+//! The store mostly holds blocks. States are derived data, and the client recomputes them by
+//! replaying the stored blocks when it starts up. What cannot be derived from the blocks, such
+//! as which of them are final, goes in a small metadata record next to them.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use super::{Block, Consensus, StateMachine};
+use crate::{
+    codec::{Decode, Encode},
+    hash, Hash,
+};
+
+/// A database of blocks, indexed by hash and by height.
+///
+/// Blocks are always inserted after their parent, and a store remembers the order in which
+/// they were inserted. Replaying them in that order is how a client rebuilds its view of the
+/// chain when it reopens a store.
+pub trait BlockStore<C: Consensus, SM: StateMachine> {
+    /// Store a block. Storing a block that is already known does nothing.
+    fn insert(&mut self, block: Block<C, SM>) -> io::Result<()>;
+
+    /// Retrieve a stored block. Returns None if the block is not known.
+    fn get(&self, block_hash: &Hash) -> Option<Block<C, SM>>;
+
+    /// Check whether a block is stored.
+    fn contains(&self, block_hash: &Hash) -> bool;
+
+    /// The hashes of all the stored blocks at the given height, in insertion order.
+    fn hashes_at_height(&self, height: u64) -> Vec<Hash>;
+
+    /// The hashes of all the stored blocks in insertion order, so each block comes after its parent.
+    fn hashes(&self) -> Vec<Hash>;
+
+    /// Remove the given blocks. Unknown hashes are ignored.
+    ///
+    /// The caller must remove every descendant of a removed block along with it, so that each
+    /// remaining block still comes after its parent.
+    fn remove(&mut self, block_hashes: &[Hash]) -> io::Result<()>;
+
+    /// The metadata record last written with [`BlockStore::set_metadata`], if any. The store
+    /// does not interpret it. It is up to the client to encode and decode it.
+    fn metadata(&self) -> Option<Vec<u8>>;
+
+    /// Replace the metadata record. Like an insert, it is on disk when this returns.
+    fn set_metadata(&mut self, metadata: Vec<u8>) -> io::Result<()>;
+}
+
+/// A block store that keeps everything in memory. This is what the client uses by default.
+pub struct MemoryStore<C: Consensus, SM: StateMachine> {
+    blocks: HashMap<Hash, Block<C, SM>>,
+    by_height: BTreeMap<u64, Vec<Hash>>,
+    order: Vec<Hash>,
+    metadata: Option<Vec<u8>>,
+}
+
+impl<C: Consensus, SM: StateMachine> MemoryStore<C, SM> {
+    pub fn new() -> Self {
+        MemoryStore {
+            blocks: HashMap::new(),
+            by_height: BTreeMap::new(),
+            order: Vec::new(),
+            metadata: None,
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Default for MemoryStore<C, SM> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, SM> BlockStore<C, SM> for MemoryStore<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Clone,
+{
+    fn insert(&mut self, block: Block<C, SM>) -> io::Result<()> {
+        let block_hash = block.hash();
+        if !self.blocks.contains_key(&block_hash) {
+            self.by_height
+                .entry(block.header().height)
+                .or_default()
+                .push(block_hash);
+            self.order.push(block_hash);
+            self.blocks.insert(block_hash, block);
+        }
+        Ok(())
+    }
+
+    fn get(&self, block_hash: &Hash) -> Option<Block<C, SM>> {
+        self.blocks.get(block_hash).cloned()
+    }
+
+    fn contains(&self, block_hash: &Hash) -> bool {
+        self.blocks.contains_key(block_hash)
+    }
+
+    fn hashes_at_height(&self, height: u64) -> Vec<Hash> {
+        self.by_height.get(&height).cloned().unwrap_or_default()
+    }
+
+    fn hashes(&self) -> Vec<Hash> {
+        self.order.clone()
+    }
+
+    fn remove(&mut self, block_hashes: &[Hash]) -> io::Result<()> {
+        let removed: HashSet<&Hash> = block_hashes.iter().collect();
+        for block_hash in block_hashes {
+            self.blocks.remove(block_hash);
+        }
+        for hashes in self.by_height.values_mut() {
+            hashes.retain(|block_hash| !removed.contains(block_hash));
+        }
+        self.by_height.retain(|_, hashes| !hashes.is_empty());
+        self.order.retain(|block_hash| !removed.contains(block_hash));
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<Vec<u8>> {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: Vec<u8>) -> io::Result<()> {
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+}
+
+/// The size of the framing in front of each record in a [`FileStore`] log: a four byte
+/// little endian payload length followed by the payload's hash.
+const RECORD_HEADER_LEN: usize = 4 + 32;
+
+/// A block store backed by a single append-only log file.
+///
+/// Each record in the log is one encoded block, framed by its length and its hash. Every
+/// insert is flushed to disk before it returns, so a stored block survives a crash. If the
+/// node dies in the middle of a write, the log ends in a torn record. The checksum catches
+/// that when the store is reopened, and the torn tail is cut off. A damaged record anywhere
+/// else cannot be explained by a crash, so the store refuses to open rather than throw away
+/// the blocks after it.
+///
+/// There is no index on disk. The indexes by hash and by height live in memory only, and are
+/// rebuilt by reading the whole log on open. That keeps the log as the single source of
+/// truth, at the price of an open that takes time proportional to the size of the chain.
+///
+/// Removing blocks rewrites the log without them, which costs as much as copying the whole
+/// log. The client only removes blocks when finality discards forks, and then removes all of
+/// them in one go. The new log is written next to the old one and then renamed over it, so a
+/// crash leaves one log or the other, never a mix of both.
+///
+/// The metadata record lives in its own file next to the log, with the extension `meta`. It
+/// is replaced the same way as the log, by writing the new record alongside and renaming it.
+pub struct FileStore<C: Consensus, SM: StateMachine> {
+    path: PathBuf,
+    file: File,
+    metadata: Option<Vec<u8>>,
+    /// Where each block's payload starts in the log, and how long it is.
+    by_hash: HashMap<Hash, (u64, usize)>,
+    by_height: BTreeMap<u64, Vec<Hash>>,
+    order: Vec<Hash>,
+    /// The end of the last complete record. New records are written here.
+    end: u64,
+    marker: PhantomData<(C, SM)>,
+}
+
+impl<C, SM> FileStore<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Decode,
+{
+    /// Open the store at the given path, creating an empty one if there is none yet.
+    ///
+    /// Fails if the log contains a damaged record before its end, or a complete record that
+    /// is not a block of this chain.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        let metadata = match fs::read(path.with_extension("meta")) {
+            Ok(metadata) => Some(metadata),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut store = FileStore {
+            path,
+            file,
+            metadata,
+            by_hash: HashMap::new(),
+            by_height: BTreeMap::new(),
+            order: Vec::new(),
+            end: 0,
+            marker: PhantomData,
+        };
+        while store.end < log.len() as u64 {
+            let payload = match next_record(&log[store.end as usize..]) {
+                Record::Complete(payload) => payload,
+                Record::Torn => break,
+                Record::Damaged => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the block log holds a damaged record at offset {}", store.end),
+                    ))
+                }
+            };
+            let block = Block::<C, SM>::decode_all(payload).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the block log holds a record that is not a block: {e:?}"),
+                )
+            })?;
+            let offset = store.end + RECORD_HEADER_LEN as u64;
+            store.index(&block, offset, payload.len());
+            store.end = offset + payload.len() as u64;
+        }
+
+        if store.end < log.len() as u64 {
+            store.file.set_len(store.end)?;
+            store.file.sync_all()?;
+        }
+        Ok(store)
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> FileStore<C, SM> {
+    fn index(&mut self, block: &Block<C, SM>, offset: u64, len: usize) {
+        let block_hash = block.hash();
+        self.by_hash.insert(block_hash, (offset, len));
+        self.by_height
+            .entry(block.header().height)
+            .or_default()
+            .push(block_hash);
+        self.order.push(block_hash);
+    }
+
+    /// Read the payload of a record whose position is known from the index.
+    fn read_payload(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut payload = vec![0; len];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+}
+
+/// Frame a payload as a record: its length, its hash, and then the payload itself.
+fn record(payload: &[u8]) -> io::Result<Vec<u8>> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block is too large to store"))?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend(len.to_le_bytes());
+    record.extend(hash(&payload).0);
+    record.extend(payload);
+    Ok(record)
+}
+
+/// What may be found at the start of a non-empty log.
+enum Record<'a> {
+    /// A complete and undamaged record, with this payload.
+    Complete(&'a [u8]),
+    /// A record that runs past the end of the log, or that fails its checksum and ends the
+    /// log. This is what a write that was cut off by a crash leaves behind.
+    Torn,
+    /// A record that fails its checksum although more of the log follows it.
+    Damaged,
+}
+
+/// Read the record at the start of the given log.
+fn next_record(log: &[u8]) -> Record<'_> {
+    let Some(header) = log.get(..RECORD_HEADER_LEN) else {
+        return Record::Torn;
+    };
+    let len = u32::from_le_bytes(header[..4].try_into().expect("the header starts with four bytes")) as usize;
+    let Some(record_len) = RECORD_HEADER_LEN.checked_add(len) else {
+        return Record::Torn;
+    };
+    let Some(payload) = log.get(RECORD_HEADER_LEN..record_len) else {
+        return Record::Torn;
+    };
+    if hash(&payload).0 == header[4..] {
+        Record::Complete(payload)
+    } else if record_len == log.len() {
+        Record::Torn
+    } else {
+        Record::Damaged
+    }
+}
+
+impl<C, SM> BlockStore<C, SM> for FileStore<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Encode + Decode,
+{
+    fn insert(&mut self, block: Block<C, SM>) -> io::Result<()> {
+        if self.by_hash.contains_key(&block.hash()) {
+            return Ok(());
+        }
+        let payload = block.encode();
+        let record = record(&payload)?;
+
+        // Cut off anything left behind by an earlier failed write, so that no stale bytes
+        // end up after the new record, and only index the block once it is safely on disk.
+        self.file.set_len(self.end)?;
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        let offset = self.end + RECORD_HEADER_LEN as u64;
+        self.index(&block, offset, payload.len());
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn get(&self, block_hash: &Hash) -> Option<Block<C, SM>> {
+        let &(offset, len) = self.by_hash.get(block_hash)?;
+        let payload = self.read_payload(offset, len).ok()?;
+        Block::decode_all(&payload).ok()
+    }
+
+    fn contains(&self, block_hash: &Hash) -> bool {
+        self.by_hash.contains_key(block_hash)
+    }
+
+    fn hashes_at_height(&self, height: u64) -> Vec<Hash> {
+        self.by_height.get(&height).cloned().unwrap_or_default()
+    }
+
+    fn hashes(&self) -> Vec<Hash> {
+        self.order.clone()
+    }
+
+    fn remove(&mut self, block_hashes: &[Hash]) -> io::Result<()> {
+        let removed: HashSet<&Hash> = block_hashes
+            .iter()
+            .filter(|block_hash| self.by_hash.contains_key(block_hash))
+            .collect();
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        let mut log = Vec::new();
+        let mut by_hash = HashMap::new();
+        for block_hash in self.order.iter().filter(|block_hash| !removed.contains(block_hash)) {
+            let (offset, len) = self.by_hash[block_hash];
+            by_hash.insert(*block_hash, ((log.len() + RECORD_HEADER_LEN) as u64, len));
+            log.extend(record(&self.read_payload(offset, len)?)?);
+        }
+        replace_file(&self.path, "compact", &log)?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.by_hash = by_hash;
+        for hashes in self.by_height.values_mut() {
+            hashes.retain(|block_hash| !removed.contains(block_hash));
+        }
+        self.by_height.retain(|_, hashes| !hashes.is_empty());
+        self.order.retain(|block_hash| !removed.contains(block_hash));
+        self.end = log.len() as u64;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Option<Vec<u8>> {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: Vec<u8>) -> io::Result<()> {
+        replace_file(&self.path.with_extension("meta"), "tmp", &metadata)?;
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+}
+
+/// Replace the contents of the file at the given path. The new contents are written to a
+/// file with the given extension first, and then renamed over the old file.
+///
+/// The rename is only on disk once the directory that holds the file is synced as well, so
+/// this is done before returning.
+fn replace_file(path: &Path, staging_extension: &str, contents: &[u8]) -> io::Result<()> {
+    let staged = path.with_extension(staging_extension);
+    let mut file = File::create(&staged)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&staged, path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c4_client::{
+        p15_grandpa::{Justification, SignedVote, VoteKind, VoterSet},
+        p2_importing_blocks::ImportBlock,
+        p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool,
+        FullClient,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type DiskClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>, FileStore<(), AccountedCurrency>>;
+
+/// A fresh path for a test's block log. Any log left over from an earlier run is removed.
+#[cfg(test)]
+fn test_log(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("bfs-{}-{name}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[cfg(test)]
+fn genesis_ledger() -> Ledger {
+    Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]))
+}
+
+#[cfg(test)]
+fn open_client(path: &Path) -> io::Result<DiskClient> {
+    let store = FileStore::open(path)?;
+    FullClient::new((), LongestChain::default(), SimplePool::new(), store, genesis_ledger())
+}
+
+#[cfg(test)]
+fn alice_transfer(nonce: u64) -> AccountingTransaction {
+    AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 10, nonce)
+}
+
+#[test]
+fn cl_7_memory_store_indexes_by_hash_and_height() {
+    let mut store = MemoryStore::<(), AccountedCurrency>::new();
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![alice_transfer(0)], Keyring::Bob.to_account_id())
+        .unwrap();
+    let c1 = g
+        .child(&(), &genesis_ledger(), vec![], Keyring::Bob.to_account_id())
+        .unwrap();
+    for block in [g.clone(), b1.clone(), c1.clone(), b1.clone()] {
+        store.insert(block).unwrap();
+    }
+
+    assert_eq!(store.hashes(), vec![g.hash(), b1.hash(), c1.hash()]);
+    assert_eq!(store.hashes_at_height(1), vec![b1.hash(), c1.hash()]);
+    assert_eq!(store.get(&b1.hash()).map(|b| b.hash()), Some(b1.hash()));
+    assert!(!store.contains(&Hash::default()));
+}
+
+#[test]
+fn cl_7_file_store_survives_reopen() {
+    let path = test_log("reopen");
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![alice_transfer(0)], Keyring::Bob.to_account_id())
+        .unwrap();
+    {
+        let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+        store.insert(g.clone()).unwrap();
+        store.insert(b1.clone()).unwrap();
+    }
+
+    let store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash(), b1.hash()]);
+    assert_eq!(store.hashes_at_height(1), vec![b1.hash()]);
+    assert_eq!(store.get(&b1.hash()).map(|b| b.body().to_vec()), Some(vec![alice_transfer(0)]));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_file_store_cuts_off_torn_write() {
+    let path = test_log("torn");
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![], Keyring::Bob.to_account_id())
+        .unwrap();
+    let intact_len = {
+        let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+        store.insert(g.clone()).unwrap();
+        let intact_len = store.end;
+        store.insert(b1.clone()).unwrap();
+        intact_len
+    };
+    // Simulate a crash halfway through writing the second record.
+    let log = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &log[..log.len() - 5]).unwrap();
+
+    let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash()]);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), intact_len);
+
+    // The torn block can simply be written again.
+    store.insert(b1.clone()).unwrap();
+    let store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash(), b1.hash()]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_file_store_overwrites_failed_write() {
+    let path = test_log("failed-write");
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![], Keyring::Bob.to_account_id())
+        .unwrap();
+    {
+        let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+        store.insert(g.clone()).unwrap();
+        // Simulate a write that failed after leaving more bytes behind than the next record
+        // takes up.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0; 4096]).unwrap();
+        store.insert(b1.clone()).unwrap();
+    }
+
+    let store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash(), b1.hash()]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_file_store_refuses_damaged_log() {
+    let path = test_log("damaged");
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![alice_transfer(0)], Keyring::Bob.to_account_id())
+        .unwrap();
+    let damaged_at = {
+        let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+        store.insert(g.clone()).unwrap();
+        let damaged_at = store.end as usize + RECORD_HEADER_LEN;
+        store.insert(b1.clone()).unwrap();
+        damaged_at
+    };
+    // Flip a bit in the first block's payload. Unlike a torn write, this is followed by an
+    // intact record, so it must not be cut off.
+    let mut log = std::fs::read(&path).unwrap();
+    log[RECORD_HEADER_LEN] ^= 1;
+    std::fs::write(&path, &log).unwrap();
+
+    let opened = FileStore::<(), AccountedCurrency>::open(&path);
+    assert_eq!(opened.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    assert_eq!(std::fs::read(&path).unwrap(), log);
+
+    // The same damage to the last record is indistinguishable from a torn write.
+    log[RECORD_HEADER_LEN] ^= 1;
+    log[damaged_at] ^= 1;
+    std::fs::write(&path, &log).unwrap();
+    let store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash()]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_file_store_removal_survives_reopen() {
+    let path = test_log("remove");
+    let g = Block::<(), AccountedCurrency>::genesis(&genesis_ledger());
+    let b1 = g
+        .child(&(), &genesis_ledger(), vec![alice_transfer(0)], Keyring::Bob.to_account_id())
+        .unwrap();
+    let c1 = g
+        .child(&(), &genesis_ledger(), vec![], Keyring::Bob.to_account_id())
+        .unwrap();
+    let b2 = b1
+        .child(&(), &b1.execute(&genesis_ledger()).unwrap(), vec![], Keyring::Bob.to_account_id())
+        .unwrap();
+    {
+        let mut store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+        for block in [g.clone(), b1.clone(), c1.clone(), b2.clone()] {
+            store.insert(block).unwrap();
+        }
+        store.remove(&[c1.hash(), Hash::default()]).unwrap();
+
+        assert!(!store.contains(&c1.hash()));
+        assert_eq!(store.hashes_at_height(1), vec![b1.hash()]);
+        assert_eq!(store.get(&b2.hash()).map(|b| b.hash()), Some(b2.hash()));
+    }
+
+    let store = FileStore::<(), AccountedCurrency>::open(&path).unwrap();
+    assert_eq!(store.hashes(), vec![g.hash(), b1.hash(), b2.hash()]);
+    assert_eq!(store.get(&b1.hash()).map(|b| b.body().to_vec()), Some(vec![alice_transfer(0)]));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_client_chain_survives_restart() {
+    let path = test_log("restart");
+    let (b1, b2, fork) = {
+        let mut client = open_client(&path).unwrap();
+        let genesis = client.genesis();
+        let b1 = client
+            .author_and_import_manual_block(vec![alice_transfer(0)], genesis)
+            .unwrap();
+        let b2 = client
+            .author_and_import_manual_block(vec![alice_transfer(1)], b1)
+            .unwrap();
+        let fork = client.author_and_import_manual_block(vec![], genesis).unwrap();
+        (b1, b2, fork)
+    };
+
+    let client = open_client(&path).unwrap();
+    let mut leaves = client.all_leaves();
+    leaves.sort();
+    let mut expected = vec![b2, fork];
+    expected.sort();
+
+    assert_eq!(leaves, expected);
+    assert_eq!(client.is_leaf(b1), Some(false));
+    assert_eq!(client.best_block(), b2);
+    assert_eq!(
+        client.get_state(b2).map(|ledger| ledger.balances),
+        Some(Balances::from([
+            (Keyring::Alice.to_account_id(), 80),
+            (Keyring::Bob.to_account_id(), 20),
+        ]))
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn cl_7_client_finality_survives_restart() {
+    let path = test_log("finality");
+    let voters = VoterSet::new(vec![Keyring::Alice.to_account_id()]);
+    let (b1, b2, fork, justification) = {
+        let mut client = open_client(&path).unwrap();
+        let genesis = client.genesis();
+        let b1 = client
+            .author_and_import_manual_block(vec![alice_transfer(0)], genesis)
+            .unwrap();
+        let b2 = client.author_and_import_manual_block(vec![], b1).unwrap();
+        let fork = client.author_and_import_manual_block(vec![], genesis).unwrap();
+        assert!(client.manually_finalize_block(b1));
+        let justification = Justification {
+            round: 0,
+            target: b2,
+            precommits: vec![SignedVote::sign(Keyring::Alice.pair(), 0, VoteKind::Precommit, b2)],
+        };
+        assert!(client.import_justification(&voters, justification.clone()));
+        (b1, b2, fork, justification)
+    };
+
+    let client = open_client(&path).unwrap();
+    assert_eq!(client.finalized_block(), b2);
+    assert_eq!(client.justification(b2), Some(&justification));
+    assert_eq!(client.justification(b1), None);
+    assert!(client.get_block(fork).is_none());
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(path.with_extension("meta")).unwrap();
+}
+
+#[test]
+fn cl_7_client_refuses_store_of_other_chain() {
+    let path = test_log("other-chain");
+    drop(open_client(&path).unwrap());
+
+    let store = FileStore::open(&path).unwrap();
+    let other_genesis = Ledger::from(Balances::from([(Keyring::Bob.to_account_id(), 100)]));
+    let client: io::Result<DiskClient> =
+        FullClient::new((), LongestChain::default(), SimplePool::new(), store, other_genesis);
+
+    assert_eq!(client.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,189 @@
+//! A full client that keeps the state after every block it has ever imported will eventually
+//! run out of memory. Most of those states are never looked at again, and any one of them can
+//! be recomputed by replaying blocks on top of an earlier state. In this section we let the
+//! client forget old states according to a pruning mode.
+//!
+//! Blocks are never pruned, only states. The genesis state is always kept, so there is always
+//! somewhere to replay from.
+
+use std::num::NonZeroU64;
+
+use super::{
+    p3_fork_choice::ForkChoice, p7_block_store::BlockStore, Consensus, FullClient, Hash,
+    StateMachine,
+};
+
+/// Which block states a client keeps in memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PruningMode {
+    /// Keep the state of every block.
+    #[default]
+    Archive,
+    /// Keep the states of the blocks at the given number of heights up to and including the
+    /// best block's height. There is always at least one, so the best state is never pruned.
+    KeepLast(NonZeroU64),
+    /// Keep the state of the last finalized block and of every block above it. Those are the
+    /// blocks that new blocks may still be built on.
+    Finalized,
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    FC: ForkChoice<C>,
+    S: BlockStore<C, SM>,
+{
+    /// Change the pruning mode. States that the new mode does not keep are dropped right away.
+    pub fn set_pruning(&mut self, pruning: PruningMode) {
+        self.pruning = pruning;
+        self.prune_states();
+    }
+
+    pub fn pruning(&self) -> PruningMode {
+        self.pruning
+    }
+
+    /// The number of block states currently held in memory.
+    pub fn retained_states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// The state after the given block. A pruned state is recomputed by replaying blocks from
+    /// the nearest ancestor whose state is retained.
+    pub(super) fn state_at(&self, block_hash: Hash) -> Option<SM::State> {
+        if let Some(state) = self.states.get(&block_hash) {
+            return Some(state.clone());
+        }
+        let mut route = vec![self.blocks.get(&block_hash)?];
+        loop {
+            let parent_hash = route.last()?.header().parent;
+            if let Some(state) = self.states.get(&parent_hash) {
+                // Every stored block was checked when it was imported, so replaying it can
+                // only fail if the store has been tampered with.
+                return route
+                    .iter()
+                    .rev()
+                    .try_fold(state.clone(), |state, block| block.execute(&state).ok());
+            }
+            route.push(self.blocks.get(&parent_hash)?);
+        }
+    }
+
+    /// Drop the states that the pruning mode does not keep.
+    pub(super) fn prune_states(&mut self) {
+        let height_of = |block_hash: &Hash| self.blocks.get(block_hash).map(|b| b.header().height);
+        // States at or above the floor are kept, and so is the exempt block's.
+        let (floor, exempt) = match self.pruning {
+            PruningMode::Archive => return,
+            PruningMode::KeepLast(n) => {
+                let best_height = height_of(&self.best_block()).unwrap_or(0);
+                ((best_height + 1).saturating_sub(n.get()), self.genesis)
+            }
+            PruningMode::Finalized => {
+                let finalized_height = height_of(&self.finalized).unwrap_or(0);
+                (finalized_height + 1, self.finalized)
+            }
+        };
+        let pruned: Vec<Hash> = self
+            .states
+            .keys()
+            .filter(|block_hash| **block_hash != self.genesis && **block_hash != exempt)
+            .filter(|block_hash| height_of(block_hash).is_some_and(|height| height < floor))
+            .copied()
+            .collect();
+
+        // The finalized block is a good base for replays, so make sure its state survives
+        // even if it had been pruned under an earlier mode.
+        if self.pruning == PruningMode::Finalized && !self.states.contains_key(&self.finalized) {
+            if let Some(state) = self.state_at(self.finalized) {
+                self.states.insert(self.finalized, state);
+            }
+        }
+        for block_hash in pruned {
+            self.states.remove(&block_hash);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c4_client::{
+        p2_importing_blocks::ImportBlock, p3_fork_choice::LongestChain,
+        p4_transaction_pool::SimplePool, p7_block_store::MemoryStore,
+    },
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerClient = FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A client with a chain of five blocks in which Alice pays Bob once per block. Returns the
+/// client and the hashes of the blocks, genesis first.
+#[cfg(test)]
+fn five_block_chain(pruning: PruningMode) -> (LedgerClient, Vec<Hash>) {
+    let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+    let mut client =
+        FullClient::new((), LongestChain::default(), SimplePool::new(), MemoryStore::new(), genesis_state).unwrap();
+    client.set_pruning(pruning);
+    let mut chain = vec![client.genesis()];
+    for nonce in 0..5 {
+        let transfer = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 1, nonce);
+        let parent = *chain.last().unwrap();
+        chain.push(client.author_and_import_manual_block(vec![transfer], parent).unwrap());
+    }
+    (client, chain)
+}
+
+#[test]
+fn cl_8_archive_keeps_every_state() {
+    let (client, _) = five_block_chain(PruningMode::Archive);
+
+    assert_eq!(client.retained_states(), 6);
+}
+
+#[test]
+fn cl_8_keep_last_recomputes_pruned_states() {
+    let (archive, chain) = five_block_chain(PruningMode::Archive);
+    let (pruned, _) = five_block_chain(PruningMode::KeepLast(NonZeroU64::new(2).unwrap()));
+
+    // Genesis, plus the blocks at heights 4 and 5.
+    assert_eq!(pruned.retained_states(), 3);
+    for block_hash in chain {
+        assert_eq!(pruned.get_state(block_hash), archive.get_state(block_hash));
+    }
+}
+
+#[test]
+fn cl_8_finalized_keeps_states_from_finalized_block_up() {
+    let (mut client, chain) = five_block_chain(PruningMode::Finalized);
+    let fork = client
+        .author_and_import_manual_block(vec![], chain[1])
+        .unwrap();
+    assert_eq!(client.retained_states(), 7);
+
+    assert!(client.manually_finalize_block(chain[3]));
+
+    // Genesis, plus the finalized block and the two above it. The fork at height 2 conflicts
+    // with finality, so it is discarded along with its state.
+    assert_eq!(client.retained_states(), 4);
+    assert_eq!(client.get_state(fork), None);
+    assert_eq!(
+        client.get_state(chain[2]).map(|ledger| ledger.balances),
+        Some(Balances::from([
+            (Keyring::Alice.to_account_id(), 98),
+            (Keyring::Bob.to_account_id(), 2),
+        ]))
+    );
+}
+
+#[test]
+fn cl_8_pruned_parent_can_still_be_built_on() {
+    let (mut client, chain) = five_block_chain(PruningMode::KeepLast(NonZeroU64::MIN));
+    let fork = client.author_and_import_manual_block(vec![], chain[2]);
+
+    assert!(fork.is_ok());
+    assert_eq!(client.get_state(fork.unwrap()), client.get_state(chain[2]));
+}
@@ -0,0 +1,392 @@
+//! Until now each client has been an island. Real clients learn about new blocks and
+//! transactions from their peers over a network. Before we touch real sockets, we simulate a
+//! network inside a single process, which lets us watch several clients follow the same chain.
+//!
+//! The simulation runs in discrete ticks rather than real time, and all of its randomness
+//! comes from a seed. That makes every run of a simulation identical, so tests can check
+//! exactly how latency, lost messages, and partitions affect fork choice.
+//!
+//! Nodes gossip by flooding. A node that imports a block or accepts a transaction it had not
+//! seen before passes it on to all of its peers.
+
+use std::{
+    collections::BTreeMap,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use super::{
+    p1_data_structure::BlockError, p2_importing_blocks::ImportBlock,
+    p4_transaction_pool::TransactionPool, p7_block_store::BlockStore, Block, Consensus,
+    ForkChoice, FullClient, Hash, MemoryStore, StateMachine,
+};
+use crate::codec::Encode;
+
+/// Identifies a node on the network. Nodes are numbered in the order they join.
+pub type PeerId = usize;
+
+/// The announcements that nodes send each other.
+#[derive(Debug)]
+pub enum Message<C: Consensus, SM: StateMachine> {
+    /// A block that the sender has just authored or imported.
+    Block(Block<C, SM>),
+    /// A transaction that the sender has just accepted into its pool.
+    Transaction(SM::Transition),
+}
+
+impl<C: Consensus, SM: StateMachine> Clone for Message<C, SM>
+where
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Message::Block(block) => Message::Block(block.clone()),
+            Message::Transaction(t) => Message::Transaction(t.clone()),
+        }
+    }
+}
+
+/// How the simulated network behaves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// The number of ticks that every message spends in flight. A message always takes at
+    /// least one tick, even if this is zero.
+    pub latency: u64,
+    /// Each message is delayed by up to this many extra ticks, chosen at random.
+    pub jitter: u64,
+    /// The chance, in percent, that any given message is lost.
+    pub drop_percent: u8,
+    /// The seed for all of the network's random choices.
+    pub seed: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            latency: 1,
+            jitter: 0,
+            drop_percent: 0,
+            seed: 0,
+        }
+    }
+}
+
+/// Both ends of a node's inbox channel.
+type Inbox<C, SM> = (Sender<Message<C, SM>>, Receiver<Message<C, SM>>);
+
+/// A message on its way from one node to another.
+struct InFlight<C: Consensus, SM: StateMachine> {
+    from: PeerId,
+    to: PeerId,
+    message: Message<C, SM>,
+}
+
+/// An in-process network of full clients.
+///
+/// Each node has an inbox channel. Messages in flight are held by the network until their
+/// delivery tick, then sent down the recipient's channel, and the recipient handles them.
+pub struct MockNetwork<C: Consensus, SM: StateMachine, FC, P, S = MemoryStore<C, SM>> {
+    config: NetworkConfig,
+    nodes: Vec<FullClient<C, SM, FC, P, S>>,
+    inboxes: Vec<Inbox<C, SM>>,
+    /// Messages in flight, keyed by their delivery tick and then the order they were sent in.
+    in_flight: BTreeMap<(u64, u64), InFlight<C, SM>>,
+    next_send: u64,
+    now: u64,
+    /// The side of the partition that each node is on. Only nodes on the same side can
+    /// reach each other. Without a partition everybody is on side zero.
+    sides: Vec<usize>,
+    /// The state of the random number generator.
+    rng: u64,
+}
+
+impl<C, SM, FC, P, S> MockNetwork<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + Encode,
+    SM::Transition: Clone + Encode,
+    FC: ForkChoice<C>,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    pub fn new(config: NetworkConfig) -> Self {
+        MockNetwork {
+            config,
+            nodes: Vec::new(),
+            inboxes: Vec::new(),
+            in_flight: BTreeMap::new(),
+            next_send: 0,
+            now: 0,
+            sides: Vec::new(),
+            // Xorshift gets stuck at zero, so never start there.
+            rng: config.seed | 1,
+        }
+    }
+
+    /// Connect a client to the network. It is connected to every other node.
+    pub fn add_node(&mut self, client: FullClient<C, SM, FC, P, S>) -> PeerId {
+        self.nodes.push(client);
+        self.inboxes.push(channel());
+        self.sides.push(0);
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&self, peer: PeerId) -> &FullClient<C, SM, FC, P, S> {
+        &self.nodes[peer]
+    }
+
+    pub fn node_mut(&mut self, peer: PeerId) -> &mut FullClient<C, SM, FC, P, S> {
+        &mut self.nodes[peer]
+    }
+
+    /// The current tick.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// The number of messages that are still on their way.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Split the network. Each group of nodes can only reach the nodes in the same group.
+    /// Nodes that are not in any group end up together in one more group. A new partition
+    /// replaces any earlier one.
+    ///
+    /// Whether a message gets through is decided when it is due, not when it is sent. A
+    /// message whose sender and receiver are on different sides at its delivery tick is
+    /// dropped for good, even if it was sent before the split.
+    pub fn partition(&mut self, groups: &[&[PeerId]]) {
+        self.sides.fill(0);
+        for (side, group) in groups.iter().enumerate() {
+            for peer in group.iter() {
+                self.sides[*peer] = side + 1;
+            }
+        }
+    }
+
+    /// Reconnect all the nodes after a partition.
+    ///
+    /// Messages that are still in flight are delivered when they are due, including ones sent
+    /// across the partition. Messages that were already dropped are not sent again, so the
+    /// sides only catch up on what they missed through new gossip or `announce_chain`.
+    pub fn heal(&mut self) {
+        self.sides.fill(0);
+    }
+
+    /// Have the given node author a block from its pool on top of its best block, and
+    /// announce the block to its peers.
+    pub fn author_block(&mut self, peer: PeerId) -> Result<Hash, BlockError<SM::Error>> {
+        let block_hash = self.nodes[peer].author_and_import_automatic_block()?;
+        let block = self.nodes[peer]
+            .get_block(block_hash)
+            .expect("the block was just imported");
+        self.broadcast(peer, Message::Block(block));
+        Ok(block_hash)
+    }
+
+    /// Submit a transaction to the given node, which announces it to its peers if it accepts
+    /// it. Returns whether the node accepted it.
+    pub fn submit_transaction(&mut self, peer: PeerId, t: SM::Transition) -> bool {
+        let accepted = self.nodes[peer].submit_transaction(t.clone());
+        if accepted {
+            self.broadcast(peer, Message::Transaction(t));
+        }
+        accepted
+    }
+
+    /// Announce every block the given node knows to its peers again. Gossip only carries new
+    /// blocks, so this is how nodes catch up after a partition heals.
+    pub fn announce_chain(&mut self, peer: PeerId) {
+        let genesis = self.nodes[peer].genesis();
+        for block_hash in self.nodes[peer].blocks.hashes() {
+            if block_hash == genesis {
+                continue;
+            }
+            if let Some(block) = self.nodes[peer].get_block(block_hash) {
+                self.broadcast(peer, Message::Block(block));
+            }
+        }
+    }
+
+    /// Advance the simulation by one tick. Messages that are due are delivered, and every
+    /// node handles everything in its inbox, in order of peer id.
+    pub fn tick(&mut self) {
+        self.now += 1;
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 > self.now {
+                break;
+            }
+            let InFlight { from, to, message } = entry.remove();
+            if self.sides[from] == self.sides[to] {
+                // The receiver lives as long as the network, so sending cannot fail.
+                let _ = self.inboxes[to].0.send(message);
+            }
+        }
+        for peer in 0..self.nodes.len() {
+            while let Ok(message) = self.inboxes[peer].1.try_recv() {
+                self.handle(peer, message);
+            }
+        }
+    }
+
+    /// Run ticks until no messages are left in flight, or the given number of ticks has
+    /// passed. Returns whether the network went quiet.
+    pub fn run_until_idle(&mut self, max_ticks: u64) -> bool {
+        for _ in 0..max_ticks {
+            if self.in_flight.is_empty() {
+                return true;
+            }
+            self.tick();
+        }
+        self.in_flight.is_empty()
+    }
+
+    fn handle(&mut self, peer: PeerId, message: Message<C, SM>) {
+        match message {
+            Message::Block(block) => {
+                let node = &mut self.nodes[peer];
+                if node.blocks.contains(&block.hash()) || !node.import_block(block.clone()) {
+                    return;
+                }
+                self.broadcast(peer, Message::Block(block));
+            }
+            Message::Transaction(t) => {
+                if self.nodes[peer].submit_transaction(t.clone()) {
+                    self.broadcast(peer, Message::Transaction(t));
+                }
+            }
+        }
+    }
+
+    /// Send a message to every other node, subject to the network's delays and losses.
+    fn broadcast(&mut self, from: PeerId, message: Message<C, SM>) {
+        for to in 0..self.nodes.len() {
+            if to == from || self.random_below(100) < u64::from(self.config.drop_percent) {
+                continue;
+            }
+            let delay = self.config.latency.max(1) + self.random_below(self.config.jitter + 1);
+            let key = (self.now + delay, self.next_send);
+            self.next_send += 1;
+            self.in_flight.insert(
+                key,
+                InFlight {
+                    from,
+                    to,
+                    message: message.clone(),
+                },
+            );
+        }
+    }
+
+    /// A pseudo random number below the given bound, from a xorshift generator.
+    fn random_below(&mut self, bound: u64) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % bound
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    c1_state_machine::p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Balances, Ledger},
+    c4_client::{p3_fork_choice::LongestChain, p4_transaction_pool::SimplePool},
+    Keyring,
+};
+
+#[cfg(test)]
+type LedgerNetwork = MockNetwork<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>>;
+
+/// A network of the given number of nodes that all start from the same ledger. Each node
+/// authors as a different development account, so their blocks never coincide.
+#[cfg(test)]
+fn ledger_network(nodes: usize, config: NetworkConfig) -> LedgerNetwork {
+    let mut network = MockNetwork::new(config);
+    for keyring in &Keyring::ALL[..nodes] {
+        let genesis_state = Ledger::from(Balances::from([(Keyring::Alice.to_account_id(), 100)]));
+        let mut client =
+            FullClient::new((), LongestChain::default(), SimplePool::new(), MemoryStore::new(), genesis_state).unwrap();
+        client.set_author(keyring.to_account_id());
+        network.add_node(client);
+    }
+    network
+}
+
+#[test]
+fn cl_9_blocks_arrive_after_latency() {
+    let mut network = ledger_network(3, NetworkConfig { latency: 2, ..NetworkConfig::default() });
+    let b1 = network.author_block(0).unwrap();
+
+    network.tick();
+    assert!(network.node(1).get_block(b1).is_none());
+    network.tick();
+    assert!(network.node(1).get_block(b1).is_some());
+    assert!(network.node(2).get_block(b1).is_some());
+    assert_eq!(network.node(2).best_block(), b1);
+
+    // The other nodes pass the block on, but everybody already has it by then.
+    assert!(network.run_until_idle(10));
+}
+
+#[test]
+fn cl_9_transactions_gossip_into_blocks() {
+    let mut network = ledger_network(3, NetworkConfig::default());
+    let t = AccountingTransaction::transfer(Keyring::Alice.pair(), Keyring::Bob.to_account_id(), 5, 0);
+
+    assert!(network.submit_transaction(0, t.clone()));
+    assert!(network.run_until_idle(10));
+    assert!(network.node(2).pool_contains(t.clone()));
+
+    let b1 = network.node_mut(2).author_and_import_automatic_block().unwrap();
+    let block = network.node(2).get_block(b1).unwrap();
+    assert_eq!(block.body(), &[t]);
+}
+
+#[test]
+fn cl_9_lost_messages_are_deterministic() {
+    let config = NetworkConfig {
+        drop_percent: 50,
+        jitter: 3,
+        seed: 42,
+        ..NetworkConfig::default()
+    };
+    let who_has_block = || {
+        let mut network = ledger_network(6, config);
+        let b1 = network.author_block(0).unwrap();
+        network.run_until_idle(20);
+        (0..6).map(|peer| network.node(peer).get_block(b1).is_some()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(who_has_block(), who_has_block());
+
+    let mut silent = ledger_network(2, NetworkConfig { drop_percent: 100, ..config });
+    let b1 = silent.author_block(0).unwrap();
+    assert_eq!(silent.in_flight(), 0);
+    assert!(silent.node(1).get_block(b1).is_none());
+}
+
+#[test]
+fn cl_9_partition_heals_to_longest_chain() {
+    let mut network = ledger_network(4, NetworkConfig::default());
+    network.partition(&[&[0, 1], &[2, 3]]);
+
+    let short = network.author_block(0).unwrap();
+    network.author_block(2).unwrap();
+    network.run_until_idle(10);
+    let long = network.author_block(3).unwrap();
+    network.run_until_idle(10);
+
+    assert_eq!(network.node(1).best_block(), short);
+    assert_eq!(network.node(2).best_block(), long);
+    assert!(network.node(1).get_block(long).is_none());
+
+    network.heal();
+    network.announce_chain(2);
+    network.announce_chain(3);
+    assert!(network.run_until_idle(10));
+
+    for peer in 0..4 {
+        assert_eq!(network.node(peer).best_block(), long);
+    }
+}
@@ -0,0 +1,485 @@
+//! A canonical byte encoding for the data structures in this tutorial.
+//!
+//! Rust's built-in `Hash` trait is designed for hash maps. Its output is allowed to change
+//! between compiler releases and platforms, which is fine for a hash map but not for a
+//! blockchain, where every node must agree on the hash of every header forever. So instead
+//! we hash the bytes produced by this encoding, which is fully specified here:
+//!
+//! * Integers are fixed width and little endian.
+//! * Booleans are a single byte, 0 or 1.
+//! * Sequences are their length as a [`Compact`] integer followed by each item. Fixed size
+//!   arrays have no length prefix because the length is part of the type.
+//! * Structs are their fields in declaration order, and enums are a single variant
+//!   index byte followed by the variant's fields.
+//! * Maps and sets are encoded as a sequence sorted by the encoding of their items,
+//!   so that iteration order does not leak into the output.
+//!
+//! This is essentially the SCALE codec used by Substrate, minus the parts we don't need.
+//!
+//! Every value has exactly one encoding. Decoding is strict about that, and rejects
+//! over-long compact integers and unsorted maps even though it could make sense of them.
+//! Otherwise two nodes could disagree about the hash of what is logically the same block.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A type that can be written out in the canonical encoding.
+pub trait Encode {
+    /// Append the encoding of this value to the destination.
+    fn encode_to(&self, dest: &mut Vec<u8>);
+
+    /// Return the encoding of this value.
+    fn encode(&self) -> Vec<u8> {
+        let mut dest = Vec::new();
+        self.encode_to(&mut dest);
+        dest
+    }
+}
+
+/// The reasons some bytes may fail to decode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeError {
+    /// The input ended part way through a value.
+    UnexpectedEnd,
+    /// An enum variant index, option tag, or boolean byte was out of range.
+    InvalidTag(u8),
+    /// A string was not valid UTF-8.
+    InvalidUtf8,
+    /// The bytes describe a value, but not in its one canonical encoding.
+    NonCanonical,
+    /// The value was decoded successfully but there were bytes left over.
+    TrailingBytes,
+}
+
+/// A type that can be read back from the canonical encoding.
+pub trait Decode: Sized {
+    /// Decode a value from the front of the input, advancing the input past it.
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+
+    /// Decode a value that must make up the entire input.
+    fn decode_all(mut input: &[u8]) -> Result<Self, DecodeError> {
+        let value = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(value)
+    }
+}
+
+/// Split the first `n` bytes off the front of the input.
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+macro_rules! codec_int {
+    ($($t:ty),*) => {
+        $(impl Encode for $t {
+            fn encode_to(&self, dest: &mut Vec<u8>) {
+                dest.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $t {
+            fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                let bytes = take(input, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().expect("took exactly the right size")))
+            }
+        })*
+    };
+}
+
+codec_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// An unsigned integer in a variable width encoding, used for sequence lengths.
+///
+/// The two low bits of the first byte say how the rest is laid out:
+/// * `0b00` - a single byte holding values up to 63.
+/// * `0b01` - two bytes holding values up to 2^14 - 1.
+/// * `0b10` - four bytes holding values up to 2^30 - 1.
+/// * `0b11` - the upper six bits of the first byte are the number of following bytes minus
+///   four, and the value is those bytes in little endian.
+///
+/// Most sequences are short so their length costs a single byte.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Compact(pub u64);
+
+impl Encode for Compact {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self.0 {
+            n if n < 1 << 6 => dest.push((n as u8) << 2),
+            n if n < 1 << 14 => ((n as u16) << 2 | 0b01).encode_to(dest),
+            n if n < 1 << 30 => ((n as u32) << 2 | 0b10).encode_to(dest),
+            n => {
+                let bytes = n.to_le_bytes();
+                let len = 8 - n.leading_zeros() as usize / 8;
+                dest.push(((len - 4) as u8) << 2 | 0b11);
+                dest.extend_from_slice(&bytes[..len]);
+            }
+        }
+    }
+}
+
+impl Decode for Compact {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let first = input.first().copied().ok_or(DecodeError::UnexpectedEnd)?;
+        let (value, min) = match first & 0b11 {
+            0b00 => (u8::decode(input)? as u64 >> 2, 0),
+            0b01 => (u16::decode(input)? as u64 >> 2, 1 << 6),
+            0b10 => (u32::decode(input)? as u64 >> 2, 1 << 14),
+            _ => {
+                take(input, 1)?;
+                let len = (first >> 2) as usize + 4;
+                if len > 8 {
+                    return Err(DecodeError::NonCanonical);
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..len].copy_from_slice(take(input, len)?);
+                let value = u64::from_le_bytes(bytes);
+                // The top byte must be used, or a shorter encoding was available.
+                if value >> (8 * (len - 1)) == 0 {
+                    return Err(DecodeError::NonCanonical);
+                }
+                (value, 1 << 30)
+            }
+        };
+        if value < min {
+            return Err(DecodeError::NonCanonical);
+        }
+        Ok(Compact(value))
+    }
+}
+
+/// Decode a sequence length.
+///
+/// Callers should not trust the length when allocating, because a corrupt length could
+/// ask for an enormous buffer. Capping the capacity at the remaining input is enough.
+fn decode_len(input: &mut &[u8]) -> Result<usize, DecodeError> {
+    let Compact(len) = Compact::decode(input)?;
+    usize::try_from(len).map_err(|_| DecodeError::UnexpectedEnd)
+}
+
+impl Encode for bool {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode_to(&self, _: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        (**self).encode_to(dest)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u64).encode_to(dest);
+        for item in self {
+            item.encode_to(dest);
+        }
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        for item in self {
+            item.encode_to(dest);
+        }
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let items = (0..N)
+            .map(|_| T::decode(input))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("decoded exactly N items")))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_slice().encode_to(dest)
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode_len(input)?;
+        // Every item takes at least one byte, except for zero sized ones like `()`.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Encode for str {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_bytes().encode_to(dest)
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.as_str().encode_to(dest)
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        String::from_utf8(Vec::decode(input)?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        match self {
+            None => dest.push(0),
+            Some(t) => {
+                dest.push(1);
+                t.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.0.encode_to(dest);
+        self.1.encode_to(dest);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.0.encode_to(dest);
+        self.1.encode_to(dest);
+        self.2.encode_to(dest);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?, C::decode(input)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode, D: Encode> Encode for (A, B, C, D) {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.0.encode_to(dest);
+        self.1.encode_to(dest);
+        self.2.encode_to(dest);
+        self.3.encode_to(dest);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode, D: Decode> Decode for (A, B, C, D) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?, C::decode(input)?, D::decode(input)?))
+    }
+}
+
+/// Encode the given items as a sequence sorted by their encodings.
+pub(crate) fn encode_sorted<T: Encode>(items: impl ExactSizeIterator<Item = T>, dest: &mut Vec<u8>) {
+    Compact(items.len() as u64).encode_to(dest);
+    let mut encoded: Vec<Vec<u8>> = items.map(|item| item.encode()).collect();
+    encoded.sort();
+    for item in encoded {
+        dest.extend_from_slice(&item);
+    }
+}
+
+/// Decode a sequence written by `encode_sorted`, checking that the items really are in
+/// strictly increasing order. That rules out both shuffled and duplicated entries.
+fn decode_sorted<T: Decode>(input: &mut &[u8]) -> Result<Vec<T>, DecodeError> {
+    let len = decode_len(input)?;
+    let mut items = Vec::with_capacity(len.min(input.len()));
+    let mut previous: &[u8] = &[];
+    for i in 0..len {
+        let before = *input;
+        items.push(T::decode(input)?);
+        let encoded = &before[..before.len() - input.len()];
+        if i > 0 && encoded <= previous {
+            return Err(DecodeError::NonCanonical);
+        }
+        previous = encoded;
+    }
+    Ok(items)
+}
+
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        encode_sorted(self.iter(), dest)
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(decode_sorted(input)?.into_iter().collect())
+    }
+}
+
+impl<T: Encode, S> Encode for HashSet<T, S> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        encode_sorted(self.iter(), dest)
+    }
+}
+
+impl<T: Decode + Eq + Hash> Decode for HashSet<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(decode_sorted(input)?.into_iter().collect())
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        encode_sorted(self.iter(), dest)
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(decode_sorted(input)?.into_iter().collect())
+    }
+}
+
+impl Encode for crate::Hash {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for crate::Hash {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(crate::Hash(<[u8; 32]>::decode(input)?))
+    }
+}
+
+#[test]
+fn codec_integers_are_little_endian() {
+    assert_eq!(1u64.encode(), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(0x0102u16.encode(), vec![2, 1]);
+    assert_eq!((-1i32).encode(), vec![0xff; 4]);
+}
+
+#[test]
+fn codec_sequences_are_length_prefixed() {
+    assert_eq!(vec![7u8, 8].encode(), vec![2 << 2, 7, 8]);
+    assert_eq!(Vec::<u8>::new().encode(), vec![0]);
+    assert_eq!([7u8, 8].encode(), vec![7, 8]);
+}
+
+#[test]
+fn codec_compact_boundaries() {
+    let cases: &[(u64, &[u8])] = &[
+        (0, &[0x00]),
+        (63, &[0xfc]),
+        (64, &[0x01, 0x01]),
+        ((1 << 14) - 1, &[0xfd, 0xff]),
+        (1 << 14, &[0x02, 0x00, 0x01, 0x00]),
+        ((1 << 30) - 1, &[0xfe, 0xff, 0xff, 0xff]),
+        (1 << 30, &[0x03, 0x00, 0x00, 0x00, 0x40]),
+        (u64::MAX, &[0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+    ];
+    for (value, encoded) in cases {
+        assert_eq!(Compact(*value).encode(), *encoded);
+        assert_eq!(Compact::decode_all(encoded), Ok(Compact(*value)));
+    }
+}
+
+#[test]
+fn codec_rejects_non_canonical_compact() {
+    // 1 written in the two byte mode
+    assert_eq!(Compact::decode_all(&[0x05, 0x00]), Err(DecodeError::NonCanonical));
+    // 1 written in the big integer mode
+    assert_eq!(
+        Compact::decode_all(&[0x03, 0x01, 0x00, 0x00, 0x00]),
+        Err(DecodeError::NonCanonical)
+    );
+}
+
+#[test]
+fn codec_std_types_round_trip() {
+    fn round_trip<T: Encode + Decode + PartialEq + std::fmt::Debug>(value: T) {
+        assert_eq!(T::decode_all(&value.encode()), Ok(value));
+    }
+
+    round_trip(-5i64);
+    round_trip(u128::MAX);
+    round_trip(true);
+    round_trip(String::from("blockchain"));
+    round_trip(vec![Some(1u32), None]);
+    round_trip((1u8, vec![2u16; 100], [3u64; 2]));
+    round_trip(HashSet::from([1u8, 2, 3]));
+    round_trip(BTreeMap::from([(1u8, 2u8), (3, 4)]));
+    round_trip(crate::hash(&0u64));
+}
+
+#[test]
+fn codec_hash_map_ignores_insertion_order() {
+    let mut a = HashMap::new();
+    let mut b = HashMap::new();
+    for i in 0..100u64 {
+        a.insert(i, i * 2);
+        b.insert(99 - i, (99 - i) * 2);
+    }
+
+    assert_eq!(a.encode(), b.encode());
+    assert_eq!(HashMap::decode_all(&a.encode()), Ok(b));
+}
+
+#[test]
+fn codec_rejects_malformed_input() {
+    assert_eq!(u32::decode_all(&[1, 2, 3]), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(u8::decode_all(&[1, 2]), Err(DecodeError::TrailingBytes));
+    assert_eq!(bool::decode_all(&[2]), Err(DecodeError::InvalidTag(2)));
+    assert_eq!(String::decode_all(&[1 << 2, 0xff]), Err(DecodeError::InvalidUtf8));
+    // A set written out of order
+    assert_eq!(HashSet::<u8>::decode_all(&[2 << 2, 2, 1]), Err(DecodeError::NonCanonical));
+}
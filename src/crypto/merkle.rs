@@ -0,0 +1,190 @@
+//! A binary Merkle tree over the extrinsics in a block.
+//!
+//! Committing to the body with a plain hash of the whole extrinsics vector works, but the only
+//! way to check that commitment is to download the whole body. With a Merkle root, anyone
+//! holding just the header can be convinced that one particular extrinsic is in the block by
+//! a proof that is logarithmic in the size of the body.
+//!
+//! Leaves are the hashes of each item's encoding. Each parent is the hash of its two children.
+//! Leaves and inner nodes are prefixed with different tags, so that nobody can pass an inner
+//! node off as a leaf. When a level has an odd number of nodes, the last one is promoted to
+//! the next level unchanged rather than being paired with a copy of itself. Duplicating it
+//! would let two different bodies share a root, which is exactly what bit Bitcoin in
+//! CVE-2012-2459.
+
+use super::{hash, Hash};
+use crate::codec::{Decode, DecodeError, Encode};
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+fn leaf_hash<T: Encode>(item: &T) -> Hash {
+    hash(&(LEAF_TAG, item))
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    hash(&(NODE_TAG, left, right))
+}
+
+/// Combine one level of the tree into the next one up.
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [promoted] => *promoted,
+            _ => unreachable!("chunks have one or two items"),
+        })
+        .collect()
+}
+
+/// Calculate the Merkle root of the given items. The root of an empty list is the
+/// all zero hash.
+pub fn merkle_root<T: Encode>(items: &[T]) -> Hash {
+    let mut level: Vec<Hash> = items.iter().map(leaf_hash).collect();
+    if level.is_empty() {
+        return Hash::default();
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// A proof that some item is at a particular position in the list committed to by a Merkle root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// The position of the item in the list.
+    index: u64,
+    /// The total number of items in the list. This tells the verifier where the
+    /// promoted nodes are.
+    leaf_count: u64,
+    /// The sibling of each node on the path from the leaf to the root, bottom up.
+    /// Promoted nodes have no sibling, so there may be fewer of these than levels.
+    siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Build a proof that the item at the given index is in the list. Returns `None` if
+    /// the index is out of range.
+    pub fn new<T: Encode>(items: &[T], index: usize) -> Option<Self> {
+        if index >= items.len() {
+            return None;
+        }
+        let mut level: Vec<Hash> = items.iter().map(leaf_hash).collect();
+        let mut position = index;
+        let mut siblings = Vec::new();
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            index: index as u64,
+            leaf_count: items.len() as u64,
+            siblings,
+        })
+    }
+
+    /// The position of the proven item in the list.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Check that the given item is at this proof's index in the list committed to by the root.
+    pub fn verify<T: Encode>(&self, root: &Hash, item: &T) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut node = leaf_hash(item);
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+        while width > 1 {
+            let promoted = position == width - 1 && !width.is_multiple_of(2);
+            if !promoted {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                node = if position.is_multiple_of(2) {
+                    node_hash(&node, sibling)
+                } else {
+                    node_hash(sibling, &node)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        siblings.next().is_none() && node == *root
+    }
+}
+
+impl Encode for MerkleProof {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.index.encode_to(dest);
+        self.leaf_count.encode_to(dest);
+        self.siblings.encode_to(dest);
+    }
+}
+
+impl Decode for MerkleProof {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(MerkleProof {
+            index: u64::decode(input)?,
+            leaf_count: u64::decode(input)?,
+            siblings: Vec::decode(input)?,
+        })
+    }
+}
+
+#[test]
+fn merkle_root_of_one_item_is_its_leaf() {
+    assert_eq!(merkle_root(&[7u64]), leaf_hash(&7u64));
+    assert_eq!(merkle_root::<u64>(&[]), Hash::default());
+}
+
+#[test]
+fn merkle_root_depends_on_order() {
+    assert_ne!(merkle_root(&[1u64, 2]), merkle_root(&[2u64, 1]));
+}
+
+#[test]
+fn merkle_root_does_not_duplicate_odd_nodes() {
+    // If the odd node were paired with itself, these two would share a root.
+    assert_ne!(merkle_root(&[1u64, 2, 3]), merkle_root(&[1u64, 2, 3, 3]));
+}
+
+#[test]
+fn merkle_proofs_verify_for_every_index() {
+    for len in 1..=9u64 {
+        let items: Vec<u64> = (0..len).collect();
+        let root = merkle_root(&items);
+        for (i, item) in items.iter().enumerate() {
+            let proof = MerkleProof::new(&items, i).unwrap();
+            assert!(proof.verify(&root, item), "len {len}, index {i}");
+            assert!(!proof.verify(&root, &(item + 100)), "len {len}, index {i}");
+        }
+        assert_eq!(MerkleProof::new(&items, len as usize), None);
+    }
+}
+
+#[test]
+fn merkle_proof_is_bound_to_its_index() {
+    let items = [10u64, 20, 30, 40];
+    let root = merkle_root(&items);
+    let mut proof = MerkleProof::new(&items, 1).unwrap();
+    proof.index = 0;
+
+    assert!(!proof.verify(&root, &20u64));
+}
+
+#[test]
+fn merkle_proof_round_trips_through_codec() {
+    let proof = MerkleProof::new(&[1u64, 2, 3, 4, 5], 4).unwrap();
+
+    assert_eq!(MerkleProof::decode_all(&proof.encode()), Ok(proof));
+}
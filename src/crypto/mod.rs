@@ -0,0 +1,189 @@
+//! The cryptographic primitives used throughout the tutorial.
+//!
+//! Everything here is implemented from scratch so that the crate has no dependencies. The code
+//! favours readability over speed and has not been audited, so please do not use it to protect
+//! anything of value.
+
+mod merkle;
+mod schnorr;
+mod secp256k1;
+mod sha256;
+mod trie;
+mod vrf;
+
+pub use merkle::{merkle_root, MerkleProof};
+pub use schnorr::{Keypair, PublicKey, Signature};
+pub use sha256::sha256;
+pub use trie::{Trie, TrieProof};
+pub use vrf::VrfProof;
+
+use crate::codec::Encode;
+use std::fmt;
+
+/// A 32 byte cryptographic hash, such as a block hash or a Merkle root.
+///
+/// When compared, hashes are read as 256-bit big-endian numbers. This is what proof of work
+/// thresholds rely on.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    /// The largest possible hash. Every other hash is below it.
+    pub const MAX: Hash = Hash([0xff; 32]);
+
+    /// Divide this hash, read as a 256-bit number, by the given divisor, rounding down.
+    ///
+    /// This makes it easy to write proof of work thresholds such as `Hash::MAX.divided_by(100)`,
+    /// which roughly one in a hundred hashes will fall below.
+    pub const fn divided_by(self, divisor: u64) -> Hash {
+        assert!(divisor != 0, "cannot divide a hash by zero");
+        let mut quotient = [0u8; 32];
+        let mut remainder: u128 = 0;
+        let mut i = 0;
+        while i < 32 {
+            remainder = (remainder << 8) | self.0[i] as u128;
+            quotient[i] = (remainder / divisor as u128) as u8;
+            remainder %= divisor as u128;
+            i += 1;
+        }
+        Hash(quotient)
+    }
+
+    /// Multiply this hash, read as a 256-bit number, by `numerator / denominator`, rounding
+    /// down. The multiplication happens first, so no precision is lost, and results that do
+    /// not fit saturate at [`Hash::MAX`]. This is how difficulty targets are adjusted.
+    pub fn scaled(self, numerator: u64, denominator: u64) -> Hash {
+        assert!(denominator != 0, "cannot divide a hash by zero");
+        // The product needs up to 320 bits, so work in 40 big-endian bytes.
+        let mut product = [0u8; 40];
+        let mut carry: u128 = 0;
+        for i in (0..32).rev() {
+            let digit = self.0[i] as u128 * numerator as u128 + carry;
+            product[i + 8] = digit as u8;
+            carry = digit >> 8;
+        }
+        for i in (0..8).rev() {
+            product[i] = carry as u8;
+            carry >>= 8;
+        }
+
+        let mut quotient = [0u8; 40];
+        let mut remainder: u128 = 0;
+        for i in 0..40 {
+            remainder = (remainder << 8) | product[i] as u128;
+            quotient[i] = (remainder / denominator as u128) as u8;
+            remainder %= denominator as u128;
+        }
+        if quotient[..8].iter().any(|byte| *byte != 0) {
+            return Hash::MAX;
+        }
+        Hash(quotient[8..].try_into().expect("32 bytes"))
+    }
+
+    /// The most significant 64 bits of this hash. Handy for quick arithmetic that does not
+    /// need the full 256 bits, such as rough estimates of accumulated work.
+    pub fn leading_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.0[..8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Whether this hash, read as a number, is even.
+    pub fn is_even(&self) -> bool {
+        self.0[31].is_multiple_of(2)
+    }
+}
+
+impl From<[u8; 32]> for Hash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash(bytes)
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes are long, so debug output shows just the first and last few bytes.
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:02x}{:02x}{:02x}{:02x}…{:02x}{:02x}{:02x}{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[28], self.0[29], self.0[30], self.0[31]
+        )
+    }
+}
+
+/// Hash any value by running its canonical encoding through SHA-256.
+///
+/// Because the encoding is canonical, equal values always have equal hashes, no matter which
+/// machine or which version of Rust computed them.
+pub fn hash<T: Encode + ?Sized>(t: &T) -> Hash {
+    Hash(sha256(&t.encode()))
+}
+
+#[test]
+fn hash_ordering_is_numeric() {
+    let mut small = [0u8; 32];
+    small[31] = 0xff;
+    let mut big = [0u8; 32];
+    big[0] = 1;
+
+    assert!(Hash(small) < Hash(big));
+    assert!(Hash(big) < Hash::MAX);
+}
+
+#[test]
+fn hash_divided_by() {
+    assert_eq!(Hash::MAX.divided_by(1), Hash::MAX);
+
+    let mut two_to_the_255 = [0u8; 32];
+    two_to_the_255[0] = 0x80;
+    let mut two_to_the_254 = [0u8; 32];
+    two_to_the_254[0] = 0x40;
+    assert_eq!(Hash(two_to_the_255).divided_by(2), Hash(two_to_the_254));
+
+    let hundredth = Hash::MAX.divided_by(100);
+    assert_eq!(hundredth.leading_u64(), u64::MAX / 100);
+}
+
+#[test]
+fn hash_scaled() {
+    let mut two_to_the_254 = [0u8; 32];
+    two_to_the_254[0] = 0x40;
+    let mut two_to_the_255 = [0u8; 32];
+    two_to_the_255[0] = 0x80;
+    assert_eq!(Hash(two_to_the_254).scaled(2, 1), Hash(two_to_the_255));
+    assert_eq!(Hash(two_to_the_254).scaled(8, 1), Hash::MAX);
+
+    // Multiplying first keeps precision that dividing first would lose.
+    let mut three = [0u8; 32];
+    three[31] = 3;
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    assert_eq!(Hash(three).scaled(2, 6), Hash(one));
+    assert_eq!(Hash::MAX.scaled(3, 3), Hash::MAX);
+}
+
+#[test]
+fn hash_is_deterministic() {
+    assert_eq!(hash(&7u64), hash(&7u64));
+    assert_ne!(hash(&7u64), hash(&8u64));
+    assert_eq!(
+        hash(&0u64).to_string(),
+        "0xaf5570f5a1810b7af78caf4bc70a660f0df51e42baf91d4de5b2328de0e83dfc"
+    );
+}
@@ -0,0 +1,287 @@
+//! Schnorr signatures over secp256k1, following Bitcoin's BIP-340.
+//!
+//! Sticking to a published standard means we can check ourselves against its test vectors.
+//! The one liberty we take is that we have no source of randomness, so the auxiliary random
+//! data that BIP-340 mixes into the nonce is always zero. The standard explicitly allows that.
+//! The nonce is still derived from the secret key and the message, so it is never reused
+//! across different messages.
+//!
+//! We always sign a [`Hash`]. To sign some larger piece of data, sign the hash of its encoding.
+
+use super::secp256k1::{Fe, Point, Scalar, U256};
+use super::{sha256, Hash};
+use crate::codec::{Decode, DecodeError, Encode};
+use std::fmt;
+
+/// BIP-340 hashes each kind of data with its own tag, so a hash computed for one purpose can
+/// never be mistaken for a hash computed for another.
+pub(super) fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256(tag.as_bytes());
+    let mut preimage = Vec::with_capacity(64 + parts.iter().map(|p| p.len()).sum::<usize>());
+    preimage.extend_from_slice(&tag_hash);
+    preimage.extend_from_slice(&tag_hash);
+    for part in parts {
+        preimage.extend_from_slice(part);
+    }
+    sha256(&preimage)
+}
+
+/// A public key. It is the x coordinate of the public point; the y coordinate is implicitly
+/// the even one of the two candidates.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublicKey(pub [u8; 32]);
+
+/// A signature. It is the x coordinate of the nonce point followed by the scalar `s`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signature(pub [u8; 64]);
+
+/// A secret key together with its public key.
+#[derive(Clone)]
+pub struct Keypair {
+    /// The secret scalar, already negated if need be so that it matches the even y public point.
+    pub(super) secret: Scalar,
+    public: PublicKey,
+}
+
+impl Keypair {
+    /// Create a keypair from the given secret. Returns `None` if the secret is zero or
+    /// not less than the group order, which happens with negligible probability for random bytes.
+    pub fn from_secret(secret: [u8; 32]) -> Option<Self> {
+        let secret = Scalar::new(U256::from_be_bytes(&secret)).filter(|s| !s.is_zero())?;
+        let (x, y) = Point::generator()
+            .mul(secret)
+            .to_affine()
+            .expect("a non-zero multiple of the generator is not infinity");
+        // The public key commits to the point with an even y, which is the negation of our
+        // point when y is odd. In that case we sign with the negated secret to match.
+        Some(Keypair {
+            secret: if y.is_even() { secret } else { secret.neg() },
+            public: PublicKey(x.to_u256().to_be_bytes()),
+        })
+    }
+
+    /// Derive a keypair from a human readable seed. This is only suitable for well known
+    /// development accounts, because anyone who knows the seed knows the secret key.
+    pub fn from_seed(seed: &str) -> Self {
+        let mut secret = sha256(seed.as_bytes());
+        loop {
+            if let Some(pair) = Keypair::from_secret(secret) {
+                return pair;
+            }
+            secret = sha256(&secret);
+        }
+    }
+
+    pub fn public(&self) -> PublicKey {
+        self.public
+    }
+
+    /// Sign the given message hash.
+    pub fn sign(&self, message: &Hash) -> Signature {
+        let d = self.secret;
+        let aux = tagged_hash("BIP0340/aux", &[&[0u8; 32]]);
+        let mut t = d.to_u256().to_be_bytes();
+        for (t, a) in t.iter_mut().zip(aux) {
+            *t ^= a;
+        }
+        let nonce = tagged_hash("BIP0340/nonce", &[&t, &self.public.0, &message.0]);
+        let k = Scalar::reduce(U256::from_be_bytes(&nonce));
+        assert!(!k.is_zero(), "a zero nonce is astronomically unlikely");
+
+        let (r_x, r_y) = Point::generator()
+            .mul(k)
+            .to_affine()
+            .expect("nonce is not zero");
+        let k = if r_y.is_even() { k } else { k.neg() };
+        let r = r_x.to_u256().to_be_bytes();
+        let e = challenge(&r, &self.public, message);
+        let s = k.add(e.mul(d));
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&r);
+        signature[32..].copy_from_slice(&s.to_u256().to_be_bytes());
+        Signature(signature)
+    }
+}
+
+/// The challenge `e` binds the signature to the nonce point, the signer, and the message.
+fn challenge(r: &[u8; 32], public: &PublicKey, message: &Hash) -> Scalar {
+    let e = tagged_hash("BIP0340/challenge", &[r, &public.0, &message.0]);
+    Scalar::reduce(U256::from_be_bytes(&e))
+}
+
+impl PublicKey {
+    /// Check that the signature was made over the given message hash by the secret key
+    /// belonging to this public key.
+    pub fn verify(&self, message: &Hash, signature: &Signature) -> bool {
+        let Some(public_point) = Fe::new(U256::from_be_bytes(&self.0)).and_then(Point::lift_x)
+        else {
+            return false;
+        };
+        let r: [u8; 32] = signature.0[..32].try_into().unwrap();
+        let Some(r_x) = Fe::new(U256::from_be_bytes(&r)) else {
+            return false;
+        };
+        let Some(s) = Scalar::new(U256::from_be_bytes(&signature.0[32..].try_into().unwrap()))
+        else {
+            return false;
+        };
+        let e = challenge(&r, self, message);
+
+        // s * G = R + e * P, so R = s * G - e * P.
+        let nonce_point = Point::generator().mul(s).add(public_point.mul(e.neg()));
+        match nonce_point.to_affine() {
+            Some((x, y)) => y.is_even() && x == r_x,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the secret.
+        f.debug_struct("Keypair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PublicKey({:02x}{:02x}{:02x}{:02x}…)",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        )
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Signature({:02x}{:02x}{:02x}{:02x}…)",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        )
+    }
+}
+
+/// The all zero signature is never valid. It is a convenient placeholder in places like
+/// genesis headers that must have a digest but are never checked.
+impl Default for Signature {
+    fn default() -> Self {
+        Signature([0; 64])
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(PublicKey(<[u8; 32]>::decode(input)?))
+    }
+}
+
+impl Encode for Signature {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for Signature {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Signature(<[u8; 64]>::decode(input)?))
+    }
+}
+
+#[cfg(test)]
+fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn schnorr_bip340_test_vector_0() {
+    let mut secret = [0u8; 32];
+    secret[31] = 3;
+    let pair = Keypair::from_secret(secret).unwrap();
+    let message = Hash([0; 32]);
+    let signature = pair.sign(&message);
+
+    assert_eq!(
+        pair.public().0,
+        from_hex("F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+    );
+    assert_eq!(
+        signature.0,
+        from_hex(
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA8215\
+             25F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0"
+        )
+    );
+    assert!(pair.public().verify(&message, &signature));
+}
+
+#[test]
+fn schnorr_signs_with_odd_y_public_point() {
+    // 6 * G has an odd y coordinate, so signing must negate the secret. Checked against the
+    // reference implementation that accompanies BIP-340.
+    let mut secret = [0u8; 32];
+    secret[31] = 6;
+    let pair = Keypair::from_secret(secret).unwrap();
+    let message = Hash(std::array::from_fn(|i| i as u8));
+    let signature = pair.sign(&message);
+
+    assert_eq!(
+        pair.public().0,
+        from_hex("FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A1460297556")
+    );
+    assert_eq!(
+        signature.0,
+        from_hex(
+            "8CADA4E83029FCEF9430BA84AC8D2F40627433AA665FE40EA4839E3C2D079DFA\
+             21988406077435B45EF05C6EF3C07554947D3D7EC1DCC70491E3F06ABDBEC803"
+        )
+    );
+    assert!(pair.public().verify(&message, &signature));
+}
+
+#[test]
+fn schnorr_rejects_wrong_message_key_or_signature() {
+    let alice = Keypair::from_seed("//Alice");
+    let bob = Keypair::from_seed("//Bob");
+    let message = crate::hash(&"hello");
+    let signature = alice.sign(&message);
+
+    assert!(alice.public().verify(&message, &signature));
+    assert!(!alice.public().verify(&crate::hash(&"goodbye"), &signature));
+    assert!(!bob.public().verify(&message, &signature));
+
+    let mut tampered = signature;
+    tampered.0[63] ^= 1;
+    assert!(!alice.public().verify(&message, &tampered));
+    assert!(!alice.public().verify(&message, &Signature::default()));
+}
+
+#[test]
+fn schnorr_rejects_out_of_range_secrets() {
+    assert!(Keypair::from_secret([0; 32]).is_none());
+    assert!(Keypair::from_secret([0xff; 32]).is_none());
+}
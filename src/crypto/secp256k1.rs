@@ -0,0 +1,451 @@
+//! Arithmetic on the secp256k1 elliptic curve, the same curve used by Bitcoin and Ethereum.
+//!
+//! The curve is `y^2 = x^3 + 7` over the integers modulo the prime `P`. Its points form a group
+//! of prime order `N`. Signatures work on that group: a secret key is a number `d` and the public
+//! key is the point `d * G` for a well known generator point `G`.
+//!
+//! Everything here is written to be read rather than to be fast, and none of it runs in constant
+//! time. That is fine for a tutorial, but it leaks secret keys through timing, so do not reuse it.
+
+use std::cmp::Ordering;
+
+/// A 256 bit unsigned integer stored as four 64 bit limbs, least significant first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct U256(pub(super) [u64; 4]);
+
+impl U256 {
+    pub(super) const ZERO: U256 = U256([0; 4]);
+
+    pub(super) fn from_be_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 24 - 8 * i;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub(super) fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = 24 - 8 * i;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub(super) fn is_zero(&self) -> bool {
+        *self == U256::ZERO
+    }
+
+    fn is_even(&self) -> bool {
+        self.0[0] & 1 == 0
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        (self.0[i / 64] >> (i % 64)) & 1 == 1
+    }
+
+    fn overflowing_add(self, other: U256) -> (U256, bool) {
+        let mut sum = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in sum.iter_mut().enumerate() {
+            let (s, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (s, c2) = s.overflowing_add(carry as u64);
+            *limb = s;
+            carry = c1 || c2;
+        }
+        (U256(sum), carry)
+    }
+
+    fn overflowing_sub(self, other: U256) -> (U256, bool) {
+        let mut difference = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in difference.iter_mut().enumerate() {
+            let (d, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (d, b2) = d.overflowing_sub(borrow as u64);
+            *limb = d;
+            borrow = b1 || b2;
+        }
+        (U256(difference), borrow)
+    }
+
+    /// The full 512 bit product, least significant limb first.
+    fn widening_mul(self, other: U256) -> [u64; 8] {
+        let mut product = [0u64; 8];
+        for i in 0..4 {
+            let mut carry: u128 = 0;
+            for j in 0..4 {
+                let v = product[i + j] as u128 + self.0[i] as u128 * other.0[j] as u128 + carry;
+                product[i + j] = v as u64;
+                carry = v >> 64;
+            }
+            product[i + 4] = carry as u64;
+        }
+        product
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+/// The field prime, `2^256 - 2^32 - 977`.
+pub(super) const P: U256 = U256([
+    0xFFFFFFFEFFFFFC2F,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+]);
+
+/// `2^256 - P`. Multiples of `2^256` can be folded back in by multiplying by this.
+const P_COMPLEMENT: u64 = 0x1000003D1;
+
+/// The order of the group, which is the modulus for secret keys and nonces.
+pub(super) const N: U256 = U256([
+    0xBFD25E8CD0364141,
+    0xBAAEDCE6AF48A03B,
+    0xFFFFFFFFFFFFFFFE,
+    0xFFFFFFFFFFFFFFFF,
+]);
+
+/// An element of the field of integers modulo `P`. Always fully reduced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Fe(U256);
+
+impl Fe {
+    const ZERO: Fe = Fe(U256::ZERO);
+    const ONE: Fe = Fe(U256([1, 0, 0, 0]));
+
+    /// Interpret the integer as a field element, or return `None` if it is not less than `P`.
+    pub(super) fn new(n: U256) -> Option<Fe> {
+        (n < P).then_some(Fe(n))
+    }
+
+    pub(super) fn to_u256(self) -> U256 {
+        self.0
+    }
+
+    pub(super) fn is_even(&self) -> bool {
+        self.0.is_even()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    fn add(self, other: Fe) -> Fe {
+        let (sum, carry) = self.0.overflowing_add(other.0);
+        if carry || sum >= P {
+            Fe(sum.overflowing_sub(P).0)
+        } else {
+            Fe(sum)
+        }
+    }
+
+    fn sub(self, other: Fe) -> Fe {
+        let (difference, borrow) = self.0.overflowing_sub(other.0);
+        if borrow {
+            Fe(difference.overflowing_add(P).0)
+        } else {
+            Fe(difference)
+        }
+    }
+
+    fn neg(self) -> Fe {
+        Fe::ZERO.sub(self)
+    }
+
+    fn mul(self, other: Fe) -> Fe {
+        let wide = self.0.widening_mul(other.0);
+
+        // Since 2^256 = P_COMPLEMENT (mod P), the high half can be multiplied by P_COMPLEMENT
+        // and added to the low half. That leaves a number just over 256 bits, so do it once more.
+        let mut folded = [0u64; 4];
+        let mut carry: u128 = 0;
+        for i in 0..4 {
+            let v = wide[i] as u128 + wide[i + 4] as u128 * P_COMPLEMENT as u128 + carry;
+            folded[i] = v as u64;
+            carry = v >> 64;
+        }
+        let top = carry * P_COMPLEMENT as u128;
+        let (mut result, overflow) =
+            U256(folded).overflowing_add(U256([top as u64, (top >> 64) as u64, 0, 0]));
+        if overflow {
+            result = result.overflowing_add(U256([P_COMPLEMENT, 0, 0, 0])).0;
+        }
+        if result >= P {
+            result = result.overflowing_sub(P).0;
+        }
+        Fe(result)
+    }
+
+    fn square(self) -> Fe {
+        self.mul(self)
+    }
+
+    fn double(self) -> Fe {
+        self.add(self)
+    }
+
+    fn pow(self, exponent: U256) -> Fe {
+        let mut result = Fe::ONE;
+        for i in (0..256).rev() {
+            result = result.square();
+            if exponent.bit(i) {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    /// The multiplicative inverse, by Fermat's little theorem. Zero has no inverse.
+    fn invert(self) -> Fe {
+        self.pow(P.overflowing_sub(U256([2, 0, 0, 0])).0)
+    }
+
+    /// A square root, if there is one. Because `P = 3 mod 4`, a root of `a` is `a^((P+1)/4)`.
+    fn sqrt(self) -> Option<Fe> {
+        const EXPONENT: U256 = U256([
+            0xFFFFFFFFBFFFFF0C,
+            0xFFFFFFFFFFFFFFFF,
+            0xFFFFFFFFFFFFFFFF,
+            0x3FFFFFFFFFFFFFFF,
+        ]);
+        let root = self.pow(EXPONENT);
+        (root.square() == self).then_some(root)
+    }
+}
+
+/// A number modulo the group order `N`, used for secret keys, nonces, and signatures.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Scalar(U256);
+
+impl Scalar {
+    /// Reduce the integer modulo `N`. A single subtraction is enough because `N > 2^255`.
+    pub(super) fn reduce(n: U256) -> Scalar {
+        if n >= N {
+            Scalar(n.overflowing_sub(N).0)
+        } else {
+            Scalar(n)
+        }
+    }
+
+    /// Interpret the integer as a scalar, or return `None` if it is not less than `N`.
+    pub(super) fn new(n: U256) -> Option<Scalar> {
+        (n < N).then_some(Scalar(n))
+    }
+
+    pub(super) fn to_u256(self) -> U256 {
+        self.0
+    }
+
+    pub(super) fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub(super) fn add(self, other: Scalar) -> Scalar {
+        let (sum, carry) = self.0.overflowing_add(other.0);
+        if carry || sum >= N {
+            Scalar(sum.overflowing_sub(N).0)
+        } else {
+            Scalar(sum)
+        }
+    }
+
+    pub(super) fn neg(self) -> Scalar {
+        if self.is_zero() {
+            self
+        } else {
+            Scalar(N.overflowing_sub(self.0).0)
+        }
+    }
+
+    /// Multiply by repeated doubling and adding. It is slow, but it is only used once per
+    /// signature so the simplicity is worth it.
+    pub(super) fn mul(self, other: Scalar) -> Scalar {
+        let mut result = Scalar(U256::ZERO);
+        for i in (0..256).rev() {
+            result = result.add(result);
+            if other.0.bit(i) {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+/// A point on the curve in Jacobian coordinates. The affine point is `(x / z^2, y / z^3)`,
+/// which lets us add points without a field inversion each time. The point at infinity,
+/// which is the group's identity, has `z = 0`.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+}
+
+impl Point {
+    const INFINITY: Point = Point {
+        x: Fe::ONE,
+        y: Fe::ONE,
+        z: Fe::ZERO,
+    };
+
+    pub(super) fn generator() -> Point {
+        Point {
+            x: Fe(U256([
+                0x59F2815B16F81798,
+                0x029BFCDB2DCE28D9,
+                0x55A06295CE870B07,
+                0x79BE667EF9DCBBAC,
+            ])),
+            y: Fe(U256([
+                0x9C47D08FFB10D4B8,
+                0xFD17B448A6855419,
+                0x5DA4FBFC0E1108A8,
+                0x483ADA7726A3C465,
+            ])),
+            z: Fe::ONE,
+        }
+    }
+
+    /// Find the point with the given x coordinate and an even y coordinate, if there is one.
+    pub(super) fn lift_x(x: Fe) -> Option<Point> {
+        let y_squared = x.square().mul(x).add(Fe(U256([7, 0, 0, 0])));
+        let y = y_squared.sqrt()?;
+        let y = if y.is_even() { y } else { y.neg() };
+        Some(Point { x, y, z: Fe::ONE })
+    }
+
+    /// The 33 byte compressed encoding of this point: a byte for the parity of y, then x.
+    /// Returns `None` for the point at infinity, which has no encoding.
+    pub(super) fn to_compressed(self) -> Option<[u8; 33]> {
+        let (x, y) = self.to_affine()?;
+        let mut bytes = [0u8; 33];
+        bytes[0] = if y.is_even() { 2 } else { 3 };
+        bytes[1..].copy_from_slice(&x.to_u256().to_be_bytes());
+        Some(bytes)
+    }
+
+    /// Decode a point from its compressed encoding. Returns `None` if the bytes do not
+    /// describe a point on the curve.
+    pub(super) fn from_compressed(bytes: &[u8; 33]) -> Option<Point> {
+        let x = Fe::new(U256::from_be_bytes(bytes[1..].try_into().expect("32 bytes")))?;
+        let even = Point::lift_x(x)?;
+        match bytes[0] {
+            2 => Some(even),
+            3 => Some(Point {
+                y: even.y.neg(),
+                ..even
+            }),
+            _ => None,
+        }
+    }
+
+    pub(super) fn is_infinity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    /// The affine coordinates of this point, or `None` for the point at infinity.
+    pub(super) fn to_affine(self) -> Option<(Fe, Fe)> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = self.z.invert();
+        let z_inv_squared = z_inv.square();
+        Some((
+            self.x.mul(z_inv_squared),
+            self.y.mul(z_inv_squared).mul(z_inv),
+        ))
+    }
+
+    fn double(self) -> Point {
+        if self.is_infinity() || self.y.is_zero() {
+            return Point::INFINITY;
+        }
+        let a = self.x.square();
+        let b = self.y.square();
+        let c = b.square();
+        let d = self.x.add(b).square().sub(a).sub(c).double();
+        let e = a.double().add(a);
+        let x = e.square().sub(d.double());
+        let y = e.mul(d.sub(x)).sub(c.double().double().double());
+        let z = self.y.mul(self.z).double();
+        Point { x, y, z }
+    }
+
+    pub(super) fn add(self, other: Point) -> Point {
+        if self.is_infinity() {
+            return other;
+        }
+        if other.is_infinity() {
+            return self;
+        }
+        let z1_squared = self.z.square();
+        let z2_squared = other.z.square();
+        let u1 = self.x.mul(z2_squared);
+        let u2 = other.x.mul(z1_squared);
+        let s1 = self.y.mul(other.z).mul(z2_squared);
+        let s2 = other.y.mul(self.z).mul(z1_squared);
+        if u1 == u2 {
+            return if s1 == s2 {
+                self.double()
+            } else {
+                Point::INFINITY
+            };
+        }
+        let h = u2.sub(u1);
+        let r = s2.sub(s1);
+        let h_squared = h.square();
+        let h_cubed = h.mul(h_squared);
+        let u1_h_squared = u1.mul(h_squared);
+        let x = r.square().sub(h_cubed).sub(u1_h_squared.double());
+        let y = r.mul(u1_h_squared.sub(x)).sub(s1.mul(h_cubed));
+        let z = h.mul(self.z).mul(other.z);
+        Point { x, y, z }
+    }
+
+    pub(super) fn mul(self, k: Scalar) -> Point {
+        let mut result = Point::INFINITY;
+        for i in (0..256).rev() {
+            result = result.double();
+            if k.0.bit(i) {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn secp256k1_field_inverse() {
+    let a = Fe(U256([12345, 0, 0, 1 << 60]));
+    assert_eq!(a.mul(a.invert()), Fe::ONE);
+}
+
+#[test]
+fn secp256k1_group_order() {
+    // N * G is the identity, and (N - 1) * G is the negation of G.
+    let g = Point::generator();
+    assert!(g
+        .mul(Scalar(N.overflowing_sub(U256([1, 0, 0, 0])).0))
+        .add(g)
+        .is_infinity());
+
+    let (x, y) = g.to_affine().unwrap();
+    let (x2, y2) = g
+        .mul(Scalar::reduce(U256([2, 0, 0, 0])))
+        .to_affine()
+        .unwrap();
+    assert_eq!(g.double().to_affine(), Some((x2, y2)));
+    assert_eq!(Point::lift_x(x).unwrap().to_affine(), Some((x, y)));
+}
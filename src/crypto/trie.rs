@@ -0,0 +1,477 @@
+//! A binary Merkle-Patricia trie, for committing to a key/value map.
+//!
+//! Hashing the whole map works as a commitment, but every change means hashing everything
+//! again, and the only way to check one entry is to download them all. A trie fixes both. Each
+//! entry sits in a leaf, at the path given by the bits of its key's hash. Each inner node
+//! commits to its two children, so changing an entry only rehashes the nodes on its path, and
+//! a single entry can be proven with one sibling hash per inner node on that path.
+//!
+//! Paths are compressed the Patricia way. An inner node only exists where the paths of the
+//! leaves below it differ, and it records which bit they differ at. The shape of the trie
+//! therefore depends only on which keys it holds, and not on the order they were inserted in,
+//! so equal maps always have equal roots.
+//!
+//! Leaves commit to their full path, so a proof can also show that a key is absent: following
+//! the key's bits from the root leads to a leaf with a different path.
+
+use super::{hash, Hash};
+use crate::codec::{encode_sorted, Decode, DecodeError, Encode};
+use std::{collections::HashMap, fmt};
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+fn leaf_hash(path: &Hash, value_hash: &Hash) -> Hash {
+    hash(&(LEAF_TAG, path, value_hash))
+}
+
+fn node_hash(bit: u8, left: &Hash, right: &Hash) -> Hash {
+    hash(&(NODE_TAG, bit, left, right))
+}
+
+/// Whether the given bit of the path is set. Bit 0 is the most significant bit of the first
+/// byte.
+fn bit_at(path: &Hash, bit: u8) -> bool {
+    path.0[bit as usize / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+/// The first bit at which two different paths differ.
+fn first_difference(a: &Hash, b: &Hash) -> u8 {
+    let byte = (0..32).find(|&i| a.0[i] != b.0[i]).expect("paths differ");
+    (byte * 8) as u8 + (a.0[byte] ^ b.0[byte]).leading_zeros() as u8
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    Leaf {
+        path: Hash,
+        key: K,
+        value: V,
+        hash: Hash,
+    },
+    /// The leaves below the left child have the bit clear, those below the right child have
+    /// it set. The paths of all of them agree on every earlier bit.
+    Inner {
+        bit: u8,
+        children: Box<[Node<K, V>; 2]>,
+        hash: Hash,
+    },
+}
+
+impl<K, V: Encode> Node<K, V> {
+    fn leaf(path: Hash, key: K, value: V) -> Self {
+        let hash = leaf_hash(&path, &hash(&value));
+        Node::Leaf {
+            path,
+            key,
+            value,
+            hash,
+        }
+    }
+
+    fn inner(bit: u8, left: Self, right: Self) -> Self {
+        let hash = node_hash(bit, left.hash(), right.hash());
+        Node::Inner {
+            bit,
+            children: Box::new([left, right]),
+            hash,
+        }
+    }
+
+    fn hash(&self) -> &Hash {
+        match self {
+            Node::Leaf { hash, .. } | Node::Inner { hash, .. } => hash,
+        }
+    }
+
+    /// The leaf reached by following the given path from this node.
+    fn closest_leaf(&self, path: &Hash) -> &Self {
+        let mut node = self;
+        while let Node::Inner { bit, children, .. } = node {
+            node = &children[bit_at(path, *bit) as usize];
+        }
+        node
+    }
+
+    /// Put a new leaf into this subtree, given the bit at which its path first differs from
+    /// the paths already in the trie.
+    fn insert_new(self, difference: u8, leaf: Self, path: &Hash) -> Self {
+        match self {
+            Node::Inner { bit, children, .. } if bit < difference => {
+                let [left, right] = *children;
+                if bit_at(path, bit) {
+                    Node::inner(bit, left, right.insert_new(difference, leaf, path))
+                } else {
+                    Node::inner(bit, left.insert_new(difference, leaf, path), right)
+                }
+            }
+            existing if bit_at(path, difference) => Node::inner(difference, existing, leaf),
+            existing => Node::inner(difference, leaf, existing),
+        }
+    }
+
+    /// Replace the value of the leaf at the given path, which must exist, returning the old
+    /// value.
+    fn replace(self, path: &Hash, new_value: V) -> (Self, V) {
+        match self {
+            Node::Leaf {
+                path, key, value, ..
+            } => (Node::leaf(path, key, new_value), value),
+            Node::Inner { bit, children, .. } => {
+                let [left, right] = *children;
+                if bit_at(path, bit) {
+                    let (right, old) = right.replace(path, new_value);
+                    (Node::inner(bit, left, right), old)
+                } else {
+                    let (left, old) = left.replace(path, new_value);
+                    (Node::inner(bit, left, right), old)
+                }
+            }
+        }
+    }
+
+    /// Take the leaf at the given path out of this subtree, which is left empty if that was
+    /// its only leaf.
+    fn remove(self, path: &Hash) -> (Option<Self>, Option<(K, V)>) {
+        match self {
+            Node::Leaf {
+                path: leaf_path,
+                key,
+                value,
+                ..
+            } if leaf_path == *path => (None, Some((key, value))),
+            leaf @ Node::Leaf { .. } => (Some(leaf), None),
+            Node::Inner { bit, children, .. } => {
+                let [left, right] = *children;
+                let (kept, removed_from, go_right) = if bit_at(path, bit) {
+                    (left, right, true)
+                } else {
+                    (right, left, false)
+                };
+                let (rest, removed) = removed_from.remove(path);
+                let node = match rest {
+                    None => kept,
+                    Some(rest) if go_right => Node::inner(bit, kept, rest),
+                    Some(rest) => Node::inner(bit, rest, kept),
+                };
+                (Some(node), removed)
+            }
+        }
+    }
+
+    fn collect<'a>(&'a self, entries: &mut Vec<(&'a K, &'a V)>) {
+        match self {
+            Node::Leaf { key, value, .. } => entries.push((key, value)),
+            Node::Inner { children, .. } => {
+                children[0].collect(entries);
+                children[1].collect(entries);
+            }
+        }
+    }
+}
+
+/// A map whose contents are committed to by a Merkle root.
+///
+/// The root is kept up to date as entries change, at the cost of rehashing one path per
+/// change.
+#[derive(Clone)]
+pub struct Trie<K, V> {
+    root: Option<Node<K, V>>,
+    len: usize,
+}
+
+impl<K, V> Default for Trie<K, V> {
+    fn default() -> Self {
+        Trie { root: None, len: 0 }
+    }
+}
+
+impl<K: Encode, V: Encode> Trie<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The Merkle root committing to every entry. The root of an empty trie is the all zero
+    /// hash.
+    pub fn root(&self) -> Hash {
+        self.root
+            .as_ref()
+            .map_or(Hash::default(), |root| *root.hash())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let path = hash(key);
+        match self.root.as_ref()?.closest_leaf(&path) {
+            Node::Leaf {
+                path: leaf_path,
+                value,
+                ..
+            } if *leaf_path == path => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Set the value for the given key, returning the previous value if there was one.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let path = hash(&key);
+        let Some(root) = self.root.take() else {
+            self.root = Some(Node::leaf(path, key, value));
+            self.len = 1;
+            return None;
+        };
+        let Node::Leaf {
+            path: closest_path, ..
+        } = root.closest_leaf(&path)
+        else {
+            unreachable!("following a path always ends at a leaf")
+        };
+        if *closest_path == path {
+            let (root, old) = root.replace(&path, value);
+            self.root = Some(root);
+            return Some(old);
+        }
+        let difference = first_difference(closest_path, &path);
+        self.root = Some(root.insert_new(difference, Node::leaf(path, key, value), &path));
+        self.len += 1;
+        None
+    }
+
+    /// Remove the given key, returning its value if it was present.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (root, removed) = self.root.take()?.remove(&hash(key));
+        self.root = root;
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed.map(|(_, value)| value)
+    }
+
+    /// All the entries, in the order of their paths.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&K, &V)> {
+        let mut entries = Vec::with_capacity(self.len);
+        if let Some(root) = &self.root {
+            root.collect(&mut entries);
+        }
+        entries.into_iter()
+    }
+
+    /// Prove whether the given key is in the trie, and if so, with which value.
+    pub fn prove(&self, key: &K) -> TrieProof {
+        let path = hash(key);
+        let mut siblings = Vec::new();
+        let Some(mut node) = self.root.as_ref() else {
+            return TrieProof {
+                siblings,
+                leaf: None,
+            };
+        };
+        while let Node::Inner { bit, children, .. } = node {
+            let direction = bit_at(&path, *bit) as usize;
+            siblings.push((*bit, *children[1 - direction].hash()));
+            node = &children[direction];
+        }
+        let Node::Leaf {
+            path: leaf_path,
+            value,
+            ..
+        } = node
+        else {
+            unreachable!("the loop only stops at a leaf")
+        };
+        TrieProof {
+            siblings,
+            leaf: Some((*leaf_path, hash(value))),
+        }
+    }
+}
+
+/// Tries with the same root hold the same entries.
+impl<K: Encode, V: Encode> PartialEq for Trie<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.root() == other.root()
+    }
+}
+
+impl<K: Encode, V: Encode> Eq for Trie<K, V> {}
+
+impl<K: Encode + fmt::Debug, V: Encode + fmt::Debug> fmt::Debug for Trie<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Encode, V: Encode> FromIterator<(K, V)> for Trie<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        let mut trie = Trie::new();
+        for (key, value) in entries {
+            trie.insert(key, value);
+        }
+        trie
+    }
+}
+
+impl<K: Encode, V: Encode, const N: usize> From<[(K, V); N]> for Trie<K, V> {
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: Encode, V: Encode> From<HashMap<K, V>> for Trie<K, V> {
+    fn from(entries: HashMap<K, V>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+/// Encoded exactly like a `HashMap` with the same entries.
+impl<K: Encode, V: Encode> Encode for Trie<K, V> {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        encode_sorted(self.iter(), dest)
+    }
+}
+
+impl<K: Decode + Encode + Eq + std::hash::Hash, V: Decode + Encode> Decode for Trie<K, V> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(HashMap::<K, V>::decode(input)?.into())
+    }
+}
+
+/// A proof of whether some key is in the map committed to by a trie root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrieProof {
+    /// The bit and the sibling hash of each inner node on the key's path, top down.
+    siblings: Vec<(u8, Hash)>,
+    /// The path and value hash of the leaf the key's path leads to, or None if the trie is
+    /// empty. This is a different key's leaf when the key is absent.
+    leaf: Option<(Hash, Hash)>,
+}
+
+impl TrieProof {
+    /// Check that the key has the given value in the map committed to by the root, or is
+    /// absent if the value is None.
+    pub fn verify<K: Encode, V: Encode>(&self, root: &Hash, key: &K, value: Option<&V>) -> bool {
+        let path = hash(key);
+        let Some((leaf_path, value_hash)) = self.leaf else {
+            return self.siblings.is_empty() && value.is_none() && *root == Hash::default();
+        };
+        let leaf_matches = match value {
+            Some(value) => leaf_path == path && value_hash == hash(value),
+            None => leaf_path != path,
+        };
+        // The bits must be strictly increasing down the path, and the leaf must really lie
+        // where the key's path leads.
+        let well_formed = self.siblings.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            && self
+                .siblings
+                .iter()
+                .all(|(bit, _)| bit_at(&leaf_path, *bit) == bit_at(&path, *bit));
+        if !leaf_matches || !well_formed {
+            return false;
+        }
+
+        let node = self.siblings.iter().rev().fold(
+            leaf_hash(&leaf_path, &value_hash),
+            |node, (bit, sibling)| {
+                if bit_at(&path, *bit) {
+                    node_hash(*bit, sibling, &node)
+                } else {
+                    node_hash(*bit, &node, sibling)
+                }
+            },
+        );
+        node == *root
+    }
+}
+
+impl Encode for TrieProof {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        self.siblings.encode_to(dest);
+        self.leaf.encode_to(dest);
+    }
+}
+
+impl Decode for TrieProof {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(TrieProof {
+            siblings: Vec::decode(input)?,
+            leaf: Option::decode(input)?,
+        })
+    }
+}
+
+#[test]
+fn trie_root_ignores_insertion_order() {
+    let forwards: Trie<u64, u64> = (0..50).map(|i| (i, i * 10)).collect();
+    let backwards: Trie<u64, u64> = (0..50).rev().map(|i| (i, i * 10)).collect();
+
+    assert_eq!(forwards.root(), backwards.root());
+    assert_eq!(forwards.len(), 50);
+    assert_ne!(forwards.root(), Trie::<u64, u64>::new().root());
+}
+
+#[test]
+fn trie_updates_and_removals_restore_roots() {
+    let mut trie: Trie<u64, u64> = (0..20).map(|i| (i, i)).collect();
+    let before = trie.root();
+
+    assert_eq!(trie.insert(7, 70), Some(7));
+    assert_eq!(trie.get(&7), Some(&70));
+    assert_ne!(trie.root(), before);
+    assert_eq!(trie.insert(7, 7), Some(70));
+    assert_eq!(trie.root(), before);
+
+    assert_eq!(trie.insert(99, 1), None);
+    assert_eq!(trie.remove(&99), Some(1));
+    assert_eq!(trie.remove(&99), None);
+    assert_eq!(trie.root(), before);
+
+    for i in 0..20 {
+        assert_eq!(trie.remove(&i), Some(i));
+    }
+    assert!(trie.is_empty());
+    assert_eq!(trie.root(), Hash::default());
+}
+
+#[test]
+fn trie_proves_present_and_absent_keys() {
+    let trie: Trie<u64, u64> = (0..30).map(|i| (i, i + 100)).collect();
+    let root = trie.root();
+
+    for i in 0..30 {
+        let proof = trie.prove(&i);
+        assert!(proof.verify(&root, &i, Some(&(i + 100))));
+        assert!(!proof.verify(&root, &i, Some(&i)));
+        assert!(!proof.verify(&root, &i, None::<&u64>));
+    }
+    let proof = trie.prove(&1000);
+    assert!(proof.verify(&root, &1000u64, None::<&u64>));
+    assert!(!proof.verify(&root, &1000u64, Some(&1100u64)));
+    // A proof about one key says nothing about another.
+    assert!(!trie.prove(&3).verify(&root, &4u64, Some(&103u64)));
+
+    let empty = Trie::<u64, u64>::new();
+    assert!(empty.prove(&1).verify(&empty.root(), &1u64, None::<&u64>));
+}
+
+#[test]
+fn trie_encodes_like_a_hash_map() {
+    let entries = HashMap::from([(1u64, 2u64), (3, 4), (5, 6)]);
+    let trie = Trie::from(entries.clone());
+
+    assert_eq!(trie.encode(), entries.encode());
+    assert_eq!(Trie::decode_all(&trie.encode()), Ok(trie.clone()));
+
+    let proof = trie.prove(&3);
+    assert_eq!(TrieProof::decode_all(&proof.encode()), Ok(proof));
+}
@@ -0,0 +1,178 @@
+//! A verifiable random function, or VRF, is like a hash that only the holder of a secret key
+//! can compute, but that anyone with the public key can check. For each input there is exactly
+//! one valid output per key. Consensus engines use VRFs to run lotteries: each authority
+//! evaluates the VRF on the slot number and wins if the output is low enough. Nobody can
+//! predict another authority's output, and nobody can grind their own, because it is unique.
+//!
+//! A Schnorr signature will not do as a VRF, because a signer is free to choose any nonce and
+//! so can produce many valid signatures for the same message.
+//!
+//! This construction follows the shape of ECVRF (RFC 9381). The input is hashed to a curve
+//! point `H`, and the output point is `Gamma = d * H` for secret key `d`. The proof shows that
+//! `Gamma` and the public key `P = d * G` share the same discrete logarithm, with a Schnorr
+//! style challenge and response. The random output is the hash of `Gamma`.
+
+use super::schnorr::tagged_hash;
+use super::secp256k1::{Fe, Point, Scalar, U256};
+use super::{Hash, Keypair, PublicKey};
+use crate::codec::{Decode, DecodeError, Encode};
+use std::fmt;
+
+/// Proof that a VRF output was computed correctly. It is the compressed output point `Gamma`,
+/// followed by the challenge `c` and the response `s`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VrfProof(pub [u8; 97]);
+
+/// Hash the input to a curve point whose discrete logarithm nobody knows, by trying successive
+/// counters until the hash is the x coordinate of a point. About half of all x coordinates are.
+fn hash_to_curve(public: &PublicKey, input: &Hash) -> Point {
+    (0u32..)
+        .find_map(|counter| {
+            let x = tagged_hash(
+                "VRF/hash_to_curve",
+                &[&public.0, &input.0, &counter.to_be_bytes()],
+            );
+            Fe::new(U256::from_be_bytes(&x)).and_then(Point::lift_x)
+        })
+        .expect("some counter gives a point")
+}
+
+/// The challenge binds the proof to the key, the input point, the output, and the two
+/// commitments.
+fn challenge(public: &PublicKey, points: [Point; 4]) -> Scalar {
+    let encoded = points.map(|point| point.to_compressed().unwrap_or([0; 33]));
+    let c = tagged_hash(
+        "VRF/challenge",
+        &[
+            &public.0,
+            &encoded[0],
+            &encoded[1],
+            &encoded[2],
+            &encoded[3],
+        ],
+    );
+    Scalar::reduce(U256::from_be_bytes(&c))
+}
+
+/// The random output is the hash of the output point.
+fn output(gamma: &[u8; 33]) -> Hash {
+    Hash(tagged_hash("VRF/output", &[gamma]))
+}
+
+impl Keypair {
+    /// Evaluate the VRF on the given input, returning the random output and a proof that
+    /// anyone with this keypair's public key can check.
+    pub fn vrf_sign(&self, input: &Hash) -> (Hash, VrfProof) {
+        let d = self.secret;
+        let h = hash_to_curve(&self.public(), input);
+        let gamma = h.mul(d);
+        let h_bytes = h.to_compressed().expect("hashed points are on the curve");
+        let nonce = tagged_hash("VRF/nonce", &[&d.to_u256().to_be_bytes(), &h_bytes]);
+        let k = Scalar::reduce(U256::from_be_bytes(&nonce));
+        assert!(!k.is_zero(), "a zero nonce is astronomically unlikely");
+
+        let c = challenge(
+            &self.public(),
+            [h, gamma, Point::generator().mul(k), h.mul(k)],
+        );
+        let s = k.add(c.mul(d));
+
+        let gamma = gamma.to_compressed().expect("d is not zero");
+        let mut proof = [0u8; 97];
+        proof[..33].copy_from_slice(&gamma);
+        proof[33..65].copy_from_slice(&c.to_u256().to_be_bytes());
+        proof[65..].copy_from_slice(&s.to_u256().to_be_bytes());
+        (output(&gamma), VrfProof(proof))
+    }
+}
+
+impl PublicKey {
+    /// Check a VRF proof made by this key on the given input. Returns the VRF output when the
+    /// proof is valid.
+    pub fn vrf_verify(&self, input: &Hash, proof: &VrfProof) -> Option<Hash> {
+        let public_point = Fe::new(U256::from_be_bytes(&self.0)).and_then(Point::lift_x)?;
+        let gamma_bytes: [u8; 33] = proof.0[..33].try_into().expect("33 bytes");
+        let gamma = Point::from_compressed(&gamma_bytes)?;
+        let c = Scalar::new(U256::from_be_bytes(
+            &proof.0[33..65].try_into().expect("32 bytes"),
+        ))?;
+        let s = Scalar::new(U256::from_be_bytes(
+            &proof.0[65..].try_into().expect("32 bytes"),
+        ))?;
+        let h = hash_to_curve(self, input);
+
+        // s = k + c * d, so k * G = s * G - c * P and k * H = s * H - c * Gamma.
+        let u = Point::generator().mul(s).add(public_point.mul(c.neg()));
+        let v = h.mul(s).add(gamma.mul(c.neg()));
+        (challenge(self, [h, gamma, u, v]) == c).then(|| output(&gamma_bytes))
+    }
+}
+
+impl fmt::Debug for VrfProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "VrfProof({:02x}{:02x}{:02x}{:02x}…)",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        )
+    }
+}
+
+/// The all zero proof is never valid, because zero is not a valid point encoding. Like the
+/// default signature, it is a placeholder for genesis digests.
+impl Default for VrfProof {
+    fn default() -> Self {
+        VrfProof([0; 97])
+    }
+}
+
+impl Encode for VrfProof {
+    fn encode_to(&self, dest: &mut Vec<u8>) {
+        dest.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for VrfProof {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(VrfProof(<[u8; 97]>::decode(input)?))
+    }
+}
+
+#[test]
+fn vrf_output_is_verifiable_and_unique() {
+    let alice = Keypair::from_seed("//Alice");
+    let input = crate::hash(&7u64);
+    let (output, proof) = alice.vrf_sign(&input);
+
+    assert_eq!(alice.public().vrf_verify(&input, &proof), Some(output));
+    // Signing again gives the same output, and a different input gives a different one.
+    assert_eq!(alice.vrf_sign(&input), (output, proof));
+    assert_ne!(alice.vrf_sign(&crate::hash(&8u64)).0, output);
+}
+
+#[test]
+fn vrf_rejects_wrong_input_key_or_proof() {
+    let alice = Keypair::from_seed("//Alice");
+    let bob = Keypair::from_seed("//Bob");
+    let input = crate::hash(&7u64);
+    let (_, proof) = alice.vrf_sign(&input);
+
+    assert_eq!(alice.public().vrf_verify(&crate::hash(&8u64), &proof), None);
+    assert_eq!(bob.public().vrf_verify(&input, &proof), None);
+    assert_eq!(
+        alice.public().vrf_verify(&input, &VrfProof::default()),
+        None
+    );
+
+    // Swapping in someone else's output point breaks the proof.
+    let mut forged = proof;
+    forged.0[..33].copy_from_slice(&bob.vrf_sign(&input).1 .0[..33]);
+    assert_eq!(alice.public().vrf_verify(&input, &forged), None);
+}
+
+#[test]
+fn vrf_proof_round_trips_through_codec() {
+    let (_, proof) = Keypair::from_seed("//Alice").vrf_sign(&crate::hash(&1u64));
+
+    assert_eq!(VrfProof::decode_all(&proof.encode()), Ok(proof));
+}